@@ -0,0 +1,117 @@
+// whatever's behind the scene: a gradient, the stylised sky or a skybox, see gpu/background.rs
+#include "general.wgsl"
+
+struct BackgroundUniform {
+    // forward + x * right + y * up is the view ray through x, y in clip space
+    // forward.w is the mode (0 gradient, 1 sky, 2 skybox), right.w the skybox's intensity
+    forward: vec4f,
+    right: vec4f,
+    up: vec4f,
+    // gradient: top, bottom. sky: zenith, horizon, ground
+    colors: array<vec4f, 3>,
+    // xyz points at the sun, w is its radius in radians
+    sun: vec4f,
+    sun_color: vec4f,
+    // x is the cover, y how fast they drift
+    clouds: vec4f,
+};
+
+@group(0) @binding(0)
+var<uniform> background: BackgroundUniform;
+@group(0) @binding(1)
+var sky_texture: texture_cube<f32>;
+@group(0) @binding(2)
+var sky_sampler: sampler;
+
+@group(1) @binding(0)
+var<uniform> time: GpuTime;
+
+struct BackgroundVertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) ndc: vec2f,
+};
+
+// one triangle covering the screen, right on the far plane
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> BackgroundVertexOutput {
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = vec2f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+
+    var out: BackgroundVertexOutput;
+    out.clip_position = vec4f(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+fn hash(p: vec2f) -> f32 {
+    return fract(sin(dot(p, vec2f(127.1, 311.7))) * 43758.5453);
+}
+
+fn value_noise(p: vec2f) -> f32 {
+    let cell = floor(p);
+    let f = fract(p);
+    let s = f * f * (3.0 - 2.0 * f);
+
+    let a = hash(cell);
+    let b = hash(cell + vec2f(1.0, 0.0));
+    let c = hash(cell + vec2f(0.0, 1.0));
+    let d = hash(cell + vec2f(1.0, 1.0));
+    return mix(mix(a, b, s.x), mix(c, d, s.x), s.y);
+}
+
+fn cloud_noise(p: vec2f) -> f32 {
+    var total = 0.0;
+    var amplitude = 0.5;
+    var q = p;
+    for (var i = 0; i < 4; i++) {
+        total += value_noise(q) * amplitude;
+        q *= 2.03;
+        amplitude *= 0.5;
+    }
+    return total;
+}
+
+fn sky(dir: vec3f) -> vec3f {
+    let zenith = background.colors[0].rgb;
+    let horizon = background.colors[1].rgb;
+    let ground = background.colors[2].rgb;
+
+    // a soft band at the horizon, then flat ground below it
+    if dir.y < 0.0 {
+        return mix(horizon, ground, smoothstep(0.0, 0.05, -dir.y));
+    }
+    var color = mix(horizon, zenith, pow(dir.y, 0.5));
+
+    // a flat layer of clouds overhead, with hard edges for the stylised look
+    let cover = background.clouds.x;
+    let drift = vec2f(time.time_secs * background.clouds.y, 0.0);
+    let layer = dir.xz / max(dir.y, 0.05) * 0.6 + drift;
+    let density = cloud_noise(layer);
+    let cloud = smoothstep(1.0 - cover, 1.05 - cover, density) * smoothstep(0.0, 0.2, dir.y);
+    color = mix(color, mix(horizon, vec3f(1.0), 0.7), cloud * 0.9);
+
+    // a hard disc for the sun, with a glow around it that the clouds don't hide
+    let sun_dir = background.sun.xyz;
+    let radius = background.sun.w;
+    let angle = acos(clamp(dot(dir, sun_dir), -1.0, 1.0));
+    let disc = 1.0 - smoothstep(radius * 0.9, radius, angle);
+    let glow = exp(-angle / max(radius * 4.0, 0.001)) * 0.3;
+    color += background.sun_color.rgb * (disc * (1.0 - cloud) + glow);
+    return color;
+}
+
+@fragment
+fn fs_main(in: BackgroundVertexOutput) -> @location(0) vec4f {
+    let dir = normalize(background.forward.xyz + in.ndc.x * background.right.xyz + in.ndc.y * background.up.xyz);
+    let mode = background.forward.w;
+
+    var color: vec3f;
+    if mode < 0.5 {
+        color = mix(background.colors[1].rgb, background.colors[0].rgb, dir.y * 0.5 + 0.5);
+    } else if mode < 1.5 {
+        color = sky(dir);
+    } else {
+        color = textureSampleLevel(sky_texture, sky_sampler, dir, 0.0).rgb * background.right.w;
+    }
+    return vec4f(color, 1.0);
+}
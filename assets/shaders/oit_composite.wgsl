@@ -0,0 +1,28 @@
+// lays the averaged weighted blended transparency over the scene, see gpu/transparency.rs
+
+@group(0) @binding(0)
+var oit_accum: texture_2d<f32>;
+@group(0) @binding(1)
+var oit_reveal: texture_2d<f32>;
+
+// one triangle big enough to cover the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4f {
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+// the pipeline blends this as color * (1 - a) + scene * a
+@fragment
+fn fs_main(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let pixel = vec2i(position.xy);
+    let reveal = textureLoad(oit_reveal, pixel, 0).r;
+    // nothing transparent got drawn here
+    if reveal >= 0.999 {
+        discard;
+    }
+
+    let accum = textureLoad(oit_accum, pixel, 0);
+    let average = accum.rgb / max(accum.a, 1e-5);
+    return vec4f(average, reveal);
+}
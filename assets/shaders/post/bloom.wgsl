@@ -0,0 +1,34 @@
+// bright things glow onto their surroundings, gathered in one pass from a spiral of samples
+// params[0]: threshold, intensity, radius in pixels
+#include "post.wgsl"
+
+const SAMPLES: i32 = 32;
+// spreads the spiral's samples out evenly
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+@fragment
+fn fs_main(in: PostVertexOutput) -> @location(0) vec4f {
+    let color = post_sample(in.uv);
+    let threshold = post_settings.params[0].x;
+    let intensity = post_settings.params[0].y;
+    let radius = post_settings.params[0].z;
+
+    var glow = vec3f(0.0);
+    var total = 0.0;
+    for (var i = 0; i < SAMPLES; i++) {
+        let along = (f32(i) + 0.5) / f32(SAMPLES);
+        let angle = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2f(cos(angle), sin(angle)) * sqrt(along) * radius * post_settings.texel;
+
+        let sample = post_sample(in.uv + offset).rgb;
+        let brightness = luminance(sample);
+        // only the part over the threshold glows
+        let bright = max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+
+        let weight = 1.0 - along;
+        glow += sample * bright * weight;
+        total += weight;
+    }
+
+    return vec4f(color.rgb + glow / total * intensity, color.a);
+}
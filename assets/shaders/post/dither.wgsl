@@ -0,0 +1,22 @@
+// posterize, with a 4x4 bayer pattern pushing pixels up or down a shade to fake the ones in between
+// params[0]: levels, pixel size
+#include "post.wgsl"
+
+@fragment
+fn fs_main(in: PostVertexOutput) -> @location(0) vec4f {
+    let color = post_sample(in.uv);
+    let steps = max(post_settings.params[0].x, 2.0) - 1.0;
+    let pixel_size = max(post_settings.params[0].y, 1.0);
+
+    var bayer = array<f32, 16>(
+        0.0, 8.0, 2.0, 10.0,
+        12.0, 4.0, 14.0, 6.0,
+        3.0, 11.0, 1.0, 9.0,
+        15.0, 7.0, 13.0, 5.0,
+    );
+    let pixel = vec2u(in.clip_position.xy / pixel_size);
+    let threshold = (bayer[(pixel.y % 4u) * 4u + pixel.x % 4u] + 0.5) / 16.0 - 0.5;
+
+    let banded = floor(to_gamma(color.rgb) * steps + 0.5 + threshold) / steps;
+    return vec4f(from_gamma(banded), color.a);
+}
@@ -0,0 +1,36 @@
+// lines where the depth jumps (silhouettes) or bends (creases)
+// params[0]: line color
+// params[1]: thickness in pixels, depth threshold, crease threshold
+#include "post.wgsl"
+
+@fragment
+fn fs_main(in: PostVertexOutput) -> @location(0) vec4f {
+    let color = post_sample(in.uv);
+    let line_color = post_settings.params[0].rgb;
+    let thickness = post_settings.params[1].x;
+    let depth_threshold = post_settings.params[1].y;
+    let crease_threshold = post_settings.params[1].z;
+
+    let step_x = vec2f(post_settings.texel.x * thickness, 0.0);
+    let step_y = vec2f(0.0, post_settings.texel.y * thickness);
+    let center = linear_depth(in.uv);
+    let left = linear_depth(in.uv - step_x);
+    let right = linear_depth(in.uv + step_x);
+    let up = linear_depth(in.uv - step_y);
+    let down = linear_depth(in.uv + step_y);
+
+    // relative to the distance, so far away things don't get outlined everywhere
+    let jump = max(
+        max(abs(left - center), abs(right - center)),
+        max(abs(up - center), abs(down - center)),
+    ) / center;
+
+    // 1 / depth changes at a steady rate across any flat surface, even at an angle,
+    // so where that rate changes two faces meet
+    let bend_x = abs(1.0 / left + 1.0 / right - 2.0 / center);
+    let bend_y = abs(1.0 / up + 1.0 / down - 2.0 / center);
+    let bend = max(bend_x, bend_y) * center;
+
+    let edge = max(step(depth_threshold, jump), step(crease_threshold, bend));
+    return vec4f(mix(color.rgb, line_color, edge), color.a);
+}
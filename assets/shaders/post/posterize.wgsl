@@ -0,0 +1,12 @@
+// cuts every channel down to a few shades
+// params[0]: levels
+#include "post.wgsl"
+
+@fragment
+fn fs_main(in: PostVertexOutput) -> @location(0) vec4f {
+    let color = post_sample(in.uv);
+    let steps = max(post_settings.params[0].x, 2.0) - 1.0;
+
+    let banded = floor(to_gamma(color.rgb) * steps + 0.5) / steps;
+    return vec4f(from_gamma(banded), color.a);
+}
@@ -0,0 +1,7 @@
+// copies the finished image onto the screen, always runs after every other effect
+#include "post.wgsl"
+
+@fragment
+fn fs_main(in: PostVertexOutput) -> @location(0) vec4f {
+    return vec4f(post_sample(in.uv).rgb, 1.0);
+}
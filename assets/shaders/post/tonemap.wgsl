@@ -0,0 +1,26 @@
+// squashes the hdr colors into what a screen can show
+// params[0]: exposure, tonemapper (0 reinhard, 1 aces)
+#include "post.wgsl"
+
+fn reinhard(color: vec3f) -> vec3f {
+    return color / (1.0 + color);
+}
+
+// krzysztof narkowicz's fit of the aces curve
+fn aces(color: vec3f) -> vec3f {
+    let a = color * (2.51 * color + 0.03);
+    let b = color * (2.43 * color + 0.59) + 0.14;
+    return clamp(a / b, vec3f(0.0), vec3f(1.0));
+}
+
+@fragment
+fn fs_main(in: PostVertexOutput) -> @location(0) vec4f {
+    let color = post_sample(in.uv);
+    let exposed = color.rgb * post_settings.params[0].x;
+
+    var mapped = reinhard(exposed);
+    if post_settings.params[0].y > 0.5 {
+        mapped = aces(exposed);
+    }
+    return vec4f(mapped, color.a);
+}
@@ -1,9 +1,27 @@
+#include "camera.wgsl"
+#include "general.wgsl"
+#include "material.wgsl"
+#include "lighting.wgsl"
+#include "transparency.wgsl"
+
+// #define TOON for cel shading instead of smooth blinn-phong
+#ifdef TOON
+const TOON_BANDS: f32 = 3.0;
+#endif
+
+// the pipeline turns these on from the material's blend mode (see BlendMode in gpu/material.rs):
+// ALPHA_CUTOUT throws away pixels under the cutoff, OIT writes into the weighted blended targets
+// instead of the scene, and PREMULTIPLIED means the color already has the alpha in it
 
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
 @group(2) @binding(0)
-var<uniform> time: GpuTime;
+var<storage, read> lights: Lights;
+@group(2) @binding(1)
+var shadow_atlas: texture_depth_2d;
+@group(2) @binding(2)
+var shadow_sampler: sampler_comparison;
 
 @group(3) @binding(0)
 var<uniform> position_matrix: ObjectPosUniform;
@@ -12,11 +30,15 @@ var<uniform> position_matrix: ObjectPosUniform;
 @vertex
 fn vs_main(
     model: MeshVertexInput,
+    instance: InstanceInput,
 ) -> MeshVertexOutput {
+    let instance_matrix = get_instance_matrix(instance);
     var out: MeshVertexOutput;
-    out.clip_position = get_projection(model.position, position_matrix);
+    out.clip_position = get_instanced_projection(model.position, position_matrix, instance_matrix);
     out.tex_coords = model.tex_coords;
-    out.normal = model.normal;
+    out.normal = get_instanced_world_normal(model.normal, position_matrix, instance_matrix);
+    out.tint = instance.tint;
+    out.world_position = get_instanced_world_position(model.position, position_matrix, instance_matrix);
     return out;
 }
 
@@ -26,12 +48,47 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(4)
+var t_specular: texture_2d<f32>;
+@group(0) @binding(5)
+var s_specular: sampler;
+@group(0) @binding(6)
+var<uniform> material: MaterialUniform;
 
 @fragment
-fn fs_main(in: MeshVertexOutput) -> @location(0) vec4<f32> {
+#ifdef OIT
+fn fs_main(in: MeshVertexOutput, @builtin(front_facing) front_facing: bool) -> OitOutput {
+#else
+fn fs_main(in: MeshVertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+#endif
+    let base = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.diffuse * in.tint;
+#ifdef ALPHA_CUTOUT
+    if base.a < material.alpha_cutoff {
+        discard;
+    }
+#endif
+    let specular = textureSample(t_specular, s_specular, in.tex_coords).rgb * material.specular;
+
+    // light the side we're looking at, back faces included
+    var normal = normalize(in.normal);
+    if !front_facing {
+        normal = -normal;
+    }
+    let view_direction = normalize(camera.view_position.xyz - in.world_position);
 
-    let texture_sample_data = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let normal_colors = vec4f(in.normal, 1.0);
+#ifdef TOON
+    let color = shade_toon(in.world_position, normal, view_direction, base.rgb, specular, material.shininess, TOON_BANDS);
+#else
+    let color = shade_blinn_phong(in.world_position, normal, view_direction, base.rgb, specular, material.shininess);
+#endif
 
-    return texture_sample_data;
+#ifdef OIT
+#ifdef PREMULTIPLIED
+    return oit_output(color, base.a, in.clip_position.z);
+#else
+    return oit_output(color * base.a, base.a, in.clip_position.z);
+#endif
+#else
+    return vec4f(color, base.a);
+#endif
 }
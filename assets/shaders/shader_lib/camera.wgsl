@@ -1,6 +1,11 @@
+#include "object.wgsl"
+
+// expects the including shader to declare `camera: CameraUniform`
 
 struct CameraUniform {
   view_proj: mat4x4<f32>,
+  // w is always 1
+  view_position: vec4f,
 };
 
 fn get_camera_projection(position: vec3f) -> vec4<f32> {
@@ -18,4 +23,21 @@ fn get_projection(pos: vec3f, position_matrix: ObjectPosUniform) -> vec4<f32> {
   var position: vec4f = get_location(pos, position_matrix);
   var view_proj: vec4f = camera.view_proj * position;
   return view_proj;
-}
\ No newline at end of file
+}
+
+// same as get_projection, but the instance gets applied before the object's location
+fn get_instanced_projection(pos: vec3f, position_matrix: ObjectPosUniform, instance: mat4x4<f32>) -> vec4<f32> {
+  var position: vec4f = position_matrix.proj * instance * vec4<f32>(pos, 1.0);
+  var view_proj: vec4f = camera.view_proj * position;
+  return view_proj;
+}
+// where the vertex ends up in the world, before the camera gets involved
+fn get_instanced_world_position(pos: vec3f, position_matrix: ObjectPosUniform, instance: mat4x4<f32>) -> vec3f {
+  return (position_matrix.proj * instance * vec4<f32>(pos, 1.0)).xyz;
+}
+
+// only right for uniform scaling, stretched meshes get slightly bent normals
+fn get_instanced_world_normal(normal: vec3f, position_matrix: ObjectPosUniform, instance: mat4x4<f32>) -> vec3f {
+  let model = position_matrix.proj * instance;
+  return normalize(mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz) * normal);
+}
@@ -1,3 +1,5 @@
+#include "object.wgsl"
+
 
 struct GpuTime {
     time_secs: f32,
@@ -7,10 +9,27 @@ struct MeshVertexInput {
     @location(0) position: vec3f,
     @location(1) tex_coords: vec2f,
     @location(2) normal: vec3f,
+    @location(3) tangent: vec4f,
 }
 
 struct MeshVertexOutput {
     @builtin(position) clip_position: vec4f,
     @location(0) tex_coords: vec2f,
+    // world space
     @location(1) normal: vec3f,
-};
\ No newline at end of file
+    @location(2) tint: vec4f,
+    @location(3) world_position: vec3f,
+};
+
+// one per instance of the mesh, see gpu/instances.rs
+struct InstanceInput {
+    @location(5) model_0: vec4f,
+    @location(6) model_1: vec4f,
+    @location(7) model_2: vec4f,
+    @location(8) model_3: vec4f,
+    @location(9) tint: vec4f,
+}
+
+fn get_instance_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+}
\ No newline at end of file
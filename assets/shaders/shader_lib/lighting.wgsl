@@ -0,0 +1,170 @@
+// every light in the scene, see gpu/lights.rs
+// expects the including shader to declare `lights: Lights` (var<storage, read>),
+// `shadow_atlas: texture_depth_2d` and `shadow_sampler: sampler_comparison`
+
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct Light {
+    position: vec3f,
+    kind: u32,
+    // normalized, where the light points
+    direction: vec3f,
+    // 0 means it never runs out
+    range: f32,
+    color: vec3f,
+    intensity: f32,
+    // spot cone, as cosines of the half angles
+    inner_cos: f32,
+    outer_cos: f32,
+    depth_bias: f32,
+    // world units along the surface normal
+    normal_bias: f32,
+    // x, y, width, height of the light's square in the shadow atlas, 0 width means no shadow
+    shadow_rect: vec4f,
+    // world to the light's clip space
+    shadow_matrix: mat4x4<f32>,
+};
+
+struct Lights {
+    ambient: vec3f,
+    count: u32,
+    lights: array<Light>,
+};
+
+// what a light does to one point, before the surface gets a say
+struct LightSample {
+    // from the surface towards the light
+    direction: vec3f,
+    // color * intensity, after falloff
+    radiance: vec3f,
+};
+
+// inverse square falloff, smoothly cut to 0 at the range so there's no hard edge
+fn light_attenuation(distance: f32, range: f32) -> f32 {
+    let inverse_square = 1.0 / max(distance * distance, 0.0001);
+    if range <= 0.0 {
+        return inverse_square;
+    }
+    let ratio = distance / range;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window * inverse_square;
+}
+
+fn sample_light(light: Light, world_position: vec3f) -> LightSample {
+    var out: LightSample;
+    if light.kind == LIGHT_DIRECTIONAL {
+        out.direction = -light.direction;
+        out.radiance = light.color * light.intensity;
+        return out;
+    }
+
+    let to_light = light.position - world_position;
+    let distance = length(to_light);
+    out.direction = to_light / max(distance, 0.0001);
+    var strength = light.intensity * light_attenuation(distance, light.range);
+
+    if light.kind == LIGHT_SPOT {
+        let cone = dot(-out.direction, light.direction);
+        strength *= smoothstep(light.outer_cos, light.inner_cos, cone);
+    }
+
+    out.radiance = light.color * strength;
+    return out;
+}
+
+// 0 in shadow, 1 lit, 3x3 pcf taps (each one is also filtered by the comparison sampler)
+fn shadow_factor(light: Light, world_position: vec3f, normal: vec3f) -> f32 {
+    if light.shadow_rect.z <= 0.0 {
+        return 1.0;
+    }
+
+    let clip = light.shadow_matrix * vec4f(world_position + normal * light.normal_bias, 1.0);
+    if clip.w <= 0.0 {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    // outside of what the light rendered, nothing can be casting onto it
+    if any(abs(ndc.xy) > vec2f(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let local_uv = ndc.xy * vec2f(0.5, -0.5) + 0.5;
+    let uv = light.shadow_rect.xy + local_uv * light.shadow_rect.zw;
+    let depth = ndc.z - light.depth_bias;
+
+    // keep the taps inside the light's own square, the neighbours belong to other lights
+    let texel = 1.0 / vec2f(textureDimensions(shadow_atlas));
+    let rect_min = light.shadow_rect.xy + texel * 0.5;
+    let rect_max = light.shadow_rect.xy + light.shadow_rect.zw - texel * 0.5;
+
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let tap = clamp(uv + vec2f(f32(x), f32(y)) * texel, rect_min, rect_max);
+            lit += textureSampleCompareLevel(shadow_atlas, shadow_sampler, tap, depth);
+        }
+    }
+    return lit / 9.0;
+}
+
+// how much light a matte surface catches
+fn lambert(normal: vec3f, light_direction: vec3f) -> f32 {
+    return max(dot(normal, light_direction), 0.0);
+}
+
+// the highlight, shininess is the material's Ns
+fn blinn_phong(normal: vec3f, light_direction: vec3f, view_direction: vec3f, shininess: f32) -> f32 {
+    if dot(normal, light_direction) <= 0.0 {
+        return 0.0;
+    }
+    let halfway = normalize(light_direction + view_direction);
+    return pow(max(dot(normal, halfway), 0.0), max(shininess, 1.0));
+}
+
+// cel shading, snaps 0..1 into a few flat bands instead of a smooth gradient
+fn toon_ramp(amount: f32, bands: f32) -> f32 {
+    return floor(clamp(amount, 0.0, 1.0) * bands + 0.5) / bands;
+}
+
+// every light added up, diffuse_color and specular_color are the surface's colors
+fn shade_blinn_phong(
+    world_position: vec3f,
+    normal: vec3f,
+    view_direction: vec3f,
+    diffuse_color: vec3f,
+    specular_color: vec3f,
+    shininess: f32,
+) -> vec3f {
+    var color = lights.ambient * diffuse_color;
+    for (var i = 0u; i < lights.count; i++) {
+        let light = sample_light(lights.lights[i], world_position);
+        let shadow = shadow_factor(lights.lights[i], world_position, normal);
+        let diffuse = lambert(normal, light.direction) * diffuse_color;
+        let specular = blinn_phong(normal, light.direction, view_direction, shininess) * specular_color;
+        color += (diffuse + specular) * light.radiance * shadow;
+    }
+    return color;
+}
+
+// same as shade_blinn_phong, but every light gets banded and the highlight is a hard spot
+fn shade_toon(
+    world_position: vec3f,
+    normal: vec3f,
+    view_direction: vec3f,
+    diffuse_color: vec3f,
+    specular_color: vec3f,
+    shininess: f32,
+    bands: f32,
+) -> vec3f {
+    var color = lights.ambient * diffuse_color;
+    for (var i = 0u; i < lights.count; i++) {
+        let light = sample_light(lights.lights[i], world_position);
+        let shadow = shadow_factor(lights.lights[i], world_position, normal);
+        let diffuse = toon_ramp(lambert(normal, light.direction), bands) * diffuse_color;
+        let highlight = step(0.5, blinn_phong(normal, light.direction, view_direction, shininess));
+        color += (diffuse + highlight * specular_color) * light.radiance * shadow;
+    }
+    return color;
+}
@@ -0,0 +1,12 @@
+// the scalar half of a mesh's material, see gpu/material.rs
+// (diffuse, normal and specular textures sit next to it in the same bind group)
+struct MaterialUniform {
+    // Kd, with d (opacity) in the alpha
+    diffuse: vec4f,
+    // Ks
+    specular: vec3f,
+    // Ns
+    shininess: f32,
+    // with ALPHA_CUTOUT, anything with less alpha than this gets discarded
+    alpha_cutoff: f32,
+};
@@ -0,0 +1,72 @@
+// everything a fullscreen post process effect in post/ shares, see gpu/post.rs
+
+struct PostSettings {
+    // the size of one pixel, in uv
+    texel: vec2f,
+    // the camera's clip planes, for making the depth linear again
+    near: f32,
+    far: f32,
+    // what's in here is up to each effect
+    params: array<vec4f, 2>,
+};
+
+// what the scene (or the effect before this one) rendered
+@group(0) @binding(0)
+var post_input: texture_2d<f32>;
+@group(0) @binding(1)
+var post_sampler: sampler;
+@group(0) @binding(2)
+#ifdef MSAA
+var scene_depth: texture_multisampled_2d<f32>;
+#else
+var scene_depth: texture_2d<f32>;
+#endif
+@group(0) @binding(3)
+var<uniform> post_settings: PostSettings;
+
+struct PostVertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) uv: vec2f,
+};
+
+// one triangle big enough to cover the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> PostVertexOutput {
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: PostVertexOutput;
+    out.clip_position = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn post_sample(uv: vec2f) -> vec4f {
+    return textureSampleLevel(post_input, post_sampler, uv, 0.0);
+}
+
+// distance from the camera, the depth buffer itself bunches everything up near the far plane
+fn linear_depth(uv: vec2f) -> f32 {
+    let size = vec2i(textureDimensions(scene_depth));
+    let pixel = clamp(vec2i(uv * vec2f(size)), vec2i(0), size - 1);
+    // the first sample with msaa on, close enough for outlines
+    let depth = textureLoad(scene_depth, pixel, 0).r;
+
+    // the projection went through OPENGL_TO_WGPU_MATRIX, so undo that first
+    let ndc = depth * 2.0 - 1.0;
+    let near = post_settings.near;
+    let far = post_settings.far;
+    return 2.0 * near * far / (far + near - ndc * (far - near));
+}
+
+fn luminance(color: vec3f) -> f32 {
+    return dot(color, vec3f(0.2126, 0.7152, 0.0722));
+}
+
+// quantizing looks even to the eye in gamma space, not in linear space
+fn to_gamma(color: vec3f) -> vec3f {
+    return pow(max(color, vec3f(0.0)), vec3f(1.0 / 2.2));
+}
+
+fn from_gamma(color: vec3f) -> vec3f {
+    return pow(max(color, vec3f(0.0)), vec3f(2.2));
+}
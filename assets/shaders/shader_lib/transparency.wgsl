@@ -0,0 +1,24 @@
+// weighted blended order independent transparency (mcguire and bavoil), see gpu/transparency.rs.
+// every transparent pixel adds its weighted color into accum and multiplies its alpha out of
+// reveal, the composite then averages accum and lays it over the scene by how much got covered
+
+struct OitOutput {
+    @location(0) accum: vec4f,
+    @location(1) reveal: f32,
+};
+
+// closer and more opaque counts for more, depth is the 0 to 1 one out of the depth buffer
+fn oit_weight(alpha: f32, depth: f32) -> f32 {
+    let far = 1.0 - depth;
+    return clamp(alpha * max(1e-2, 3e3 * far * far * far), 1e-2, 3e3);
+}
+
+// premultiplied is the color already multiplied by alpha
+fn oit_output(premultiplied: vec3f, alpha: f32, depth: f32) -> OitOutput {
+    let weight = oit_weight(alpha, depth);
+
+    var out: OitOutput;
+    out.accum = vec4f(premultiplied, alpha) * weight;
+    out.reveal = alpha;
+    return out;
+}
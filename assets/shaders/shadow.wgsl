@@ -0,0 +1,22 @@
+// depth only, renders the scene from a light into its square of the shadow atlas (see gpu/shadows.rs)
+#include "general.wgsl"
+#include "object.wgsl"
+
+struct ShadowView {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> shadow_view: ShadowView;
+
+@group(1) @binding(0)
+var<uniform> position_matrix: ObjectPosUniform;
+
+@vertex
+fn vs_main(
+    model: MeshVertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4f {
+    let world_position = position_matrix.proj * get_instance_matrix(instance) * vec4f(model.position, 1.0);
+    return shadow_view.view_proj * world_position;
+}
@@ -1,20 +1,27 @@
 // gets every process and organises it's types to be ran
 
-use std::{sync::Arc, time};
+use std::{path::PathBuf, time};
+#[cfg(feature = "window")]
+use std::sync::Arc;
 
 use crate::{
+  files::shader_watcher::ShaderWatcher,
   gpu::{
     camera, device_drivers, object, gpu_pointers,
+    capture::FrameCapture,
     render::{self, RenderTask},
     gpu_data::{self, GpuTime},
+    gpu_pointers::{BindGroupSet, EngineResource},
+    settings::GraphicsSettings,
     texture,
   },
-  window::{sdl_handle::SdlHandle, tickrate, translate_surface, user_input},
+  window::tickrate,
 };
+#[cfg(feature = "window")]
+use crate::window::{sdl_handle::SdlHandle, translate_surface, user_input};
 
 pub struct Engine {
-  #[allow(unused)]
-  pub data_bindgroups: gpu_pointers::MemoryLayouts,
+  pub data_bindgroups: gpu_pointers::BindingRegistry,
   pub texture_bundle: texture::TextureBundle,
 
   pub camera: camera::GpuCamera,
@@ -26,22 +33,42 @@ pub struct Engine {
   pub gpu_time: GpuTime,
   pub engine_start_time: time::Instant,
 
-  pub user_input: user_input::MovementHandler,
+  // None when headless, there's nothing to get input from
+  #[cfg(feature = "window")]
+  pub user_input: Option<user_input::MovementHandler>,
+
+  // None if the os wouldn't let us watch the shader folder
+  shader_watcher: Option<ShaderWatcher>,
+
+  // pending screenshots/png sequences
+  capture: FrameCapture,
 
   is_running: bool,
 }
 
 impl Engine {
   pub fn redraw(&mut self) {
+    self.hot_reload_shaders();
     self.update_gpu_buffers();
 
+    let capture_path = self.capture.next_path();
+
     // try and render crap
-    match self.render_task.render(&self) {
-      Ok(_) => {}
+    match self.render_task.render_frame(&self, capture_path.is_some()) {
+      Ok(readback) => {
+        if let (Some(readback), Some(path)) = (readback, capture_path) {
+          let saved = readback
+            .finish(&self.drivers)
+            .and_then(|image| FrameCapture::save(&image, &path));
+          if let Err(error) = saved {
+            log::error!("failed to save {}: {}", path.display(), error);
+          }
+        }
+      }
 
       // reconfigure the surface if it's bad
       Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-        let size = self.get_window().0.size();
+        let size = self.drivers.get_target_size();
         self.resize(size.0, size.1);
       }
 
@@ -51,28 +78,41 @@ impl Engine {
     }
   }
 
-  async fn new_closed(sdl_handle: &SdlHandle, window: Arc<sdl3::video::Window>) -> Self {
-    let mut data_bindgroups = gpu_pointers::MemoryLayouts::new();
-    let drivers = device_drivers::Drivers::new(window.clone()).await;
+  fn from_drivers(drivers: device_drivers::Drivers, shader_watcher: Option<ShaderWatcher>) -> Self {
+    let mut data_bindgroups = gpu_pointers::BindingRegistry::new();
 
     let texture_bundle =
       texture::TextureBundle::new(&drivers).expect("failed to load texture bundle");
 
-    let render_task = render::RenderTask::new(&drivers);
+    let mut render_task = render::RenderTask::new(&drivers);
 
-    let cam = camera::GpuCamera::new(&drivers.device, window.size());
+    let cam = camera::GpuCamera::new(&drivers.device, drivers.get_size());
 
     let gpu_time = gpu_data::create_time_bind_group(&drivers.device);
 
-    data_bindgroups.add_bind(&texture_bundle);
-    data_bindgroups.add_bind(&cam);
-    data_bindgroups.add_bind(&gpu_time);
-    data_bindgroups.add_bind(&render_task);
+    // shaders get matched up with these by variable name, see gpu_pointers::EngineResource
+    data_bindgroups.add_bind(EngineResource::Material, &texture_bundle);
+    data_bindgroups.add_bind(EngineResource::Camera, &cam);
+    data_bindgroups.add_bind(EngineResource::Time, &gpu_time);
+    data_bindgroups.add_bind(EngineResource::ObjectLocation, &render_task);
+    data_bindgroups.add_bind(EngineResource::Light, render_task.get_lights());
+    data_bindgroups.add_bind(EngineResource::ShadowView, render_task.get_shadow_pass());
+    data_bindgroups.add_bind(EngineResource::PostProcess, render_task.get_post_stack());
+    data_bindgroups.add_bind(
+      EngineResource::Background,
+      render_task.get_background_pass(),
+    );
+    data_bindgroups.add_bind(
+      EngineResource::Transparency,
+      render_task.get_transparency_pass(),
+    );
+    render_task.init_shadow_pipeline(&drivers, &data_bindgroups);
+    render_task.init_background_pipeline(&drivers, &data_bindgroups);
+    render_task.init_transparency_pipeline(&drivers, &data_bindgroups);
+    render_task.init_post_pipeline(&drivers, &data_bindgroups);
 
     let tickrate = tickrate::Tickrate::new();
 
-    let user_input = user_input::MovementHandler::new(sdl_handle, window.clone());
-
     Self {
       render_task,
       texture_bundle,
@@ -82,41 +122,152 @@ impl Engine {
       drivers,
       gpu_time,
       engine_start_time: time::Instant::now(),
-      user_input,
+      #[cfg(feature = "window")]
+      user_input: None,
+      shader_watcher,
+      capture: FrameCapture::new(),
       is_running: true,
     }
   }
 
-  pub async fn new(sdl_handle: &SdlHandle, window: Arc<sdl3::video::Window>) -> Self {
-    let engine = Self::new_closed(sdl_handle, window).await;
+  #[cfg(feature = "window")]
+  async fn new_closed(
+    sdl_handle: &SdlHandle,
+    window: Arc<sdl3::video::Window>,
+    settings: &GraphicsSettings,
+  ) -> anyhow::Result<Self> {
+    let drivers = device_drivers::Drivers::new(window.clone(), settings).await?;
+
+    let user_input = user_input::MovementHandler::new(sdl_handle, window.clone());
+
+    let shader_watcher = ShaderWatcher::new()
+      .inspect_err(|error| log::warn!("shader hot reloading is disabled: {}", error))
+      .ok();
+
+    let mut engine = Self::from_drivers(drivers, shader_watcher);
+    engine.user_input = Some(user_input);
+    Ok(engine)
+  }
+
+  /// errors if not even a software adapter could be found
+  #[cfg(feature = "window")]
+  pub async fn new(
+    sdl_handle: &SdlHandle,
+    window: Arc<sdl3::video::Window>,
+    settings: &GraphicsSettings,
+  ) -> anyhow::Result<Self> {
+    let engine = Self::new_closed(sdl_handle, window, settings).await;
     return engine;
   }
 
+  /// an engine without a window or sdl, everything gets rendered into an offscreen texture
+  /// (see gpu::readback to get it back). no input and no shader hot reloading
+  pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+    Self::new_headless_with_settings(width, height, &GraphicsSettings::default()).await
+  }
+
+  pub async fn new_headless_with_settings(
+    width: u32,
+    height: u32,
+    settings: &GraphicsSettings,
+  ) -> anyhow::Result<Self> {
+    let drivers = device_drivers::Drivers::new_headless(width, height, settings).await?;
+    Ok(Self::from_drivers(drivers, None))
+  }
+
+  /// what the engine is really running with, the gpu might not have had everything asked for
+  pub fn get_graphics_settings(&self) -> &GraphicsSettings {
+    self.drivers.get_settings()
+  }
+
+  /// applies the settings from the next frame on, rebuilding whatever msaa touches.
+  /// returns what was actually applied
+  pub fn set_graphics_settings(&mut self, settings: GraphicsSettings) -> GraphicsSettings {
+    let old_samples = self.drivers.get_sample_count();
+    let applied = self.drivers.apply_settings(settings);
+
+    if applied.msaa_samples != old_samples {
+      self
+        .render_task
+        .rebuild_pipelines(&self.drivers, &mut self.data_bindgroups);
+    }
+    applied
+  }
+
   pub fn resize(&mut self, width: u32, height: u32) {
     if width > 0 && height > 0 {
       // resize window
       self.camera.set_aspect((width, height));
-      self.drivers.surface_config.width = width;
-      self.drivers.surface_config.height = height;
-      self
-        .drivers
-        .surface
-        .configure(&self.drivers.device, &self.drivers.surface_config);
-      // resize textures (make a new one)
-      self.texture_bundle.depth_buffer =
-        texture::DynamicTexture::create_depth_buffer(&self.drivers);
+      self.drivers.resize(width, height);
+      // the render graph's attachments that follow the surface's size
+      self.render_task.resize(&self.drivers);
     }
   }
 
-  pub fn get_window(&self) -> translate_surface::SyncWindow {
-    translate_surface::SyncWindow(self.drivers.window.clone())
+  /// None when running headless
+  #[cfg(feature = "window")]
+  pub fn get_window(&self) -> Option<translate_surface::SyncWindow> {
+    let window = self.drivers.get_window()?;
+    Some(translate_surface::SyncWindow(window.clone()))
+  }
+
+  /// bind groups that stay the same for every mesh in a frame
+  pub fn get_universal_bind_groups(&self) -> BindGroupSet<'_> {
+    let mut universal = BindGroupSet::new();
+    universal.set(EngineResource::Camera, &self.camera.camera_bind_group);
+    universal.set(EngineResource::Time, &self.gpu_time.bindgroup);
+    universal.set(
+      EngineResource::Light,
+      self.render_task.get_lights().get_bind_group(),
+    );
+    universal
+  }
+
+  /// saves the next rendered frame, the image format comes from the file extension
+  pub fn screenshot(&mut self, path: impl Into<PathBuf>) {
+    self.capture.screenshot(path.into());
+  }
+
+  /// saves the next frame_count rendered frames as numbered pngs in folder
+  pub fn record_frames(&mut self, folder: impl Into<PathBuf>, frame_count: u32) {
+    self.capture.record_sequence(folder.into(), frame_count);
+  }
+
+  /// renders a frame right now and hands it back instead of saving it
+  pub fn capture_frame(&mut self) -> anyhow::Result<image::RgbaImage> {
+    self.update_gpu_buffers();
+
+    let readback = self
+      .render_task
+      .render_frame(&self, true)?
+      .ok_or(anyhow::Error::msg("this frame couldn't be captured"))?;
+    readback.finish(&self.drivers)
   }
 
   // **************************************** //
   // ************     TASKS     ************* //
   // **************************************** //
 
+  /// recompiles any shaders that were edited since the last frame
+  pub fn hot_reload_shaders(&mut self) {
+    let Some(watcher) = &self.shader_watcher else {
+      return;
+    };
+
+    let changes = watcher.poll_changes();
+    if !changes.is_empty() {
+      self
+        .render_task
+        .reload_shaders(&self.drivers, &self.data_bindgroups, &changes);
+    }
+  }
+
   pub fn update_gpu_buffers(&mut self) {
+    // every object's location, has to be written before the render pass starts
+    self
+      .render_task
+      .prepare_frame(&self.drivers, &self.camera.camera);
+
     self
       .camera
       .camera_uniform
@@ -129,6 +280,18 @@ impl Engine {
       &[self.camera.camera_uniform],
     );
 
+    // the background looks along the camera's view rays
+    self
+      .render_task
+      .get_background_pass()
+      .write(&self.drivers, &self.camera.camera);
+
+    // every post effect's settings, they need the camera's clip planes
+    self
+      .render_task
+      .get_post_stack()
+      .write(&self.drivers, &self.camera.camera);
+
     // write the time variable on the gpu
     let secs_since_started = time::Instant::now()
       .duration_since(self.engine_start_time)
@@ -0,0 +1,54 @@
+// validates shaders without needing a gpu or a window, handy for ci or a pre-commit hook
+//
+// usage (from the folder that has ./assets in it):
+//   cargo run -p paper --bin check_shaders                 checks every shader
+//   cargo run -p paper --bin check_shaders -- sample.wgsl  checks just the ones given
+//   cargo run -p paper --bin check_shaders -- -D FOG ...   turns on an #ifdef toggle
+//
+// on a box without sdl3 add --no-default-features, it turns off the window feature
+
+use std::process::ExitCode;
+
+use paper::{files, gpu::shaders::validation};
+
+fn main() -> ExitCode {
+  let mut defines = Vec::new();
+  let mut shaders = Vec::new();
+
+  let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    if arg == "-D" {
+      defines.extend(args.next());
+    } else {
+      shaders.push(arg);
+    }
+  }
+
+  if shaders.is_empty() {
+    match files::list_shader_files() {
+      Ok(found) => shaders = found,
+      Err(error) => {
+        eprintln!("couldn't list the shaders folder: {}", error);
+        return ExitCode::FAILURE;
+      }
+    }
+  }
+
+  let mut failed = 0;
+  for shader in &shaders {
+    match validation::validate_shader(shader, &defines) {
+      Ok(_) => println!("ok    {}", shader),
+      Err(error) => {
+        println!("error {}", shader);
+        eprintln!("{}\n", error);
+        failed += 1;
+      }
+    }
+  }
+
+  println!("{} checked, {} failed", shaders.len(), failed);
+  if failed > 0 {
+    return ExitCode::FAILURE;
+  }
+  ExitCode::SUCCESS
+}
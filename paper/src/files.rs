@@ -2,13 +2,18 @@ use std::fs::{self, File};
 use std::io::{self, Read};
 use std::path::Path;
 
+pub mod preprocessor;
+pub mod shader_watcher;
+
 const DEFAULT_PATH: &'static str = "./assets";
 
 pub enum FileType {
   Image,
   Obj,
+  Gltf,
   Shader,
   ShaderLib,
+  Settings,
 }
 
 fn bytes_to_str(bytes: Vec<u8>) -> io::Result<String> {
@@ -23,57 +28,82 @@ fn load_file_string(filetype: FileType, filename: &str) -> io::Result<String> {
 
 // ********************** SHADERS **************************** //
 
-/// i know loading every shader file in the lib folder every time is slow,
-/// but shut up it doesn't REALLY matter for this kinda thing
-/// plus it means i get to keep everything pretty and **functional**
-fn load_lib_files() -> io::Result<Vec<String>> {
-  let lib_path = &get_path(FileType::ShaderLib);
-  fs::create_dir_all(Path::new(&lib_path))?;
-  let entries = fs::read_dir(lib_path)?;
-
-  let mut lib_files = Vec::new();
-
-  for entry in entries {
-    if let Ok(valid_entry) = entry {
-      let found_file = &valid_entry.file_name();
-      let filename = std::ffi::OsStr::to_str(found_file);
-      if let Some(valid_filename) = filename {
-        let file_str = load_file_string(FileType::ShaderLib, valid_filename)?;
-        lib_files.push(file_str);
+/// loads a shader from the shaders folder and runs it through the preprocessor,
+/// so only the shader lib files it actually #include's get pulled in
+pub fn load_shader(
+  filename: &str,
+  defines: &[String],
+) -> Result<preprocessor::PreprocessedShader, preprocessor::PreprocessError> {
+  let loader = preprocessor::AssetLoader;
+  preprocessor::Preprocessor::new(&loader, defines).process(filename)
+}
+
+fn collect_shader_files(dir: &Path, relative: &str, found: &mut Vec<String>) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+      continue;
+    };
+    let path = if relative.is_empty() {
+      name.clone()
+    } else {
+      format!("{}/{}", relative, name)
+    };
+
+    if entry.file_type()?.is_dir() {
+      // lib files only make sense when included from something else
+      if path != folder_names::SHADER_LIB {
+        collect_shader_files(&entry.path(), &path, found)?;
       }
+    } else if name.ends_with(".wgsl") {
+      found.push(path);
     }
   }
+  Ok(())
+}
 
-  return Ok(lib_files);
+/// every shader in the shaders folder (not the lib), relative to it, sorted
+pub fn list_shader_files() -> io::Result<Vec<String>> {
+  let mut found = Vec::new();
+  collect_shader_files(Path::new(&get_path(FileType::Shader)), "", &mut found)?;
+  found.sort();
+  Ok(found)
 }
 
-fn format_shader_libraries() -> io::Result<String> {
-  let shader_libs = load_lib_files()?;
-  let mut formated_shaders = String::new();
+// ********************** OBJ FILES **************************** //
+pub fn load_obj_str(filename: &str) -> io::Result<String> {
+  return Ok(load_file_string(FileType::Obj, filename)?);
+}
 
-  for shader in shader_libs {
-    formated_shaders.push_str(&shader);
-    formated_shaders.push('\n');
-    formated_shaders.push_str("// hi");
-    formated_shaders.push('\n');
-  }
+/// whether there's a file with this name in the obj folder
+pub fn obj_exists(filename: &str) -> bool {
+  Path::new(&get_file_path(FileType::Obj, filename)).is_file()
+}
 
-  return Ok(formated_shaders);
+// ********************** GLTF FILES **************************** //
+/// loads a .gltf or .glb from the gltf folder, along with every buffer and image it uses
+/// (embedded ones, or files next to it)
+pub fn load_gltf(
+  filename: &str,
+) -> gltf::Result<(
+  gltf::Document,
+  Vec<gltf::buffer::Data>,
+  Vec<gltf::image::Data>,
+)> {
+  gltf::import(get_file_path(FileType::Gltf, filename))
 }
 
-pub fn load_shader_str(filename: &str) -> io::Result<String> {
-  let shader_libraries = &format_shader_libraries()?;
-  let shader_data = &load_file_string(FileType::Shader, filename)?;
-  let mut shader = String::new();
-  // literally just dumps everything in the shader lib folder at the front of the shader file
-  shader.push_str(shader_libraries);
-  shader.push_str(shader_data);
-  return Ok(shader);
+// ********************** SETTINGS FILES **************************** //
+pub fn load_settings_str(filename: &str) -> io::Result<String> {
+  load_file_string(FileType::Settings, filename)
 }
 
-// ********************** OBJ FILES **************************** //
-pub fn load_obj_str(filename: &str) -> io::Result<String> {
-  return Ok(load_file_string(FileType::Obj, filename)?);
+/// makes the settings folder if it isn't there yet
+pub fn save_settings_str(filename: &str, contents: &str) -> io::Result<()> {
+  let path_str = get_file_path(FileType::Settings, filename);
+  let path = Path::new(&path_str);
+  ensure_directory_exists(path)?;
+  fs::write(path, contents)
 }
 
 // ********************** IMAGE FILES **************************** //
@@ -102,24 +132,28 @@ fn ensure_directory_exists(path: &Path) -> io::Result<()> {
   Ok(())
 }
 
-mod folder_names {
+pub(crate) mod folder_names {
   pub const IMAGES: &'static str = "images";
   pub const OBJECTS: &'static str = "obj";
+  pub const GLTF: &'static str = "gltf";
   pub const SHADERS: &'static str = "shaders";
   pub const SHADER_LIB: &'static str = "shader_lib";
+  pub const SETTINGS: &'static str = "settings";
 }
 
-fn get_path(filetype: FileType) -> String {
+pub(crate) fn get_path(filetype: FileType) -> String {
   let mut path = String::new();
   path.push_str(DEFAULT_PATH);
   match filetype {
     FileType::Image => add_directory(&mut path, folder_names::IMAGES),
     FileType::Obj => add_directory(&mut path, folder_names::OBJECTS),
+    FileType::Gltf => add_directory(&mut path, folder_names::GLTF),
     FileType::Shader => add_directory(&mut path, folder_names::SHADERS),
     FileType::ShaderLib => {
       add_directory(&mut path, folder_names::SHADERS);
       add_directory(&mut path, folder_names::SHADER_LIB);
     }
+    FileType::Settings => add_directory(&mut path, folder_names::SETTINGS),
   }
   return path;
 }
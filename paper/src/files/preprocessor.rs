@@ -0,0 +1,399 @@
+// a tiny c-style preprocessor for wgsl, so shaders can pull in only the
+// bits of the shader lib they actually use.
+//
+// supported directives (must be the first thing on the line):
+//   #include "camera.wgsl"   (#import works too) pulls a file in, once per shader
+//   #define NAME / #undef NAME
+//   #ifdef NAME / #ifndef NAME / #else / #endif
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+
+use crate::files::{self, folder_names, FileType};
+
+/// where an output line of a preprocessed shader originally came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+  /// path relative to the shaders folder, eg. "shader_lib/camera.wgsl"
+  pub file: String,
+  /// 1 based, same as every text editor
+  pub line: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+  // one entry for every line in the preprocessed output
+  lines: Vec<SourceLocation>,
+  // every file that ended up in the output, in include order
+  files: Vec<String>,
+}
+
+impl SourceMap {
+  /// maps a 1 based line of the preprocessed shader back to the file it came from
+  pub fn lookup(&self, output_line: usize) -> Option<&SourceLocation> {
+    self.lines.get(output_line.checked_sub(1)?)
+  }
+
+  pub fn get_files(&self) -> &[String] {
+    &self.files
+  }
+
+  pub fn depends_on(&self, file: &str) -> bool {
+    self.files.iter().any(|included| included == file)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct PreprocessedShader {
+  pub source: String,
+  pub source_map: SourceMap,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreprocessError {
+  pub file: String,
+  /// 0 if the error isn't tied to a line, like the root file not existing
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for PreprocessError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}: {}", self.file, self.line, self.message)
+  }
+}
+
+impl std::error::Error for PreprocessError {}
+
+/// where the preprocessor gets its files from, paths are relative to the shaders folder
+pub trait ShaderFileLoader {
+  fn read(&self, path: &str) -> io::Result<String>;
+  fn exists(&self, path: &str) -> bool;
+}
+
+/// reads straight from ./assets/shaders
+pub struct AssetLoader;
+
+impl ShaderFileLoader for AssetLoader {
+  fn read(&self, path: &str) -> io::Result<String> {
+    let bytes = files::load_file_bytes(FileType::Shader, path)?;
+    String::from_utf8(bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+  }
+
+  fn exists(&self, path: &str) -> bool {
+    let mut full_path = files::get_path(FileType::Shader);
+    full_path.push('/');
+    full_path.push_str(path);
+    std::path::Path::new(&full_path).is_file()
+  }
+}
+
+struct Conditional {
+  // the line the #ifdef/#ifndef was on, for error messages
+  line: usize,
+  parent_active: bool,
+  condition: bool,
+  seen_else: bool,
+}
+
+impl Conditional {
+  fn is_active(&self) -> bool {
+    self.parent_active && (self.condition != self.seen_else)
+  }
+}
+
+pub struct Preprocessor<'a, L: ShaderFileLoader> {
+  loader: &'a L,
+  defines: HashSet<String>,
+
+  // files currently being processed, used to catch include cycles
+  include_stack: Vec<String>,
+  // files that were fully included already, every file only gets included once
+  included: HashSet<String>,
+
+  output: String,
+  source_map: SourceMap,
+}
+
+impl<'a, L: ShaderFileLoader> Preprocessor<'a, L> {
+  pub fn new(loader: &'a L, defines: &[String]) -> Self {
+    Self {
+      loader,
+      defines: defines.iter().cloned().collect(),
+      include_stack: Vec::new(),
+      included: HashSet::new(),
+      output: String::new(),
+      source_map: SourceMap::default(),
+    }
+  }
+
+  pub fn process(mut self, root_file: &str) -> Result<PreprocessedShader, PreprocessError> {
+    self.process_file(root_file, None)?;
+
+    Ok(PreprocessedShader {
+      source: self.output,
+      source_map: self.source_map,
+    })
+  }
+
+  /// include names are looked up in shader_lib first, then relative to the shaders folder
+  fn resolve_include(&self, name: &str) -> Option<String> {
+    let lib_path = format!("{}/{}", folder_names::SHADER_LIB, name);
+    if self.loader.exists(&lib_path) {
+      return Some(lib_path);
+    }
+    if self.loader.exists(name) {
+      return Some(name.to_owned());
+    }
+    None
+  }
+
+  fn error_at(file: &str, line: usize, message: String) -> PreprocessError {
+    PreprocessError {
+      file: file.to_owned(),
+      line,
+      message,
+    }
+  }
+
+  fn process_file(
+    &mut self,
+    path: &str,
+    included_from: Option<(&str, usize)>,
+  ) -> Result<(), PreprocessError> {
+    let (from_file, from_line) = included_from.unwrap_or((path, 0));
+
+    if self.include_stack.iter().any(|open| open == path) {
+      let mut chain = self.include_stack.clone();
+      chain.push(path.to_owned());
+      let message = format!("include cycle: {}", chain.join(" -> "));
+      return Err(Self::error_at(from_file, from_line, message));
+    }
+
+    if self.included.contains(path) {
+      return Ok(());
+    }
+
+    let source = self.loader.read(path).map_err(|error| {
+      let message = format!("failed to read {}: {}", path, error);
+      Self::error_at(from_file, from_line, message)
+    })?;
+
+    self.include_stack.push(path.to_owned());
+    self.source_map.files.push(path.to_owned());
+    self.process_source(path, &source)?;
+    self.include_stack.pop();
+    self.included.insert(path.to_owned());
+
+    Ok(())
+  }
+
+  fn process_source(&mut self, path: &str, source: &str) -> Result<(), PreprocessError> {
+    let mut conditionals: Vec<Conditional> = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+      let line_number = index + 1;
+      let active = conditionals.last().is_none_or(|c| c.is_active());
+
+      let Some(directive) = line.trim_start().strip_prefix('#') else {
+        if active {
+          self.output.push_str(line);
+          self.output.push('\n');
+          self.source_map.lines.push(SourceLocation {
+            file: path.to_owned(),
+            line: line_number,
+          });
+        }
+        continue;
+      };
+
+      let directive = directive.trim();
+      let (name, argument) = directive
+        .split_once(char::is_whitespace)
+        .map(|(name, argument)| (name, argument.trim()))
+        .unwrap_or((directive, ""));
+
+      match name {
+        "ifdef" | "ifndef" => {
+          let define = Self::expect_name(path, line_number, name, argument)?;
+          let defined = self.defines.contains(define);
+          conditionals.push(Conditional {
+            line: line_number,
+            parent_active: active,
+            condition: defined == (name == "ifdef"),
+            seen_else: false,
+          });
+        }
+        "else" => match conditionals.last_mut() {
+          Some(conditional) if !conditional.seen_else => conditional.seen_else = true,
+          Some(_) => {
+            return Err(Self::error_at(
+              path,
+              line_number,
+              "duplicate #else".to_owned(),
+            ));
+          }
+          None => {
+            return Err(Self::error_at(
+              path,
+              line_number,
+              "#else without #ifdef".to_owned(),
+            ));
+          }
+        },
+        "endif" => {
+          if conditionals.pop().is_none() {
+            return Err(Self::error_at(
+              path,
+              line_number,
+              "#endif without #ifdef".to_owned(),
+            ));
+          }
+        }
+
+        // everything below is ignored inside a disabled block
+        _ if !active => {}
+
+        "define" => {
+          let define = Self::expect_name(path, line_number, name, argument)?;
+          self.defines.insert(define.to_owned());
+        }
+        "undef" => {
+          let define = Self::expect_name(path, line_number, name, argument)?;
+          self.defines.remove(define);
+        }
+        "include" | "import" => {
+          let include_name = argument
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .ok_or_else(|| {
+              let message = format!("expected #{} \"file.wgsl\", got: {}", name, argument);
+              Self::error_at(path, line_number, message)
+            })?;
+
+          let include_path = self.resolve_include(include_name).ok_or_else(|| {
+            let message = format!("couldn't find included file: {}", include_name);
+            Self::error_at(path, line_number, message)
+          })?;
+
+          self.process_file(&include_path, Some((path, line_number)))?;
+        }
+        _ => {
+          let message = format!("unknown preprocessor directive: #{}", name);
+          return Err(Self::error_at(path, line_number, message));
+        }
+      }
+    }
+
+    if let Some(unclosed) = conditionals.last() {
+      let message = "#ifdef/#ifndef is missing its #endif".to_owned();
+      return Err(Self::error_at(path, unclosed.line, message));
+    }
+
+    Ok(())
+  }
+
+  fn expect_name<'b>(
+    path: &str,
+    line: usize,
+    directive: &str,
+    argument: &'b str,
+  ) -> Result<&'b str, PreprocessError> {
+    let name = argument.split_whitespace().next().unwrap_or("");
+    if name.is_empty() {
+      let message = format!("#{} needs a name", directive);
+      return Err(Self::error_at(path, line, message));
+    }
+    Ok(name)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  struct MemoryLoader(HashMap<&'static str, &'static str>);
+
+  impl ShaderFileLoader for MemoryLoader {
+    fn read(&self, path: &str) -> io::Result<String> {
+      self
+        .0
+        .get(path)
+        .map(|source| source.to_string())
+        .ok_or(io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+      self.0.contains_key(path)
+    }
+  }
+
+  fn run(
+    files: &[(&'static str, &'static str)],
+    defines: &[&str],
+  ) -> Result<PreprocessedShader, PreprocessError> {
+    let loader = MemoryLoader(files.iter().cloned().collect());
+    let defines: Vec<String> = defines.iter().map(|d| d.to_string()).collect();
+    Preprocessor::new(&loader, &defines).process("main.wgsl")
+  }
+
+  #[test]
+  fn includes_once_and_maps_lines() {
+    let shader = run(
+      &[
+        (
+          "main.wgsl",
+          "#include \"a.wgsl\"\n#include \"a.wgsl\"\nmain",
+        ),
+        ("shader_lib/a.wgsl", "a1\na2"),
+      ],
+      &[],
+    )
+    .unwrap();
+
+    assert_eq!(shader.source, "a1\na2\nmain\n");
+    let main_line = shader.source_map.lookup(3).unwrap();
+    assert_eq!(main_line.file, "main.wgsl");
+    assert_eq!(main_line.line, 3);
+    assert_eq!(
+      shader.source_map.lookup(2).unwrap().file,
+      "shader_lib/a.wgsl"
+    );
+    assert!(shader.source_map.depends_on("shader_lib/a.wgsl"));
+  }
+
+  #[test]
+  fn feature_toggles() {
+    let files = [(
+      "main.wgsl",
+      "#ifdef FOG\nfog\n#else\nclear\n#endif\n#define FOG\n#ifndef FOG\nnope\n#endif",
+    )];
+    assert_eq!(run(&files, &[]).unwrap().source, "clear\n");
+    assert_eq!(run(&files, &["FOG"]).unwrap().source, "fog\n");
+  }
+
+  #[test]
+  fn detects_cycles() {
+    let error = run(
+      &[
+        ("main.wgsl", "#include \"a.wgsl\""),
+        ("shader_lib/a.wgsl", "#include \"b.wgsl\""),
+        ("shader_lib/b.wgsl", "\n#include \"a.wgsl\""),
+      ],
+      &[],
+    )
+    .unwrap_err();
+
+    assert_eq!(error.file, "shader_lib/b.wgsl");
+    assert_eq!(error.line, 2);
+    assert!(error.message.contains("include cycle"));
+  }
+
+  #[test]
+  fn reports_unclosed_conditionals() {
+    let error = run(&[("main.wgsl", "ok\n#ifdef X\nnope")], &[]).unwrap_err();
+    assert_eq!(error.line, 2);
+  }
+}
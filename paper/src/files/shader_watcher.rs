@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::files::{self, FileType};
+
+/// a shader file that changed on disk since the last poll,
+/// paths are relative to the shaders folder, same as the preprocessor uses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderChange {
+  /// a shader in the shaders folder, eg. "sample.wgsl"
+  Shader(String),
+  /// something in the shader lib folder, eg. "shader_lib/camera.wgsl"
+  Library(String),
+}
+
+impl ShaderChange {
+  pub fn get_path(&self) -> &str {
+    match self {
+      ShaderChange::Shader(path) | ShaderChange::Library(path) => path,
+    }
+  }
+}
+
+/// watches the shaders folder (and shader_lib inside it) so the engine
+/// can recompile pipelines while the game is still running
+pub struct ShaderWatcher {
+  // has to be kept alive, dropping it stops the watching
+  _watcher: notify::RecommendedWatcher,
+  shader_dir: PathBuf,
+  events: mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl ShaderWatcher {
+  pub fn new() -> anyhow::Result<Self> {
+    let shader_dir = files::get_path(FileType::Shader);
+    std::fs::create_dir_all(&shader_dir)?;
+    // notify hands back absolute paths, so this has to be absolute too
+    let shader_dir = Path::new(&shader_dir).canonicalize()?;
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(&shader_dir, RecursiveMode::Recursive)?;
+
+    Ok(Self {
+      _watcher: watcher,
+      shader_dir,
+      events,
+    })
+  }
+
+  fn is_shader_file(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some("wgsl")
+  }
+
+  fn to_change(&self, path: &Path) -> Option<ShaderChange> {
+    if !Self::is_shader_file(path) {
+      return None;
+    }
+
+    let relative = path.strip_prefix(&self.shader_dir).ok()?;
+    let relative_path = relative
+      .iter()
+      .map(|part| part.to_str())
+      .collect::<Option<Vec<&str>>>()?
+      .join("/");
+
+    if relative.starts_with(files::folder_names::SHADER_LIB) {
+      return Some(ShaderChange::Library(relative_path));
+    }
+    Some(ShaderChange::Shader(relative_path))
+  }
+
+  /// drains every event since the last call, editors tend to write a file
+  /// a few times in a row on save, so duplicates get squashed
+  pub fn poll_changes(&self) -> Vec<ShaderChange> {
+    let mut changes: Vec<ShaderChange> = Vec::new();
+
+    for event in self.events.try_iter() {
+      let event = match event {
+        Ok(event) => event,
+        Err(error) => {
+          log::warn!("shader watcher error: {}", error);
+          continue;
+        }
+      };
+
+      if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+        continue;
+      }
+
+      for path in &event.paths {
+        if let Some(change) = self.to_change(path) {
+          if !changes.contains(&change) {
+            changes.push(change);
+          }
+        }
+      }
+    }
+
+    changes
+  }
+}
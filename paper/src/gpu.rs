@@ -1,13 +1,28 @@
+pub mod animation;
+pub mod background;
+pub mod benchmark;
+pub mod bounds;
 pub mod camera;
+pub mod capture;
 pub mod device_drivers;
 pub mod geometry;
+pub mod gltf_loader;
 pub mod gpu_data;
 pub mod gpu_pointers;
 pub mod instances;
 pub mod lights;
+pub mod locations;
+pub mod lod;
 pub mod material;
 pub mod mesh;
 pub mod object;
+pub mod post;
+pub mod readback;
 pub mod render;
+pub mod render_graph;
+pub mod render_queue;
+pub mod settings;
 pub mod shaders;
+pub mod shadows;
 pub mod texture;
+pub mod transparency;
@@ -0,0 +1,334 @@
+// node hierarchies, skins and keyframe animations, as imported from gltf.
+// all of this lives on the cpu, sampling an animation just moves the nodes around
+
+use std::ops::{Add, Mul};
+
+use cgmath::{InnerSpace, Matrix4, SquareMatrix};
+
+use crate::gpu::object::Rot;
+use crate::maths::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeTransform {
+  pub translation: Vec3,
+  pub rotation: Rot,
+  pub scale: Vec3,
+}
+
+impl Default for NodeTransform {
+  fn default() -> Self {
+    Self {
+      translation: Vec3::new(0.0, 0.0, 0.0),
+      rotation: Rot::new(1.0, 0.0, 0.0, 0.0),
+      scale: Vec3::new(1.0, 1.0, 1.0),
+    }
+  }
+}
+
+impl NodeTransform {
+  pub fn to_matrix(&self) -> Matrix4<f32> {
+    let scale = Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
+    Matrix4::from_translation(self.translation) * Matrix4::from(self.rotation) * scale
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneNode {
+  pub name: Option<String>,
+  pub parent: Option<usize>,
+  pub children: Vec<usize>,
+  /// relative to the parent
+  pub transform: NodeTransform,
+  /// indices into Object::meshes, one per primitive
+  pub meshes: Vec<usize>,
+}
+
+/// the node's transform relative to the object, parents included
+pub fn world_matrix(nodes: &[SceneNode], index: usize) -> Matrix4<f32> {
+  let mut matrix = nodes[index].transform.to_matrix();
+  let mut parent = nodes[index].parent;
+  while let Some(parent_index) = parent {
+    matrix = nodes[parent_index].transform.to_matrix() * matrix;
+    parent = nodes[parent_index].parent;
+  }
+  matrix
+}
+
+#[derive(Debug, Clone)]
+pub struct Skin {
+  pub name: Option<String>,
+  /// node indices, a vertex's joint index points into this
+  pub joints: Vec<usize>,
+  /// one per joint, takes a vertex from the mesh into the joint's space
+  pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+}
+
+impl Skin {
+  /// what every joint does to the vertices it's weighted to, with the nodes where they are right now
+  pub fn joint_matrices(&self, nodes: &[SceneNode]) -> Vec<Matrix4<f32>> {
+    self
+      .joints
+      .iter()
+      .enumerate()
+      .map(|(i, joint)| {
+        let inverse_bind = self
+          .inverse_bind_matrices
+          .get(i)
+          .copied()
+          .unwrap_or(Matrix4::identity());
+        world_matrix(nodes, *joint) * inverse_bind
+      })
+      .collect()
+  }
+}
+
+/// which joints (from the mesh's skin) move each vertex, and by how much
+#[derive(Debug, Clone)]
+pub struct SkinWeights {
+  /// index into Object::skins
+  pub skin: usize,
+  pub joints: Vec<[u16; 4]>,
+  pub weights: Vec<[f32; 4]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+  Step,
+  Linear,
+  /// every key has an in tangent, the value and an out tangent, in that order
+  CubicSpline,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChannelOutput {
+  Translation(Vec<Vec3>),
+  Rotation(Vec<Rot>),
+  Scale(Vec<Vec3>),
+}
+
+impl ChannelOutput {
+  fn len(&self) -> usize {
+    match self {
+      ChannelOutput::Translation(values) | ChannelOutput::Scale(values) => values.len(),
+      ChannelOutput::Rotation(values) => values.len(),
+    }
+  }
+}
+
+/// keyframes for one property of one node
+#[derive(Debug, Clone)]
+pub struct Channel {
+  pub node: usize,
+  pub interpolation: Interpolation,
+  times: Vec<f32>,
+  output: ChannelOutput,
+}
+
+impl Channel {
+  /// None if there aren't as many values as the interpolation needs for the key times
+  pub fn new(
+    node: usize,
+    interpolation: Interpolation,
+    times: Vec<f32>,
+    output: ChannelOutput,
+  ) -> Option<Self> {
+    let values_per_key = match interpolation {
+      Interpolation::CubicSpline => 3,
+      _ => 1,
+    };
+    if times.is_empty() || output.len() != times.len() * values_per_key {
+      return None;
+    }
+
+    Some(Self {
+      node,
+      interpolation,
+      times,
+      output,
+    })
+  }
+
+  pub fn duration(&self) -> f32 {
+    *self.times.last().unwrap_or(&0.0)
+  }
+
+  fn apply(&self, time: f32, transform: &mut NodeTransform) {
+    match &self.output {
+      ChannelOutput::Translation(values) => {
+        transform.translation = sample(&self.times, values, self.interpolation, time, |a, b, s| {
+          a + (b - a) * s
+        });
+      }
+      ChannelOutput::Scale(values) => {
+        transform.scale = sample(&self.times, values, self.interpolation, time, |a, b, s| {
+          a + (b - a) * s
+        });
+      }
+      ChannelOutput::Rotation(values) => {
+        let rotation = sample(&self.times, values, self.interpolation, time, |a, b, s| {
+          // the short way round
+          let b = if a.dot(b) < 0.0 { -b } else { b };
+          a.slerp(b, s)
+        });
+        transform.rotation = rotation.normalize();
+      }
+    }
+  }
+}
+
+/// the value at time, held at the first/last key outside of the keyframes
+fn sample<T>(
+  times: &[f32],
+  values: &[T],
+  interpolation: Interpolation,
+  time: f32,
+  lerp: impl Fn(T, T, f32) -> T,
+) -> T
+where
+  T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+  let last = times.len() - 1;
+  let value = |key: usize| match interpolation {
+    Interpolation::CubicSpline => values[key * 3 + 1],
+    _ => values[key],
+  };
+
+  if time <= times[0] {
+    return value(0);
+  }
+  if time >= times[last] {
+    return value(last);
+  }
+
+  let next = times.partition_point(|key_time| *key_time <= time);
+  let previous = next - 1;
+  let delta = times[next] - times[previous];
+  let s = (time - times[previous]) / delta;
+
+  match interpolation {
+    Interpolation::Step => value(previous),
+    Interpolation::Linear => lerp(value(previous), value(next), s),
+    Interpolation::CubicSpline => {
+      let (s2, s3) = (s * s, s * s * s);
+      let out_tangent = values[previous * 3 + 2];
+      let in_tangent = values[next * 3];
+      value(previous) * (2.0 * s3 - 3.0 * s2 + 1.0)
+        + out_tangent * ((s3 - 2.0 * s2 + s) * delta)
+        + value(next) * (-2.0 * s3 + 3.0 * s2)
+        + in_tangent * ((s3 - s2) * delta)
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+  pub name: Option<String>,
+  pub channels: Vec<Channel>,
+}
+
+impl Animation {
+  /// how long until the last keyframe, in seconds
+  pub fn duration(&self) -> f32 {
+    self
+      .channels
+      .iter()
+      .map(Channel::duration)
+      .fold(0.0, f32::max)
+  }
+
+  /// poses the nodes the way they are at time (in seconds)
+  pub fn apply(&self, time: f32, nodes: &mut [SceneNode]) {
+    for channel in &self.channels {
+      if let Some(node) = nodes.get_mut(channel.node) {
+        channel.apply(time, &mut node.transform);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn node(parent: Option<usize>, translation: Vec3) -> SceneNode {
+    SceneNode {
+      name: None,
+      parent,
+      children: Vec::new(),
+      transform: NodeTransform {
+        translation,
+        ..Default::default()
+      },
+      meshes: Vec::new(),
+    }
+  }
+
+  #[test]
+  fn children_move_with_their_parents() {
+    let nodes = [
+      node(None, Vec3::new(1.0, 0.0, 0.0)),
+      node(Some(0), Vec3::new(0.0, 2.0, 0.0)),
+    ];
+    let matrix = world_matrix(&nodes, 1);
+    assert_eq!(matrix.w.truncate(), Vec3::new(1.0, 2.0, 0.0));
+  }
+
+  #[test]
+  fn translations_are_interpolated_between_keys() {
+    let times = vec![0.0, 1.0];
+    let values = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0)];
+    let animation = Animation {
+      name: None,
+      channels: vec![Channel::new(
+        0,
+        Interpolation::Linear,
+        times.clone(),
+        ChannelOutput::Translation(values.clone()),
+      )
+      .unwrap()],
+    };
+
+    let mut nodes = [node(None, Vec3::new(0.0, 0.0, 0.0))];
+    animation.apply(0.25, &mut nodes);
+    assert_eq!(nodes[0].transform.translation, Vec3::new(0.5, 0.0, 0.0));
+    // held at the last key
+    animation.apply(5.0, &mut nodes);
+    assert_eq!(nodes[0].transform.translation, Vec3::new(2.0, 0.0, 0.0));
+
+    let step = Channel::new(
+      0,
+      Interpolation::Step,
+      times,
+      ChannelOutput::Translation(values),
+    )
+    .unwrap();
+    step.apply(0.9, &mut nodes[0].transform);
+    assert_eq!(nodes[0].transform.translation, Vec3::new(0.0, 0.0, 0.0));
+  }
+
+  #[test]
+  fn channels_need_enough_values() {
+    let values = ChannelOutput::Scale(vec![Vec3::new(1.0, 1.0, 1.0); 2]);
+    assert!(Channel::new(0, Interpolation::CubicSpline, vec![0.0, 1.0], values).is_none());
+  }
+
+  #[test]
+  fn joints_in_their_bind_pose_dont_move_anything() {
+    let nodes = [
+      node(None, Vec3::new(0.0, 1.0, 0.0)),
+      node(Some(0), Vec3::new(0.0, 1.0, 0.0)),
+    ];
+    let skin = Skin {
+      name: None,
+      joints: vec![0, 1],
+      inverse_bind_matrices: vec![
+        world_matrix(&nodes, 0).invert().unwrap(),
+        world_matrix(&nodes, 1).invert().unwrap(),
+      ],
+    };
+
+    for matrix in skin.joint_matrices(&nodes) {
+      assert_eq!(matrix, Matrix4::identity());
+    }
+  }
+}
@@ -0,0 +1,542 @@
+// what's behind everything. a solid color is just the scene pass's clear color, everything else
+// is one fullscreen triangle drawn at the far plane after the objects, so only the pixels
+// nothing covered get shaded. background.wgsl gets the camera's view rays to work out
+// which way every pixel looks, gradients and the sky go by how far up that is
+
+use std::sync::Arc;
+
+use cgmath::InnerSpace;
+
+use crate::{
+  files::{preprocessor::SourceMap, shader_watcher::ShaderChange},
+  gpu::{
+    camera::Camera,
+    device_drivers::Drivers,
+    geometry::GetBufferLayout,
+    gpu_pointers::{BindGroupSet, BindingRegistry, EngineResource, MemoryLayouts},
+    object::WORLD_UP,
+    shaders::{
+      validation::{ShaderError, ShaderErrorKind},
+      ShaderBuilder,
+    },
+    texture::{
+      layered::{LayerKind, LayeredTexture},
+      ColorSpace, DynamicTexture, SamplerSettings,
+    },
+  },
+  maths::Vec3,
+};
+
+/// the stylised sky's look. colors are linear and can go past 1, it's drawn into the hdr target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkySettings {
+  /// straight up
+  pub zenith: [f32; 3],
+  pub horizon: [f32; 3],
+  /// everything below the horizon
+  pub ground: [f32; 3],
+  /// pointing at the sun, the opposite of a directional light's direction
+  pub sun_direction: Vec3,
+  pub sun_color: [f32; 3],
+  /// the sun's radius, in radians
+  pub sun_size: f32,
+  /// 0 is a clear sky, 1 is overcast
+  pub cloud_cover: f32,
+  /// how fast the clouds drift, 0 stops them
+  pub cloud_speed: f32,
+}
+
+impl Default for SkySettings {
+  fn default() -> Self {
+    Self {
+      zenith: [0.15, 0.35, 0.8],
+      horizon: [0.7, 0.8, 0.95],
+      ground: [0.2, 0.18, 0.16],
+      sun_direction: Vec3::new(0.5, 1.0, 0.3),
+      sun_color: [4.0, 3.8, 3.4],
+      sun_size: 0.04,
+      cloud_cover: 0.4,
+      cloud_speed: 0.02,
+    }
+  }
+}
+
+#[derive(Clone)]
+pub enum Background {
+  Solid([f32; 3]),
+  /// by which way the camera looks, not by the screen, so it stays level when looking up
+  Gradient {
+    top: [f32; 3],
+    bottom: [f32; 3],
+  },
+  /// procedural, the clouds move with the engine's time
+  Sky(SkySettings),
+  /// a cubemap, see TextureBundle::add_cubemap_from_files or add_cubemap_from_equirect_file.
+  /// intensity scales its colors, ldr skyboxes usually look too dim next to hdr lighting
+  Skybox {
+    cubemap: Arc<LayeredTexture>,
+    intensity: f32,
+  },
+}
+
+impl Default for Background {
+  fn default() -> Self {
+    Background::Solid([0.0; 3])
+  }
+}
+
+impl Background {
+  pub fn sky() -> Self {
+    Background::Sky(SkySettings::default())
+  }
+
+  pub fn skybox(cubemap: Arc<LayeredTexture>) -> Self {
+    Background::Skybox {
+      cubemap,
+      intensity: 1.0,
+    }
+  }
+
+  /// which branch of background.wgsl draws it, None if there's nothing to draw
+  fn get_mode(&self) -> Option<f32> {
+    match self {
+      Background::Solid(_) => None,
+      Background::Gradient { .. } => Some(0.0),
+      Background::Sky(_) => Some(1.0),
+      Background::Skybox { .. } => Some(2.0),
+    }
+  }
+
+  fn get_clear_color(&self) -> wgpu::Color {
+    match self {
+      Background::Solid([r, g, b]) => wgpu::Color {
+        r: *r as f64,
+        g: *g as f64,
+        b: *b as f64,
+        a: 1.0,
+      },
+      _ => wgpu::Color::BLACK,
+    }
+  }
+}
+
+/// the camera's forward, right and up, with right and up scaled so that
+/// forward + x * right + y * up is the ray through x, y in clip space
+fn view_ray_basis(camera: &Camera) -> [Vec3; 3] {
+  let forward = camera.forward_vector();
+  let right = forward.cross(WORLD_UP).normalize();
+  let up = right.cross(forward);
+
+  let half_height = (camera.fov_degrees.to_radians() * 0.5).tan();
+  [
+    forward,
+    right * half_height * camera.aspect,
+    up * half_height,
+  ]
+}
+
+// BackgroundUniform in background.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct BackgroundRaw {
+  // xyz is the view ray basis, w of forward is the mode and w of right the skybox's intensity
+  forward: [f32; 4],
+  right: [f32; 4],
+  up: [f32; 4],
+  // top/zenith, bottom/horizon, ground
+  colors: [[f32; 4]; 3],
+  // xyz points at the sun, w is its size
+  sun: [f32; 4],
+  sun_color: [f32; 4],
+  // cover, speed
+  clouds: [f32; 4],
+}
+
+impl BackgroundRaw {
+  fn new(background: &Background, camera: &Camera) -> Self {
+    let [forward, right, up] = view_ray_basis(camera);
+    let mode = background.get_mode().unwrap_or(0.0);
+    let color = |rgb: [f32; 3]| [rgb[0], rgb[1], rgb[2], 1.0];
+
+    let mut raw = Self {
+      forward: [forward.x, forward.y, forward.z, mode],
+      right: [right.x, right.y, right.z, 1.0],
+      up: [up.x, up.y, up.z, 0.0],
+      colors: [[0.0; 4]; 3],
+      sun: [0.0, 1.0, 0.0, 0.0],
+      sun_color: [0.0; 4],
+      clouds: [0.0; 4],
+    };
+
+    match background {
+      Background::Solid(rgb) => raw.colors[0] = color(*rgb),
+      Background::Gradient { top, bottom } => {
+        raw.colors[0] = color(*top);
+        raw.colors[1] = color(*bottom);
+      }
+      Background::Sky(sky) => {
+        raw.colors = [color(sky.zenith), color(sky.horizon), color(sky.ground)];
+        let sun = sky.sun_direction.normalize();
+        raw.sun = [sun.x, sun.y, sun.z, sky.sun_size];
+        raw.sun_color = color(sky.sun_color);
+        raw.clouds = [sky.cloud_cover, sky.cloud_speed, 0.0, 0.0];
+      }
+      Background::Skybox { intensity, .. } => raw.right[3] = *intensity,
+    }
+    raw
+  }
+}
+
+/// the fullscreen pipeline made from background.wgsl
+struct BackgroundPipeline {
+  render_pipeline: wgpu::RenderPipeline,
+  bindgroups: MemoryLayouts,
+  source_map: SourceMap,
+  sample_count: u32,
+}
+
+impl BackgroundPipeline {
+  const SHADER_FILE: &str = "background.wgsl";
+
+  fn compile(drivers: &Drivers, registry: &BindingRegistry) -> Result<Self, ShaderError> {
+    let shader = ShaderBuilder::from_file(Self::SHADER_FILE.to_owned()).validate()?;
+    let bindgroups = MemoryLayouts::reflect(&shader.module, registry)
+      .map_err(|error| ShaderError::from_binding_error(error, &shader, Self::SHADER_FILE))?;
+
+    drivers
+      .device
+      .push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let module = drivers
+      .device
+      .create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(Self::SHADER_FILE),
+        source: wgpu::ShaderSource::Wgsl(shader.source.as_str().into()),
+      });
+    let layout = drivers
+      .device
+      .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Background Pipeline Layout"),
+        bind_group_layouts: &bindgroups.collect_slice(),
+        push_constant_ranges: &[],
+      });
+    let sample_count = drivers.get_sample_count();
+    let render_pipeline = drivers
+      .device
+      .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Background Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+          module: &module,
+          entry_point: Some("vs_main"),
+          buffers: &[],
+          compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+          module: &module,
+          entry_point: Some("fs_main"),
+          targets: &[Some(wgpu::ColorTargetState {
+            format: DynamicTexture::HDR_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+          })],
+          compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // sits exactly on the far plane, so it only passes where the depth is still cleared
+        depth_stencil: Some(wgpu::DepthStencilState {
+          format: DynamicTexture::DEPTH_BUFFER_FORMAT,
+          depth_write_enabled: false,
+          depth_compare: wgpu::CompareFunction::LessEqual,
+          stencil: wgpu::StencilState::default(),
+          bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+          count: sample_count,
+          ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+      });
+
+    if let Some(error) = pollster::block_on(drivers.device.pop_error_scope()) {
+      let message = error.to_string();
+      return Err(ShaderError::new(
+        ShaderErrorKind::Pipeline,
+        Self::SHADER_FILE,
+        message,
+      ));
+    }
+
+    Ok(Self {
+      render_pipeline,
+      bindgroups,
+      source_map: shader.source_map,
+      sample_count,
+    })
+  }
+}
+
+/// the background's settings and skybox in one bind group, plus the pipeline that draws it
+pub struct BackgroundPass {
+  background: Background,
+  pipeline: Option<BackgroundPipeline>,
+
+  buffer: wgpu::Buffer,
+  layout: wgpu::BindGroupLayout,
+  bind_group: wgpu::BindGroup,
+  // bound when there's no skybox, the layout always wants a cubemap
+  empty_cubemap: LayeredTexture,
+}
+
+impl GetBufferLayout for BackgroundPass {
+  fn get_bind_layout(&self) -> wgpu::BindGroupLayout {
+    self.layout.clone()
+  }
+
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+    Self::bind_entries()
+  }
+}
+
+impl BackgroundPass {
+  const UNIFORM_SIZE: u64 = std::mem::size_of::<BackgroundRaw>() as u64;
+
+  fn bind_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+    let fragment = |binding, ty| wgpu::BindGroupLayoutEntry {
+      binding,
+      visibility: wgpu::ShaderStages::FRAGMENT,
+      ty,
+      count: None,
+    };
+
+    vec![
+      fragment(
+        0,
+        wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: wgpu::BufferSize::new(Self::UNIFORM_SIZE),
+        },
+      ),
+      fragment(
+        1,
+        wgpu::BindingType::Texture {
+          sample_type: wgpu::TextureSampleType::Float { filterable: true },
+          view_dimension: LayerKind::Cube.get_view_dimension(),
+          multisampled: false,
+        },
+      ),
+      fragment(
+        2,
+        wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+      ),
+    ]
+  }
+
+  fn init_bind_group_layout(drivers: &Drivers) -> wgpu::BindGroupLayout {
+    drivers
+      .device
+      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &Self::bind_entries(),
+        label: Some("background_bind_group_layout"),
+      })
+  }
+
+  fn init_bind_group(
+    drivers: &Drivers,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    cubemap: &LayeredTexture,
+  ) -> wgpu::BindGroup {
+    drivers
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::TextureView(&cubemap.view),
+          },
+          wgpu::BindGroupEntry {
+            binding: 2,
+            resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+          },
+        ],
+        label: Some("background_bind_group"),
+      })
+  }
+
+  pub fn new(drivers: &Drivers) -> Self {
+    let face = image::DynamicImage::new_rgba8(1, 1);
+    let empty_cubemap = LayeredTexture::cubemap_from_images(
+      drivers,
+      &std::array::from_fn(|_| face.clone()),
+      Some("Empty Cubemap"),
+      ColorSpace::Linear,
+      SamplerSettings::default().create(drivers),
+    )
+    .expect("a 1x1 cubemap can always be made");
+
+    let buffer = drivers.device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Background Buffer"),
+      size: Self::UNIFORM_SIZE,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let layout = Self::init_bind_group_layout(drivers);
+    let bind_group = Self::init_bind_group(drivers, &layout, &buffer, &empty_cubemap);
+
+    Self {
+      background: Background::default(),
+      pipeline: None,
+      buffer,
+      layout,
+      bind_group,
+      empty_cubemap,
+    }
+  }
+
+  /// compiles background.wgsl, the registry needs the Background layout by now
+  pub fn init_pipeline(&mut self, drivers: &Drivers, registry: &BindingRegistry) {
+    match BackgroundPipeline::compile(drivers, registry) {
+      Ok(pipeline) => self.pipeline = Some(pipeline),
+      Err(error) => log::error!(
+        "only solid backgrounds will work, {} didn't compile: {}",
+        BackgroundPipeline::SHADER_FILE,
+        error
+      ),
+    }
+  }
+
+  pub fn reload_shaders(
+    &mut self,
+    drivers: &Drivers,
+    registry: &BindingRegistry,
+    changes: &[ShaderChange],
+  ) {
+    let affected = match &self.pipeline {
+      Some(pipeline) => changes
+        .iter()
+        .any(|change| pipeline.source_map.depends_on(change.get_path())),
+      None => false,
+    };
+    if !affected {
+      return;
+    }
+
+    match BackgroundPipeline::compile(drivers, registry) {
+      Ok(pipeline) => {
+        self.pipeline = Some(pipeline);
+        log::info!("reloaded shader: {}", BackgroundPipeline::SHADER_FILE);
+      }
+      Err(error) => log::error!(
+        "failed to reload shader {}, keeping the old one: {}",
+        BackgroundPipeline::SHADER_FILE,
+        error
+      ),
+    }
+  }
+
+  /// for when the msaa sample count changed, the old pipeline can't draw into the new targets
+  pub fn rebuild(&mut self, drivers: &Drivers, registry: &BindingRegistry) {
+    self.pipeline = None;
+    self.init_pipeline(drivers, registry);
+  }
+
+  #[inline]
+  pub fn get_background(&self) -> &Background {
+    &self.background
+  }
+
+  /// shows up from the next frame on
+  pub fn set_background(&mut self, drivers: &Drivers, background: Background) {
+    let cubemap = match &background {
+      Background::Skybox { cubemap, .. } => cubemap.as_ref(),
+      _ => &self.empty_cubemap,
+    };
+    if cubemap.get_kind() != LayerKind::Cube {
+      log::error!("skyboxes have to be cubemaps, not texture arrays");
+      return;
+    }
+
+    self.bind_group = Self::init_bind_group(drivers, &self.layout, &self.buffer, cubemap);
+    self.background = background;
+  }
+
+  /// uploads the settings and the camera's view rays, has to run before the frame is rendered
+  pub fn write(&self, drivers: &Drivers, camera: &Camera) {
+    let raw = BackgroundRaw::new(&self.background, camera);
+    drivers
+      .queue
+      .write_buffer(&self.buffer, 0, bytemuck::bytes_of(&raw));
+  }
+
+  pub fn get_clear_color(&self) -> wgpu::Color {
+    self.background.get_clear_color()
+  }
+
+  /// draws into the scene pass after the objects, does nothing for solid backgrounds.
+  /// bind_groups needs the Time group, the background's own gets added to it
+  pub fn render<'a>(
+    &'a self,
+    render_pass: &mut wgpu::RenderPass<'_>,
+    drivers: &Drivers,
+    mut bind_groups: BindGroupSet<'a>,
+  ) {
+    if self.background.get_mode().is_none() {
+      return;
+    }
+    let Some(pipeline) = &self.pipeline else {
+      return;
+    };
+    // left over from before msaa changed, and it didn't rebuild
+    if pipeline.sample_count != drivers.get_sample_count() {
+      return;
+    }
+
+    bind_groups.set(EngineResource::Background, &self.bind_group);
+    render_pass.set_pipeline(&pipeline.render_pipeline);
+    pipeline
+      .bindgroups
+      .set_bind_groups(render_pass, &bind_groups, false);
+    render_pass.draw(0..3, 0..1);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn view_rays_reach_the_corners_of_the_screen() {
+    let mut camera = Camera::new(cgmath::Point3::new(0.0, 0.0, 0.0), 90.0, (200, 100));
+    camera.pitch_radians = 0.0;
+    let [forward, right, up] = view_ray_basis(&camera);
+    assert!((forward - Vec3::unit_x()).magnitude() < 1e-5);
+    // 90 degrees tall, so the top edge is 45 degrees up, and twice as wide as it is tall
+    assert!((up - Vec3::unit_y()).magnitude() < 1e-5);
+    assert!((right - Vec3::new(0.0, 0.0, 2.0)).magnitude() < 1e-5);
+  }
+
+  #[test]
+  fn backgrounds_are_packed_like_the_shader_expects() {
+    assert_eq!(std::mem::size_of::<BackgroundRaw>(), 144);
+
+    let camera = Camera::new(cgmath::Point3::new(0.0, 0.0, 0.0), 60.0, (200, 100));
+    let solid = Background::Solid([0.5, 0.0, 1.0]);
+    assert_eq!(solid.get_mode(), None);
+    assert_eq!(solid.get_clear_color().r, 0.5);
+
+    let raw = BackgroundRaw::new(&Background::sky(), &camera);
+    assert_eq!(raw.forward[3], 1.0);
+    // the sun's direction comes out normalized
+    let sun = Vec3::new(raw.sun[0], raw.sun[1], raw.sun[2]);
+    assert!((sun.magnitude() - 1.0).abs() < 1e-5);
+    assert_eq!(raw.clouds[0], SkySettings::default().cloud_cover);
+  }
+}
@@ -0,0 +1,277 @@
+// times the same little scene on every backend the machine has, so the engine can start on
+// whichever one runs it best. older gpus can be faster (or at least steadier) on gl than vulkan,
+// the winner gets saved so this only happens on the first start
+
+use std::time::{Duration, Instant};
+
+use cgmath::ElementWise;
+
+use crate::{
+  engine::Engine,
+  gpu::{
+    geometry::{compute_tangents, ModelVertex, Vertex},
+    lights::Light,
+    material::Material,
+    mesh::MeshBuilder,
+    object::{Location, ObjectBuilder},
+    settings::{BackendPreference, GraphicsSettings},
+  },
+  maths::Vec3,
+};
+
+const BENCHMARK_SIZE: (u32, u32) = (640, 360);
+// the first few frames compile pipelines and fill caches, they'd make every backend look bad
+const WARMUP_FRAMES: u32 = 5;
+/// how many frames get timed per backend on the first start
+pub const DEFAULT_FRAMES: u32 = 60;
+
+#[derive(Debug, Clone)]
+pub struct BenchmarkResult {
+  pub backend: BackendPreference,
+  pub adapter_name: String,
+  pub frame_times: Vec<Duration>,
+}
+
+impl BenchmarkResult {
+  pub fn get_average(&self) -> Duration {
+    let total: Duration = self.frame_times.iter().sum();
+    total / self.frame_times.len().max(1) as u32
+  }
+
+  /// how much the frame times jump around, the standard deviation
+  pub fn get_deviation(&self) -> Duration {
+    let average = self.get_average().as_secs_f64();
+    let variance = self
+      .frame_times
+      .iter()
+      .map(|time| (time.as_secs_f64() - average).powi(2))
+      .sum::<f64>()
+      / self.frame_times.len().max(1) as f64;
+    Duration::from_secs_f64(variance.sqrt())
+  }
+
+  /// lower is better. stutters count against it, so a steady backend beats a slightly faster one
+  pub fn get_score(&self) -> f64 {
+    self.get_average().as_secs_f64() + 2.0 * self.get_deviation().as_secs_f64()
+  }
+}
+
+/// the backend with the best score, None if nothing could be benchmarked
+pub fn pick_fastest(results: &[BenchmarkResult]) -> Option<BackendPreference> {
+  results
+    .iter()
+    .filter(|result| !result.frame_times.is_empty())
+    .min_by(|a, b| a.get_score().total_cmp(&b.get_score()))
+    .map(|result| result.backend)
+}
+
+/// every backend that has at least one adapter, in the order wgpu lists them
+pub fn available_backends() -> Vec<BackendPreference> {
+  let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+    backends: wgpu::Backends::all(),
+    ..Default::default()
+  });
+
+  let mut found = Vec::new();
+  for adapter in instance.enumerate_adapters(wgpu::Backends::all()) {
+    let backend = BackendPreference::from_backend(adapter.get_info().backend);
+    if let Some(backend) = backend.filter(|backend| !found.contains(backend)) {
+      found.push(backend);
+    }
+  }
+  found
+}
+
+/// renders a block on a floor with a light while the camera circles it,
+/// every frame is waited on so the gpu's time is counted too
+pub async fn run_benchmark(
+  backend: BackendPreference,
+  frame_count: u32,
+) -> anyhow::Result<BenchmarkResult> {
+  let settings = GraphicsSettings {
+    backend,
+    ..Default::default()
+  };
+  let (width, height) = BENCHMARK_SIZE;
+  let mut engine = Engine::new_headless_with_settings(width, height, &settings).await?;
+
+  // it'd be timing the wrong thing if the drivers had to fall back to something else
+  let info = engine.drivers.adapter.get_info();
+  if BackendPreference::from_backend(info.backend) != Some(backend) {
+    return Err(anyhow::Error::msg(format!(
+      "asked for {}, but only got {:?}",
+      backend.get_name(),
+      info.backend
+    )));
+  }
+
+  init_scene(&mut engine).await?;
+
+  let mut frame_times = Vec::with_capacity(frame_count as usize);
+  for frame in 0..WARMUP_FRAMES + frame_count {
+    let angle = frame as f32 * 0.1;
+    let camera = &mut engine.camera.camera;
+    camera.position = cgmath::Point3::new(angle.cos() * 5.0, 2.0, angle.sin() * 5.0);
+    camera.yaw_radians = angle + std::f32::consts::PI;
+    camera.pitch_radians = -0.3;
+
+    let start = Instant::now();
+    engine.redraw();
+    engine.drivers.device.poll(wgpu::Maintain::Wait);
+    if frame >= WARMUP_FRAMES {
+      frame_times.push(start.elapsed());
+    }
+  }
+
+  Ok(BenchmarkResult {
+    backend,
+    adapter_name: info.name,
+    frame_times,
+  })
+}
+
+/// a box made of 6 quads, each face has its own vertices so the edges stay sharp
+fn box_mesh(center: Vec3, half_size: Vec3) -> MeshBuilder {
+  // each face's normal and the two directions across it, u x v = normal so it winds ccw
+  let faces = [
+    (Vec3::unit_x(), -Vec3::unit_z(), Vec3::unit_y()),
+    (-Vec3::unit_x(), Vec3::unit_z(), Vec3::unit_y()),
+    (Vec3::unit_y(), Vec3::unit_x(), -Vec3::unit_z()),
+    (-Vec3::unit_y(), Vec3::unit_x(), Vec3::unit_z()),
+    (Vec3::unit_z(), Vec3::unit_x(), Vec3::unit_y()),
+    (-Vec3::unit_z(), -Vec3::unit_x(), Vec3::unit_y()),
+  ];
+  let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+
+  let (mut positions, mut tex_coords, mut normals, mut indices) = (vec![], vec![], vec![], vec![]);
+  for (normal, u, v) in faces {
+    let first = positions.len() as u32;
+    for (x, y) in corners {
+      let corner = center + (normal + u * x + v * y).mul_element_wise(half_size);
+      positions.push(corner.into());
+      tex_coords.push([(x + 1.0) * 0.5, (1.0 - y) * 0.5]);
+      normals.push(normal.into());
+    }
+    indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+  }
+
+  let tangents = compute_tangents(&positions, &tex_coords, &normals, &indices);
+  let vertices = (0..positions.len())
+    .map(|i| {
+      Box::new(ModelVertex {
+        pos: positions[i],
+        tex_coords: tex_coords[i],
+        normal: normals[i],
+        tangent: tangents[i],
+      }) as Vertex
+    })
+    .collect();
+  MeshBuilder::new(vertices, indices)
+}
+
+// made in code so the benchmark doesn't depend on whatever assets the game ships with
+async fn init_scene(engine: &mut Engine) -> anyhow::Result<()> {
+  let white = engine.texture_bundle.get_white_texture();
+  let material = Material::new_basic(&engine.drivers, &engine.texture_bundle, white);
+  let floor = box_mesh(Vec3::new(0.0, -1.1, 0.0), Vec3::new(3.0, 0.1, 3.0));
+  let block = box_mesh(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.5, 1.0, 0.5));
+
+  let object = ObjectBuilder::new()
+    .set_shared_location(Location::from_pos(Vec3::new(0.0, 0.0, 0.0)).to_shared())
+    .add_mesh(&engine.drivers, floor, material.clone())?
+    .add_mesh(&engine.drivers, block, material)?
+    .build();
+  engine
+    .render_task
+    .add_object(object, &engine.drivers, &engine.data_bindgroups)
+    .await?;
+
+  let sun = Light::directional(Vec3::new(-0.5, -1.0, -0.3), [1.0, 0.95, 0.9]);
+  engine.render_task.add_light(sun);
+  Ok(())
+}
+
+/// benchmarks every available backend one after the other, the ones that fail are left out
+pub async fn benchmark_backends(frame_count: u32) -> Vec<BenchmarkResult> {
+  let mut results = Vec::new();
+  for backend in available_backends() {
+    match run_benchmark(backend, frame_count).await {
+      Ok(result) => {
+        log::info!(
+          "{} on {}: {:?} per frame, {:?} deviation",
+          backend.get_name(),
+          result.adapter_name,
+          result.get_average(),
+          result.get_deviation()
+        );
+        results.push(result);
+      }
+      Err(error) => log::warn!("couldn't benchmark {}: {}", backend.get_name(), error),
+    }
+  }
+  results
+}
+
+/// the saved graphics settings, or if there aren't any yet, benchmarks the backends and
+/// saves the winner. never fails, worst case it's the defaults (and those get saved instead)
+pub async fn load_or_benchmark() -> GraphicsSettings {
+  match GraphicsSettings::load_file() {
+    Ok(Some(settings)) => return settings,
+    Ok(None) => {}
+    Err(error) => {
+      log::warn!(
+        "couldn't read the graphics settings, using the defaults: {}",
+        error
+      );
+      return GraphicsSettings::default();
+    }
+  }
+
+  let results = benchmark_backends(DEFAULT_FRAMES).await;
+  let mut settings = GraphicsSettings::default();
+
+  // the defaults still get saved if nothing worked, another go next time would only fail again
+  match pick_fastest(&results) {
+    Some(backend) => {
+      log::info!("picked {} as the fastest backend", backend.get_name());
+      settings.backend = backend;
+    }
+    None => log::warn!("no backend could be benchmarked, using the defaults"),
+  }
+  if let Err(error) = settings.save_file() {
+    log::warn!("couldn't save the graphics settings: {}", error);
+  }
+  settings
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn result(backend: BackendPreference, millis: &[u64]) -> BenchmarkResult {
+    BenchmarkResult {
+      backend,
+      adapter_name: String::new(),
+      frame_times: millis.iter().copied().map(Duration::from_millis).collect(),
+    }
+  }
+
+  #[test]
+  fn steady_backends_beat_stuttering_ones() {
+    // vulkan is faster on average, but every other frame hitches
+    let results = [
+      result(BackendPreference::Vulkan, &[2, 14, 2, 14]),
+      result(BackendPreference::Gl, &[9, 9, 9, 9]),
+    ];
+    assert_eq!(results[0].get_average(), Duration::from_millis(8));
+    assert_eq!(pick_fastest(&results), Some(BackendPreference::Gl));
+
+    let results = [
+      result(BackendPreference::Vulkan, &[5, 5, 5, 5]),
+      result(BackendPreference::Gl, &[9, 9, 9, 9]),
+      result(BackendPreference::Dx12, &[]),
+    ];
+    assert_eq!(pick_fastest(&results), Some(BackendPreference::Vulkan));
+    assert_eq!(pick_fastest(&[]), None);
+  }
+}
@@ -0,0 +1,275 @@
+// bounding volumes for culling. every mesh gets a box and a sphere around its vertices when
+// it's built, those get moved along with its instances and its object's location, and
+// anything that ends up completely outside the camera's frustum isn't drawn that frame
+
+use cgmath::{InnerSpace, Matrix4, Vector4};
+
+use crate::maths::Vec3;
+
+/// an axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+  pub min: Vec3,
+  pub max: Vec3,
+}
+
+impl Aabb {
+  pub fn new(min: Vec3, max: Vec3) -> Self {
+    Self { min, max }
+  }
+
+  /// None if there aren't any points
+  pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+    let mut points = points.into_iter();
+    let first = points.next()?;
+    Some(points.fold(Self::new(first, first), |aabb, point| {
+      Self::new(
+        Vec3::new(
+          aabb.min.x.min(point.x),
+          aabb.min.y.min(point.y),
+          aabb.min.z.min(point.z),
+        ),
+        Vec3::new(
+          aabb.max.x.max(point.x),
+          aabb.max.y.max(point.y),
+          aabb.max.z.max(point.z),
+        ),
+      )
+    }))
+  }
+
+  #[inline]
+  pub fn get_center(&self) -> Vec3 {
+    (self.min + self.max) * 0.5
+  }
+
+  /// half the size along each axis
+  #[inline]
+  pub fn get_extents(&self) -> Vec3 {
+    (self.max - self.min) * 0.5
+  }
+
+  pub fn get_corners(&self) -> [Vec3; 8] {
+    let (min, max) = (self.min, self.max);
+    [
+      Vec3::new(min.x, min.y, min.z),
+      Vec3::new(max.x, min.y, min.z),
+      Vec3::new(min.x, max.y, min.z),
+      Vec3::new(max.x, max.y, min.z),
+      Vec3::new(min.x, min.y, max.z),
+      Vec3::new(max.x, min.y, max.z),
+      Vec3::new(min.x, max.y, max.z),
+      Vec3::new(max.x, max.y, max.z),
+    ]
+  }
+
+  pub fn union(&self, other: &Aabb) -> Aabb {
+    Self::from_points([self.min, self.max, other.min, other.max]).unwrap()
+  }
+
+  /// the box around this one after it's been moved, rotated boxes get bigger
+  pub fn transformed(&self, matrix: &Matrix4<f32>) -> Aabb {
+    let corners = self
+      .get_corners()
+      .map(|corner| transform_point(matrix, corner));
+    Self::from_points(corners).unwrap()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+  pub center: Vec3,
+  pub radius: f32,
+}
+
+impl BoundingSphere {
+  /// centered on the points' box, not the smallest sphere there is but close enough for culling
+  pub fn from_points(points: &[Vec3]) -> Option<Self> {
+    let center = Aabb::from_points(points.iter().copied())?.get_center();
+    let radius = points
+      .iter()
+      .map(|point| (point - center).magnitude())
+      .fold(0.0, f32::max);
+    Some(Self { center, radius })
+  }
+
+  /// the smallest sphere around both
+  pub fn union(&self, other: &BoundingSphere) -> BoundingSphere {
+    let offset = other.center - self.center;
+    let distance = offset.magnitude();
+    if distance + other.radius <= self.radius {
+      return *self;
+    }
+    if distance + self.radius <= other.radius {
+      return *other;
+    }
+
+    let radius = (distance + self.radius + other.radius) * 0.5;
+    let center = self.center + offset * ((radius - self.radius) / distance);
+    BoundingSphere { center, radius }
+  }
+
+  /// the radius grows with the biggest scale, so it still fits after a non uniform scale
+  pub fn transformed(&self, matrix: &Matrix4<f32>) -> BoundingSphere {
+    let scale = [matrix.x, matrix.y, matrix.z]
+      .iter()
+      .map(|axis| axis.truncate().magnitude())
+      .fold(0.0, f32::max);
+    BoundingSphere {
+      center: transform_point(matrix, self.center),
+      radius: self.radius * scale,
+    }
+  }
+}
+
+/// a box and a sphere around the same thing, the sphere is the quicker test
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+  pub aabb: Aabb,
+  pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+  pub fn from_points(points: &[Vec3]) -> Option<Self> {
+    Some(Self {
+      aabb: Aabb::from_points(points.iter().copied())?,
+      sphere: BoundingSphere::from_points(points)?,
+    })
+  }
+
+  pub fn union(&self, other: &Bounds) -> Bounds {
+    Bounds {
+      aabb: self.aabb.union(&other.aabb),
+      sphere: self.sphere.union(&other.sphere),
+    }
+  }
+
+  pub fn transformed(&self, matrix: &Matrix4<f32>) -> Bounds {
+    Bounds {
+      aabb: self.aabb.transformed(matrix),
+      sphere: self.sphere.transformed(matrix),
+    }
+  }
+}
+
+fn transform_point(matrix: &Matrix4<f32>, point: Vec3) -> Vec3 {
+  (matrix * point.extend(1.0)).truncate()
+}
+
+/// the six planes around what a camera can see, normals pointing inwards
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+  // xyz is the normal, w the distance, a point's inside if dot(normal, p) + w >= 0
+  planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+  /// pulls the planes straight out of the view projection matrix (gribb and hartmann).
+  /// the near plane is where wgpu clips, at a clip space z of 0
+  pub fn from_view_projection(matrix: &Matrix4<f32>) -> Self {
+    let row = |i: usize| Vector4::new(matrix.x[i], matrix.y[i], matrix.z[i], matrix.w[i]);
+    let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+    let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+      let length = plane.truncate().magnitude();
+      match length > 0.0 {
+        true => plane / length,
+        false => plane,
+      }
+    });
+    Self { planes }
+  }
+
+  pub fn contains_sphere(&self, sphere: &BoundingSphere) -> bool {
+    self
+      .planes
+      .iter()
+      .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+  }
+
+  /// only says no if the box is completely behind one of the planes,
+  /// boxes right outside a corner still count as visible
+  pub fn contains_aabb(&self, aabb: &Aabb) -> bool {
+    self.planes.iter().all(|plane| {
+      // the corner furthest along the plane's normal
+      let normal = plane.truncate();
+      let corner = Vec3::new(
+        if normal.x >= 0.0 {
+          aabb.max.x
+        } else {
+          aabb.min.x
+        },
+        if normal.y >= 0.0 {
+          aabb.max.y
+        } else {
+          aabb.min.y
+        },
+        if normal.z >= 0.0 {
+          aabb.max.z
+        } else {
+          aabb.min.z
+        },
+      );
+      normal.dot(corner) + plane.w >= 0.0
+    })
+  }
+
+  /// the sphere first, it's quicker and throws most things out already
+  pub fn contains(&self, bounds: &Bounds) -> bool {
+    self.contains_sphere(&bounds.sphere) && self.contains_aabb(&bounds.aabb)
+  }
+}
+
+/// how many meshes got drawn and how many got culled last frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullStats {
+  pub drawn: u32,
+  pub culled: u32,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gpu::camera::Camera;
+
+  fn unit_cube() -> Bounds {
+    let aabb = Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+    Bounds::from_points(&aabb.get_corners()).unwrap()
+  }
+
+  #[test]
+  fn bounds_fit_their_points() {
+    let bounds = unit_cube();
+    assert_eq!(bounds.aabb.get_center(), Vec3::new(0.0, 0.0, 0.0));
+    assert!((bounds.sphere.radius - 3.0f32.sqrt()).abs() < 1e-5);
+    assert_eq!(Bounds::from_points(&[]), None);
+
+    // moved and scaled up, the sphere grows with the biggest axis
+    let matrix = Matrix4::from_translation(Vec3::new(5.0, 0.0, 0.0))
+      * Matrix4::from_nonuniform_scale(1.0, 3.0, 1.0);
+    let moved = bounds.transformed(&matrix);
+    assert_eq!(moved.aabb.min, Vec3::new(4.0, -3.0, -1.0));
+    assert_eq!(moved.sphere.center, Vec3::new(5.0, 0.0, 0.0));
+    assert!((moved.sphere.radius - 3.0 * 3.0f32.sqrt()).abs() < 1e-4);
+
+    let both = bounds.union(&moved);
+    assert_eq!(both.aabb.max, Vec3::new(6.0, 3.0, 1.0));
+    assert!(both.sphere.radius >= (moved.sphere.center - both.sphere.center).magnitude());
+  }
+
+  #[test]
+  fn only_what_the_camera_sees_is_inside() {
+    // at the origin, looking down +x
+    let camera = Camera::new(cgmath::Point3::new(0.0, 0.0, 0.0), 60.0, (100, 100));
+    let frustum = camera.get_frustum();
+    let at = |x, y, z| unit_cube().transformed(&Matrix4::from_translation(Vec3::new(x, y, z)));
+
+    assert!(frustum.contains(&at(10.0, 0.0, 0.0)));
+    // behind, off to the side, and past the far plane
+    assert!(!frustum.contains(&at(-10.0, 0.0, 0.0)));
+    assert!(!frustum.contains(&at(10.0, 0.0, 30.0)));
+    assert!(!frustum.contains(&at(camera.zfar + 5.0, 0.0, 0.0)));
+    // poking in from the edge still counts
+    assert!(frustum.contains(&at(10.0, 0.0, 6.5)));
+  }
+}
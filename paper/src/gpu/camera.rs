@@ -1,8 +1,12 @@
-use cgmath::{InnerSpace, Vector3};
+use cgmath::{EuclideanSpace, InnerSpace, Vector3};
 use wgpu::util::DeviceExt;
 
 use crate::{
-  gpu::{geometry::GetBufferLayout, object},
+  gpu::{
+    bounds::{BoundingSphere, Frustum},
+    geometry::GetBufferLayout,
+    object,
+  },
   maths,
   window::user_input::InputType,
 };
@@ -22,6 +26,8 @@ pub struct CameraUniform {
   // We can't use cgmath with bytemuck directly, so we'll have
   // to convert the Matrix4 into a 4x4 f32 array
   view_proj: [[f32; 4]; 4],
+  // w is unused, it's only there to pad the vec3 out. lighting needs it for specular
+  view_position: [f32; 4],
 }
 
 impl CameraUniform {
@@ -29,11 +35,13 @@ impl CameraUniform {
     use cgmath::SquareMatrix;
     Self {
       view_proj: cgmath::Matrix4::identity().into(),
+      view_position: [0.0, 0.0, 0.0, 1.0],
     }
   }
 
   pub fn update_view_proj(&mut self, camera: &Camera) {
     self.view_proj = camera.build_view_projection_matrix().into();
+    self.view_position = camera.position.to_homogeneous().into();
   }
 }
 
@@ -83,6 +91,21 @@ impl Camera {
     return view_projection * view;
   }
 
+  /// what the camera can see, anything completely outside it doesn't need drawing
+  pub fn get_frustum(&self) -> Frustum {
+    Frustum::from_view_projection(&self.build_view_projection_matrix())
+  }
+
+  /// how much of the screen's height the sphere takes up, 1 fills it. from inside it's infinite
+  pub fn get_screen_size(&self, sphere: &BoundingSphere) -> f32 {
+    let distance = (sphere.center - self.position.to_vec()).magnitude();
+    if distance <= sphere.radius {
+      return f32::INFINITY;
+    }
+    let half_fov = cgmath::Rad::from(cgmath::Deg(self.fov_degrees * 0.5));
+    sphere.radius / (distance * cgmath::Angle::tan(half_fov))
+  }
+
   pub fn forward_vector(&self) -> Vector3<f32> {
     maths::Vec3::new(
       self.yaw_radians.cos() * self.pitch_radians.cos(),
@@ -138,9 +161,26 @@ impl GetBufferLayout for GpuCamera {
   fn get_bind_layout(&self) -> wgpu::BindGroupLayout {
     self.camera_bind_group_layout.clone()
   }
+
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+    Self::bind_entries()
+  }
 }
 
 impl GpuCamera {
+  fn bind_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+    vec![wgpu::BindGroupLayoutEntry {
+      binding: 0,
+      visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    }]
+  }
+
   pub fn new(device: &wgpu::Device, size: (u32, u32)) -> Self {
     let default_camera_position = cgmath::Point3::new(1.0, 0.0, 0.0);
     let camera = Camera::new(default_camera_position, 45.0, size);
@@ -156,16 +196,7 @@ impl GpuCamera {
 
     let camera_bind_group_layout =
       device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        entries: &[wgpu::BindGroupLayoutEntry {
-          binding: 0,
-          visibility: wgpu::ShaderStages::VERTEX,
-          ty: wgpu::BindingType::Buffer {
-            ty: wgpu::BufferBindingType::Uniform,
-            has_dynamic_offset: false,
-            min_binding_size: None,
-          },
-          count: None,
-        }],
+        entries: &Self::bind_entries(),
         label: Some("camera_bind_group_layout"),
       });
 
@@ -0,0 +1,127 @@
+// screenshots and png sequences, the engine asks this for a path every frame
+// and only copies the frame back from the gpu when it gets one
+
+use std::path::PathBuf;
+
+enum CaptureRequest {
+  Screenshot(PathBuf),
+  Sequence {
+    folder: PathBuf,
+    next_frame: u32,
+    frames_left: u32,
+  },
+}
+
+#[derive(Default)]
+pub struct FrameCapture {
+  pending: Option<CaptureRequest>,
+}
+
+impl FrameCapture {
+  pub fn new() -> Self {
+    Self { pending: None }
+  }
+
+  /// the next frame gets saved to path, the image format comes from the extension.
+  /// formats without alpha (jpg, pnm) get the frame without it
+  pub fn screenshot(&mut self, path: PathBuf) {
+    self.pending = Some(CaptureRequest::Screenshot(path));
+  }
+
+  /// saves the next frame_count frames as folder/frame_00000.png, folder/frame_00001.png, etc.
+  pub fn record_sequence(&mut self, folder: PathBuf, frame_count: u32) {
+    if frame_count == 0 {
+      return;
+    }
+
+    self.pending = Some(CaptureRequest::Sequence {
+      folder,
+      next_frame: 0,
+      frames_left: frame_count,
+    });
+  }
+
+  pub fn is_capturing(&self) -> bool {
+    self.pending.is_some()
+  }
+
+  /// where the frame about to be rendered should be saved, None if nothing was asked for
+  pub fn next_path(&mut self) -> Option<PathBuf> {
+    match self.pending.take()? {
+      CaptureRequest::Screenshot(path) => Some(path),
+      CaptureRequest::Sequence {
+        folder,
+        next_frame,
+        frames_left,
+      } => {
+        let path = folder.join(format!("frame_{:05}.png", next_frame));
+        if frames_left > 1 {
+          self.pending = Some(CaptureRequest::Sequence {
+            folder,
+            next_frame: next_frame + 1,
+            frames_left: frames_left - 1,
+          });
+        }
+        Some(path)
+      }
+    }
+  }
+
+  pub fn save(image: &image::RgbaImage, path: &PathBuf) -> anyhow::Result<()> {
+    if let Some(folder) = path.parent() {
+      std::fs::create_dir_all(folder)?;
+    }
+
+    // the image crate won't write rgba to formats that can't hold it
+    match image::ImageFormat::from_path(path)? {
+      image::ImageFormat::Jpeg | image::ImageFormat::Pnm => {
+        image::DynamicImage::ImageRgba8(image.clone())
+          .to_rgb8()
+          .save(path)?
+      }
+      _ => image.save(path)?,
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sequences_number_their_frames() {
+    let mut capture = FrameCapture::new();
+    capture.record_sequence(PathBuf::from("shots"), 2);
+
+    assert_eq!(
+      capture.next_path(),
+      Some(PathBuf::from("shots/frame_00000.png"))
+    );
+    assert_eq!(
+      capture.next_path(),
+      Some(PathBuf::from("shots/frame_00001.png"))
+    );
+    assert_eq!(capture.next_path(), None);
+  }
+
+  #[test]
+  fn screenshots_only_take_one_frame() {
+    let mut capture = FrameCapture::new();
+    capture.screenshot(PathBuf::from("shot.png"));
+
+    assert!(capture.is_capturing());
+    assert_eq!(capture.next_path(), Some(PathBuf::from("shot.png")));
+    assert!(!capture.is_capturing());
+  }
+
+  #[test]
+  fn formats_without_alpha_can_be_saved() {
+    let folder = std::env::temp_dir().join("paper_capture_test");
+    let image = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+    for name in ["shot.jpg", "shot.png"] {
+      FrameCapture::save(&image, &folder.join(name)).unwrap();
+    }
+    assert!(FrameCapture::save(&image, &folder.join("shot.nope")).is_err());
+  }
+}
@@ -1,56 +1,152 @@
+#[cfg(feature = "window")]
 use std::sync::Arc;
 
+use crate::gpu::{
+  settings::{self, AdapterRequest, BackendPreference, GraphicsSettings},
+  shaders::pipeline_state::PipelineState,
+  texture::DynamicTexture,
+};
+#[cfg(feature = "window")]
 use crate::window::translate_surface;
 
+/// where finished frames end up
+pub enum RenderTarget {
+  /// a real sdl window, frames get presented to its surface
+  #[cfg(feature = "window")]
+  Window {
+    surface: wgpu::Surface<'static>,
+    window: Arc<sdl3::video::Window>,
+  },
+  /// no window at all, frames get drawn into a texture that can be read back (tests, ci, etc.)
+  Offscreen { texture: wgpu::Texture },
+}
+
+/// a frame that's being drawn to, has to be handed back to Drivers::present when done
+pub enum Frame {
+  Surface(wgpu::SurfaceTexture),
+  Offscreen(wgpu::Texture),
+}
+
+impl Frame {
+  pub fn get_texture(&self) -> &wgpu::Texture {
+    match self {
+      Frame::Surface(output) => &output.texture,
+      Frame::Offscreen(texture) => texture,
+    }
+  }
+
+  pub fn create_view(&self) -> wgpu::TextureView {
+    self
+      .get_texture()
+      .create_view(&wgpu::TextureViewDescriptor::default())
+  }
+}
+
+/// makes the window's surface, every adapter fallback needs one for its own instance
+type MakeSurface<'a> = &'a dyn Fn(&wgpu::Instance) -> anyhow::Result<wgpu::Surface<'static>>;
+
 pub struct Drivers {
-  pub surface: wgpu::Surface<'static>,
+  pub target: RenderTarget,
+  pub adapter: wgpu::Adapter,
   pub device: wgpu::Device,
   pub queue: wgpu::Queue,
-  pub window: Arc<sdl3::video::Window>,
+  // the offscreen texture uses this too, so the size/format is always in one place
   pub surface_config: wgpu::SurfaceConfiguration,
+
+  // what was actually applied, not what was asked for
+  settings: GraphicsSettings,
+  // what the surface can do, only fifo when headless
+  present_modes: Vec<wgpu::PresentMode>,
 }
 
 impl Drivers {
-  async fn init_window<'a>(
-    window: Arc<sdl3::video::Window>,
-  ) -> (
-    wgpu::Surface<'static>,
+  /// tries every step of settings::adapter_fallbacks until one gives a device,
+  /// the surface is only there when there's a window
+  async fn request_device(
+    backend: BackendPreference,
+    make_surface: Option<MakeSurface<'_>>,
+  ) -> anyhow::Result<(
+    Option<wgpu::Surface<'static>>,
+    wgpu::Adapter,
     wgpu::Device,
     wgpu::Queue,
-    (u32, u32),
-    wgpu::TextureFormat,
-    wgpu::SurfaceCapabilities,
-  ) {
+  )> {
+    let mut last_error = anyhow::Error::msg("there was nothing to try");
+    for request in settings::adapter_fallbacks(backend, make_surface.is_none()) {
+      match Self::try_request_device(request, make_surface).await {
+        Ok(found) => return Ok(found),
+        Err(error) => {
+          log::warn!("couldn't get a device with {:?}: {}", request, error);
+          last_error = error;
+        }
+      }
+    }
+
+    Err(last_error.context("no gpu adapter worked, not even a software one"))
+  }
+
+  async fn try_request_device(
+    request: AdapterRequest,
+    make_surface: Option<MakeSurface<'_>>,
+  ) -> anyhow::Result<(
+    Option<wgpu::Surface<'static>>,
+    wgpu::Adapter,
+    wgpu::Device,
+    wgpu::Queue,
+  )> {
+    // The instance is a handle to our GPU
     let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-      backends: wgpu::Backends::PRIMARY,
+      backends: request.backends,
       ..Default::default()
     });
-    // The instance is a handle to our GPU
-    // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
-    let surface = translate_surface::create_surface(&instance, window.clone()).unwrap();
+    let surface = match make_surface {
+      Some(make_surface) => Some(make_surface(&instance)?),
+      None => None,
+    };
 
-    let size = window.size();
     let adapter = instance
       .request_adapter(&wgpu::RequestAdapterOptions {
         power_preference: wgpu::PowerPreference::default(),
-        compatible_surface: Some(&surface),
-        force_fallback_adapter: false,
+        compatible_surface: surface.as_ref(),
+        force_fallback_adapter: request.force_fallback_adapter,
       })
       .await
-      .unwrap();
+      .ok_or(anyhow::Error::msg("no adapter"))?;
 
     let (device, queue) = adapter
       .request_device(
         &wgpu::DeviceDescriptor {
           label: None,
-          required_features: wgpu::Features::empty(),
-          required_limits: wgpu::Limits::default(),
+          // compressed textures get decompressed on the cpu when there's no bc support,
+          // line polygons are only for the wireframe view
+          required_features: adapter.features()
+            & (wgpu::Features::TEXTURE_COMPRESSION_BC | wgpu::Features::POLYGON_MODE_LINE),
+          // software/gl adapters don't always reach the default limits
+          required_limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
           memory_hints: Default::default(),
         },
         None,
       )
-      .await
-      .unwrap();
+      .await?;
+
+    log::info!("rendering with {:?}", adapter.get_info());
+    Ok((surface, adapter, device, queue))
+  }
+
+  /// falls back to gl, then a software adapter, if the backend in settings doesn't work
+  #[cfg(feature = "window")]
+  pub async fn new(
+    window: Arc<sdl3::video::Window>,
+    settings: &GraphicsSettings,
+  ) -> anyhow::Result<Self> {
+    let make_surface = |instance: &wgpu::Instance| {
+      translate_surface::create_surface(instance, window.clone()).map_err(anyhow::Error::msg)
+    };
+    let (surface, adapter, device, queue) =
+      Self::request_device(settings.backend, Some(&make_surface)).await?;
+    let surface = surface.ok_or(anyhow::Error::msg("the window has no surface"))?;
+
+    let size = window.size();
     let surface_caps = surface.get_capabilities(&adapter);
     // formatted to srgb, check docs to change (please don't)
     let surface_format = surface_caps
@@ -60,32 +156,228 @@ impl Drivers {
       .find(|f| f.is_srgb())
       .unwrap_or(surface_caps.formats[0]);
 
-    return (surface, device, queue, size, surface_format, surface_caps);
-  }
-
-  pub async fn new(window: Arc<sdl3::video::Window>) -> Self {
-    let (surface, device, queue, size, surface_format, surface_caps) =
-      Self::init_window(window.clone()).await;
+    // screenshots copy straight out of the surface, most backends allow it
+    let copy_usage = surface_caps.usages & wgpu::TextureUsages::COPY_SRC;
     let surface_config = wgpu::SurfaceConfiguration {
-      usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | copy_usage,
       format: surface_format,
       width: size.0,  // width
       height: size.1, // height
-      present_mode: surface_caps.present_modes[0],
+      // both get set by apply_settings
+      present_mode: wgpu::PresentMode::Fifo,
       alpha_mode: surface_caps.alpha_modes[0],
-      // may change later, the amount of frames queued for rendering, 1 means lower latency
       desired_maximum_frame_latency: 1,
       view_formats: vec![],
     };
 
     device.poll(wgpu::Maintain::Wait);
 
-    Self {
-      surface,
+    let mut drivers = Self {
+      target: RenderTarget::Window { surface, window },
+      adapter,
       device,
       queue,
-      window,
       surface_config,
+      settings: *settings,
+      present_modes: surface_caps.present_modes,
+    };
+    drivers.apply_settings(*settings);
+    Ok(drivers)
+  }
+
+  /// gets a device without a window, with the same fallbacks as Drivers::new
+  pub async fn new_headless(
+    width: u32,
+    height: u32,
+    settings: &GraphicsSettings,
+  ) -> anyhow::Result<Self> {
+    let (_, adapter, device, queue) = Self::request_device(settings.backend, None).await?;
+
+    let surface_config = wgpu::SurfaceConfiguration {
+      usage: Self::OFFSCREEN_USAGE,
+      format: Self::OFFSCREEN_FORMAT,
+      width,
+      height,
+      present_mode: wgpu::PresentMode::Fifo,
+      alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+      desired_maximum_frame_latency: 1,
+      view_formats: vec![],
+    };
+
+    let texture = Self::create_offscreen_texture(&device, &surface_config);
+
+    let mut drivers = Self {
+      target: RenderTarget::Offscreen { texture },
+      adapter,
+      device,
+      queue,
+      surface_config,
+      settings: *settings,
+      present_modes: vec![wgpu::PresentMode::Fifo],
+    };
+    drivers.apply_settings(*settings);
+    Ok(drivers)
+  }
+
+  pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+  const OFFSCREEN_USAGE: wgpu::TextureUsages = wgpu::TextureUsages::RENDER_ATTACHMENT
+    .union(wgpu::TextureUsages::COPY_SRC)
+    .union(wgpu::TextureUsages::TEXTURE_BINDING);
+
+  fn create_offscreen_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+  ) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("offscreen_target"),
+      size: wgpu::Extent3d {
+        width: config.width,
+        height: config.height,
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: config.format,
+      usage: config.usage,
+      view_formats: &[],
+    })
+  }
+
+  pub fn is_headless(&self) -> bool {
+    matches!(self.target, RenderTarget::Offscreen { .. })
+  }
+
+  /// None when headless
+  #[cfg(feature = "window")]
+  pub fn get_window(&self) -> Option<&Arc<sdl3::video::Window>> {
+    match &self.target {
+      RenderTarget::Window { window, .. } => Some(window),
+      RenderTarget::Offscreen { .. } => None,
+    }
+  }
+
+  #[inline]
+  pub fn get_settings(&self) -> &GraphicsSettings {
+    &self.settings
+  }
+
+  /// how many samples the scene's color and depth get, 1 when msaa is off
+  #[inline]
+  pub fn get_sample_count(&self) -> u32 {
+    self.settings.msaa_samples
+  }
+
+  /// whether pipelines can be drawn as lines, for the wireframe view
+  pub fn supports_wireframe(&self) -> bool {
+    PipelineState::supports_polygon_mode(wgpu::PolygonMode::Line, self.device.features())
+  }
+
+  /// whether the scene's attachments can have this many samples
+  fn supports_sample_count(&self, count: u32) -> bool {
+    [
+      DynamicTexture::HDR_FORMAT,
+      DynamicTexture::DEPTH_BUFFER_FORMAT,
+    ]
+    .into_iter()
+    .all(|format| {
+      let features = self.adapter.get_texture_format_features(format);
+      features.flags.sample_count_supported(count)
+    })
+  }
+
+  /// clamps the settings to what the gpu can do and reconfigures the surface,
+  /// anything that depends on the sample count (pipelines, attachments) is up to the caller.
+  /// returns the settings that were actually applied
+  pub fn apply_settings(&mut self, settings: GraphicsSettings) -> GraphicsSettings {
+    let applied = GraphicsSettings {
+      msaa_samples: settings::pick_sample_count(settings.msaa_samples, |count| {
+        self.supports_sample_count(count)
+      }),
+      present_mode: settings::pick_present_mode(settings.present_mode, &self.present_modes),
+      frame_latency: settings.frame_latency.max(1),
+      // can't change without a new device
+      backend: self.settings.backend,
+    };
+
+    if applied.msaa_samples != settings.msaa_samples {
+      log::warn!(
+        "{}x msaa isn't supported, using {}x",
+        settings.msaa_samples,
+        applied.msaa_samples
+      );
+    }
+    if applied.present_mode != settings.present_mode {
+      log::warn!(
+        "{:?} isn't supported, using {:?}",
+        settings.present_mode,
+        applied.present_mode
+      );
+    }
+    if settings.backend != self.settings.backend {
+      log::warn!("the backend only changes after a restart");
+    }
+
+    self.settings = applied;
+    self.surface_config.present_mode = applied.present_mode.to_wgpu();
+    self.surface_config.desired_maximum_frame_latency = applied.frame_latency;
+    #[cfg(feature = "window")]
+    if let RenderTarget::Window { surface, .. } = &self.target {
+      surface.configure(&self.device, &self.surface_config);
+    }
+
+    applied
+  }
+
+  pub fn get_size(&self) -> (u32, u32) {
+    (self.surface_config.width, self.surface_config.height)
+  }
+
+  /// the window's size when there is one, it can be out of date with the surface
+  pub fn get_target_size(&self) -> (u32, u32) {
+    match &self.target {
+      #[cfg(feature = "window")]
+      RenderTarget::Window { window, .. } => window.size(),
+      RenderTarget::Offscreen { .. } => self.get_size(),
+    }
+  }
+
+  /// reconfigures the surface, or makes a new offscreen texture at the new size
+  pub fn resize(&mut self, width: u32, height: u32) {
+    self.surface_config.width = width;
+    self.surface_config.height = height;
+
+    match &mut self.target {
+      #[cfg(feature = "window")]
+      RenderTarget::Window { surface, .. } => surface.configure(&self.device, &self.surface_config),
+      RenderTarget::Offscreen { texture } => {
+        *texture = Self::create_offscreen_texture(&self.device, &self.surface_config);
+      }
+    }
+  }
+
+  pub fn get_current_frame(&self) -> Result<Frame, wgpu::SurfaceError> {
+    match &self.target {
+      #[cfg(feature = "window")]
+      RenderTarget::Window { surface, .. } => Ok(Frame::Surface(surface.get_current_texture()?)),
+      RenderTarget::Offscreen { texture } => Ok(Frame::Offscreen(texture.clone())),
+    }
+  }
+
+  pub fn present(&self, frame: Frame) {
+    match frame {
+      Frame::Surface(output) => output.present(),
+      // nothing to show, the texture just keeps the last frame until it's read back
+      Frame::Offscreen(_) => {}
+    }
+  }
+
+  /// the offscreen texture, None if there's a window
+  pub fn get_offscreen_texture(&self) -> Option<&wgpu::Texture> {
+    match &self.target {
+      #[cfg(feature = "window")]
+      RenderTarget::Window { .. } => None,
+      RenderTarget::Offscreen { texture } => Some(texture),
     }
   }
 }
@@ -6,12 +6,20 @@ pub trait VertexTrait {
     Self: Sized;
 
   fn as_bytes(&self) -> Vec<u8>;
+
+  /// where the vertex is, for working out a mesh's bounds. vertices that don't have one
+  /// leave the mesh without bounds, and it never gets culled
+  fn get_position(&self) -> Option<[f32; 3]> {
+    None
+  }
 }
 
 pub type Vertex = Box<dyn VertexTrait>;
 
 pub trait GetBufferLayout {
   fn get_bind_layout(&self) -> wgpu::BindGroupLayout;
+  /// what the layout was made from, shaders get checked against it before their pipeline is
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry>;
 }
 
 #[repr(C)]
@@ -20,14 +28,17 @@ pub struct ModelVertex {
   pub pos: [f32; 3],
   pub tex_coords: [f32; 2],
   pub normal: [f32; 3],
+  /// xyz points along +u, w is which way the bitangent goes (1 or -1)
+  pub tangent: [f32; 4],
 }
 
 impl VertexTrait for ModelVertex {
   fn desc() -> VertexBufferLayout<'static> {
-    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+    const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
       0 => Float32x3, // pos
       1 => Float32x2, // tex_coords
-      2 => Float32x3  // normal
+      2 => Float32x3, // normal
+      3 => Float32x4  // tangent
     ];
 
     wgpu::VertexBufferLayout {
@@ -42,8 +53,13 @@ impl VertexTrait for ModelVertex {
     bytes.extend(bytemuck::cast_slice(&self.pos));
     bytes.extend(bytemuck::cast_slice(&self.tex_coords));
     bytes.extend(bytemuck::cast_slice(&self.normal));
+    bytes.extend(bytemuck::cast_slice(&self.tangent));
     return bytes;
   }
+
+  fn get_position(&self) -> Option<[f32; 3]> {
+    Some(self.pos)
+  }
 }
 
 pub fn vertex_list_as_bytes(vertex_list: &Vec<Vertex>) -> Vec<u8> {
@@ -58,3 +74,111 @@ pub fn vertex_list_as_bytes(vertex_list: &Vec<Vertex>) -> Vec<u8> {
   }
   return vertex_bytes;
 }
+
+/// per vertex tangents from the uv layout of each triangle, for meshes that don't come with their own.
+/// vertices without usable uvs get one that's just perpendicular to the normal
+pub fn compute_tangents(
+  positions: &[[f32; 3]],
+  tex_coords: &[[f32; 2]],
+  normals: &[[f32; 3]],
+  indices: &[u32],
+) -> Vec<[f32; 4]> {
+  use cgmath::{InnerSpace, Vector3};
+
+  let mut tangents = vec![Vector3::new(0.0f32, 0.0, 0.0); positions.len()];
+  let mut bitangents = vec![Vector3::new(0.0f32, 0.0, 0.0); positions.len()];
+
+  if tex_coords.len() == positions.len() {
+    for triangle in indices.chunks_exact(3) {
+      let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+      let p = [a, b, c].map(|i| Vector3::from(positions[i]));
+      let uv = [a, b, c].map(|i| tex_coords[i]);
+
+      let (edge1, edge2) = (p[1] - p[0], p[2] - p[0]);
+      let (du1, dv1) = (uv[1][0] - uv[0][0], uv[1][1] - uv[0][1]);
+      let (du2, dv2) = (uv[2][0] - uv[0][0], uv[2][1] - uv[0][1]);
+
+      let determinant = du1 * dv2 - du2 * dv1;
+      if determinant.abs() < f32::EPSILON {
+        continue;
+      }
+      let r = 1.0 / determinant;
+      let tangent = (edge1 * dv2 - edge2 * dv1) * r;
+      let bitangent = (edge2 * du1 - edge1 * du2) * r;
+
+      for i in [a, b, c] {
+        tangents[i] += tangent;
+        bitangents[i] += bitangent;
+      }
+    }
+  }
+
+  (0..positions.len())
+    .map(|i| {
+      let normal = normals
+        .get(i)
+        .map(|n| Vector3::from(*n))
+        .filter(|n| n.magnitude2() > 0.0)
+        .map(|n| n.normalize())
+        .unwrap_or(Vector3::unit_y());
+
+      // gram-schmidt, so the tangent actually lies on the surface
+      let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+      if tangent.magnitude2() < f32::EPSILON {
+        let helper = if normal.x.abs() < 0.9 {
+          Vector3::unit_x()
+        } else {
+          Vector3::unit_y()
+        };
+        tangent = helper - normal * normal.dot(helper);
+      }
+      let tangent = tangent.normalize();
+
+      let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+        -1.0
+      } else {
+        1.0
+      };
+      [tangent.x, tangent.y, tangent.z, handedness]
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tangents_follow_the_u_direction() {
+    // a quad on the xz plane facing up, u goes along +x
+    let positions = [
+      [0.0, 0.0, 0.0],
+      [1.0, 0.0, 0.0],
+      [1.0, 0.0, 1.0],
+      [0.0, 0.0, 1.0],
+    ];
+    let tex_coords = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+    let normals = [[0.0, 1.0, 0.0]; 4];
+    let indices = [0, 1, 2, 0, 2, 3];
+
+    let tangents = compute_tangents(&positions, &tex_coords, &normals, &indices);
+    for tangent in tangents {
+      assert!((tangent[0] - 1.0).abs() < 1e-5);
+      assert!(tangent[1].abs() < 1e-5 && tangent[2].abs() < 1e-5);
+      assert_eq!(tangent[3].abs(), 1.0);
+    }
+  }
+
+  #[test]
+  fn missing_uvs_still_give_a_perpendicular_tangent() {
+    let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]];
+    let normals = [[0.0, 1.0, 0.0]; 3];
+
+    let tangents = compute_tangents(&positions, &[], &normals, &[0, 1, 2]);
+    for tangent in tangents {
+      assert!(tangent[1].abs() < 1e-5);
+      let length = (tangent[0].powi(2) + tangent[2].powi(2)).sqrt();
+      assert!((length - 1.0).abs() < 1e-5);
+    }
+  }
+}
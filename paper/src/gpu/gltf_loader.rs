@@ -0,0 +1,514 @@
+// turns a .gltf/.glb into meshes, materials, nodes, skins and animations.
+// every primitive becomes its own mesh, baked into place with its node's transform at load
+
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::files;
+use crate::gpu::animation::{
+  world_matrix, Animation, Channel, ChannelOutput, Interpolation, NodeTransform, SceneNode, Skin,
+  SkinWeights,
+};
+use crate::gpu::device_drivers::Drivers;
+use crate::gpu::geometry::{compute_tangents, ModelVertex, Vertex};
+use crate::gpu::instances::Instance;
+use crate::gpu::material::{Material, MaterialParams, MaterialTextures};
+use crate::gpu::mesh;
+use crate::gpu::object::SharedLocation;
+use crate::gpu::texture::{
+  ColorSpace, ImageTexture, SamplerSettings, TextureBundle, TextureFilter, WrapMode,
+};
+use crate::maths::Vec3;
+
+/// everything that came out of one gltf file, indices all point into these lists
+pub struct GltfScene {
+  pub meshes: Vec<mesh::Mesh>,
+  pub nodes: Vec<SceneNode>,
+  pub skins: Vec<Skin>,
+  pub animations: Vec<Animation>,
+}
+
+struct GltfLoader<'a> {
+  file_name: &'a str,
+  drivers: &'a Drivers,
+  texture_bundle: &'a mut TextureBundle,
+  buffers: Vec<gltf::buffer::Data>,
+  images: Vec<gltf::image::Data>,
+}
+
+pub fn load_gltf(
+  texture_bundle: &mut TextureBundle,
+  drivers: &Drivers,
+  file_name: &str,
+  shared_location: &SharedLocation,
+  instances: Option<&[Instance]>,
+) -> anyhow::Result<GltfScene> {
+  let (document, buffers, images) = files::load_gltf(file_name)?;
+
+  let mut loader = GltfLoader {
+    file_name,
+    drivers,
+    texture_bundle,
+    buffers,
+    images,
+  };
+
+  let materials: Vec<Material> = document
+    .materials()
+    .map(|material| loader.load_material(&material))
+    .collect();
+  // what primitives without a material get, according to the spec that's plain white
+  let default_material = Material::new_basic(
+    drivers,
+    loader.texture_bundle,
+    loader.texture_bundle.get_white_texture(),
+  );
+
+  let mut nodes = load_nodes(&document);
+  let mut meshes = Vec::new();
+
+  let scene = document
+    .default_scene()
+    .or_else(|| document.scenes().next());
+  let roots: Vec<gltf::Node> = match scene {
+    Some(scene) => scene.nodes().collect(),
+    // no scenes at all, just draw whatever doesn't have a parent
+    None => document
+      .nodes()
+      .filter(|node| nodes[node.index()].parent.is_none())
+      .collect(),
+  };
+
+  let mut stack: Vec<gltf::Node> = roots;
+  while let Some(node) = stack.pop() {
+    stack.extend(node.children());
+
+    let Some(gltf_mesh) = node.mesh() else {
+      continue;
+    };
+
+    // skinned meshes are positioned by their joints, not the node they hang off of
+    let transform = match node.skin() {
+      Some(_) => Matrix4::identity(),
+      None => world_matrix(&nodes, node.index()),
+    };
+
+    for primitive in gltf_mesh.primitives() {
+      if primitive.mode() != gltf::mesh::Mode::Triangles {
+        log::warn!(
+          "{}: skipping a {:?} primitive in mesh {}, only triangles are supported",
+          file_name,
+          primitive.mode(),
+          gltf_mesh.index()
+        );
+        continue;
+      }
+
+      let material = match primitive.material().index() {
+        Some(index) => materials[index].clone(),
+        None => default_material.clone(),
+      };
+
+      let Some(mut mesh_builder) = loader.load_primitive(&primitive, transform, node.skin()) else {
+        continue;
+      };
+      if let Some(instances) = instances {
+        mesh_builder = mesh_builder.set_instances(instances.to_vec());
+      }
+
+      let mesh = mesh_builder.build(drivers, material, shared_location.clone())?;
+      nodes[node.index()].meshes.push(meshes.len());
+      meshes.push(mesh);
+    }
+  }
+
+  let skins = document
+    .skins()
+    .map(|skin| loader.load_skin(&skin))
+    .collect();
+  let animations = document
+    .animations()
+    .map(|animation| loader.load_animation(&animation))
+    .collect();
+
+  Ok(GltfScene {
+    meshes,
+    nodes,
+    skins,
+    animations,
+  })
+}
+
+fn load_nodes(document: &gltf::Document) -> Vec<SceneNode> {
+  let mut nodes: Vec<SceneNode> = document
+    .nodes()
+    .map(|node| {
+      let (translation, rotation, scale) = node.transform().decomposed();
+      let [x, y, z, w] = rotation;
+      SceneNode {
+        name: node.name().map(str::to_owned),
+        parent: None,
+        children: node.children().map(|child| child.index()).collect(),
+        transform: NodeTransform {
+          translation: Vec3::from(translation),
+          rotation: cgmath::Quaternion::new(w, x, y, z),
+          scale: Vec3::from(scale),
+        },
+        meshes: Vec::new(),
+      }
+    })
+    .collect();
+
+  for index in 0..nodes.len() {
+    for child in nodes[index].children.clone() {
+      nodes[child].parent = Some(index);
+    }
+  }
+  nodes
+}
+
+/// gltf can wrap each axis differently, the engine's samplers only do one for both (s wins)
+fn sampler_from_gltf(sampler: &gltf::texture::Sampler) -> SamplerSettings {
+  use gltf::texture::{MagFilter, WrappingMode};
+
+  let wrap = match sampler.wrap_s() {
+    WrappingMode::ClampToEdge => WrapMode::Clamp,
+    WrappingMode::MirroredRepeat => WrapMode::Mirror,
+    WrappingMode::Repeat => WrapMode::Repeat,
+  };
+  let filter = match sampler.mag_filter() {
+    Some(MagFilter::Nearest) => TextureFilter::Nearest,
+    _ => TextureFilter::Linear,
+  };
+  SamplerSettings::default()
+    .with_filter(filter)
+    .with_wrap(wrap)
+}
+
+/// decodes whatever pixel format gltf handed back into something the texture code takes
+fn image_from_gltf(data: &gltf::image::Data) -> anyhow::Result<image::DynamicImage> {
+  use gltf::image::Format;
+  use image::DynamicImage;
+
+  let (width, height) = (data.width, data.height);
+  let pixels = data.pixels.clone();
+  let wide = || -> Vec<u16> {
+    data
+      .pixels
+      .chunks_exact(2)
+      .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+      .collect()
+  };
+  let float = || -> Vec<f32> {
+    data
+      .pixels
+      .chunks_exact(4)
+      .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+      .collect()
+  };
+
+  let image = match data.format {
+    Format::R8 => image::GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+    Format::R8G8 => {
+      image::GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8)
+    }
+    Format::R8G8B8 => image::RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+    Format::R8G8B8A8 => {
+      image::RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+    }
+    Format::R16 => {
+      image::ImageBuffer::from_raw(width, height, wide()).map(DynamicImage::ImageLuma16)
+    }
+    Format::R16G16 => {
+      image::ImageBuffer::from_raw(width, height, wide()).map(DynamicImage::ImageLumaA16)
+    }
+    Format::R16G16B16 => {
+      image::ImageBuffer::from_raw(width, height, wide()).map(DynamicImage::ImageRgb16)
+    }
+    Format::R16G16B16A16 => {
+      image::ImageBuffer::from_raw(width, height, wide()).map(DynamicImage::ImageRgba16)
+    }
+    Format::R32G32B32FLOAT => {
+      image::ImageBuffer::from_raw(width, height, float()).map(DynamicImage::ImageRgb32F)
+    }
+    Format::R32G32B32A32FLOAT => {
+      image::ImageBuffer::from_raw(width, height, float()).map(DynamicImage::ImageRgba32F)
+    }
+  };
+
+  image.ok_or(anyhow::anyhow!(
+    "{:?} image data doesn't fit {}x{}",
+    data.format,
+    width,
+    height
+  ))
+}
+
+impl GltfLoader<'_> {
+  fn buffer_data<'s>(&'s self) -> impl Fn(gltf::Buffer) -> Option<&'s [u8]> + Clone {
+    |buffer: gltf::Buffer| self.buffers.get(buffer.index()).map(|data| &data.0[..])
+  }
+
+  /// textures are stored in the bundle under the file name and image index,
+  /// so reloading the same file shares them
+  fn load_texture(
+    &mut self,
+    texture: gltf::Texture,
+    color_space: ColorSpace,
+  ) -> anyhow::Result<Arc<ImageTexture>> {
+    let index = texture.source().index();
+    let sampler = sampler_from_gltf(&texture.sampler());
+    let label = format!(
+      "{}#image{}:{:?}:{:?}",
+      self.file_name, index, color_space, sampler
+    );
+
+    let data = self
+      .images
+      .get(index)
+      .ok_or(anyhow::anyhow!("image {} wasn't loaded", index))?;
+    let image = image_from_gltf(data)?;
+
+    self
+      .texture_bundle
+      .add_image(self.drivers, &image, &label, color_space, sampler)
+  }
+
+  fn load_material_texture(
+    &mut self,
+    texture: Option<gltf::Texture>,
+    color_space: ColorSpace,
+    missing: Arc<ImageTexture>,
+  ) -> Arc<ImageTexture> {
+    let Some(texture) = texture else {
+      return missing;
+    };
+    match self.load_texture(texture, color_space) {
+      Ok(texture) => texture,
+      Err(error) => {
+        log::warn!("{}: couldn't load a texture: {}", self.file_name, error);
+        self.texture_bundle.get_fallback_texture()
+      }
+    }
+  }
+
+  fn load_material(&mut self, material: &gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let white = self.texture_bundle.get_white_texture();
+    let flat_normal = self.texture_bundle.get_flat_normal_texture();
+
+    // there's no specular map in core gltf, so the highlights are left to the params
+    let textures = MaterialTextures {
+      diffuse: self.load_material_texture(
+        pbr.base_color_texture().map(|info| info.texture()),
+        ColorSpace::Srgb,
+        white.clone(),
+      ),
+      normal: self.load_material_texture(
+        material.normal_texture().map(|info| info.texture()),
+        ColorSpace::Linear,
+        flat_normal,
+      ),
+      specular: white,
+    };
+
+    let name = material.name().unwrap_or("gltf material");
+    Material::new(
+      self.drivers,
+      self.texture_bundle,
+      name,
+      MaterialParams::from_gltf(material),
+      textures,
+    )
+  }
+
+  fn load_primitive(
+    &self,
+    primitive: &gltf::Primitive,
+    transform: Matrix4<f32>,
+    skin: Option<gltf::Skin>,
+  ) -> Option<mesh::MeshBuilder> {
+    let reader = primitive.reader(self.buffer_data());
+
+    let Some(positions) = reader.read_positions() else {
+      log::warn!("{}: skipping a primitive without positions", self.file_name);
+      return None;
+    };
+    let positions: Vec<[f32; 3]> = positions.collect();
+
+    let mut indices: Vec<u32> = match reader.read_indices() {
+      Some(indices) => indices.into_u32().collect(),
+      None => (0..positions.len() as u32).collect(),
+    };
+
+    let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+      Some(tex_coords) => tex_coords.into_f32().collect(),
+      None => vec![[0.0, 0.0]; positions.len()],
+    };
+    let normals: Vec<[f32; 3]> = match reader.read_normals() {
+      Some(normals) => normals.collect(),
+      None => compute_normals(&positions, &indices),
+    };
+    let tangents: Vec<[f32; 4]> = match reader.read_tangents() {
+      Some(tangents) => tangents.collect(),
+      None => compute_tangents(&positions, &tex_coords, &normals, &indices),
+    };
+
+    // normals have to be moved by the inverse transpose, or non uniform scales skew them
+    let linear = Matrix3::from_cols(
+      transform.x.truncate(),
+      transform.y.truncate(),
+      transform.z.truncate(),
+    );
+    let normal_matrix = linear
+      .invert()
+      .map(|inverse| inverse.transpose())
+      .unwrap_or(linear);
+    // a mirrored node turns the triangles inside out
+    let mirrored = linear.determinant() < 0.0;
+    if mirrored {
+      indices
+        .chunks_exact_mut(3)
+        .for_each(|triangle| triangle.swap(1, 2));
+    }
+
+    let vertices: Vec<Vertex> = (0..positions.len())
+      .map(|i| {
+        let pos = transform * Vector4::new(positions[i][0], positions[i][1], positions[i][2], 1.0);
+        let normal = normal_matrix * Vector3::from(normals[i]);
+        let normal = if normal.magnitude2() > 0.0 {
+          normal.normalize()
+        } else {
+          normal
+        };
+        let [tx, ty, tz, handedness] = tangents[i];
+        let tangent = linear * Vector3::new(tx, ty, tz);
+        let tangent = if tangent.magnitude2() > 0.0 {
+          tangent.normalize()
+        } else {
+          tangent
+        };
+        let handedness = if mirrored { -handedness } else { handedness };
+
+        Box::new(ModelVertex {
+          pos: [pos.x, pos.y, pos.z],
+          tex_coords: tex_coords[i],
+          normal: normal.into(),
+          tangent: [tangent.x, tangent.y, tangent.z, handedness],
+        }) as Vertex
+      })
+      .collect();
+
+    let mut mesh_builder = mesh::MeshBuilder::new(vertices, indices);
+
+    if let (Some(skin), Some(joints), Some(weights)) =
+      (skin, reader.read_joints(0), reader.read_weights(0))
+    {
+      mesh_builder = mesh_builder.set_skin(SkinWeights {
+        skin: skin.index(),
+        joints: joints.into_u16().collect(),
+        weights: weights.into_f32().collect(),
+      });
+    }
+
+    Some(mesh_builder)
+  }
+
+  fn load_skin(&self, skin: &gltf::Skin) -> Skin {
+    let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+    let inverse_bind_matrices = match skin.reader(self.buffer_data()).read_inverse_bind_matrices() {
+      Some(matrices) => matrices.map(Matrix4::from).collect(),
+      // leaving them out means they're all identity
+      None => vec![Matrix4::identity(); joints.len()],
+    };
+
+    Skin {
+      name: skin.name().map(str::to_owned),
+      joints,
+      inverse_bind_matrices,
+    }
+  }
+
+  fn load_animation(&self, animation: &gltf::Animation) -> Animation {
+    use gltf::animation::util::ReadOutputs;
+
+    let channels = animation
+      .channels()
+      .filter_map(|channel| {
+        let reader = channel.reader(self.buffer_data());
+        let times: Vec<f32> = reader.read_inputs()?.collect();
+
+        let output = match reader.read_outputs()? {
+          ReadOutputs::Translations(values) => {
+            ChannelOutput::Translation(values.map(Vec3::from).collect())
+          }
+          ReadOutputs::Rotations(values) => ChannelOutput::Rotation(
+            values
+              .into_f32()
+              .map(|[x, y, z, w]| cgmath::Quaternion::new(w, x, y, z))
+              .collect(),
+          ),
+          ReadOutputs::Scales(values) => ChannelOutput::Scale(values.map(Vec3::from).collect()),
+          ReadOutputs::MorphTargetWeights(_) => {
+            log::warn!(
+              "{}: morph target animations aren't supported, skipping one",
+              self.file_name
+            );
+            return None;
+          }
+        };
+
+        let interpolation = match channel.sampler().interpolation() {
+          gltf::animation::Interpolation::Step => Interpolation::Step,
+          gltf::animation::Interpolation::Linear => Interpolation::Linear,
+          gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+        };
+
+        let node = channel.target().node().index();
+        let channel = Channel::new(node, interpolation, times, output);
+        if channel.is_none() {
+          log::warn!(
+            "{}: an animation channel has the wrong number of keyframes, skipping it",
+            self.file_name
+          );
+        }
+        channel
+      })
+      .collect();
+
+    Animation {
+      name: animation.name().map(str::to_owned),
+      channels,
+    }
+  }
+}
+
+/// smooth normals from the triangles around each vertex, for primitives that don't have any
+fn compute_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+  let mut normals = vec![Vector3::new(0.0f32, 0.0, 0.0); positions.len()];
+  for triangle in indices.chunks_exact(3) {
+    let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+    let (pa, pb, pc) = (
+      Vector3::from(positions[a]),
+      Vector3::from(positions[b]),
+      Vector3::from(positions[c]),
+    );
+    // not normalized, so bigger triangles count for more
+    let face_normal = (pb - pa).cross(pc - pa);
+    for i in [a, b, c] {
+      normals[i] += face_normal;
+    }
+  }
+
+  normals
+    .into_iter()
+    .map(|normal| {
+      if normal.magnitude2() > 0.0 {
+        normal.normalize().into()
+      } else {
+        [0.0, 1.0, 0.0]
+      }
+    })
+    .collect()
+}
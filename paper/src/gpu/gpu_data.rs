@@ -12,6 +12,24 @@ impl GetBufferLayout for GpuTime {
   fn get_bind_layout(&self) -> wgpu::BindGroupLayout {
     self.layout.clone()
   }
+
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+    time_bind_entries()
+  }
+}
+
+fn time_bind_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+  vec![wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    // the sky's clouds move with it too
+    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+    ty: wgpu::BindingType::Buffer {
+      ty: wgpu::BufferBindingType::Uniform,
+      has_dynamic_offset: false,
+      min_binding_size: None,
+    },
+    count: None,
+  }]
 }
 
 pub fn create_time_bind_group(device: &wgpu::Device) -> GpuTime {
@@ -22,16 +40,7 @@ pub fn create_time_bind_group(device: &wgpu::Device) -> GpuTime {
   });
 
   let time_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-    entries: &[wgpu::BindGroupLayoutEntry {
-      binding: 0,
-      visibility: wgpu::ShaderStages::VERTEX,
-      ty: wgpu::BindingType::Buffer {
-        ty: wgpu::BufferBindingType::Uniform,
-        has_dynamic_offset: false,
-        min_binding_size: None,
-      },
-      count: None,
-    }],
+    entries: &time_bind_entries(),
     label: Some("time_bind_group_layout"),
   });
 
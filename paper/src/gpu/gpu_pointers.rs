@@ -1,27 +1,552 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
 use wgpu::BindGroupLayout;
 
 use crate::gpu::geometry::GetBufferLayout;
 
-pub struct MemoryLayouts {
-  binds: Vec<wgpu::BindGroupLayout>,
+/// everything the engine knows how to bind to a shader.
+/// shaders don't pick bind group numbers to match the engine anymore,
+/// the engine reads the shader's @group/@binding's and matches them up by variable name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EngineResource {
+  Material,
+  Camera,
+  Time,
+  ObjectLocation,
+  Light,
+  /// the light a shadow is being rendered from, only used by the shadow pass
+  ShadowView,
+  /// the image a post process effect works on, plus the depth and its settings (see gpu/post.rs)
+  PostProcess,
+  /// the background's settings and skybox, only used by background.wgsl (see gpu/background.rs)
+  Background,
+  /// the weighted blended transparency targets, only used by oit_composite.wgsl (see gpu/transparency.rs)
+  Transparency,
 }
 
-impl MemoryLayouts {
+impl EngineResource {
+  const ALL: [EngineResource; 9] = [
+    EngineResource::Material,
+    EngineResource::Camera,
+    EngineResource::Time,
+    EngineResource::ObjectLocation,
+    EngineResource::Light,
+    EngineResource::ShadowView,
+    EngineResource::PostProcess,
+    EngineResource::Background,
+    EngineResource::Transparency,
+  ];
+
+  /// the variable name the shader has to use for each binding, the index is the @binding
+  pub fn binding_names(&self) -> &'static [&'static str] {
+    match self {
+      EngineResource::Material => &[
+        "t_diffuse",
+        "s_diffuse",
+        "t_normal",
+        "s_normal",
+        "t_specular",
+        "s_specular",
+        "material",
+      ],
+      EngineResource::Camera => &["camera"],
+      EngineResource::Time => &["time"],
+      EngineResource::ObjectLocation => &["position_matrix"],
+      EngineResource::Light => &["lights", "shadow_atlas", "shadow_sampler"],
+      EngineResource::ShadowView => &["shadow_view"],
+      EngineResource::PostProcess => {
+        &["post_input", "post_sampler", "scene_depth", "post_settings"]
+      }
+      EngineResource::Background => &["background", "sky_texture", "sky_sampler"],
+      EngineResource::Transparency => &["oit_accum", "oit_reveal"],
+    }
+  }
+
+  /// stuff that changes between meshes, everything else gets bound once per shader
+  pub fn is_per_mesh(&self) -> bool {
+    matches!(
+      self,
+      EngineResource::Material | EngineResource::ObjectLocation
+    )
+  }
+
+  /// which resource (and which binding inside it) a shader variable belongs to
+  pub fn from_variable(name: &str) -> Option<(Self, u32)> {
+    Self::ALL.into_iter().find_map(|resource| {
+      let binding = resource.binding_names().iter().position(|n| *n == name)?;
+      Some((resource, binding as u32))
+    })
+  }
+}
+
+/// a shader's bindings didn't line up with what the engine has
+#[derive(Debug, Clone)]
+pub struct BindingError {
+  pub message: String,
+  /// where in the shader the bad declaration is, undefined if it isn't one variable's fault
+  pub span: naga::Span,
+}
+
+impl BindingError {
+  fn new(message: String, span: naga::Span) -> Self {
+    Self { message, span }
+  }
+}
+
+impl fmt::Display for BindingError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+impl std::error::Error for BindingError {}
+
+/// every bind group layout the engine can provide, shaders get their layouts picked from here
+#[derive(Clone, Default)]
+pub struct BindingRegistry {
+  // the entries are kept to check the shader's declarations against, wgpu doesn't give them back
+  layouts: HashMap<EngineResource, (wgpu::BindGroupLayout, Vec<wgpu::BindGroupLayoutEntry>)>,
+}
+
+impl BindingRegistry {
   pub fn new() -> Self {
-    Self { binds: vec![] }
+    Self {
+      layouts: HashMap::new(),
+    }
+  }
+
+  pub fn add_bind(&mut self, resource: EngineResource, bindable: &dyn GetBufferLayout) {
+    let entries = bindable.get_bind_entries();
+    self
+      .layouts
+      .insert(resource, (bindable.get_bind_layout(), entries));
   }
 
-  pub fn add_bind(&mut self, bindable: &dyn GetBufferLayout) {
-    self.binds.push(bindable.get_bind_layout());
+  /// entries has to be what the layout was made from
+  pub fn add_bind_raw(
+    &mut self,
+    resource: EngineResource,
+    bindable: &wgpu::BindGroupLayout,
+    entries: &[wgpu::BindGroupLayoutEntry],
+  ) {
+    self
+      .layouts
+      .insert(resource, (bindable.clone(), entries.to_vec()));
   }
 
-  pub fn add_bind_raw(&mut self, bindable: &wgpu::BindGroupLayout) {
-    self.binds.push(bindable.clone());
+  pub fn get_layout(&self, resource: EngineResource) -> Option<&wgpu::BindGroupLayout> {
+    self.layouts.get(&resource).map(|(layout, _)| layout)
+  }
+
+  pub fn get_entries(&self, resource: EngineResource) -> Option<&[wgpu::BindGroupLayoutEntry]> {
+    self
+      .layouts
+      .get(&resource)
+      .map(|(_, entries)| entries.as_slice())
+  }
+}
+
+/// the bind groups a draw call can use, looked up by resource when a shader needs them
+#[derive(Default)]
+pub struct BindGroupSet<'a> {
+  // the offset is for bind groups with a dynamic offset, like the object locations
+  groups: Vec<(EngineResource, &'a wgpu::BindGroup, Option<u32>)>,
+}
+
+impl<'a> BindGroupSet<'a> {
+  pub fn new() -> Self {
+    Self { groups: Vec::new() }
+  }
+
+  /// adds the bind group, replacing whatever was there for that resource
+  pub fn set(&mut self, resource: EngineResource, group: &'a wgpu::BindGroup) {
+    self.groups.retain(|(existing, _, _)| *existing != resource);
+    self.groups.push((resource, group, None));
+  }
+
+  /// same as set, but for bind groups made with `has_dynamic_offset`
+  pub fn set_dynamic(&mut self, resource: EngineResource, group: &'a wgpu::BindGroup, offset: u32) {
+    self.groups.retain(|(existing, _, _)| *existing != resource);
+    self.groups.push((resource, group, Some(offset)));
+  }
+
+  pub fn get(&self, resource: EngineResource) -> Option<&'a wgpu::BindGroup> {
+    self.get_with_offset(resource).map(|(group, _)| group)
+  }
+
+  fn get_with_offset(
+    &self,
+    resource: EngineResource,
+  ) -> Option<(&'a wgpu::BindGroup, Option<u32>)> {
+    self
+      .groups
+      .iter()
+      .find(|(existing, _, _)| *existing == resource)
+      .map(|(_, group, offset)| (*group, *offset))
+  }
+}
+
+/// reads the @group/@binding's out of the shader and works out which engine resource
+/// goes in each group, doesn't need a gpu so shaders can be checked offline
+pub fn reflect_resources(
+  module: &naga::Module,
+) -> Result<Vec<(EngineResource, naga::Span)>, BindingError> {
+  let mut groups: BTreeMap<u32, (EngineResource, naga::Span)> = BTreeMap::new();
+
+  for (handle, variable) in module.global_variables.iter() {
+    let Some(binding) = &variable.binding else {
+      continue;
+    };
+    let span = module.global_variables.get_span(handle);
+    let name = variable.name.as_deref().unwrap_or("<unnamed>");
+
+    let (resource, expected_binding) = EngineResource::from_variable(name).ok_or_else(|| {
+      let message = format!(
+        "@group({}) @binding({}) var {} doesn't match anything the engine provides",
+        binding.group, binding.binding, name
+      );
+      BindingError::new(message, span)
+    })?;
+
+    if binding.binding != expected_binding {
+      let message = format!(
+        "{} has to be @binding({}) of the {:?} group, not @binding({})",
+        name, expected_binding, resource, binding.binding
+      );
+      return Err(BindingError::new(message, span));
+    }
+
+    match groups.get(&binding.group) {
+      Some((existing, _)) if *existing != resource => {
+        let message = format!(
+          "{} is part of {:?}, but @group({}) is already used for {:?}",
+          name, resource, binding.group, existing
+        );
+        return Err(BindingError::new(message, span));
+      }
+      Some(_) => {}
+      None => {
+        groups.insert(binding.group, (resource, span));
+      }
+    }
+  }
+
+  let mut resources: Vec<(EngineResource, naga::Span)> = Vec::new();
+
+  for (index, (group, (resource, span))) in groups.into_iter().enumerate() {
+    if group != index as u32 {
+      let message = format!(
+        "@group({}) is never used, bind groups have to be numbered without gaps",
+        index
+      );
+      return Err(BindingError::new(message, span));
+    }
+
+    if resources.iter().any(|(existing, _)| *existing == resource) {
+      let message = format!("{:?} is bound in more than one @group", resource);
+      return Err(BindingError::new(message, span));
+    }
+
+    resources.push((resource, span));
+  }
+
+  Ok(resources)
+}
+
+/// what a binding looks like to a shader, so the engine's layout entries and the shader's
+/// globals can be compared before wgpu does it with a much less helpful message
+#[derive(Debug, Clone, PartialEq)]
+enum BindingShape {
+  Uniform,
+  Storage {
+    writable: bool,
+  },
+  Sampler {
+    comparison: bool,
+  },
+  Texture {
+    dimension: wgpu::TextureViewDimension,
+    class: naga::ImageClass,
+  },
+  StorageTexture {
+    dimension: wgpu::TextureViewDimension,
+  },
+  Other(String),
+}
+
+impl BindingShape {
+  fn from_entry(ty: &wgpu::BindingType) -> Self {
+    match *ty {
+      wgpu::BindingType::Buffer { ty, .. } => match ty {
+        wgpu::BufferBindingType::Uniform => BindingShape::Uniform,
+        wgpu::BufferBindingType::Storage { read_only } => BindingShape::Storage {
+          writable: !read_only,
+        },
+      },
+      wgpu::BindingType::Sampler(sampler) => BindingShape::Sampler {
+        comparison: sampler == wgpu::SamplerBindingType::Comparison,
+      },
+      wgpu::BindingType::Texture {
+        sample_type,
+        view_dimension,
+        multisampled: multi,
+      } => {
+        let kind = match sample_type {
+          wgpu::TextureSampleType::Float { .. } => naga::ScalarKind::Float,
+          wgpu::TextureSampleType::Sint => naga::ScalarKind::Sint,
+          wgpu::TextureSampleType::Uint => naga::ScalarKind::Uint,
+          wgpu::TextureSampleType::Depth => {
+            return BindingShape::Texture {
+              dimension: view_dimension,
+              class: naga::ImageClass::Depth { multi },
+            }
+          }
+        };
+        BindingShape::Texture {
+          dimension: view_dimension,
+          class: naga::ImageClass::Sampled { kind, multi },
+        }
+      }
+      wgpu::BindingType::StorageTexture { view_dimension, .. } => BindingShape::StorageTexture {
+        dimension: view_dimension,
+      },
+      ref other => BindingShape::Other(format!("{:?}", other)),
+    }
+  }
+
+  fn from_global(module: &naga::Module, variable: &naga::GlobalVariable) -> Self {
+    match variable.space {
+      naga::AddressSpace::Uniform => BindingShape::Uniform,
+      naga::AddressSpace::Storage { access } => BindingShape::Storage {
+        writable: access.contains(naga::StorageAccess::STORE),
+      },
+      naga::AddressSpace::Handle => match module.types[variable.ty].inner {
+        naga::TypeInner::Sampler { comparison } => BindingShape::Sampler { comparison },
+        naga::TypeInner::Image {
+          dim,
+          arrayed,
+          class,
+        } => {
+          let dimension = match (dim, arrayed) {
+            (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+            (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+            (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+            (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+            (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+            (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+          };
+          match class {
+            naga::ImageClass::Storage { .. } => BindingShape::StorageTexture { dimension },
+            class => BindingShape::Texture { dimension, class },
+          }
+        }
+        ref other => BindingShape::Other(format!("{:?}", other)),
+      },
+      other => BindingShape::Other(format!("var<{:?}>", other)),
+    }
+  }
+}
+
+/// written the way wgsl declares it
+impl fmt::Display for BindingShape {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let dimension_name = |dimension: &wgpu::TextureViewDimension| match dimension {
+      wgpu::TextureViewDimension::D1 => "1d",
+      wgpu::TextureViewDimension::D2 => "2d",
+      wgpu::TextureViewDimension::D2Array => "2d_array",
+      wgpu::TextureViewDimension::Cube => "cube",
+      wgpu::TextureViewDimension::CubeArray => "cube_array",
+      wgpu::TextureViewDimension::D3 => "3d",
+    };
+
+    match self {
+      BindingShape::Uniform => write!(f, "var<uniform>"),
+      BindingShape::Storage { writable: true } => write!(f, "var<storage, read_write>"),
+      BindingShape::Storage { writable: false } => write!(f, "var<storage, read>"),
+      BindingShape::Sampler { comparison: true } => write!(f, "sampler_comparison"),
+      BindingShape::Sampler { comparison: false } => write!(f, "sampler"),
+      BindingShape::Texture { dimension, class } => {
+        let dimension = dimension_name(dimension);
+        match class {
+          naga::ImageClass::Depth { multi: true } => {
+            write!(f, "texture_depth_multisampled_{}", dimension)
+          }
+          naga::ImageClass::Depth { multi: false } => write!(f, "texture_depth_{}", dimension),
+          naga::ImageClass::Sampled { kind, multi } => {
+            let kind = match kind {
+              naga::ScalarKind::Sint => "i32",
+              naga::ScalarKind::Uint => "u32",
+              _ => "f32",
+            };
+            match multi {
+              true => write!(f, "texture_multisampled_{}<{}>", dimension, kind),
+              false => write!(f, "texture_{}<{}>", dimension, kind),
+            }
+          }
+          naga::ImageClass::Storage { .. } => write!(f, "texture_storage_{}", dimension),
+        }
+      }
+      BindingShape::StorageTexture { dimension } => {
+        write!(f, "texture_storage_{}", dimension_name(dimension))
+      }
+      BindingShape::Other(description) => write!(f, "{}", description),
+    }
+  }
+}
+
+/// whether every global the shader binds is declared as what the engine's layout has there
+fn check_binding_types(
+  module: &naga::Module,
+  registry: &BindingRegistry,
+) -> Result<(), BindingError> {
+  for (handle, variable) in module.global_variables.iter() {
+    let Some(binding) = &variable.binding else {
+      continue;
+    };
+    let name = variable.name.as_deref().unwrap_or("<unnamed>");
+    // reflect_resources already made sure every binding is one of the engine's
+    let Some((resource, _)) = EngineResource::from_variable(name) else {
+      continue;
+    };
+    let Some(entries) = registry.get_entries(resource) else {
+      continue;
+    };
+    let span = module.global_variables.get_span(handle);
+
+    let entry = entries
+      .iter()
+      .find(|entry| entry.binding == binding.binding)
+      .ok_or_else(|| {
+        let message = format!(
+          "{} is @binding({}) of {:?}, but the engine's layout has nothing there",
+          name, binding.binding, resource
+        );
+        BindingError::new(message, span)
+      })?;
+
+    let expected = BindingShape::from_entry(&entry.ty);
+    let declared = BindingShape::from_global(module, variable);
+    if declared != expected {
+      let message = format!(
+        "{} is declared as {}, but the engine binds {} there",
+        name, declared, expected
+      );
+      return Err(BindingError::new(message, span));
+    }
+  }
+
+  Ok(())
+}
+
+/// the bind group layouts of one shader, in @group order
+#[derive(Clone)]
+pub struct MemoryLayouts {
+  binds: Vec<(EngineResource, wgpu::BindGroupLayout)>,
+}
+
+impl MemoryLayouts {
+  /// picks the layout for every group the shader uses out of the engine's registry
+  pub fn reflect(module: &naga::Module, registry: &BindingRegistry) -> Result<Self, BindingError> {
+    let mut binds: Vec<(EngineResource, wgpu::BindGroupLayout)> = Vec::new();
+    let resources = reflect_resources(module)?;
+    check_binding_types(module, registry)?;
+
+    for (resource, span) in resources {
+      let layout = registry.get_layout(resource).ok_or_else(|| {
+        let message = format!("{:?} isn't available for this shader", resource);
+        BindingError::new(message, span)
+      })?;
+
+      binds.push((resource, layout.clone()));
+    }
+
+    Ok(Self { binds })
   }
 
   pub fn collect_slice<'a>(&self) -> Vec<&BindGroupLayout> {
     let binding = &self.binds;
-    let layout: Vec<&wgpu::BindGroupLayout> = binding.iter().collect();
+    let layout: Vec<&wgpu::BindGroupLayout> = binding.iter().map(|(_, layout)| layout).collect();
     return layout;
   }
+
+  /// sets every bind group this shader uses, either the per shader ones or the per mesh ones.
+  /// gives back how many it set
+  pub fn set_bind_groups(
+    &self,
+    render_pass: &mut wgpu::RenderPass<'_>,
+    bind_groups: &BindGroupSet,
+    per_mesh: bool,
+  ) -> u32 {
+    let mut set = 0;
+    for (group, (resource, _)) in self.binds.iter().enumerate() {
+      if resource.is_per_mesh() != per_mesh {
+        continue;
+      }
+
+      match bind_groups.get_with_offset(*resource) {
+        Some((bind_group, offset)) => {
+          render_pass.set_bind_group(group as u32, bind_group, offset.as_slice());
+          set += 1;
+        }
+        None => log::error!("nothing to bind for {:?} in @group({})", resource, group),
+      }
+    }
+    set
+  }
+
+  /// only sets the groups that are in the set (and the shader uses), the others stay as they were
+  pub fn set_bind_groups_from(
+    &self,
+    render_pass: &mut wgpu::RenderPass<'_>,
+    bind_groups: &BindGroupSet,
+  ) -> u32 {
+    let mut set = 0;
+    for (group, (resource, _)) in self.binds.iter().enumerate() {
+      if let Some((bind_group, offset)) = bind_groups.get_with_offset(*resource) {
+        render_pass.set_bind_group(group as u32, bind_group, offset.as_slice());
+        set += 1;
+      }
+    }
+    set
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn global_shape(source: &str) -> BindingShape {
+    let module = naga::front::wgsl::parse_str(source).unwrap();
+    let (_, variable) = module.global_variables.iter().next().unwrap();
+    BindingShape::from_global(&module, variable)
+  }
+
+  #[test]
+  fn shader_globals_are_compared_with_the_layout_entries() {
+    let uniform = BindingShape::from_entry(&wgpu::BindingType::Buffer {
+      ty: wgpu::BufferBindingType::Uniform,
+      has_dynamic_offset: false,
+      min_binding_size: None,
+    });
+    let camera = "@group(0) @binding(0) var<uniform> camera: mat4x4<f32>;";
+    assert_eq!(global_shape(camera), uniform);
+
+    // the right name with the wrong type
+    let camera = "@group(0) @binding(0) var camera: texture_2d<f32>;";
+    assert_ne!(global_shape(camera), uniform);
+    assert_eq!(global_shape(camera).to_string(), "texture_2d<f32>");
+
+    // depth textures and comparison samplers have to be declared as such
+    let depth = BindingShape::from_entry(&wgpu::BindingType::Texture {
+      sample_type: wgpu::TextureSampleType::Depth,
+      view_dimension: wgpu::TextureViewDimension::D2,
+      multisampled: false,
+    });
+    let atlas = "@group(0) @binding(1) var shadow_atlas: texture_depth_2d;";
+    assert_eq!(global_shape(atlas), depth);
+    let sampler = "@group(0) @binding(2) var shadow_sampler: sampler;";
+    let comparison = wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison);
+    assert_ne!(global_shape(sampler), BindingShape::from_entry(&comparison));
+  }
 }
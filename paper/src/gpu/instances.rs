@@ -1,35 +1,70 @@
-use crate::gpu::geometry::VertexTrait;
+use wgpu::util::DeviceExt;
 
-// basically unused code, because i hate instances
+use crate::gpu::{device_drivers::Drivers, geometry::VertexTrait};
+use crate::maths::Vec3;
 
+// every mesh draws at least one instance, a mesh that was never given any
+// just draws a single Instance::default() so the object's location is all that moves it
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Instance {
   pub pos: cgmath::Vector3<f32>,
   pub rot: cgmath::Quaternion<f32>,
+  pub scale: cgmath::Vector3<f32>,
+  /// multiplied with the mesh's color, white leaves it alone
+  pub tint: [f32; 4],
+}
+
+impl Default for Instance {
+  fn default() -> Self {
+    Self {
+      pos: Vec3::new(0.0, 0.0, 0.0),
+      rot: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+      scale: Vec3::new(1.0, 1.0, 1.0),
+      tint: [1.0, 1.0, 1.0, 1.0],
+    }
+  }
 }
 
 impl Instance {
+  pub fn new(pos: Vec3) -> Self {
+    Self {
+      pos,
+      ..Default::default()
+    }
+  }
+
+  pub fn with_rotation(mut self, rot: cgmath::Quaternion<f32>) -> Self {
+    self.rot = rot;
+    self
+  }
+
+  pub fn with_scale(mut self, scale: Vec3) -> Self {
+    self.scale = scale;
+    self
+  }
+
+  pub fn with_tint(mut self, tint: [f32; 4]) -> Self {
+    self.tint = tint;
+    self
+  }
+
+  pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+    let scale = cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
+    cgmath::Matrix4::from_translation(self.pos) * cgmath::Matrix4::from(self.rot) * scale
+  }
+
   pub fn to_raw(&self) -> InstanceRaw {
     InstanceRaw {
-      model: (cgmath::Matrix4::from_translation(self.pos) * cgmath::Matrix4::from(self.rot)).into(),
+      model: self.to_matrix().into(),
+      tint: self.tint,
     }
   }
 }
 
-#[allow(unused)]
 impl VertexTrait for Instance {
   fn as_bytes(&self) -> Vec<u8> {
-    // TODO: make this actually do something.
-    let mut bytes = vec![];
-    let floats = [
-      self.pos.x,
-      self.pos.y,
-      self.pos.z,
-      self.rot.s,
-      self.rot.v.x,
-      self.rot.v.y,
-      self.rot.v.z,
-    ];
-    return bytes;
+    bytemuck::bytes_of(&self.to_raw()).to_vec()
   }
 
   fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -45,8 +80,7 @@ impl VertexTrait for Instance {
         // for each vec4. We'll have to reassemble the mat4 in the shader.
         wgpu::VertexAttribute {
           offset: 0,
-          // While our vertex shader only uses locations 0, and 1 now, in later tutorials, we'll
-          // be using 2, 3, and 4, for Vertex. We'll start at slot 5, not conflict with them later
+          // the mesh vertex gets locations 0 to 4, instances start at 5 (see InstanceInput in general.wgsl)
           shader_location: 5,
           format: wgpu::VertexFormat::Float32x4,
         },
@@ -65,6 +99,12 @@ impl VertexTrait for Instance {
           shader_location: 8,
           format: wgpu::VertexFormat::Float32x4,
         },
+        // tint
+        wgpu::VertexAttribute {
+          offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+          shader_location: 9,
+          format: wgpu::VertexFormat::Float32x4,
+        },
       ],
     }
   }
@@ -72,7 +112,98 @@ impl VertexTrait for Instance {
 
 // for feeding the stupid gpu it's stupid numbers stupid
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
   model: [[f32; 4]; 4],
+  tint: [f32; 4],
+}
+
+/// the gpu side of a mesh's instances, grows when it's given more than it can fit
+pub struct InstanceBuffer {
+  buffer: wgpu::Buffer,
+  count: u32,
+  capacity: u32,
+}
+
+impl InstanceBuffer {
+  fn to_raw_list(instances: &[Instance]) -> Vec<InstanceRaw> {
+    instances.iter().map(Instance::to_raw).collect()
+  }
+
+  fn create_buffer(device: &wgpu::Device, raw: &[InstanceRaw]) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Instance Buffer"),
+      contents: bytemuck::cast_slice(raw),
+      usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
+  }
+
+  pub fn new(device: &wgpu::Device, instances: &[Instance]) -> Self {
+    // a zero sized vertex buffer can't be bound, so there's always room for one
+    let mut raw = Self::to_raw_list(instances);
+    let count = raw.len() as u32;
+    if raw.is_empty() {
+      raw.push(Instance::default().to_raw());
+    }
+
+    Self {
+      buffer: Self::create_buffer(device, &raw),
+      count,
+      capacity: raw.len() as u32,
+    }
+  }
+
+  /// replaces every instance, has to happen before the frame is rendered
+  pub fn update(&mut self, drivers: &Drivers, instances: &[Instance]) {
+    let raw = Self::to_raw_list(instances);
+    self.count = raw.len() as u32;
+
+    if raw.is_empty() {
+      return;
+    }
+
+    if self.count > self.capacity {
+      self.buffer = Self::create_buffer(&drivers.device, &raw);
+      self.capacity = self.count;
+      return;
+    }
+
+    drivers
+      .queue
+      .write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw));
+  }
+
+  pub fn get_buffer(&self) -> &wgpu::Buffer {
+    &self.buffer
+  }
+
+  pub fn get_count(&self) -> u32 {
+    self.count
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn default_instance_is_identity() {
+    use cgmath::SquareMatrix;
+    let identity: [[f32; 4]; 4] = cgmath::Matrix4::identity().into();
+    let raw = Instance::default().to_raw();
+
+    assert_eq!(raw.model, identity);
+    assert_eq!(raw.tint, [1.0; 4]);
+  }
+
+  #[test]
+  fn scale_happens_before_moving() {
+    let raw = Instance::new(Vec3::new(1.0, 2.0, 3.0))
+      .with_scale(Vec3::new(2.0, 2.0, 2.0))
+      .to_raw();
+
+    assert_eq!(raw.model[0][0], 2.0);
+    // translation isn't scaled
+    assert_eq!(raw.model[3], [1.0, 2.0, 3.0, 1.0]);
+  }
 }
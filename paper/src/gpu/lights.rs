@@ -1,147 +1,523 @@
-use std::sync::Arc;
+// every light in the scene lives in one storage buffer that's bound to every mesh shader,
+// the shader loops over them (see shader_lib/lighting.wgsl). lights are just data now,
+// they don't draw anything themselves
 
-use wgpu::util::DeviceExt;
+use cgmath::InnerSpace;
 
-use crate::gpu::{
-  self, device_drivers::Drivers, mesh, object::{self, SharedLocation}, shaders::{RenderingBundle, ShaderPipeline}
+use crate::{
+  gpu::{
+    device_drivers::Drivers,
+    geometry::GetBufferLayout,
+    shadows::{self, ShadowCaster, ShadowSettings},
+    texture::DynamicTexture,
+  },
+  maths::Vec3,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+  /// shines in every direction from its position
+  Point,
+  /// infinitely far away, only the direction matters (the sun)
+  Directional,
+  /// a point light cut down to a cone, full brightness inside inner_angle
+  /// and fading out until outer_angle. both are half angles in radians
+  Spot { inner_angle: f32, outer_angle: f32 },
+}
+
+impl LightKind {
+  // has to match the LIGHT_ constants in lighting.wgsl
+  fn to_raw(self) -> u32 {
+    match self {
+      LightKind::Point => 0,
+      LightKind::Directional => 1,
+      LightKind::Spot { .. } => 2,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Light {
-  shader: ShaderPipeline,
-  location: SharedLocation,
-  light_buffer: wgpu::Buffer,
-  light_binding_layout: wgpu::BindGroupLayout,
-  light_binding: wgpu::BindGroup,
+  pub kind: LightKind,
+  /// ignored by directional lights
+  pub position: Vec3,
+  /// where the light points, ignored by point lights
+  pub direction: Vec3,
+  /// linear rgb
+  pub color: [f32; 3],
+  pub intensity: f32,
+  /// past this distance the light does nothing, 0 means it never runs out
+  pub range: f32,
+  /// None doesn't cast any, point lights never do
+  pub shadows: Option<ShadowSettings>,
 }
 
-// lib.rs
+impl Light {
+  fn new(kind: LightKind, position: Vec3, direction: Vec3, color: [f32; 3]) -> Self {
+    Self {
+      kind,
+      position,
+      direction,
+      color,
+      intensity: 1.0,
+      range: 0.0,
+      shadows: None,
+    }
+  }
+
+  pub fn point(position: Vec3, color: [f32; 3]) -> Self {
+    Self::new(LightKind::Point, position, Vec3::new(0.0, -1.0, 0.0), color)
+  }
+
+  pub fn directional(direction: Vec3, color: [f32; 3]) -> Self {
+    Self::new(
+      LightKind::Directional,
+      Vec3::new(0.0, 0.0, 0.0),
+      direction,
+      color,
+    )
+  }
+
+  /// the angles are half angles of the cone, in radians
+  pub fn spot(
+    position: Vec3,
+    direction: Vec3,
+    inner_angle: f32,
+    outer_angle: f32,
+    color: [f32; 3],
+  ) -> Self {
+    let kind = LightKind::Spot {
+      inner_angle,
+      outer_angle,
+    };
+    Self::new(kind, position, direction, color)
+  }
+
+  pub fn with_intensity(mut self, intensity: f32) -> Self {
+    self.intensity = intensity;
+    self
+  }
+
+  pub fn with_range(mut self, range: f32) -> Self {
+    self.range = range;
+    self
+  }
+
+  /// directional lights shadow a box around their position, see ShadowSettings::extent
+  pub fn with_shadows(mut self, settings: ShadowSettings) -> Self {
+    self.shadows = Some(settings);
+    self
+  }
+
+  /// caster is where the shadow went in the atlas, if it got a spot
+  fn to_raw(self, caster: Option<&ShadowCaster>, atlas_size: u32) -> LightRaw {
+    // the shader compares cosines, cheaper than taking the angle of every fragment
+    let (inner_cos, outer_cos) = match self.kind {
+      LightKind::Spot {
+        inner_angle,
+        outer_angle,
+      } => {
+        let outer = outer_angle.cos();
+        // a cone where inner == outer would divide by zero in the shader
+        (inner_angle.cos().max(outer + 0.0001), outer)
+      }
+      _ => (0.0, 0.0),
+    };
+
+    let direction = match self.direction.magnitude2() > 0.0 {
+      true => self.direction.normalize(),
+      false => Vec3::new(0.0, -1.0, 0.0),
+    };
+
+    let settings = self.shadows.unwrap_or_default();
+    let (shadow_rect, shadow_matrix) = match caster {
+      Some(caster) => (caster.tile.uv_rect(atlas_size), caster.view_proj.into()),
+      // an empty rect tells the shader there's no shadow
+      None => ([0.0; 4], [[0.0; 4]; 4]),
+    };
+
+    LightRaw {
+      position: self.position.into(),
+      kind: self.kind.to_raw(),
+      direction: direction.into(),
+      range: self.range.max(0.0),
+      color: self.color,
+      intensity: self.intensity,
+      inner_cos,
+      outer_cos,
+      depth_bias: settings.depth_bias,
+      normal_bias: settings.normal_bias,
+      shadow_rect,
+      shadow_matrix,
+    }
+  }
+}
+
+// same layout as Light in lighting.wgsl
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct LightUniform {
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
   position: [f32; 3],
-  _stupidfuckingpaddingbecausewgpuisstupidandneeds16bytealignmenttowork: u32,
+  kind: u32,
+  direction: [f32; 3],
+  range: f32,
   color: [f32; 3],
-  _thispaddingisbasedthoughbecauseitlikesteamfortress2: u32,
+  intensity: f32,
+  inner_cos: f32,
+  outer_cos: f32,
+  depth_bias: f32,
+  normal_bias: f32,
+  shadow_rect: [f32; 4],
+  shadow_matrix: [[f32; 4]; 4],
 }
 
-impl LightUniform {
-  pub fn from_location(loc: &object::Location, color: [f32; 3]) -> Self {
-    Self {
-      _stupidfuckingpaddingbecausewgpuisstupidandneeds16bytealignmenttowork: 0,
-      _thispaddingisbasedthoughbecauseitlikesteamfortress2: 0,
-      position: loc.pos.into(),
-      color,
-    }
+// sits in front of the light array in the buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightsHeader {
+  ambient: [f32; 3],
+  count: u32,
+}
+
+/// handed out by LightBuffer::add, stays valid until that light is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightId(usize);
+
+pub struct LightBuffer {
+  // removed lights leave a hole so every other LightId keeps pointing at the right light
+  lights: Vec<Option<Light>>,
+  ambient: [f32; 3],
+  // only upload when something changed
+  dirty: bool,
+
+  buffer: wgpu::Buffer,
+  bind_group: wgpu::BindGroup,
+  layout: wgpu::BindGroupLayout,
+  // how many lights fit in the buffer right now
+  capacity: u32,
+
+  // every shadow gets a square of this, it's bound right next to the lights
+  shadow_atlas: DynamicTexture,
+  shadow_casters: Vec<ShadowCaster>,
+}
+
+impl GetBufferLayout for LightBuffer {
+  fn get_bind_layout(&self) -> wgpu::BindGroupLayout {
+    self.layout.clone()
+  }
+
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+    Self::bind_entries()
   }
 }
 
-impl Light {
-  fn init_buffer(
-    drivers: &Drivers,
-    light_location: &object::Location,
-    color: [f32; 3],
-  ) -> wgpu::Buffer {
-    let light_uniform = LightUniform::from_location(light_location, color);
+impl LightBuffer {
+  const HEADER_SIZE: u64 = std::mem::size_of::<LightsHeader>() as u64;
+  const LIGHT_SIZE: u64 = std::mem::size_of::<LightRaw>() as u64;
+  const STARTING_CAPACITY: u32 = 16;
+  const DEFAULT_AMBIENT: [f32; 3] = [0.1, 0.1, 0.1];
+  const SHADOW_ATLAS_SIZE: u32 = 4096;
 
-    drivers
-      .device
-      .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Light VB"),
-        contents: bytemuck::cast_slice(&[light_uniform]),
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-      })
+  fn bind_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+    vec![
+      wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Storage { read_only: true },
+          has_dynamic_offset: false,
+          min_binding_size: wgpu::BufferSize::new(Self::HEADER_SIZE + Self::LIGHT_SIZE),
+        },
+        count: None,
+      },
+      wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+          sample_type: wgpu::TextureSampleType::Depth,
+          view_dimension: wgpu::TextureViewDimension::D2,
+          multisampled: false,
+        },
+        count: None,
+      },
+      wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+        count: None,
+      },
+    ]
   }
 
   fn init_bind_group_layout(drivers: &Drivers) -> wgpu::BindGroupLayout {
     drivers
       .device
       .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        entries: &[wgpu::BindGroupLayoutEntry {
-          binding: 0,
-          visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-          ty: wgpu::BindingType::Buffer {
-            ty: wgpu::BufferBindingType::Uniform,
-            has_dynamic_offset: false,
-            min_binding_size: None,
-          },
-          count: None,
-        }],
-        label: None,
+        entries: &Self::bind_entries(),
+        label: Some("lights_bind_group_layout"),
       })
   }
 
-  fn init_bind_group(
+  fn init_buffer(
     drivers: &Drivers,
-    buffer: &wgpu::Buffer,
     layout: &wgpu::BindGroupLayout,
-  ) -> wgpu::BindGroup {
-    drivers
+    capacity: u32,
+    shadow_atlas: &DynamicTexture,
+  ) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let buffer = drivers.device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Light Buffer"),
+      size: Self::HEADER_SIZE + Self::LIGHT_SIZE * capacity as u64,
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+
+    let bind_group = drivers
       .device
       .create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: layout,
-        entries: &[wgpu::BindGroupEntry {
-          binding: 0,
-          resource: buffer.as_entire_binding(),
-        }],
-        label: None,
-      })
+        layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::TextureView(&shadow_atlas.view),
+          },
+          wgpu::BindGroupEntry {
+            binding: 2,
+            resource: wgpu::BindingResource::Sampler(&shadow_atlas.sampler),
+          },
+        ],
+        label: Some("lights_bind_group"),
+      });
+
+    (buffer, bind_group)
   }
 
-  fn init_gpu_handles(
-    drivers: &Drivers,
-    light_location: &object::Location,
-    light_color: &[f32; 3],
-  ) -> (wgpu::Buffer, wgpu::BindGroup, wgpu::BindGroupLayout) {
-    let buffer = Self::init_buffer(drivers, light_location, light_color.clone());
-    let bind_layout = Self::init_bind_group_layout(drivers);
-    let bind_group = Self::init_bind_group(drivers, &buffer, &bind_layout);
-    return (buffer, bind_group, bind_layout);
+  pub fn new(drivers: &Drivers) -> Self {
+    let layout = Self::init_bind_group_layout(drivers);
+    let atlas_size = Self::SHADOW_ATLAS_SIZE.min(drivers.device.limits().max_texture_dimension_2d);
+    let shadow_atlas = DynamicTexture::create_shadow_atlas(drivers, atlas_size);
+    let (buffer, bind_group) =
+      Self::init_buffer(drivers, &layout, Self::STARTING_CAPACITY, &shadow_atlas);
+
+    Self {
+      lights: Vec::new(),
+      ambient: Self::DEFAULT_AMBIENT,
+      dirty: true,
+      buffer,
+      bind_group,
+      layout,
+      capacity: Self::STARTING_CAPACITY,
+      shadow_atlas,
+      shadow_casters: Vec::new(),
+    }
   }
 
-  fn init_shader_pipeline(
-    drivers: &Drivers,
-    shaders: &RenderingBundle,
-    shader_module: &wgpu::ShaderModule,
-    bind_layout: &wgpu::BindGroupLayout,
-  ) -> ShaderPipeline {
-    let meshes = shaders.get_meshes();
-
-    let mut bindgroup_data = gpu::gpu_pointers::MemoryLayouts::new();
-    bindgroup_data.add_bind_raw(bind_layout);
-
-    let render_pipeline = ShaderPipeline::init_render_pipeline(
-      &drivers.device,
-      shader_module,
-      &drivers.surface_config,
-      &bindgroup_data,
-    );
+  pub fn add(&mut self, light: Light) -> LightId {
+    self.dirty = true;
+    // reuse a hole if there is one
+    if let Some(slot) = self.lights.iter().position(Option::is_none) {
+      self.lights[slot] = Some(light);
+      return LightId(slot);
+    }
+    self.lights.push(Some(light));
+    LightId(self.lights.len() - 1)
+  }
+
+  pub fn remove(&mut self, id: LightId) -> Option<Light> {
+    let light = self.lights.get_mut(id.0)?.take();
+    self.dirty |= light.is_some();
+    light
+  }
+
+  /// removes every light, every LightId handed out so far is dead after this
+  pub fn clear(&mut self) {
+    self.lights.clear();
+    self.dirty = true;
+  }
+
+  pub fn get(&self, id: LightId) -> Option<&Light> {
+    self.lights.get(id.0)?.as_ref()
+  }
+
+  /// anything changed through this gets uploaded on the next frame
+  pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+    let light = self.lights.get_mut(id.0)?.as_mut()?;
+    self.dirty = true;
+    Some(light)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Light> {
+    self.lights.iter().flatten()
+  }
+
+  pub fn len(&self) -> usize {
+    self.iter().count()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// the light every surface gets no matter what, linear rgb
+  pub fn set_ambient(&mut self, color: [f32; 3]) {
+    self.ambient = color;
+    self.dirty = true;
+  }
+
+  #[inline]
+  pub fn get_ambient(&self) -> [f32; 3] {
+    self.ambient
+  }
 
-    ShaderPipeline {
-      render_pipeline,
-      meshes,
+  /// gives every light that wants a shadow a square of the atlas and a matrix to render it with
+  fn update_shadow_casters(&mut self) -> Vec<Option<ShadowCaster>> {
+    let atlas_size = self.get_shadow_atlas_size();
+    let wants_shadow: Vec<(&Light, ShadowSettings)> = self
+      .iter()
+      .filter_map(|light| Some((light, light.shadows?)))
+      .filter(|(light, _)| light.kind != LightKind::Point)
+      .collect();
+
+    let resolutions: Vec<u32> = wants_shadow.iter().map(|(_, s)| s.resolution).collect();
+    let tiles = shadows::pack_atlas(&resolutions, atlas_size);
+    if tiles.iter().any(Option::is_none) {
+      log::warn!("the shadow atlas is full, some lights won't cast shadows");
     }
+
+    let mut casters = wants_shadow.iter().zip(tiles);
+    let per_light: Vec<Option<ShadowCaster>> = self
+      .iter()
+      .map(|light| {
+        if light.shadows.is_none() || light.kind == LightKind::Point {
+          return None;
+        }
+        let ((_, settings), tile) = casters.next()?;
+        Some(ShadowCaster {
+          tile: tile?,
+          view_proj: shadows::shadow_view_proj(light, settings)?,
+        })
+      })
+      .collect();
+
+    self.shadow_casters = per_light.iter().flatten().copied().collect();
+    per_light
   }
 
-  pub fn add_mesh(&mut self, mesh: Arc<mesh::Mesh>) {
-    self.shader.meshes.push(mesh);
+  /// what ends up in the buffer, the header and then every light without the holes
+  fn to_bytes(&self, casters: &[Option<ShadowCaster>]) -> Vec<u8> {
+    let atlas_size = self.get_shadow_atlas_size();
+    let raw: Vec<LightRaw> = self
+      .iter()
+      .zip(casters)
+      .map(|(light, caster)| light.to_raw(caster.as_ref(), atlas_size))
+      .collect();
+    let header = LightsHeader {
+      ambient: self.ambient,
+      count: raw.len() as u32,
+    };
+
+    let mut bytes = bytemuck::bytes_of(&header).to_vec();
+    bytes.extend_from_slice(bytemuck::cast_slice(&raw));
+    bytes
   }
 
-  pub fn new_colored(
-    drivers: &Drivers, 
-    scene: &RenderingBundle, 
-    shader: wgpu::ShaderModule,
-    location: object::Location, 
-    color: [f32; 3],
-  ) -> Self {
-    let (buffer, bindgroup, grouplayout) = Self::init_gpu_handles(drivers, &location, &color);
-    let shader_pipeline = Self::init_shader_pipeline(drivers, scene, &shader, &grouplayout);
-    Self {
-      shader: shader_pipeline,
-      location: location.to_shared(),
-      light_buffer: buffer,
-      light_binding_layout: grouplayout,
-      light_binding: bindgroup,
+  /// uploads the lights if they changed since last time, growing the buffer if they don't fit
+  pub fn write(&mut self, drivers: &Drivers) {
+    if !self.dirty {
+      return;
     }
+
+    let needed = self.len() as u32;
+    if needed > self.capacity {
+      let capacity = needed.next_power_of_two();
+      (self.buffer, self.bind_group) =
+        Self::init_buffer(drivers, &self.layout, capacity, &self.shadow_atlas);
+      self.capacity = capacity;
+    }
+
+    let casters = self.update_shadow_casters();
+    drivers
+      .queue
+      .write_buffer(&self.buffer, 0, &self.to_bytes(&casters));
+    self.dirty = false;
+  }
+
+  pub fn get_bind_group(&self) -> &wgpu::BindGroup {
+    &self.bind_group
+  }
+
+  /// the shadows to render this frame, as of the last write
+  pub fn get_shadow_casters(&self) -> &[ShadowCaster] {
+    &self.shadow_casters
+  }
+
+  pub fn get_shadow_atlas(&self) -> &DynamicTexture {
+    &self.shadow_atlas
+  }
+
+  pub fn get_shadow_atlas_size(&self) -> u32 {
+    self.shadow_atlas.texture.width()
   }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lights_are_packed_like_the_shader_expects() {
+    // 16 byte aligned vec3's, see lighting.wgsl
+    assert_eq!(std::mem::size_of::<LightRaw>(), 144);
+    assert_eq!(std::mem::size_of::<LightsHeader>(), 16);
 
+    let spot = Light::spot(
+      Vec3::new(1.0, 2.0, 3.0),
+      Vec3::new(0.0, 0.0, -2.0),
+      0.2,
+      0.4,
+      [1.0, 0.5, 0.0],
+    )
+    .with_range(10.0)
+    .to_raw(None, 1024);
+
+    assert_eq!(spot.kind, 2);
+    assert_eq!(spot.direction, [0.0, 0.0, -1.0]);
+    assert_eq!(spot.range, 10.0);
+    assert!(spot.inner_cos > spot.outer_cos);
+    assert_eq!(spot.outer_cos, 0.4f32.cos());
+    // no caster, no shadow
+    assert_eq!(spot.shadow_rect, [0.0; 4]);
+  }
+
+  #[test]
+  fn shadows_land_in_their_atlas_square() {
+    let sun = Light::directional(Vec3::new(0.0, -1.0, 0.0), [1.0; 3]);
+    let caster = ShadowCaster {
+      tile: shadows::AtlasTile {
+        x: 1024,
+        y: 0,
+        size: 512,
+      },
+      view_proj: shadows::shadow_view_proj(&sun, &ShadowSettings::default()).unwrap(),
+    };
+    let raw = sun.to_raw(Some(&caster), 2048);
+    assert_eq!(raw.shadow_rect, [0.5, 0.0, 0.25, 0.25]);
+    assert_eq!(raw.depth_bias, ShadowSettings::default().depth_bias);
+  }
+
+  #[test]
+  fn a_spot_cone_without_a_falloff_still_has_one() {
+    let spot = Light::spot(
+      Vec3::new(0.0, 0.0, 0.0),
+      Vec3::new(1.0, 0.0, 0.0),
+      0.3,
+      0.3,
+      [1.0; 3],
+    );
+    let raw = spot.to_raw(None, 1024);
+    assert!(raw.inner_cos > raw.outer_cos);
+  }
 }
@@ -0,0 +1,130 @@
+// every object's transform for the frame lives in one uniform buffer,
+// each mesh picks its own slot out of it with a dynamic offset.
+// the buffer gets written once before the render pass, writing it per mesh
+// inside the pass made every mesh use whatever was written last
+
+use crate::gpu::{device_drivers::Drivers, object::LocationUniform};
+
+pub struct LocationBuffer {
+  buffer: wgpu::Buffer,
+  bind_group: wgpu::BindGroup,
+  layout: wgpu::BindGroupLayout,
+
+  // bytes between slots, dynamic offsets have to be aligned to the device's limit
+  stride: u32,
+  // how many slots fit in the buffer right now
+  capacity: u32,
+}
+
+impl LocationBuffer {
+  const LOCATION_SIZE: u64 = std::mem::size_of::<LocationUniform>() as u64;
+  const STARTING_CAPACITY: u32 = 64;
+
+  pub fn bind_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+    vec![wgpu::BindGroupLayoutEntry {
+      binding: 0,
+      visibility: wgpu::ShaderStages::VERTEX,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: true,
+        min_binding_size: wgpu::BufferSize::new(Self::LOCATION_SIZE),
+      },
+      count: None,
+    }]
+  }
+
+  fn init_bind_group_layout(drivers: &Drivers) -> wgpu::BindGroupLayout {
+    drivers
+      .device
+      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &Self::bind_entries(),
+        label: Some("object_position_bind_group_layout"),
+      })
+  }
+
+  fn init_buffer(
+    drivers: &Drivers,
+    layout: &wgpu::BindGroupLayout,
+    stride: u32,
+    capacity: u32,
+  ) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let buffer = drivers.device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Location Buffer"),
+      size: stride as u64 * capacity as u64,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+
+    // the binding only covers one slot, the dynamic offset moves it along the buffer
+    let bind_group = drivers
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(Self::LOCATION_SIZE),
+          }),
+        }],
+        label: Some("object_location_bind_group"),
+      });
+
+    (buffer, bind_group)
+  }
+
+  pub fn new(drivers: &Drivers) -> Self {
+    let alignment = drivers.device.limits().min_uniform_buffer_offset_alignment;
+    let stride = (Self::LOCATION_SIZE as u32).div_ceil(alignment) * alignment;
+
+    let layout = Self::init_bind_group_layout(drivers);
+    let (buffer, bind_group) = Self::init_buffer(drivers, &layout, stride, Self::STARTING_CAPACITY);
+
+    Self {
+      buffer,
+      bind_group,
+      layout,
+      stride,
+      capacity: Self::STARTING_CAPACITY,
+    }
+  }
+
+  /// writes every location in one go, slot i ends up at get_offset(i).
+  /// the buffer grows (and the bind group gets remade) if they don't fit
+  pub fn write(&mut self, drivers: &Drivers, locations: &[LocationUniform]) {
+    if locations.is_empty() {
+      return;
+    }
+
+    let needed = locations.len() as u32;
+    if needed > self.capacity {
+      let capacity = needed.next_power_of_two();
+      (self.buffer, self.bind_group) =
+        Self::init_buffer(drivers, &self.layout, self.stride, capacity);
+      self.capacity = capacity;
+    }
+
+    let mut bytes = vec![0u8; self.stride as usize * locations.len()];
+    for (slot, location) in locations.iter().enumerate() {
+      let start = slot * self.stride as usize;
+      let location_bytes = bytemuck::bytes_of(location);
+      bytes[start..start + location_bytes.len()].copy_from_slice(location_bytes);
+    }
+
+    drivers.queue.write_buffer(&self.buffer, 0, &bytes);
+  }
+
+  /// the dynamic offset for a slot written by write
+  pub fn get_offset(&self, slot: u32) -> u32 {
+    slot * self.stride
+  }
+
+  pub fn get_bind_group(&self) -> &wgpu::BindGroup {
+    &self.bind_group
+  }
+
+  pub fn get_layout(&self) -> &wgpu::BindGroupLayout {
+    &self.layout
+  }
+}
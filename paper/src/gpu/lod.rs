@@ -0,0 +1,132 @@
+// cheaper versions of an object for when it's far away. level 0 is the object's own meshes,
+// every level after it takes over once the object is smaller than its screen size
+
+use std::sync::Arc;
+
+use crate::gpu::{bounds::Bounds, camera::Camera, mesh::Mesh};
+
+/// how far past a level's screen size the object has to get before it switches, as a fraction of it.
+/// stops objects right on the edge from flicking back and forth between two levels
+pub const DEFAULT_HYSTERESIS: f32 = 0.1;
+
+/// the screen size the first lod level takes over at, every level after halves it
+pub const DEFAULT_FIRST_LOD_SIZE: f32 = 0.5;
+
+/// "table.obj" and 2 is "table_lod2.obj"
+pub fn lod_file_name(file_name: &str, level: usize) -> String {
+  match file_name.rsplit_once('.') {
+    Some((stem, extension)) => format!("{}_lod{}.{}", stem, level, extension),
+    None => format!("{}_lod{}", file_name, level),
+  }
+}
+
+pub struct LodLevel {
+  pub meshes: Vec<Arc<Mesh>>,
+  /// drawn once the object takes up less than this much of the screen's height
+  pub screen_size: f32,
+}
+
+impl LodLevel {
+  pub fn new(meshes: Vec<Arc<Mesh>>, screen_size: f32) -> Self {
+    Self {
+      meshes,
+      screen_size,
+    }
+  }
+}
+
+/// which level to draw, starting from the current one. screen_sizes[i] is where level i + 1 takes over,
+/// biggest first, and the size has to get past it by the hysteresis to switch either way
+pub fn select_level(
+  screen_sizes: &[f32],
+  current: usize,
+  screen_size: f32,
+  hysteresis: f32,
+) -> usize {
+  let mut level = current.min(screen_sizes.len());
+  while level < screen_sizes.len() && screen_size < screen_sizes[level] * (1.0 - hysteresis) {
+    level += 1;
+  }
+  while level > 0 && screen_size > screen_sizes[level - 1] * (1.0 + hysteresis) {
+    level -= 1;
+  }
+  level
+}
+
+/// every level of one object, and the one that got drawn last frame
+pub(crate) struct LodGroup {
+  // every mesh with the shader it's drawn with
+  levels: Vec<Vec<(usize, Arc<Mesh>)>>,
+  screen_sizes: Vec<f32>,
+  current: usize,
+}
+
+impl LodGroup {
+  /// shader_for picks the shader each mesh gets drawn with
+  pub fn new(meshes: &[Arc<Mesh>], lods: &[LodLevel], shader_for: impl Fn(&Mesh) -> usize) -> Self {
+    let mut lods: Vec<&LodLevel> = lods.iter().collect();
+    lods.sort_by(|a, b| b.screen_size.total_cmp(&a.screen_size));
+
+    let with_shaders = |meshes: &[Arc<Mesh>]| {
+      meshes
+        .iter()
+        .map(|mesh| (shader_for(mesh), mesh.clone()))
+        .collect::<Vec<_>>()
+    };
+    let mut levels = vec![with_shaders(meshes)];
+    levels.extend(lods.iter().map(|lod| with_shaders(&lod.meshes)));
+    Self {
+      levels,
+      screen_sizes: lods.iter().map(|lod| lod.screen_size).collect(),
+      current: 0,
+    }
+  }
+
+  #[inline]
+  pub fn get_current(&self) -> usize {
+    self.current
+  }
+
+  /// picks the level from how big level 0 looks from the camera, objects that can't
+  /// be bounded (skinned ones) stay on whatever level they're on
+  pub fn select(&mut self, camera: &Camera, hysteresis: f32) -> &[(usize, Arc<Mesh>)] {
+    let bounds = self.levels[0]
+      .iter()
+      .map(|(_, mesh)| mesh.get_world_bounds())
+      .collect::<Option<Vec<Bounds>>>()
+      .and_then(|bounds| bounds.into_iter().reduce(|a, b| a.union(&b)));
+
+    if let Some(bounds) = bounds {
+      let screen_size = camera.get_screen_size(&bounds.sphere);
+      self.current = select_level(&self.screen_sizes, self.current, screen_size, hysteresis);
+    }
+    &self.levels[self.current]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn levels_only_switch_past_the_hysteresis() {
+    let sizes = [0.5, 0.25];
+    assert_eq!(select_level(&sizes, 0, 1.0, 0.1), 0);
+    assert_eq!(select_level(&sizes, 0, 0.1, 0.1), 2);
+    // just under the first switch isn't enough to leave level 0
+    assert_eq!(select_level(&sizes, 0, 0.48, 0.1), 0);
+    assert_eq!(select_level(&sizes, 0, 0.44, 0.1), 1);
+    // and just over it isn't enough to go back
+    assert_eq!(select_level(&sizes, 1, 0.52, 0.1), 1);
+    assert_eq!(select_level(&sizes, 1, 0.56, 0.1), 0);
+    // without any lods there's only level 0
+    assert_eq!(select_level(&[], 3, 0.01, 0.1), 0);
+  }
+
+  #[test]
+  fn lod_files_are_named_after_the_original() {
+    assert_eq!(lod_file_name("table.obj", 1), "table_lod1.obj");
+    assert_eq!(lod_file_name("cars/truck.obj", 2), "cars/truck_lod2.obj");
+    assert_eq!(lod_file_name("table", 1), "table_lod1");
+  }
+}
@@ -1,12 +1,559 @@
+use std::sync::{
+  atomic::{self, AtomicU64},
+  Arc,
+};
+
+use wgpu::util::DeviceExt;
+
+use crate::gpu::{
+  device_drivers::Drivers,
+  texture::{ColorSpace, ImageTexture, TextureBundle},
+};
+
+/// how a material's color gets onto what's already been drawn.
+/// every mode gets its own pipeline, the blend state and depth writes are baked into it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+  /// covers whatever's behind it, alpha is ignored
+  #[default]
+  Opaque,
+  /// mixed with what's behind by its alpha (glass, water, smoke)
+  AlphaBlend,
+  /// added on top of what's behind, scaled by its alpha (fire, glows, lasers)
+  Additive,
+  /// like AlphaBlend, but the color was already multiplied by the alpha
+  Premultiplied,
+  /// opaque, but pixels under the alpha cutoff get thrown away (leaves, fences)
+  AlphaCutout,
+}
+
+impl BlendMode {
+  pub fn get_blend_state(&self) -> wgpu::BlendState {
+    match self {
+      BlendMode::Opaque | BlendMode::AlphaCutout => wgpu::BlendState::REPLACE,
+      BlendMode::AlphaBlend => wgpu::BlendState::ALPHA_BLENDING,
+      BlendMode::Additive => wgpu::BlendState {
+        color: wgpu::BlendComponent {
+          src_factor: wgpu::BlendFactor::SrcAlpha,
+          dst_factor: wgpu::BlendFactor::One,
+          operation: wgpu::BlendOperation::Add,
+        },
+        // whatever alpha was there stays
+        alpha: wgpu::BlendComponent {
+          src_factor: wgpu::BlendFactor::Zero,
+          dst_factor: wgpu::BlendFactor::One,
+          operation: wgpu::BlendOperation::Add,
+        },
+      },
+      BlendMode::Premultiplied => wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+    }
+  }
+
+  /// drawn in the transparent pass after everything opaque, back to front
+  #[inline]
+  pub fn is_transparent(&self) -> bool {
+    matches!(
+      self,
+      BlendMode::AlphaBlend | BlendMode::Additive | BlendMode::Premultiplied
+    )
+  }
+
+  /// transparent things don't hide what's drawn behind them after
+  #[inline]
+  pub fn writes_depth(&self) -> bool {
+    !self.is_transparent()
+  }
+}
+
+/// the scalar bits of a material, straight out of the .mtl (Kd, Ks, Ns, d)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialParams {
+  pub diffuse_color: [f32; 3],
+  pub specular_color: [f32; 3],
+  pub shininess: f32,
+  /// 1.0 is fully opaque
+  pub opacity: f32,
+  pub blend_mode: BlendMode,
+  /// only for AlphaCutout, pixels with less alpha than this are thrown away
+  pub alpha_cutoff: f32,
+}
+
+impl Default for MaterialParams {
+  fn default() -> Self {
+    Self {
+      diffuse_color: [1.0, 1.0, 1.0],
+      specular_color: [0.0, 0.0, 0.0],
+      shininess: 32.0,
+      opacity: 1.0,
+      blend_mode: BlendMode::Opaque,
+      alpha_cutoff: 0.5,
+    }
+  }
+}
+
+impl MaterialParams {
+  /// anything the .mtl leaves out keeps its default, a d under 1 makes it alpha blended
+  pub fn from_mtl(mtl: &tobj::Material) -> Self {
+    let defaults = Self::default();
+    let opacity = mtl.dissolve.unwrap_or(defaults.opacity);
+    let blend_mode = match opacity < 1.0 {
+      true => BlendMode::AlphaBlend,
+      false => BlendMode::Opaque,
+    };
+    Self {
+      diffuse_color: mtl.diffuse.unwrap_or(defaults.diffuse_color),
+      specular_color: mtl.specular.unwrap_or(defaults.specular_color),
+      shininess: mtl.shininess.unwrap_or(defaults.shininess),
+      opacity,
+      blend_mode,
+      ..defaults
+    }
+  }
+
+  /// gltf materials are metallic/roughness, this gets as close as blinn-phong params can
+  pub fn from_gltf(material: &gltf::Material) -> Self {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, a] = pbr.base_color_factor();
+    let metallic = pbr.metallic_factor();
+    // a perfect mirror would need an infinite exponent
+    let roughness = pbr.roughness_factor().clamp(0.05, 1.0);
+    let alpha = roughness * roughness;
+
+    // metals tint their highlights, everything else reflects about 4% white
+    let specular = |channel: f32| 0.04 + (channel - 0.04) * metallic;
+
+    let blend_mode = match material.alpha_mode() {
+      gltf::material::AlphaMode::Opaque => BlendMode::Opaque,
+      gltf::material::AlphaMode::Mask => BlendMode::AlphaCutout,
+      gltf::material::AlphaMode::Blend => BlendMode::AlphaBlend,
+    };
+
+    Self {
+      diffuse_color: [r, g, b],
+      specular_color: [specular(r), specular(g), specular(b)],
+      shininess: (2.0 / (alpha * alpha) - 2.0).max(1.0),
+      opacity: a,
+      blend_mode,
+      // gltf's default cutoff is the same as ours
+      alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+    }
+  }
+
+  fn to_uniform(&self) -> MaterialUniform {
+    let [r, g, b] = self.diffuse_color;
+    MaterialUniform {
+      diffuse: [r, g, b, self.opacity],
+      specular: self.specular_color,
+      shininess: self.shininess,
+      alpha_cutoff: self.alpha_cutoff,
+      _padding: [0.0; 3],
+    }
+  }
+}
+
+// matches MaterialUniform in shader_lib/material.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+  diffuse: [f32; 4],
+  specular: [f32; 3],
+  shininess: f32,
+  alpha_cutoff: f32,
+  _padding: [f32; 3],
+}
+
+#[derive(Clone)]
+pub struct MaterialTextures {
+  pub diffuse: Arc<ImageTexture>,
+  pub normal: Arc<ImageTexture>,
+  pub specular: Arc<ImageTexture>,
+}
+
+/// every new bind group gets a new one, clones share it since they share the bind group.
+/// draws get sorted by it and only bind the material again when it changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialId(u64);
+
+impl MaterialId {
+  pub fn next() -> Self {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    Self(NEXT.fetch_add(1, atomic::Ordering::Relaxed))
+  }
+}
+
+/// everything a mesh needs to be shaded, all in one bind group:
+/// diffuse, normal and specular textures (binding 0 to 5) and the params uniform (binding 6)
 #[derive(Clone)]
 pub struct Material {
-  pub diffuse_texture: wgpu::BindGroup,
+  pub name: String,
+  id: MaterialId,
+  params: MaterialParams,
+  textures: MaterialTextures,
+
+  uniform_buffer: wgpu::Buffer,
+  layout: wgpu::BindGroupLayout,
+  bind_group: wgpu::BindGroup,
 }
 
 impl Material {
-  pub fn new_basic(diffuse: wgpu::BindGroup) -> Self {
+  pub fn bind_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+    let texture_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+      binding,
+      visibility: wgpu::ShaderStages::FRAGMENT,
+      ty: wgpu::BindingType::Texture {
+        multisampled: false,
+        view_dimension: wgpu::TextureViewDimension::D2,
+        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+      },
+      count: None,
+    };
+    let sampler_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+      binding,
+      visibility: wgpu::ShaderStages::FRAGMENT,
+      ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+      count: None,
+    };
+
+    vec![
+      texture_entry(0),
+      sampler_entry(1),
+      texture_entry(2),
+      sampler_entry(3),
+      texture_entry(4),
+      sampler_entry(5),
+      wgpu::BindGroupLayoutEntry {
+        binding: 6,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      },
+    ]
+  }
+
+  pub fn create_bind_group_layout(drivers: &Drivers) -> wgpu::BindGroupLayout {
+    drivers
+      .device
+      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &Self::bind_entries(),
+        label: Some("material_bind_group_layout"),
+      })
+  }
+
+  fn create_bind_group(
+    drivers: &Drivers,
+    layout: &wgpu::BindGroupLayout,
+    textures: &MaterialTextures,
+    uniform_buffer: &wgpu::Buffer,
+  ) -> wgpu::BindGroup {
+    drivers
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&textures.diffuse.view),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(&textures.diffuse.sampler),
+          },
+          wgpu::BindGroupEntry {
+            binding: 2,
+            resource: wgpu::BindingResource::TextureView(&textures.normal.view),
+          },
+          wgpu::BindGroupEntry {
+            binding: 3,
+            resource: wgpu::BindingResource::Sampler(&textures.normal.sampler),
+          },
+          wgpu::BindGroupEntry {
+            binding: 4,
+            resource: wgpu::BindingResource::TextureView(&textures.specular.view),
+          },
+          wgpu::BindGroupEntry {
+            binding: 5,
+            resource: wgpu::BindingResource::Sampler(&textures.specular.sampler),
+          },
+          wgpu::BindGroupEntry {
+            binding: 6,
+            resource: uniform_buffer.as_entire_binding(),
+          },
+        ],
+        label: Some("material_bind_group"),
+      })
+  }
+
+  pub fn new(
+    drivers: &Drivers,
+    texture_bundle: &TextureBundle,
+    name: &str,
+    params: MaterialParams,
+    textures: MaterialTextures,
+  ) -> Self {
+    let uniform_buffer = drivers
+      .device
+      .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Material Buffer"),
+        contents: bytemuck::cast_slice(&[params.to_uniform()]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      });
+
+    let layout = texture_bundle.get_material_layout().clone();
+    let bind_group = Self::create_bind_group(drivers, &layout, &textures, &uniform_buffer);
+
     Self {
-      diffuse_texture: diffuse,
+      name: name.to_owned(),
+      id: MaterialId::next(),
+      params,
+      textures,
+      uniform_buffer,
+      layout,
+      bind_group,
+    }
+  }
+
+  /// just a diffuse texture, what meshes without a .mtl get
+  pub fn new_basic(
+    drivers: &Drivers,
+    texture_bundle: &TextureBundle,
+    diffuse: Arc<ImageTexture>,
+  ) -> Self {
+    let textures = MaterialTextures {
+      diffuse,
+      normal: texture_bundle.get_flat_normal_texture(),
+      specular: texture_bundle.get_white_texture(),
+    };
+    Self::new(
+      drivers,
+      texture_bundle,
+      "basic",
+      MaterialParams::default(),
+      textures,
+    )
+  }
+
+  /// builds a material out of a .mtl entry, textures are loaded through the bundle
+  /// (relative to the images folder). missing textures get logged and replaced, not returned as errors
+  pub fn from_mtl(
+    drivers: &Drivers,
+    texture_bundle: &mut TextureBundle,
+    mtl: &tobj::Material,
+  ) -> Self {
+    let params = MaterialParams::from_mtl(mtl);
+
+    // without a texture the diffuse is only the Kd color, so white lets it through untouched
+    let white = texture_bundle.get_white_texture();
+    let flat_normal = texture_bundle.get_flat_normal_texture();
+    let fallback = texture_bundle.get_fallback_texture();
+
+    let mut load = |file: &Option<String>, color_space: ColorSpace, missing: Arc<ImageTexture>| {
+      let Some(file) = file else {
+        return missing;
+      };
+      match Self::load_mtl_texture(drivers, texture_bundle, file, color_space) {
+        Ok(texture) => texture,
+        Err(error) => {
+          log::warn!("material {} couldn't load {}: {}", mtl.name, file, error);
+          fallback.clone()
+        }
+      }
+    };
+
+    let textures = MaterialTextures {
+      diffuse: load(&mtl.diffuse_texture, ColorSpace::Srgb, white.clone()),
+      normal: load(&mtl.normal_texture, ColorSpace::Linear, flat_normal),
+      specular: load(&mtl.specular_texture, ColorSpace::Linear, white),
+    };
+
+    Self::new(drivers, texture_bundle, &mtl.name, params, textures)
+  }
+
+  fn load_mtl_texture(
+    drivers: &Drivers,
+    texture_bundle: &mut TextureBundle,
+    file: &str,
+    color_space: ColorSpace,
+  ) -> anyhow::Result<Arc<ImageTexture>> {
+    // blender likes to write paths relative to wherever the .blend was,
+    // so fall back to just the file name inside the images folder
+    texture_bundle
+      .load_texture(drivers, file, color_space)
+      .or_else(|error| {
+        let file_name = std::path::Path::new(file)
+          .file_name()
+          .and_then(|name| name.to_str())
+          .filter(|name| *name != file)
+          .ok_or(error)?;
+        texture_bundle.load_texture(drivers, file_name, color_space)
+      })
+  }
+
+  /// same material, different diffuse texture
+  pub fn with_diffuse(&self, drivers: &Drivers, diffuse: Arc<ImageTexture>) -> Self {
+    let textures = MaterialTextures {
+      diffuse,
+      ..self.textures.clone()
+    };
+    self.with_textures(drivers, textures)
+  }
+
+  /// same material, every texture sampled with this (see TextureBundle::get_sampler)
+  pub fn with_sampler(&self, drivers: &Drivers, sampler: &wgpu::Sampler) -> Self {
+    let resample = |texture: &Arc<ImageTexture>| Arc::new(texture.with_sampler(sampler.clone()));
+    let textures = MaterialTextures {
+      diffuse: resample(&self.textures.diffuse),
+      normal: resample(&self.textures.normal),
+      specular: resample(&self.textures.specular),
+    };
+    self.with_textures(drivers, textures)
+  }
+
+  /// same textures, other params. unlike set_params the copy gets its own uniform buffer,
+  /// so whatever else shares the original isn't touched
+  pub fn with_params(&self, drivers: &Drivers, params: MaterialParams) -> Self {
+    let mut material = self.clone();
+    material.params = params;
+    material.with_textures(drivers, self.textures.clone())
+  }
+
+  fn with_textures(&self, drivers: &Drivers, textures: MaterialTextures) -> Self {
+    let mut material = self.clone();
+    material.textures = textures;
+    // the uniform buffer is shared with the original, so the params need their own copy
+    material.uniform_buffer =
+      drivers
+        .device
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+          label: Some("Material Buffer"),
+          contents: bytemuck::cast_slice(&[material.params.to_uniform()]),
+          usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+    material.bind_group = Self::create_bind_group(
+      drivers,
+      &material.layout,
+      &material.textures,
+      &material.uniform_buffer,
+    );
+    material.id = MaterialId::next();
+    material
+  }
+
+  pub fn get_params(&self) -> &MaterialParams {
+    &self.params
+  }
+
+  /// the blend mode is baked into the pipeline when the mesh is added to the scene,
+  /// changing it afterwards won't do anything (use with_params before adding it)
+  pub fn set_params(&mut self, drivers: &Drivers, params: MaterialParams) {
+    self.params = params;
+    drivers.queue.write_buffer(
+      &self.uniform_buffer,
+      0,
+      bytemuck::cast_slice(&[params.to_uniform()]),
+    );
+  }
+
+  #[inline]
+  pub fn get_blend_mode(&self) -> BlendMode {
+    self.params.blend_mode
+  }
+
+  #[inline]
+  pub fn get_id(&self) -> MaterialId {
+    self.id
+  }
+
+  pub fn get_bind_group(&self) -> &wgpu::BindGroup {
+    &self.bind_group
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mtl_colors_end_up_in_the_uniform() {
+    let mtl_text = "newmtl Red\nKd 1.0 0.0 0.0\nKs 0.5 0.5 0.5\nNs 10.0\nd 0.25\n";
+    let (materials, _) =
+      tobj::load_mtl_buf(&mut std::io::BufReader::new(mtl_text.as_bytes())).unwrap();
+    let uniform = MaterialParams::from_mtl(&materials[0]).to_uniform();
+
+    assert_eq!(uniform.diffuse, [1.0, 0.0, 0.0, 0.25]);
+    assert_eq!(uniform.specular, [0.5, 0.5, 0.5]);
+    assert_eq!(uniform.shininess, 10.0);
+    // anything see through gets blended
+    assert_eq!(
+      MaterialParams::from_mtl(&materials[0]).blend_mode,
+      BlendMode::AlphaBlend
+    );
+    // vec4, vec3 + f32, then the cutoff padded out to 16 bytes
+    assert_eq!(std::mem::size_of::<MaterialUniform>(), 48);
+  }
+
+  #[test]
+  fn gltf_roughness_turns_into_shininess() {
+    let json = r#"{
+      "asset": { "version": "2.0" },
+      "materials": [
+        { "pbrMetallicRoughness": { "baseColorFactor": [0.5, 0.5, 0.5, 0.5], "metallicFactor": 0.0, "roughnessFactor": 1.0 } },
+        { "pbrMetallicRoughness": { "metallicFactor": 1.0, "roughnessFactor": 0.2 }, "alphaMode": "MASK", "alphaCutoff": 0.3 }
+      ]
+    }"#;
+    let gltf = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+    let materials: Vec<_> = gltf
+      .materials()
+      .map(|m| MaterialParams::from_gltf(&m))
+      .collect();
+
+    assert_eq!(materials[0].diffuse_color, [0.5, 0.5, 0.5]);
+    assert_eq!(materials[0].opacity, 0.5);
+    assert_eq!(materials[0].specular_color, [0.04, 0.04, 0.04]);
+    // smoother is shinier, metals reflect their own color
+    assert!(materials[1].shininess > materials[0].shininess);
+    assert_eq!(materials[1].specular_color, [1.0, 1.0, 1.0]);
+    assert_eq!(materials[0].blend_mode, BlendMode::Opaque);
+    assert_eq!(materials[1].blend_mode, BlendMode::AlphaCutout);
+    assert_eq!(materials[1].alpha_cutoff, 0.3);
+  }
+
+  #[test]
+  fn only_transparent_modes_blend_and_skip_depth() {
+    assert_eq!(
+      BlendMode::Opaque.get_blend_state(),
+      wgpu::BlendState::REPLACE
+    );
+    assert_eq!(
+      BlendMode::AlphaCutout.get_blend_state(),
+      wgpu::BlendState::REPLACE
+    );
+    assert!(BlendMode::AlphaCutout.writes_depth());
+
+    for mode in [
+      BlendMode::AlphaBlend,
+      BlendMode::Additive,
+      BlendMode::Premultiplied,
+    ] {
+      assert!(mode.is_transparent() && !mode.writes_depth());
+      assert_ne!(mode.get_blend_state(), wgpu::BlendState::REPLACE);
     }
+    assert_eq!(
+      BlendMode::Additive.get_blend_state().color.dst_factor,
+      wgpu::BlendFactor::One
+    );
+  }
+
+  #[test]
+  fn missing_mtl_values_keep_the_defaults() {
+    let (materials, _) =
+      tobj::load_mtl_buf(&mut std::io::BufReader::new("newmtl Empty\n".as_bytes())).unwrap();
+    assert_eq!(
+      MaterialParams::from_mtl(&materials[0]),
+      MaterialParams::default()
+    );
   }
 }
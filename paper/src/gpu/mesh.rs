@@ -1,14 +1,20 @@
-use std::sync::Arc;
+use std::{
+  cell::{Cell, RefCell},
+  rc::Rc,
+  sync::Arc,
+};
 
 use wgpu::{util::DeviceExt, RenderPass};
+use crate::gpu::bounds::Bounds;
 use crate::gpu::geometry::{Vertex, vertex_list_as_bytes};
-use crate::gpu::object::SharedLocation;
-use crate::gpu::render::{self, RenderTask};
+use crate::maths::Vec3;
+use crate::gpu::gpu_pointers::{BindGroupSet, EngineResource, MemoryLayouts};
+use crate::gpu::object::{Location, LocationUniform, SharedLocation};
 use crate::{
-  engine,
   gpu::{
     device_drivers::Drivers,
-    //instances::{self, Instance},
+    animation::SkinWeights,
+    instances::{Instance, InstanceBuffer},
     material::Material,
   },
 };
@@ -16,16 +22,50 @@ use crate::{
 pub struct MeshBuilder {
   vertices: Vec<Vertex>,
   indicies: Vec<u32>,
+  instances: Vec<Instance>,
+  skin: Option<SkinWeights>,
+  bounds: Option<Bounds>,
 }
 
 impl MeshBuilder {
   pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+    let bounds = Self::compute_bounds(&vertices);
     Self {
       vertices,
       indicies: indices,
+      // one instance that doesn't move anything
+      instances: vec![Instance::default()],
+      skin: None,
+      bounds,
     }
   }
 
+  fn compute_bounds(vertices: &[Vertex]) -> Option<Bounds> {
+    let positions = vertices
+      .iter()
+      .map(|vertex| vertex.get_position().map(Vec3::from))
+      .collect::<Option<Vec<Vec3>>>()?;
+    Bounds::from_points(&positions)
+  }
+
+  /// for vertices that get moved around in the shader, so the ones worked out from them are wrong
+  pub fn set_bounds(mut self, bounds: Bounds) -> Self {
+    self.bounds = Some(bounds);
+    self
+  }
+
+  /// the joints and weights for each vertex, the skin itself is kept on the object
+  pub fn set_skin(mut self, skin: SkinWeights) -> Self {
+    self.skin = Some(skin);
+    self
+  }
+
+  /// draws the mesh once per instance, on top of the object's own location
+  pub fn set_instances(mut self, instances: Vec<Instance>) -> Self {
+    self.instances = instances;
+    self
+  }
+
   pub fn build(
     self,
     drivers: &Drivers,
@@ -44,6 +84,12 @@ pub struct Mesh {
   num_indicies: u32,
   material: Material,
   shared_location: SharedLocation,
+  // shared between clones, same as the location
+  instances: Rc<RefCell<InstanceBuffer>>,
+  skin: Option<Arc<SkinWeights>>,
+  // around the vertices, and around every instance of them
+  bounds: Option<Bounds>,
+  instanced_bounds: Rc<Cell<Option<Bounds>>>,
 }
 
 impl Mesh {
@@ -51,6 +97,23 @@ impl Mesh {
     self.material = new_material;
   }
 
+  #[inline]
+  pub fn get_material<'a>(&'a self) -> &'a Material {
+    &self.material
+  }
+
+  #[inline]
+  pub fn get_skin(&self) -> Option<&SkinWeights> {
+    self.skin.as_deref()
+  }
+
+  /// for when the mesh's object ends up with skins from more than one file
+  pub(crate) fn offset_skin(&mut self, skin_offset: usize) {
+    if let Some(skin) = self.skin.as_mut() {
+      Arc::make_mut(skin).skin += skin_offset;
+    }
+  }
+
   fn create_vertex_buffer(mesh_builder: &MeshBuilder, device: &wgpu::Device) -> wgpu::Buffer {
     let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
       label: Some("Vertex Buffer"),
@@ -78,7 +141,9 @@ impl Mesh {
     let vertex_buffer = Self::create_vertex_buffer(&mesh_builder, device);
     let index_buffer = Self::create_index_buffer(&mesh_builder, device);
 
-    // let instance_buffer = Self::add_optional_instances(&mesh_builder, device);
+    let instances = InstanceBuffer::new(device, &mesh_builder.instances);
+    let instanced_bounds =
+      Self::compute_instanced_bounds(mesh_builder.bounds, &mesh_builder.instances);
 
     Self {
       vertex_buffer,
@@ -86,119 +151,122 @@ impl Mesh {
       num_indicies: mesh_builder.indicies.len() as u32,
       material,
       shared_location: object_location,
+      instances: Rc::new(RefCell::new(instances)),
+      skin: mesh_builder.skin.map(Arc::new),
+      bounds: mesh_builder.bounds,
+      instanced_bounds: Rc::new(Cell::new(instanced_bounds)),
     }
   }
 
-  const TEXTURE_BINDGROUP: u32 = 0;
-  const CAMERA_TRANSFORM_BINDGROUP: u32 = 1;
-  const TIME_BINDGROUP: u32 = 2;
-  const LOCATION_BINDGROUP: u32 = 3;
-  fn set_universal_bind_groups(render_pass: &mut RenderPass<'_>, engine: &engine::Engine) {
-    // set the camera transform
-    render_pass.set_bind_group(
-      Self::CAMERA_TRANSFORM_BINDGROUP,
-      &engine.camera.camera_bind_group,
-      &[],
-    );
+  fn compute_instanced_bounds(bounds: Option<Bounds>, instances: &[Instance]) -> Option<Bounds> {
+    let bounds = bounds?;
+    instances
+      .iter()
+      .map(|instance| bounds.transformed(&instance.to_matrix()))
+      .reduce(|a, b| a.union(&b))
+  }
 
-    // current time
-    render_pass.set_bind_group(Self::TIME_BINDGROUP, &engine.gpu_time.bindgroup, &[]);
+  /// replaces every instance of this mesh, call it before the frame gets rendered
+  pub fn update_instances(&self, drivers: &Drivers, instances: &[Instance]) {
+    self.instances.borrow_mut().update(drivers, instances);
+    self
+      .instanced_bounds
+      .set(Self::compute_instanced_bounds(self.bounds, instances));
   }
 
-  fn set_local_bind_groups(&self, render_pass: &mut RenderPass<'_>, engine: &engine::Engine) {
-    // dynamic position
-    RenderTask::write_to_buffer(
-      engine,
-      engine.render_task.get_location_buffer(),
-      &[self.shared_location.get_location_ref().to_uniform()],
-    );
-    render_pass.set_bind_group(
-      Self::LOCATION_BINDGROUP,
-      engine.render_task.get_location_bindgroup(),
-      &[],
-    );
+  /// around the vertices as they were built, before any instance or location moves them
+  #[inline]
+  pub fn get_bounds(&self) -> Option<Bounds> {
+    self.bounds
+  }
+
+  /// around every instance, wherever the object is right now.
+  /// skinned meshes don't have any, their joints can move the vertices anywhere
+  pub fn get_world_bounds(&self) -> Option<Bounds> {
+    if self.skin.is_some() {
+      return None;
+    }
+    let location = self.shared_location.get_location_ref().to_matrix();
+    self
+      .instanced_bounds
+      .get()
+      .map(|bounds| bounds.transformed(&location))
+  }
+
+  pub fn get_instance_count(&self) -> u32 {
+    self.instances.borrow().get_count()
+  }
+
+  #[inline]
+  pub fn get_location(&self) -> Location {
+    *self.shared_location.get_location_ref()
+  }
+
+  pub fn get_location_uniform(&self) -> LocationUniform {
+    self.shared_location.get_location_ref().to_uniform()
+  }
 
-    // set the diffuse texture
-    render_pass.set_bind_group(Self::TEXTURE_BINDGROUP, &self.material.diffuse_texture, &[]);
+  /// the material's textures and params, meshes drawn one after another with the same
+  /// material only need it set once. gives back how many bind groups it set
+  pub fn bind_material(&self, bindgroups: &MemoryLayouts, render_pass: &mut RenderPass<'_>) -> u32 {
+    let mut mesh_groups = BindGroupSet::new();
+    mesh_groups.set(EngineResource::Material, self.material.get_bind_group());
+    bindgroups.set_bind_groups_from(render_pass, &mesh_groups)
   }
 
-  fn set_bind_groups(&self, render_pass: &mut RenderPass<'_>, engine: &engine::Engine) {
-    Self::set_universal_bind_groups(render_pass, engine);
-    self.set_local_bind_groups(render_pass, engine);
+  fn bind_location(
+    &self,
+    render_pass: &mut RenderPass<'_>,
+    bindgroups: &MemoryLayouts,
+    location_bindgroup: &wgpu::BindGroup,
+    location_offset: u32,
+  ) -> u32 {
+    let mut mesh_groups = BindGroupSet::new();
+    // every mesh's location is in the same buffer, the offset picks out this one
+    mesh_groups.set_dynamic(
+      EngineResource::ObjectLocation,
+      location_bindgroup,
+      location_offset,
+    );
+    bindgroups.set_bind_groups_from(render_pass, &mesh_groups)
   }
 
   fn set_geometry_buffers(&self, render_pass: &mut RenderPass<'_>) {
     render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+    render_pass.set_vertex_buffer(1, self.instances.borrow().get_buffer().slice(..));
     render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
   }
 
   fn submit_for_rendering(&self, render_pass: &mut RenderPass<'_>) {
-    render_pass.draw_indexed(0..self.num_indicies, 0, 0..1);
+    let instance_count = self.get_instance_count();
+    render_pass.draw_indexed(0..self.num_indicies, 0, 0..instance_count);
   }
 
-  pub fn render_meshes(
-    meshes: &Vec<Arc<Self>>,
+  /// draws the mesh, the shader's universal bind groups (camera, time, etc.) have to be set already.
+  /// the location offset comes from RenderTask::prepare_frame
+  pub fn render_mesh(
+    &self,
+    bindgroups: &MemoryLayouts,
     render_pass: &mut RenderPass<'_>,
-    engine: &engine::Engine,
+    location_bindgroup: &wgpu::BindGroup,
+    location_offset: u32,
   ) {
-    Self::set_universal_bind_groups(render_pass, engine);
-
-    for mesh in meshes {
-      mesh.set_local_bind_groups(render_pass, engine);
-      mesh.set_geometry_buffers(render_pass);
-      mesh.submit_for_rendering(render_pass);
-    }
+    self.bind_material(bindgroups, render_pass);
+    self.draw(bindgroups, render_pass, location_bindgroup, location_offset);
   }
 
-  pub fn render_mesh(&self, render_pass: &mut RenderPass<'_>, engine: &engine::Engine) {
+  /// the same as render_mesh, but the material has to be bound already (see bind_material).
+  /// gives back how many bind groups it set
+  pub fn draw(
+    &self,
+    bindgroups: &MemoryLayouts,
+    render_pass: &mut RenderPass<'_>,
+    location_bindgroup: &wgpu::BindGroup,
+    location_offset: u32,
+  ) -> u32 {
+    let set = self.bind_location(render_pass, bindgroups, location_bindgroup, location_offset);
     self.set_geometry_buffers(render_pass);
-    self.set_bind_groups(render_pass, engine);
     self.submit_for_rendering(render_pass);
+    set
   }
 }
-
-// leaving this dead instance code here
-
-// let instance_range = 0..1;
-// if let Some(instance) = &self.instance_buffer {
-//   render_pass.set_vertex_buffer(1, instance.slice(..));
-//   render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-//   unsafe { instance_range = 0..self.instances.as_ref().unwrap_unchecked().len() as _; }
-// } else {
-//   // this fix makes it not crash if there isnt instances, but its absolitely amazingly stupid to leave it like this
-//   render_pass.set_vertex_buffer(1, self.vertex_buffer.slice(..));
-//   render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-//   instance_range = 0..1;
-// }
-
-// i've disabled instances for now, because it hate dealing with them, and i don't
-// see myself using this any time soon for the engine, also i hate them. did i mention i hate them?
-// #[allow(unused)]
-// fn create_instance_buffer(mesh_builder: &MeshBuilder, device: &wgpu::Device) -> wgpu::Buffer {
-//   let instance_data: Vec<instances::InstanceRaw> = mesh_builder
-//     .instances
-//     .as_ref()
-//     .unwrap()
-//     .iter()
-//     .map(|x| x.to_raw())
-//     .collect();
-//   let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-//     label: Some("Instance Buffer"),
-//     contents: bytemuck::cast_slice(&instance_data),
-//     usage: wgpu::BufferUsages::VERTEX,
-//   });
-//   instance_buffer
-// }
-// #[allow(unused)]
-// fn add_optional_instances(
-//   mesh_builder: &MeshBuilder,
-//   device: &wgpu::Device,
-// ) -> Option<wgpu::Buffer> {
-//   let instance_buffer;
-//   if mesh_builder.instances.is_some() {
-//     instance_buffer = Some(Self::create_instance_buffer(&mesh_builder, device));
-//   } else {
-//     instance_buffer = None;
-//   }
-//   return instance_buffer;
-// }
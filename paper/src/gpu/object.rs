@@ -5,9 +5,14 @@ use std::sync::Arc;
 
 use crate::files::{self, load_obj_str};
 use crate::gpu::device_drivers::Drivers;
-use crate::gpu::geometry::{ModelVertex, Vertex, VertexTrait};
-use crate::gpu::texture::TextureBundle;
-use crate::gpu::{material, mesh};
+use crate::gpu::geometry::{compute_tangents, ModelVertex, Vertex, VertexTrait};
+use crate::gpu::animation::{Animation, SceneNode, Skin};
+use crate::gpu::bounds::Bounds;
+use crate::gpu::instances::Instance;
+use crate::gpu::texture::{ImageTexture, SamplerSettings, TextureBundle};
+use crate::gpu::lod::{self, LodLevel};
+use crate::gpu::material::{BlendMode, MaterialParams};
+use crate::gpu::{gltf_loader, material, mesh};
 use crate::maths::Vec3;
 
 pub type Rot = cgmath::Quaternion<f32>;
@@ -40,7 +45,7 @@ pub struct Location {
 }
 
 impl Location {
-  pub fn to_uniform(&self) -> LocationUniform {
+  pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
     use cgmath::Matrix4;
 
     // convert quaternion into a rotation matrix
@@ -50,11 +55,13 @@ impl Location {
     let trans_mat = Matrix4::from_translation(self.pos);
 
     // combine them: translate * rotate (scale can go here too if needed)
-    let model = trans_mat * rot_mat;
+    trans_mat * rot_mat
+  }
 
+  pub fn to_uniform(&self) -> LocationUniform {
     // convert into [[f32; 4]; 4] for uniforms
     LocationUniform {
-      location_projection: model.into(),
+      location_projection: self.to_matrix().into(),
     }
   }
 
@@ -121,6 +128,12 @@ impl SharedLocation {
 pub struct Object {
   pub meshes: Vec<Arc<mesh::Mesh>>,
   pub shared_location: SharedLocation,
+  // only gltf files fill these in, obj files don't have them
+  pub nodes: Vec<SceneNode>,
+  pub skins: Vec<Skin>,
+  pub animations: Vec<Animation>,
+  /// cheaper meshes for when it's far away, meshes is level 0
+  pub lods: Vec<LodLevel>,
 }
 
 impl Object3D for Object {
@@ -139,10 +152,18 @@ fn load_string(file_name: &str) -> anyhow::Result<String> {
 
 pub struct ObjectBuilder {
   meshes: Vec<mesh::Mesh>,
+  nodes: Vec<SceneNode>,
+  skins: Vec<Skin>,
+  animations: Vec<Animation>,
 
-  diffuse: Option<wgpu::BindGroup>,
+  diffuse: Option<Arc<ImageTexture>>,
+  sampler: Option<wgpu::Sampler>,
+  blend_mode: Option<BlendMode>,
 
   global_location: SharedLocation,
+  instances: Option<Vec<Instance>>,
+  // each level's meshes and the screen size it takes over at
+  lods: Vec<(Vec<mesh::Mesh>, f32)>,
 }
 
 impl ObjectBuilder {
@@ -159,16 +180,86 @@ impl ObjectBuilder {
   pub fn new() -> Self {
     Self {
       meshes: Vec::new(),
+      nodes: Vec::new(),
+      skins: Vec::new(),
+      animations: Vec::new(),
       diffuse: None,
+      sampler: None,
+      blend_mode: None,
       global_location: Location::new_world_origin().to_shared(),
+      instances: None,
+      lods: Vec::new(),
     }
   }
 
-  pub fn add_diffuse_texture(mut self, diffuse: wgpu::BindGroup) -> Self {
+  /// overrides the diffuse texture of every mesh, the rest of each mesh's material stays.
+  /// meshes loaded after this get it too
+  pub fn add_diffuse_texture(mut self, drivers: &Drivers, diffuse: Arc<ImageTexture>) -> Self {
+    let diffuse = match &self.sampler {
+      Some(sampler) => Arc::new(diffuse.with_sampler(sampler.clone())),
+      None => diffuse,
+    };
+    Self::apply_diffuse(&mut self.meshes, drivers, &diffuse);
+    for (meshes, _) in self.lods.iter_mut() {
+      Self::apply_diffuse(meshes, drivers, &diffuse);
+    }
     self.diffuse = Some(diffuse);
     self
   }
 
+  fn apply_diffuse(meshes: &mut [mesh::Mesh], drivers: &Drivers, diffuse: &Arc<ImageTexture>) {
+    meshes.iter_mut().for_each(|mesh| {
+      let material = mesh.get_material().with_diffuse(drivers, diffuse.clone());
+      mesh.change_material(material);
+    });
+  }
+
+  /// samples every texture of every mesh with these settings, meshes loaded after this
+  /// and the diffuse override too
+  pub fn set_sampler(
+    mut self,
+    drivers: &Drivers,
+    texture_bundle: &mut TextureBundle,
+    settings: SamplerSettings,
+  ) -> Self {
+    let sampler = texture_bundle.get_sampler(drivers, settings);
+    Self::apply_sampler(&mut self.meshes, drivers, &sampler);
+    for (meshes, _) in self.lods.iter_mut() {
+      Self::apply_sampler(meshes, drivers, &sampler);
+    }
+    self.sampler = Some(sampler);
+    self
+  }
+
+  fn apply_sampler(meshes: &mut [mesh::Mesh], drivers: &Drivers, sampler: &wgpu::Sampler) {
+    meshes.iter_mut().for_each(|mesh| {
+      let material = mesh.get_material().with_sampler(drivers, sampler);
+      mesh.change_material(material);
+    });
+  }
+
+  /// draws every mesh with this blend mode instead of whatever its material asked for,
+  /// meshes loaded after this get it too
+  pub fn set_blend_mode(mut self, drivers: &Drivers, blend_mode: BlendMode) -> Self {
+    Self::apply_blend_mode(&mut self.meshes, drivers, blend_mode);
+    for (meshes, _) in self.lods.iter_mut() {
+      Self::apply_blend_mode(meshes, drivers, blend_mode);
+    }
+    self.blend_mode = Some(blend_mode);
+    self
+  }
+
+  fn apply_blend_mode(meshes: &mut [mesh::Mesh], drivers: &Drivers, blend_mode: BlendMode) {
+    meshes.iter_mut().for_each(|mesh| {
+      let params = MaterialParams {
+        blend_mode,
+        ..*mesh.get_material().get_params()
+      };
+      let material = mesh.get_material().with_params(drivers, params);
+      mesh.change_material(material);
+    });
+  }
+
   pub fn set_location(mut self, location: Location) -> Self {
     self.global_location = location.to_shared();
     self
@@ -179,34 +270,205 @@ impl ObjectBuilder {
     self
   }
 
+  /// every mesh loaded after this gets drawn once per instance,
+  /// same as the location it has to be set before loading the meshes
+  pub fn set_instances(mut self, instances: Vec<Instance>) -> Self {
+    self.instances = Some(instances);
+    self
+  }
+
+  /// loads every model in the .obj, materials come from its .mtl (textures get loaded into the bundle)
   pub fn load_meshes_from_objfile(
     mut self,
-    texture_bundle: &TextureBundle,
+    texture_bundle: &mut TextureBundle,
     drivers: &Drivers,
     file_name: &str,
   ) -> anyhow::Result<Self> {
-    let shared_location = self.global_location.clone();
-    let object = Object::from_obj_file(texture_bundle, drivers, file_name, &shared_location)?;
+    let object = self.load_obj_meshes(texture_bundle, drivers, file_name)?;
     self.meshes.extend(object);
     Ok(self)
   }
 
-  pub fn build(mut self) -> Object {
-    Self::when_some(self.diffuse, |diffuse_bind| {
-      let material = material::Material::new_basic(diffuse_bind);
-      self.meshes.iter_mut().for_each(|mesh| {
-        mesh.change_material(material.clone());
-      });
+  /// the same as load_meshes_from_objfile, and then every lod next to it that follows the
+  /// naming (table.obj, table_lod1.obj, table_lod2.obj...). each level takes over at half
+  /// the screen size of the one before it
+  pub fn load_meshes_from_objfile_with_lods(
+    self,
+    texture_bundle: &mut TextureBundle,
+    drivers: &Drivers,
+    file_name: &str,
+  ) -> anyhow::Result<Self> {
+    let mut builder = self.load_meshes_from_objfile(texture_bundle, drivers, file_name)?;
+    let mut screen_size = lod::DEFAULT_FIRST_LOD_SIZE;
+    for level in 1.. {
+      let lod_file = lod::lod_file_name(file_name, level);
+      if !files::obj_exists(&lod_file) {
+        break;
+      }
+      builder = builder.add_lod_from_objfile(texture_bundle, drivers, &lod_file, screen_size)?;
+      screen_size *= 0.5;
+    }
+    Ok(builder)
+  }
+
+  /// another level of detail, drawn instead of the object's meshes once it takes up less than
+  /// screen_size of the screen's height. it gets the same location, instances, diffuse and sampler
+  pub fn add_lod_from_objfile(
+    mut self,
+    texture_bundle: &mut TextureBundle,
+    drivers: &Drivers,
+    file_name: &str,
+    screen_size: f32,
+  ) -> anyhow::Result<Self> {
+    let meshes = self.load_obj_meshes(texture_bundle, drivers, file_name)?;
+    self.lods.push((meshes, screen_size));
+    Ok(self)
+  }
+
+  fn load_obj_meshes(
+    &self,
+    texture_bundle: &mut TextureBundle,
+    drivers: &Drivers,
+    file_name: &str,
+  ) -> anyhow::Result<Vec<mesh::Mesh>> {
+    let shared_location = self.global_location.clone();
+    let mut object = Object::from_obj_file(
+      texture_bundle,
+      drivers,
+      file_name,
+      &shared_location,
+      self.instances.as_deref(),
+    )?;
+    self.apply_overrides(&mut object, drivers);
+    Ok(object)
+  }
+
+  /// the diffuse, sampler and blend mode that were set before the meshes got added
+  fn apply_overrides(&self, meshes: &mut [mesh::Mesh], drivers: &Drivers) {
+    Self::when_some(self.diffuse.as_ref(), |diffuse| {
+      Self::apply_diffuse(meshes, drivers, diffuse);
+    });
+    Self::when_some(self.sampler.as_ref(), |sampler| {
+      Self::apply_sampler(meshes, drivers, sampler);
+    });
+    Self::when_some(self.blend_mode, |blend_mode| {
+      Self::apply_blend_mode(meshes, drivers, blend_mode);
+    });
+  }
+
+  /// a mesh made in code instead of loaded from a file. it gets the object's location and
+  /// instances, and the same overrides as loaded meshes
+  pub fn add_mesh(
+    mut self,
+    drivers: &Drivers,
+    mesh: mesh::MeshBuilder,
+    material: material::Material,
+  ) -> anyhow::Result<Self> {
+    let mesh = match &self.instances {
+      Some(instances) => mesh.set_instances(instances.clone()),
+      None => mesh,
+    };
+    let mut meshes = [mesh.build(drivers, material, self.global_location.clone())?];
+    self.apply_overrides(&mut meshes, drivers);
+    self.meshes.extend(meshes);
+    Ok(self)
+  }
+
+  /// loads a .gltf or .glb from the gltf folder, with its node hierarchy, materials,
+  /// embedded textures, skins and animations
+  pub fn load_meshes_from_gltf(
+    mut self,
+    texture_bundle: &mut TextureBundle,
+    drivers: &Drivers,
+    file_name: &str,
+  ) -> anyhow::Result<Self> {
+    let shared_location = self.global_location.clone();
+    let mut scene = gltf_loader::load_gltf(
+      texture_bundle,
+      drivers,
+      file_name,
+      &shared_location,
+      self.instances.as_deref(),
+    )?;
+    Self::when_some(self.diffuse.as_ref(), |diffuse| {
+      Self::apply_diffuse(&mut scene.meshes, drivers, diffuse);
+    });
+    Self::when_some(self.sampler.as_ref(), |sampler| {
+      Self::apply_sampler(&mut scene.meshes, drivers, sampler);
+    });
+    Self::when_some(self.blend_mode, |blend_mode| {
+      Self::apply_blend_mode(&mut scene.meshes, drivers, blend_mode);
     });
 
+    // the scene's indices start at 0, anything loaded before it pushes them along
+    let (mesh_offset, node_offset, skin_offset) =
+      (self.meshes.len(), self.nodes.len(), self.skins.len());
+    for node in scene.nodes.iter_mut() {
+      node.parent = node.parent.map(|parent| parent + node_offset);
+      node
+        .children
+        .iter_mut()
+        .for_each(|child| *child += node_offset);
+      node.meshes.iter_mut().for_each(|mesh| *mesh += mesh_offset);
+    }
+    for skin in scene.skins.iter_mut() {
+      skin
+        .joints
+        .iter_mut()
+        .for_each(|joint| *joint += node_offset);
+    }
+    for animation in scene.animations.iter_mut() {
+      animation
+        .channels
+        .iter_mut()
+        .for_each(|channel| channel.node += node_offset);
+    }
+    for mesh in scene.meshes.iter_mut() {
+      mesh.offset_skin(skin_offset);
+    }
+
+    self.meshes.extend(scene.meshes);
+    self.nodes.extend(scene.nodes);
+    self.skins.extend(scene.skins);
+    self.animations.extend(scene.animations);
+    Ok(self)
+  }
+
+  pub fn build(self) -> Object {
     Object {
       meshes: Self::arcify_vec(self.meshes),
-      shared_location: Location::new_world_origin().to_shared(),
+      shared_location: self.global_location,
+      nodes: self.nodes,
+      skins: self.skins,
+      animations: self.animations,
+      lods: self
+        .lods
+        .into_iter()
+        .map(|(meshes, screen_size)| LodLevel::new(Self::arcify_vec(meshes), screen_size))
+        .collect(),
     }
   }
 }
 
 impl Object {
+  /// replaces the instances of every mesh in the object, call it before the frame gets rendered
+  pub fn update_instances(&self, drivers: &Drivers, instances: &[Instance]) {
+    let lod_meshes = self.lods.iter().flat_map(|lod| lod.meshes.iter());
+    for mesh in self.meshes.iter().chain(lod_meshes) {
+      mesh.update_instances(drivers, instances);
+    }
+  }
+
+  /// the world space bounds around every mesh, None if one of them can't be bounded (skinned meshes)
+  pub fn get_world_bounds(&self) -> Option<Bounds> {
+    let bounds = self
+      .meshes
+      .iter()
+      .map(|mesh| mesh.get_world_bounds())
+      .collect::<Option<Vec<Bounds>>>()?;
+    bounds.into_iter().reduce(|a, b| a.union(&b))
+  }
+
   pub fn extract_meshes(self) -> Vec<mesh::Mesh> {
     let mut extracted = Vec::with_capacity(self.meshes.capacity());
     self
@@ -217,10 +479,11 @@ impl Object {
   }
 
   fn from_obj_file(
-    texture_bundle: &TextureBundle,
+    texture_bundle: &mut TextureBundle,
     drivers: &Drivers,
     filename: &str,
     shared_location: &SharedLocation,
+    instances: Option<&[Instance]>,
   ) -> anyhow::Result<Vec<mesh::Mesh>> {
     use std::io::{BufReader, Cursor};
 
@@ -229,77 +492,102 @@ impl Object {
     let obj_reader = BufReader::new(obj_cursor);
 
     let (models, obj_materials) = Self::get_file_info(obj_reader)?;
-    let obj_materials = obj_materials?;
+    // a broken or missing .mtl isn't worth failing the whole object over, the meshes just get the fallback
+    let obj_materials = obj_materials.unwrap_or_else(|error| {
+      log::warn!("couldn't load the materials for {}: {}", filename, error);
+      Vec::new()
+    });
 
-    Self::load_materials(obj_materials)?;
+    let materials = Self::load_materials(texture_bundle, drivers, &obj_materials);
 
-    let meshes = Self::load_meshes(texture_bundle, drivers, models, shared_location);
+    let meshes = Self::load_meshes(
+      texture_bundle,
+      drivers,
+      models,
+      &materials,
+      shared_location,
+      instances,
+    );
 
     Ok(meshes)
   }
 
   fn obj_to_vertexes(m: &tobj::Model) -> Vec<Box<dyn VertexTrait>> {
-    let vertices: Vec<Vertex> = (0..m.mesh.positions.len() / 3)
+    let vertex_count = m.mesh.positions.len() / 3;
+    let positions: Vec<[f32; 3]> = m
+      .mesh
+      .positions
+      .chunks_exact(3)
+      .map(|p| [p[0], p[1], p[2]])
+      .collect();
+    let tex_coords: Vec<[f32; 2]> = m
+      .mesh
+      .texcoords
+      .chunks_exact(2)
+      .map(|uv| [uv[0], 1.0 - uv[1]])
+      .collect();
+    let normals: Vec<[f32; 3]> = m
+      .mesh
+      .normals
+      .chunks_exact(3)
+      .map(|n| [n[0], n[1], n[2]])
+      .collect();
+
+    // obj files don't store tangents, so they're worked out from the uvs
+    let tangents = compute_tangents(&positions, &tex_coords, &normals, &m.mesh.indices);
+
+    (0..vertex_count)
       .map(|i| {
-        if m.mesh.normals.is_empty() {
-          Box::new(ModelVertex {
-            pos: [
-              m.mesh.positions[i * 3],
-              m.mesh.positions[i * 3 + 1],
-              m.mesh.positions[i * 3 + 2],
-            ],
-            tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-            normal: [0.0, 0.0, 0.0],
-          })
-        } else {
-          Box::new(ModelVertex {
-            pos: [
-              m.mesh.positions[i * 3],
-              m.mesh.positions[i * 3 + 1],
-              m.mesh.positions[i * 3 + 2],
-            ],
-            tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-            normal: [
-              m.mesh.normals[i * 3],
-              m.mesh.normals[i * 3 + 1],
-              m.mesh.normals[i * 3 + 2],
-            ],
-          })
-        }
+        Box::new(ModelVertex {
+          pos: positions[i],
+          tex_coords: tex_coords.get(i).copied().unwrap_or([0.0, 0.0]),
+          normal: normals.get(i).copied().unwrap_or([0.0, 0.0, 0.0]),
+          tangent: tangents[i],
+        }) as Vertex
       })
-      .map(|v: Box<ModelVertex>| v as Vertex)
-      .collect();
-    vertices
+      .collect()
   }
 
-  /// basically does nothing right now
-  #[allow(unused)]
-  fn load_materials(obj_materials: Vec<tobj::Material>) -> anyhow::Result<()> {
-    for m in obj_materials {
-      let diffuse_texture_name = m.diffuse_texture;
-    }
-
-    Ok(())
+  /// one Material per .mtl entry, in the same order so a model's material_id indexes it
+  fn load_materials(
+    texture_bundle: &mut TextureBundle,
+    drivers: &Drivers,
+    obj_materials: &[tobj::Material],
+  ) -> Vec<material::Material> {
+    obj_materials
+      .iter()
+      .map(|m| material::Material::from_mtl(drivers, texture_bundle, m))
+      .collect()
   }
 
   fn load_meshes(
     texture_bundle: &TextureBundle,
     drivers: &Drivers,
     models: Vec<tobj::Model>,
+    materials: &[material::Material],
     shared_location: &SharedLocation,
+    instances: Option<&[Instance]>,
   ) -> Vec<mesh::Mesh> {
     let meshes = models
       .into_iter()
       .map(|m| {
         let vertices = Self::obj_to_vertexes(&m);
 
-        let fallback_texture_binds = texture_bundle
-          .get_fallback_texture()
-          .diffuse_bind_group
-          .clone();
-        let material = material::Material::new_basic(fallback_texture_binds);
+        let material = match m.mesh.material_id.and_then(|id| materials.get(id)) {
+          Some(material) => material.clone(),
+          None => material::Material::new_basic(
+            drivers,
+            texture_bundle,
+            texture_bundle.get_fallback_texture(),
+          ),
+        };
+
+        let mut mesh_builder = mesh::MeshBuilder::new(vertices, m.mesh.indices);
+        if let Some(instances) = instances {
+          mesh_builder = mesh_builder.set_instances(instances.to_vec());
+        }
 
-        let mesh = mesh::MeshBuilder::new(vertices, m.mesh.indices)
+        let mesh = mesh_builder
           .build(drivers, material, shared_location.clone())
           .unwrap();
 
@@ -327,7 +615,7 @@ impl Object {
       },
       move |p| {
         let filename = OsStr::to_str(p.file_name().unwrap()).unwrap();
-        let mat_text = load_string(filename).unwrap();
+        let mat_text = load_string(filename).map_err(|_| tobj::LoadError::OpenFileFailed)?;
         tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
       },
     )?;
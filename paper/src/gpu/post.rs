@@ -0,0 +1,613 @@
+// fullscreen effects that run on the finished scene, one after the other.
+// the scene renders into the hdr attachment, every effect reads the last result and writes
+// the next one (bouncing between hdr and post_scratch), and present copies it onto the screen.
+// effects live in assets/shaders/post and get hot reloaded like every other shader
+
+use crate::{
+  files::{preprocessor::SourceMap, shader_watcher::ShaderChange},
+  gpu::{
+    camera::Camera,
+    device_drivers::Drivers,
+    geometry::GetBufferLayout,
+    gpu_pointers::{BindingRegistry, EngineResource, MemoryLayouts},
+    render_graph::{self, PassContext},
+    shaders::{
+      validation::{ShaderError, ShaderErrorKind},
+      ShaderBuilder,
+    },
+    texture::DynamicTexture,
+  },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+  Reinhard,
+  /// the filmic curve most games use, punchier than reinhard
+  Aces,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PostEffect {
+  /// lines where the depth jumps (silhouettes) or bends (creases)
+  Outline {
+    color: [f32; 3],
+    /// in pixels
+    thickness: f32,
+    /// how big a jump in depth has to be, relative to how far away it is
+    depth_threshold: f32,
+    /// how sharp a bend has to be to count as a crease
+    crease_threshold: f32,
+  },
+  /// cuts every channel down to a few shades
+  Posterize { levels: f32 },
+  /// posterize, with an ordered (bayer) pattern to fake the shades in between
+  Dither { levels: f32, pixel_size: f32 },
+  /// bright things glow onto their surroundings
+  Bloom {
+    /// how bright (luminance) a pixel has to be before it glows
+    threshold: f32,
+    intensity: f32,
+    /// in pixels
+    radius: f32,
+  },
+  /// squashes the hdr colors back into what a screen can show
+  Tonemap {
+    exposure: f32,
+    tonemapper: Tonemapper,
+  },
+  /// any other shader in assets/shaders/post, params end up in post_settings.params
+  Custom {
+    shader: String,
+    params: [[f32; 4]; 2],
+  },
+}
+
+impl PostEffect {
+  pub fn outline() -> Self {
+    PostEffect::Outline {
+      color: [0.0, 0.0, 0.0],
+      thickness: 1.0,
+      depth_threshold: 0.1,
+      crease_threshold: 0.05,
+    }
+  }
+
+  pub fn bloom() -> Self {
+    PostEffect::Bloom {
+      threshold: 1.0,
+      intensity: 0.6,
+      radius: 12.0,
+    }
+  }
+
+  pub fn tonemap() -> Self {
+    PostEffect::Tonemap {
+      exposure: 1.0,
+      tonemapper: Tonemapper::Aces,
+    }
+  }
+
+  /// relative to the shaders folder
+  pub fn get_shader_file(&self) -> String {
+    let name = match self {
+      PostEffect::Outline { .. } => "outline.wgsl",
+      PostEffect::Posterize { .. } => "posterize.wgsl",
+      PostEffect::Dither { .. } => "dither.wgsl",
+      PostEffect::Bloom { .. } => "bloom.wgsl",
+      PostEffect::Tonemap { .. } => "tonemap.wgsl",
+      PostEffect::Custom { shader, .. } => shader,
+    };
+    format!("{}/{}", PostStack::SHADER_FOLDER, name)
+  }
+
+  /// what the shader reads out of post_settings.params, each shader says what goes where
+  fn get_params(&self) -> [[f32; 4]; 2] {
+    match self {
+      PostEffect::Outline {
+        color,
+        thickness,
+        depth_threshold,
+        crease_threshold,
+      } => [
+        [color[0], color[1], color[2], 1.0],
+        [*thickness, *depth_threshold, *crease_threshold, 0.0],
+      ],
+      PostEffect::Posterize { levels } => [[*levels, 0.0, 0.0, 0.0], [0.0; 4]],
+      PostEffect::Dither { levels, pixel_size } => [[*levels, *pixel_size, 0.0, 0.0], [0.0; 4]],
+      PostEffect::Bloom {
+        threshold,
+        intensity,
+        radius,
+      } => [[*threshold, *intensity, *radius, 0.0], [0.0; 4]],
+      PostEffect::Tonemap {
+        exposure,
+        tonemapper,
+      } => {
+        let tonemapper = match tonemapper {
+          Tonemapper::Reinhard => 0.0,
+          Tonemapper::Aces => 1.0,
+        };
+        [[*exposure, tonemapper, 0.0, 0.0], [0.0; 4]]
+      }
+      PostEffect::Custom { params, .. } => *params,
+    }
+  }
+}
+
+// PostSettings in shader_lib/post.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostSettingsRaw {
+  texel: [f32; 2],
+  near: f32,
+  far: f32,
+  params: [[f32; 4]; 2],
+}
+
+impl PostSettingsRaw {
+  fn new(size: (u32, u32), camera: &Camera, params: [[f32; 4]; 2]) -> Self {
+    Self {
+      texel: [1.0 / size.0.max(1) as f32, 1.0 / size.1.max(1) as f32],
+      near: camera.znear,
+      far: camera.zfar,
+      params,
+    }
+  }
+}
+
+/// a fullscreen pipeline, no vertex buffers, just a triangle covering the screen
+struct PostPipeline {
+  render_pipeline: wgpu::RenderPipeline,
+  bindgroups: MemoryLayouts,
+  source_map: SourceMap,
+}
+
+impl PostPipeline {
+  fn compile(
+    drivers: &Drivers,
+    registry: &BindingRegistry,
+    file: &str,
+    format: wgpu::TextureFormat,
+  ) -> Result<Self, ShaderError> {
+    let mut builder = ShaderBuilder::from_file(file.to_owned());
+    // the depth buffer is multisampled along with the scene
+    if drivers.get_sample_count() > 1 {
+      builder = builder.define("MSAA");
+    }
+    let shader = builder.validate()?;
+    let bindgroups = MemoryLayouts::reflect(&shader.module, registry)
+      .map_err(|error| ShaderError::from_binding_error(error, &shader, file))?;
+
+    drivers
+      .device
+      .push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let module = drivers
+      .device
+      .create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(file),
+        source: wgpu::ShaderSource::Wgsl(shader.source.as_str().into()),
+      });
+    let layout = drivers
+      .device
+      .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Post Pipeline Layout"),
+        bind_group_layouts: &bindgroups.collect_slice(),
+        push_constant_ranges: &[],
+      });
+    let render_pipeline = drivers
+      .device
+      .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(file),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+          module: &module,
+          entry_point: Some("vs_main"),
+          buffers: &[],
+          compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+          module: &module,
+          entry_point: Some("fs_main"),
+          targets: &[Some(wgpu::ColorTargetState {
+            format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+          })],
+          compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+      });
+
+    if let Some(error) = pollster::block_on(drivers.device.pop_error_scope()) {
+      let message = error.to_string();
+      return Err(ShaderError::new(ShaderErrorKind::Pipeline, file, message));
+    }
+
+    Ok(Self {
+      render_pipeline,
+      bindgroups,
+      source_map: shader.source_map,
+    })
+  }
+}
+
+struct PostStep {
+  effect: PostEffect,
+  pipeline: PostPipeline,
+  // this step's PostSettings
+  buffer: wgpu::Buffer,
+}
+
+pub struct PostStack {
+  steps: Vec<PostStep>,
+  // copies the result onto the surface, None if present.wgsl didn't compile
+  present: Option<PostPipeline>,
+  present_buffer: wgpu::Buffer,
+
+  layout: wgpu::BindGroupLayout,
+  // whether the layout's depth is multisampled, it follows the msaa setting
+  multisampled: bool,
+  sampler: wgpu::Sampler,
+}
+
+impl GetBufferLayout for PostStack {
+  fn get_bind_layout(&self) -> wgpu::BindGroupLayout {
+    self.layout.clone()
+  }
+
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+    Self::bind_entries(self.multisampled)
+  }
+}
+
+impl PostStack {
+  pub const SHADER_FOLDER: &str = "post";
+  const PRESENT_SHADER: &str = "post/present.wgsl";
+  const SETTINGS_SIZE: u64 = std::mem::size_of::<PostSettingsRaw>() as u64;
+
+  fn bind_entries(multisampled: bool) -> Vec<wgpu::BindGroupLayoutEntry> {
+    let fragment = |binding, ty| wgpu::BindGroupLayoutEntry {
+      binding,
+      visibility: wgpu::ShaderStages::FRAGMENT,
+      ty,
+      count: None,
+    };
+
+    vec![
+      fragment(
+        0,
+        wgpu::BindingType::Texture {
+          sample_type: wgpu::TextureSampleType::Float { filterable: true },
+          view_dimension: wgpu::TextureViewDimension::D2,
+          multisampled: false,
+        },
+      ),
+      fragment(
+        1,
+        wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+      ),
+      // a plain float texture, textureLoad from depth textures doesn't work on gl
+      fragment(
+        2,
+        wgpu::BindingType::Texture {
+          sample_type: wgpu::TextureSampleType::Float { filterable: false },
+          view_dimension: wgpu::TextureViewDimension::D2,
+          multisampled,
+        },
+      ),
+      fragment(
+        3,
+        wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: wgpu::BufferSize::new(Self::SETTINGS_SIZE),
+        },
+      ),
+    ]
+  }
+
+  fn init_bind_group_layout(drivers: &Drivers) -> wgpu::BindGroupLayout {
+    drivers
+      .device
+      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &Self::bind_entries(drivers.get_sample_count() > 1),
+        label: Some("post_bind_group_layout"),
+      })
+  }
+
+  fn init_settings_buffer(drivers: &Drivers, label: &str) -> wgpu::Buffer {
+    drivers.device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some(label),
+      size: Self::SETTINGS_SIZE,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    })
+  }
+
+  pub fn new(drivers: &Drivers) -> Self {
+    let sampler = drivers.device.create_sampler(&wgpu::SamplerDescriptor {
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Linear,
+      ..Default::default()
+    });
+
+    Self {
+      steps: Vec::new(),
+      present: None,
+      present_buffer: Self::init_settings_buffer(drivers, "Present Settings"),
+      layout: Self::init_bind_group_layout(drivers),
+      multisampled: drivers.get_sample_count() > 1,
+      sampler,
+    }
+  }
+
+  /// compiles present.wgsl, the registry needs the PostProcess layout by now.
+  /// without it nothing reaches the screen, so that gets logged loudly
+  pub fn init_pipeline(&mut self, drivers: &Drivers, registry: &BindingRegistry) {
+    let format = drivers.surface_config.format;
+    match PostPipeline::compile(drivers, registry, Self::PRESENT_SHADER, format) {
+      Ok(pipeline) => self.present = Some(pipeline),
+      Err(error) => log::error!(
+        "nothing will be drawn, {} didn't compile: {}",
+        Self::PRESENT_SHADER,
+        error
+      ),
+    }
+  }
+
+  /// compiles the effect and puts it at the end of the chain,
+  /// if the shader is broken the chain is left as it was
+  pub fn add_effect(
+    &mut self,
+    drivers: &Drivers,
+    registry: &BindingRegistry,
+    effect: PostEffect,
+  ) -> Result<(), ShaderError> {
+    let file = effect.get_shader_file();
+    let pipeline = PostPipeline::compile(drivers, registry, &file, DynamicTexture::HDR_FORMAT)?;
+
+    self.steps.push(PostStep {
+      effect,
+      pipeline,
+      buffer: Self::init_settings_buffer(drivers, &file),
+    });
+    Ok(())
+  }
+
+  pub fn remove_effect(&mut self, index: usize) -> Option<PostEffect> {
+    if index >= self.steps.len() {
+      return None;
+    }
+    Some(self.steps.remove(index).effect)
+  }
+
+  pub fn clear(&mut self) {
+    self.steps.clear();
+  }
+
+  pub fn iter_effects(&self) -> impl Iterator<Item = &PostEffect> {
+    self.steps.iter().map(|step| &step.effect)
+  }
+
+  /// for changing an effect's settings, they're picked up next frame.
+  /// changing which shader a Custom effect uses needs remove_effect and add_effect
+  pub fn get_effect_mut(&mut self, index: usize) -> Option<&mut PostEffect> {
+    self.steps.get_mut(index).map(|step| &mut step.effect)
+  }
+
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.steps.len()
+  }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.steps.is_empty()
+  }
+
+  /// rebuilds every effect touched by the changed files,
+  /// a broken effect keeps running its old pipeline
+  pub fn reload_shaders(
+    &mut self,
+    drivers: &Drivers,
+    registry: &BindingRegistry,
+    changes: &[ShaderChange],
+  ) {
+    let affects = |pipeline: &PostPipeline| {
+      changes
+        .iter()
+        .any(|change| pipeline.source_map.depends_on(change.get_path()))
+    };
+
+    for step in &mut self.steps {
+      if !affects(&step.pipeline) {
+        continue;
+      }
+
+      let file = step.effect.get_shader_file();
+      match PostPipeline::compile(drivers, registry, &file, DynamicTexture::HDR_FORMAT) {
+        Ok(pipeline) => {
+          step.pipeline = pipeline;
+          log::info!("reloaded shader: {}", file);
+        }
+        Err(error) => log::error!(
+          "failed to reload shader {}, keeping the old one: {}",
+          file,
+          error
+        ),
+      }
+    }
+
+    if self.present.as_ref().is_some_and(affects) {
+      self.init_pipeline(drivers, registry);
+    }
+  }
+
+  /// for when the msaa sample count changed, the depth binding changes with it,
+  /// so the layout in the registry gets replaced too.
+  /// effects that don't compile anymore get taken out, their old pipelines can't be used
+  pub fn rebuild(&mut self, drivers: &Drivers, registry: &mut BindingRegistry) {
+    self.layout = Self::init_bind_group_layout(drivers);
+    self.multisampled = drivers.get_sample_count() > 1;
+    registry.add_bind(EngineResource::PostProcess, self);
+
+    self.steps.retain_mut(|step| {
+      let file = step.effect.get_shader_file();
+      match PostPipeline::compile(drivers, registry, &file, DynamicTexture::HDR_FORMAT) {
+        Ok(pipeline) => {
+          step.pipeline = pipeline;
+          true
+        }
+        Err(error) => {
+          log::error!("removed {} from the post effects: {}", file, error);
+          false
+        }
+      }
+    });
+
+    self.present = None;
+    self.init_pipeline(drivers, registry);
+  }
+
+  /// uploads every effect's settings, has to run before the frame is rendered
+  pub fn write(&self, drivers: &Drivers, camera: &Camera) {
+    let size = drivers.get_size();
+
+    for step in &self.steps {
+      let settings = PostSettingsRaw::new(size, camera, step.effect.get_params());
+      drivers
+        .queue
+        .write_buffer(&step.buffer, 0, bytemuck::bytes_of(&settings));
+    }
+
+    let settings = PostSettingsRaw::new(size, camera, [[0.0; 4]; 2]);
+    drivers
+      .queue
+      .write_buffer(&self.present_buffer, 0, bytemuck::bytes_of(&settings));
+  }
+
+  fn create_bind_group(
+    &self,
+    drivers: &Drivers,
+    input: &wgpu::TextureView,
+    depth: &wgpu::TextureView,
+    settings: &wgpu::Buffer,
+  ) -> wgpu::BindGroup {
+    drivers
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &self.layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(input),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(&self.sampler),
+          },
+          wgpu::BindGroupEntry {
+            binding: 2,
+            resource: wgpu::BindingResource::TextureView(depth),
+          },
+          wgpu::BindGroupEntry {
+            binding: 3,
+            resource: settings.as_entire_binding(),
+          },
+        ],
+        label: Some("post_bind_group"),
+      })
+  }
+
+  fn draw(
+    &self,
+    context: &mut PassContext<'_>,
+    pipeline: &PostPipeline,
+    input: &wgpu::TextureView,
+    output: &wgpu::TextureView,
+    settings: &wgpu::Buffer,
+  ) {
+    let Some(depth) = context.get_view(render_graph::DEPTH) else {
+      return;
+    };
+    let bind_group = self.create_bind_group(&context.engine.drivers, input, depth, settings);
+    let mut bind_groups = context.engine.get_universal_bind_groups();
+    bind_groups.set(EngineResource::PostProcess, &bind_group);
+
+    let mut render_pass = context
+      .encoder
+      .begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Post Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+          view: output,
+          resolve_target: None,
+          ops: wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            store: wgpu::StoreOp::Store,
+          },
+        })],
+        ..Default::default()
+      });
+    render_pass.set_pipeline(&pipeline.render_pipeline);
+    pipeline
+      .bindgroups
+      .set_bind_groups(&mut render_pass, &bind_groups, false);
+    render_pass.draw(0..3, 0..1);
+  }
+
+  /// runs every effect and then present, hdr ends up holding garbage afterwards
+  pub(crate) fn render(&self, context: &mut PassContext<'_>) {
+    let (Some(hdr), Some(scratch), Some(surface)) = (
+      context.get_view(render_graph::HDR),
+      context.get_view(render_graph::POST_SCRATCH),
+      context.get_view(render_graph::SURFACE),
+    ) else {
+      log::error!("the post pass ran before its attachments were made");
+      return;
+    };
+
+    let (mut input, mut output) = (hdr, scratch);
+    for step in &self.steps {
+      self.draw(context, &step.pipeline, input, output, &step.buffer);
+      std::mem::swap(&mut input, &mut output);
+    }
+
+    if let Some(present) = &self.present {
+      self.draw(context, present, input, surface, &self.present_buffer);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn settings_are_packed_like_the_shader_expects() {
+    // texel, near, far, then two vec4s, 16 byte aligned
+    assert_eq!(std::mem::size_of::<PostSettingsRaw>(), 48);
+
+    let camera = Camera::new(cgmath::Point3::new(0.0, 0.0, 0.0), 60.0, (200, 100));
+    let settings = PostSettingsRaw::new((200, 100), &camera, PostEffect::outline().get_params());
+    assert_eq!(settings.texel, [0.005, 0.01]);
+    assert_eq!((settings.near, settings.far), (camera.znear, camera.zfar));
+    assert_eq!(settings.params[1][0], 1.0);
+  }
+
+  #[test]
+  fn effects_live_in_the_post_folder() {
+    assert_eq!(PostEffect::bloom().get_shader_file(), "post/bloom.wgsl");
+    let custom = PostEffect::Custom {
+      shader: "vignette.wgsl".to_owned(),
+      params: [[0.0; 4]; 2],
+    };
+    assert_eq!(custom.get_shader_file(), "post/vignette.wgsl");
+  }
+}
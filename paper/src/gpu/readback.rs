@@ -0,0 +1,141 @@
+// copies textures back from the gpu, for headless rendering, screenshots and tests
+
+use crate::gpu::device_drivers::Drivers;
+
+/// bytes per row have to be a multiple of 256 when copying a texture into a buffer,
+/// so rows get padded on the gpu side and unpadded again here
+fn padded_bytes_per_row(width: u32, bytes_per_pixel: u32) -> u32 {
+  let unpadded = width * bytes_per_pixel;
+  let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+  unpadded.div_ceil(align) * align
+}
+
+/// whether the red and blue channels have to be swapped to get rgba,
+/// None if the format can't be turned into a png at all
+fn swaps_red_and_blue(format: wgpu::TextureFormat) -> Option<bool> {
+  // srgb and plain unorm formats both already hold the bytes the monitor shows,
+  // so neither needs converting, a png is assumed to be srgb anyway
+  match format {
+    wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => Some(false),
+    wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => Some(true),
+    _ => None,
+  }
+}
+
+/// a texture copy that was recorded into an encoder, but hasn't been read yet
+pub struct TextureReadback {
+  buffer: wgpu::Buffer,
+  width: u32,
+  height: u32,
+  padded_row: u32,
+  swap_red_blue: bool,
+}
+
+impl TextureReadback {
+  const BYTES_PER_PIXEL: u32 = 4;
+
+  /// records a copy of the texture into the encoder, the texture has to be
+  /// 8 bit rgba/bgra and made with COPY_SRC
+  pub fn copy_from(
+    drivers: &Drivers,
+    encoder: &mut wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+  ) -> anyhow::Result<Self> {
+    let format = texture.format();
+    let swap_red_blue = swaps_red_and_blue(format).ok_or(anyhow::anyhow!(
+      "can't read back {:?} textures, only 8 bit rgba/bgra",
+      format
+    ))?;
+
+    if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+      anyhow::bail!("the texture can't be copied from, it needs TextureUsages::COPY_SRC");
+    }
+
+    let (width, height) = (texture.width(), texture.height());
+    let padded_row = padded_bytes_per_row(width, Self::BYTES_PER_PIXEL);
+
+    let buffer = drivers.device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("readback_buffer"),
+      size: (padded_row * height) as u64,
+      usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+      mapped_at_creation: false,
+    });
+
+    encoder.copy_texture_to_buffer(
+      texture.as_image_copy(),
+      wgpu::TexelCopyBufferInfo {
+        buffer: &buffer,
+        layout: wgpu::TexelCopyBufferLayout {
+          offset: 0,
+          bytes_per_row: Some(padded_row),
+          rows_per_image: Some(height),
+        },
+      },
+      texture.size(),
+    );
+
+    Ok(Self {
+      buffer,
+      width,
+      height,
+      padded_row,
+      swap_red_blue,
+    })
+  }
+
+  /// waits for the copy to finish and turns it into an image,
+  /// the encoder the copy was recorded into has to be submitted first
+  pub fn finish(self, drivers: &Drivers) -> anyhow::Result<image::RgbaImage> {
+    let slice = self.buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+      let _ = sender.send(result);
+    });
+    drivers.device.poll(wgpu::Maintain::Wait);
+    receiver.recv()??;
+
+    let row_bytes = (self.width * Self::BYTES_PER_PIXEL) as usize;
+    let mut pixels = Vec::with_capacity(row_bytes * self.height as usize);
+    {
+      let data = slice.get_mapped_range();
+      for row in data.chunks(self.padded_row as usize) {
+        pixels.extend_from_slice(&row[..row_bytes]);
+      }
+    }
+    self.buffer.unmap();
+
+    if self.swap_red_blue {
+      for pixel in pixels.chunks_exact_mut(Self::BYTES_PER_PIXEL as usize) {
+        pixel.swap(0, 2);
+      }
+    }
+
+    image::RgbaImage::from_raw(self.width, self.height, pixels)
+      .ok_or(anyhow::Error::msg("readback buffer was the wrong size"))
+  }
+}
+
+/// reads a texture back into an image, blocks until the gpu is done with it
+pub fn read_texture(
+  drivers: &Drivers,
+  texture: &wgpu::Texture,
+) -> anyhow::Result<image::RgbaImage> {
+  let mut encoder = drivers
+    .device
+    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+      label: Some("Readback Encoder"),
+    });
+
+  let readback = TextureReadback::copy_from(drivers, &mut encoder, texture)?;
+  drivers.queue.submit(std::iter::once(encoder.finish()));
+
+  readback.finish(drivers)
+}
+
+/// reads back whatever was last rendered when running headless
+pub fn read_offscreen(drivers: &Drivers) -> anyhow::Result<image::RgbaImage> {
+  let texture = drivers.get_offscreen_texture().ok_or(anyhow::Error::msg(
+    "there's no offscreen texture, the engine has a window",
+  ))?;
+  read_texture(drivers, texture)
+}
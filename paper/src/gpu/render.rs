@@ -1,95 +1,455 @@
-use std::{iter, sync::Arc};
-use wgpu::{util::DeviceExt, RenderPass};
+use std::{cell::Cell, collections::HashMap, iter, sync::Arc};
+use cgmath::{EuclideanSpace, InnerSpace};
+use wgpu::RenderPass;
 
 use crate::{
   engine,
+  files::shader_watcher::ShaderChange,
   gpu::{
-    device_drivers, geometry::GetBufferLayout, gpu_pointers, lights, mesh, object::{self, Object}, shaders::{RenderingBundle, ShaderBuilder, ShaderPipeline}, texture
+    background::{Background, BackgroundPass}, bounds::CullStats, camera::Camera, device_drivers, geometry::GetBufferLayout, gpu_pointers, lights::{Light, LightBuffer, LightId}, locations::LocationBuffer, lod::{self, LodGroup}, shadows::ShadowPass, material::BlendMode, mesh, render_queue::{QueuedDraw, RenderQueue, RenderStats, SortKey}, object::Object, post::{PostEffect, PostStack}, readback::TextureReadback, render_graph::{self, AttachmentDesc, AttachmentSamples, AttachmentSize, GraphError, GraphPass, PassContext, PassDesc, RenderGraph}, shaders::{validation::ShaderError, RenderingBundle, ShaderBuilder, ShaderPipeline}, texture, transparency::{TransparencyMode, TransparencyPass}
   },
 };
+
+/// every shadow casting light's depth, into the shadow atlas
+struct ShadowGraphPass;
+
+impl GraphPass for ShadowGraphPass {
+  fn desc(&self) -> PassDesc {
+    PassDesc::new("shadows").writes(render_graph::SHADOW_ATLAS)
+  }
+
+  fn run(&self, context: &mut PassContext<'_>) {
+    context.engine.render_task.render_shadows(context.encoder);
+  }
+}
+
+/// every opaque object, lit and shadowed, and then the background behind them,
+/// into the hdr attachment (through hdr_msaa with msaa on)
+struct SceneGraphPass;
+
+impl GraphPass for SceneGraphPass {
+  fn desc(&self) -> PassDesc {
+    PassDesc::new("scene")
+      .reads(render_graph::SHADOW_ATLAS)
+      .writes(render_graph::HDR_MSAA)
+      .writes(render_graph::HDR)
+      .writes(render_graph::DEPTH)
+  }
+
+  fn run(&self, context: &mut PassContext<'_>) {
+    // hdr_msaa is only there with msaa on, it gets resolved into hdr at the end of the pass
+    let (Some((color, resolve)), Some(depth)) = (
+      context.get_target(render_graph::HDR, render_graph::HDR_MSAA),
+      context.get_view(render_graph::DEPTH),
+    ) else {
+      log::error!("the scene pass ran before its attachments were made");
+      return;
+    };
+
+    let engine = context.engine;
+    let render_task = &engine.render_task;
+    let mut render_pass = render_task.init_render_pass(color, resolve, depth, context.encoder);
+    render_task.render_buffers(&mut render_pass, engine);
+    // after the objects, so it's only shaded where they didn't cover it
+    render_task.background.render(
+      &mut render_pass,
+      &engine.drivers,
+      engine.get_universal_bind_groups(),
+    );
+  }
+}
+
+/// everything blended, over the finished scene and tested against its depth
+/// (see gpu/transparency.rs)
+struct TransparentGraphPass;
+
+impl GraphPass for TransparentGraphPass {
+  fn desc(&self) -> PassDesc {
+    PassDesc::new("transparent")
+      .reads(render_graph::DEPTH)
+      .reads(render_graph::HDR_MSAA)
+      .reads(render_graph::HDR)
+      .writes(render_graph::HDR_MSAA)
+      .writes(render_graph::HDR)
+  }
+
+  fn run(&self, context: &mut PassContext<'_>) {
+    context.engine.render_task.render_transparent(context);
+  }
+}
+
+/// the post stack's effects, and the result onto the surface
+struct PostGraphPass;
+
+impl GraphPass for PostGraphPass {
+  fn desc(&self) -> PassDesc {
+    PassDesc::new("post")
+      .reads(render_graph::HDR)
+      .reads(render_graph::DEPTH)
+      .writes(render_graph::HDR)
+      .writes(render_graph::POST_SCRATCH)
+      .writes(render_graph::SURFACE)
+  }
+
+  fn run(&self, context: &mut PassContext<'_>) {
+    context.engine.render_task.post.render(context);
+  }
+}
+
 pub struct RenderTask {
   pub objects: Vec<Object>,
   scene: RenderingBundle,
 
-  object_location_bindgroup: wgpu::BindGroup,
-  object_location_layout: wgpu::BindGroupLayout,
-  object_location_buffer: wgpu::Buffer,
+  locations: LocationBuffer,
+  // built by prepare_frame
+  queue: RenderQueue,
+  culling: bool,
+  cull_stats: CullStats,
+  // filled in while rendering, which only gets &self
+  render_stats: Cell<RenderStats>,
+  // for the objects that have lods, by the object's index
+  lods: HashMap<usize, LodGroup>,
+  lod_hysteresis: f32,
+
+  lights: LightBuffer,
+  shadows: ShadowPass,
+  post: PostStack,
+  background: BackgroundPass,
+  transparency: TransparencyPass,
+
+  graph: RenderGraph,
 }
 
 impl GetBufferLayout for RenderTask {
   fn get_bind_layout(&self) -> wgpu::BindGroupLayout {
-    return self.object_location_layout.clone();
+    return self.locations.get_layout().clone();
+  }
+
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+    LocationBuffer::bind_entries()
   }
 }
 
 impl RenderTask {
   #[inline]
-  pub fn get_location_buffer<'a>(&'a self) -> &'a wgpu::Buffer {
-    return &self.object_location_buffer;
+  pub fn get_lights(&self) -> &LightBuffer {
+    &self.lights
+  }
+
+  #[inline]
+  pub fn get_shadow_pass(&self) -> &ShadowPass {
+    &self.shadows
+  }
+
+  /// needs the ShadowView and ObjectLocation layouts in the registry
+  pub fn init_shadow_pipeline(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    registry: &gpu_pointers::BindingRegistry,
+  ) {
+    self.shadows.init_pipeline(drivers, registry);
+  }
+
+  #[inline]
+  pub fn get_post_stack(&self) -> &PostStack {
+    &self.post
   }
 
   #[inline]
-  pub fn get_location_bindgroup<'a>(&'a self) -> &'a wgpu::BindGroup {
-    return &self.object_location_bindgroup;
+  pub fn get_post_stack_mut(&mut self) -> &mut PostStack {
+    &mut self.post
+  }
+
+  /// needs the PostProcess layout in the registry
+  pub fn init_post_pipeline(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    registry: &gpu_pointers::BindingRegistry,
+  ) {
+    self.post.init_pipeline(drivers, registry);
+  }
+
+  #[inline]
+  pub fn get_background_pass(&self) -> &BackgroundPass {
+    &self.background
+  }
+
+  /// needs the Background and Time layouts in the registry
+  pub fn init_background_pipeline(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    registry: &gpu_pointers::BindingRegistry,
+  ) {
+    self.background.init_pipeline(drivers, registry);
+  }
+
+  #[inline]
+  pub fn get_transparency_pass(&self) -> &TransparencyPass {
+    &self.transparency
+  }
+
+  /// needs the Transparency layout in the registry
+  pub fn init_transparency_pipeline(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    registry: &gpu_pointers::BindingRegistry,
+  ) {
+    self.transparency.init_pipeline(drivers, registry);
+  }
+
+  #[inline]
+  pub fn get_transparency_mode(&self) -> TransparencyMode {
+    self.transparency.get_mode()
+  }
+
+  /// switches how blended materials are drawn from the next frame on,
+  /// the alpha blended and premultiplied pipelines get rebuilt for it
+  pub fn set_transparency_mode(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    mode: TransparencyMode,
+  ) {
+    self.transparency.set_mode(&mut self.graph, mode);
+    self.scene.set_transparency_mode(drivers, mode);
+    self.graph.prepare(drivers);
+  }
+
+  #[inline]
+  pub fn is_wireframe(&self) -> bool {
+    self.scene.is_wireframe()
+  }
+
+  /// the debug view, every object's triangles drawn as lines from the next frame on.
+  /// gives back false and leaves things as they were if the device can't draw lines
+  pub fn set_wireframe(&mut self, drivers: &device_drivers::Drivers, wireframe: bool) -> bool {
+    self.scene.set_wireframe(drivers, wireframe)
+  }
+
+  #[inline]
+  pub fn get_background(&self) -> &Background {
+    self.background.get_background()
+  }
+
+  /// what's drawn wherever no object is, from the next frame on
+  pub fn set_background(&mut self, drivers: &device_drivers::Drivers, background: Background) {
+    self.background.set_background(drivers, background);
+  }
+
+  /// puts the effect at the end of the post processing chain
+  pub fn add_post_effect(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    registry: &gpu_pointers::BindingRegistry,
+    effect: PostEffect,
+  ) -> Result<(), ShaderError> {
+    self.post.add_effect(drivers, registry, effect)
+  }
+
+  #[inline]
+  pub fn get_graph(&self) -> &RenderGraph {
+    &self.graph
+  }
+
+  /// for adding attachments, see add_pass to add passes
+  #[inline]
+  pub fn get_graph_mut(&mut self) -> &mut RenderGraph {
+    &mut self.graph
+  }
+
+  /// runs every frame from now on, ordered against the other passes by what it reads and writes
+  pub fn add_pass(&mut self, pass: impl GraphPass + 'static) -> Result<(), GraphError> {
+    self.graph.add_pass(Box::new(pass))
+  }
+
+  /// remakes the graph's attachments that depend on the surface's size
+  pub fn resize(&mut self, drivers: &device_drivers::Drivers) {
+    self.graph.prepare(drivers);
+  }
+
+  /// everything that depends on the msaa sample count: the scene, background, transparency and
+  /// post pipelines, the post layout in the registry, and the multisampled attachments
+  pub fn rebuild_pipelines(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    registry: &mut gpu_pointers::BindingRegistry,
+  ) {
+    self.scene.rebuild_shaders(drivers);
+    self.background.rebuild(drivers, registry);
+    self.transparency.rebuild(drivers, registry);
+    self.post.rebuild(drivers, registry);
+    self.graph.prepare(drivers);
+  }
+
+  #[inline]
+  pub fn get_lights_mut(&mut self) -> &mut LightBuffer {
+    &mut self.lights
   }
 
   pub fn render(&self, engine: &engine::Engine) -> std::result::Result<(), wgpu::SurfaceError> {
-    let output = engine.drivers.surface.get_current_texture()?;
+    self.render_frame(engine, false)?;
+    Ok(())
+  }
+
+  /// renders, and if capture is true also copies the finished frame out before it's presented.
+  /// the copy still has to be read with TextureReadback::finish
+  pub fn render_frame(
+    &self,
+    engine: &engine::Engine,
+    capture: bool,
+  ) -> std::result::Result<Option<TextureReadback>, wgpu::SurfaceError> {
+    // either the window's surface, or the offscreen texture when headless
+    let frame = engine.drivers.get_current_frame()?;
+    let view = frame.create_view();
     let mut encoder = self.init_encoder(&engine.drivers);
-    let render_pass = self.init_render_pass(&output, &mut encoder, &engine.texture_bundle);
 
-    // tell the gpu what buffers to render
-    self.render_buffers(render_pass, &engine);
+    // every pass, shadows and the scene included, in the order their attachments need
+    self.graph.execute(engine, &mut encoder, &view);
+
+    // has to be recorded before presenting, the surface texture is gone after that
+    let readback = match capture {
+      true => TextureReadback::copy_from(&engine.drivers, &mut encoder, frame.get_texture())
+        .inspect_err(|error| log::error!("can't capture this frame: {}", error))
+        .ok(),
+      false => None,
+    };
 
     engine
       .render_task
-      .finish_rendering(output, encoder, &engine.drivers);
+      .finish_rendering(frame, encoder, &engine.drivers);
 
-    Ok(())
+    Ok(readback)
   }
 
   pub async fn add_object(
     &mut self,
     object: Object,
     drivers: &device_drivers::Drivers,
-    bind_groups: &gpu_pointers::MemoryLayouts,
+    bind_groups: &gpu_pointers::BindingRegistry,
   ) -> anyhow::Result<()> {
     let shader_builder = ShaderBuilder::from_file("sample.wgsl".to_owned());
-    let mut shader = ShaderPipeline::from_shader(bind_groups, drivers, shader_builder).await?;
+    self
+      .add_object_with_shader(object, drivers, bind_groups, shader_builder)
+      .await
+  }
 
-    for mesh in object.meshes.clone() {
-      shader.meshes.push(mesh);
+  /// same as add_object, but drawn with another shader (or the same one with other #define's).
+  /// objects with the same shader and defines share one pipeline per blend mode
+  pub async fn add_object_with_shader(
+    &mut self,
+    object: Object,
+    drivers: &device_drivers::Drivers,
+    bind_groups: &gpu_pointers::BindingRegistry,
+    shader_builder: ShaderBuilder,
+  ) -> anyhow::Result<()> {
+    // every blend mode the object's materials use needs its own pipeline
+    let lod_meshes = object.lods.iter().flat_map(|lod| lod.meshes.iter());
+    let mut shaders: HashMap<BlendMode, usize> = HashMap::new();
+    for mesh in object.meshes.iter().chain(lod_meshes) {
+      let blend_mode = mesh.get_material().get_blend_mode();
+      if shaders.contains_key(&blend_mode) {
+        continue;
+      }
+      let index = self
+        .find_or_add_shader(drivers, bind_groups, &shader_builder, blend_mode)
+        .await?;
+      shaders.insert(blend_mode, index);
     }
+    let shader_for = |mesh: &mesh::Mesh| shaders[&mesh.get_material().get_blend_mode()];
 
-    self.update_lights(&object.meshes);
+    // objects with lods pick their meshes every frame, so they aren't in the shaders' lists
+    if object.lods.is_empty() {
+      for mesh in &object.meshes {
+        if let Some(shader) = self.scene.get_shader_mut(shader_for(mesh)) {
+          shader.meshes.push(mesh.clone());
+        }
+      }
+    } else {
+      let group = LodGroup::new(&object.meshes, &object.lods, shader_for);
+      self.lods.insert(self.objects.len(), group);
+    }
 
     self.objects.push(object);
-    self.scene.add_shader(shader)?;
-
     Ok(())
   }
 
-  // kinda a bad way to do that, i would rather store a universal mesh list or something
-  fn update_lights(&mut self, meshes: &Vec<Arc<mesh::Mesh>>) {
-    for light in self.scene.iter_mut_lights() {
-      for mesh in meshes {
-        light.add_mesh(mesh.clone());
-      }
+  async fn find_or_add_shader(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    bind_groups: &gpu_pointers::BindingRegistry,
+    shader_builder: &ShaderBuilder,
+    blend_mode: BlendMode,
+  ) -> anyhow::Result<usize> {
+    if let Some(index) = self.scene.find_shader(shader_builder, blend_mode) {
+      return Ok(index);
+    }
+
+    let mut shader = ShaderPipeline::new_blended(
+      bind_groups,
+      drivers,
+      shader_builder.clone(),
+      blend_mode,
+      self.transparency.get_mode(),
+    )?;
+    // built after the wireframe view was turned on
+    if self.scene.is_wireframe() {
+      shader.set_wireframe(drivers, true)?;
     }
+    self.scene.add_shader(shader)?;
+    Ok(self.scene.get_shader_count() - 1)
+  }
+
+  /// the light shows up from the next frame on
+  pub fn add_light(&mut self, light: Light) -> LightId {
+    self.lights.add(light)
   }
 
-  pub fn add_light(
+  pub fn reload_shaders(
     &mut self,
     drivers: &device_drivers::Drivers,
-    location: object::Location,
-    color: [f32;3],
-    shader: ShaderBuilder,
-  ) -> anyhow::Result<()> {
-    let shader = shader.build(drivers).ok_or("failed to compile shader").unwrap();
-    let light = lights::Light::new_colored(drivers, &self.scene, shader, location, color);
-    self.scene.add_light(light);
-    
-    Ok(())
+    registry: &gpu_pointers::BindingRegistry,
+    changes: &[ShaderChange],
+  ) {
+    self.scene.reload_shaders(drivers, changes);
+    self.shadows.reload_shaders(drivers, registry, changes);
+    self.background.reload_shaders(drivers, registry, changes);
+    self.transparency.reload_shaders(drivers, registry, changes);
+    self.post.reload_shaders(drivers, registry, changes);
+  }
+
+  /// how many meshes the last prepare_frame kept and how many it culled
+  #[inline]
+  pub fn get_cull_stats(&self) -> CullStats {
+    self.cull_stats
+  }
+
+  /// on by default, turning it off draws everything (handy for checking the bounds aren't too small)
+  pub fn set_culling(&mut self, culling: bool) {
+    self.culling = culling;
+  }
+
+  /// how many pipelines, bind groups and draws the scene and transparent passes used last frame
+  #[inline]
+  pub fn get_render_stats(&self) -> RenderStats {
+    self.render_stats.get()
+  }
+
+  /// how many different pipelines the scene is drawn with
+  #[inline]
+  pub fn get_pipeline_count(&self) -> usize {
+    self.scene.get_shader_count()
+  }
+
+  /// the level of detail the object got drawn with last frame, 0 for objects without lods
+  pub fn get_current_lod(&self, object_index: usize) -> usize {
+    self
+      .lods
+      .get(&object_index)
+      .map_or(0, |group| group.get_current())
+  }
+
+  /// how far past a level's screen size objects have to get before switching, as a fraction of it
+  pub fn set_lod_hysteresis(&mut self, hysteresis: f32) {
+    self.lod_hysteresis = hysteresis.max(0.0);
   }
 
   pub fn write_to_buffer<T>(engine: &engine::Engine, buffer: &wgpu::Buffer, data: &[T])
@@ -102,85 +462,259 @@ impl RenderTask {
       .write_buffer(&buffer, 0, bytemuck::cast_slice(data));
   }
 
-  fn init_bind_group_layout(drivers: &device_drivers::Drivers) -> wgpu::BindGroupLayout {
-    drivers
-      .device
-      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        entries: &[wgpu::BindGroupLayoutEntry {
-          binding: 0,
-          visibility: wgpu::ShaderStages::VERTEX,
-          ty: wgpu::BindingType::Buffer {
-            ty: wgpu::BufferBindingType::Uniform,
-            has_dynamic_offset: false,
-            min_binding_size: None,
-          },
-          count: None,
-        }],
-        label: Some("object_position_bind_group_layout"),
-      })
-  }
-
-  fn init_object_bindings(
-    drivers: &device_drivers::Drivers,
-  ) -> (wgpu::BindGroup, wgpu::BindGroupLayout, wgpu::Buffer) {
-    let nil_location = object::Location::new_world_origin();
+  /// works out what gets drawn this frame and uploads every mesh's location,
+  /// has to run before render, nothing can be written to the buffer once the pass has started.
+  /// objects with lods get the level that fits their size on screen, meshes outside the
+  /// camera's frustum are skipped (unless culling is off) and the rest get sorted,
+  /// blended ones separately from the opaque ones
+  pub fn prepare_frame(&mut self, drivers: &device_drivers::Drivers, camera: &Camera) {
+    self.queue.clear();
+    self.cull_stats = CullStats::default();
+    let mut locations = Vec::new();
+    let frustum = camera.get_frustum();
+    let eye = camera.position.to_vec();
 
-    let bind_layout = Self::init_bind_group_layout(drivers);
+    let mut queue_mesh = |shader_index: usize, mesh: &Arc<mesh::Mesh>| {
+      let bounds = mesh.get_world_bounds();
+      // meshes without bounds can't be culled, so they're always drawn
+      let visible = !self.culling || bounds.is_none_or(|bounds| frustum.contains(&bounds));
+      match visible {
+        true => self.cull_stats.drawn += 1,
+        false => self.cull_stats.culled += 1,
+      }
 
-    let loc_buffer = drivers
-      .device
-      .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Location Buffer"),
-        contents: bytemuck::cast_slice(&[nil_location.to_uniform()]),
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-      });
+      let center = bounds.map_or(mesh.get_location().pos, |bounds| bounds.sphere.center);
+      let key = SortKey {
+        pipeline: shader_index,
+        material: mesh.get_material().get_id(),
+        depth: (center - eye).magnitude2(),
+      };
+      let draw = QueuedDraw {
+        mesh: mesh.clone(),
+        key,
+        location_slot: locations.len() as u32,
+        visible,
+      };
+      match mesh.get_material().get_blend_mode().is_transparent() {
+        true => self.queue.push_transparent(draw),
+        false => self.queue.push_opaque(draw),
+      }
+      locations.push(mesh.get_location_uniform());
+    };
 
-    let layout = drivers
-      .device
-      .create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &bind_layout,
-        entries: &[wgpu::BindGroupEntry {
-          binding: 0,
-          resource: loc_buffer.as_entire_binding(),
-        }],
-        label: Some("object_location_bind_group"),
-      });
+    for (shader_index, shader) in self.scene.iter_shaders().enumerate() {
+      for mesh in &shader.meshes {
+        queue_mesh(shader_index, mesh);
+      }
+    }
+    for group in self.lods.values_mut() {
+      for (shader_index, mesh) in group.select(camera, self.lod_hysteresis) {
+        queue_mesh(*shader_index, mesh);
+      }
+    }
+    self.queue.sort();
 
-    (layout, bind_layout, loc_buffer)
+    self.locations.write(drivers, &locations);
+    self.lights.write(drivers);
+    self
+      .shadows
+      .write(drivers, self.lights.get_shadow_casters());
+    self.graph.prepare(drivers);
   }
 
   pub fn new(drivers: &device_drivers::Drivers) -> Self {
-    let (object_location_bindgroup, object_location_layout, object_location_buffer) =
-      Self::init_object_bindings(drivers);
     Self {
       objects: vec![],
       scene: RenderingBundle::new(),
 
-      object_location_bindgroup,
-      object_location_buffer,
-      object_location_layout,
+      locations: LocationBuffer::new(drivers),
+      queue: RenderQueue::new(),
+      culling: true,
+      cull_stats: CullStats::default(),
+      render_stats: Cell::new(RenderStats::default()),
+      lods: HashMap::new(),
+      lod_hysteresis: lod::DEFAULT_HYSTERESIS,
+      lights: LightBuffer::new(drivers),
+      shadows: ShadowPass::new(drivers),
+      post: PostStack::new(drivers),
+      background: BackgroundPass::new(drivers),
+      transparency: TransparencyPass::new(drivers),
+      graph: Self::init_graph(drivers),
+    }
+  }
+
+  fn init_graph(drivers: &device_drivers::Drivers) -> RenderGraph {
+    let mut graph = RenderGraph::new();
+    let depth = AttachmentDesc::new(
+      texture::DynamicTexture::DEPTH_BUFFER_FORMAT,
+      AttachmentSize::Surface,
+    );
+    let depth = depth.with_samples(AttachmentSamples::Msaa);
+    let hdr = AttachmentDesc::new(texture::DynamicTexture::HDR_FORMAT, AttachmentSize::Surface);
+    let hdr_msaa = hdr.with_samples(AttachmentSamples::MsaaOnly);
+
+    // none of these can fail on an empty graph
+    graph
+      .add_attachment(render_graph::DEPTH, depth)
+      .and_then(|_| graph.add_attachment(render_graph::HDR, hdr))
+      .and_then(|_| graph.add_attachment(render_graph::HDR_MSAA, hdr_msaa))
+      .and_then(|_| graph.add_attachment(render_graph::POST_SCRATCH, hdr))
+      .and_then(|_| graph.add_external(render_graph::SHADOW_ATLAS))
+      .and_then(|_| graph.add_pass(Box::new(ShadowGraphPass)))
+      .and_then(|_| graph.add_pass(Box::new(SceneGraphPass)))
+      .and_then(|_| graph.add_pass(Box::new(TransparentGraphPass)))
+      .and_then(|_| graph.add_pass(Box::new(PostGraphPass)))
+      .expect("the built in render graph is broken");
+
+    graph.prepare(drivers);
+    graph
+  }
+
+  /// renders every shadow casting light's depth into its square of the atlas
+  pub(crate) fn render_shadows(&self, encoder: &mut wgpu::CommandEncoder) {
+    let casters = self.lights.get_shadow_casters();
+    let Some(pipeline) = self.shadows.get_pipeline() else {
+      return;
+    };
+    if casters.is_empty() {
+      return;
+    }
+
+    let mut render_pass = self.shadows.begin(encoder, self.lights.get_shadow_atlas());
+    render_pass.set_pipeline(&pipeline.render_pipeline);
+
+    for (slot, caster) in casters.iter().enumerate() {
+      let tile = caster.tile;
+      let (x, y, size) = (tile.x as f32, tile.y as f32, tile.size as f32);
+      render_pass.set_viewport(x, y, size, size, 0.0, 1.0);
+      render_pass.set_scissor_rect(tile.x, tile.y, tile.size, tile.size);
+
+      let mut view = gpu_pointers::BindGroupSet::new();
+      view.set_dynamic(
+        gpu_pointers::EngineResource::ShadowView,
+        self.shadows.get_bind_group(),
+        self.shadows.get_offset(slot as u32),
+      );
+      pipeline
+        .get_bindgroups()
+        .set_bind_groups(&mut render_pass, &view, false);
+
+      // see through things don't cast shadows
+      for draw in self.queue.iter_opaque() {
+        draw.mesh.render_mesh(
+          pipeline.get_bindgroups(),
+          &mut render_pass,
+          self.locations.get_bind_group(),
+          self.locations.get_offset(draw.location_slot),
+        );
+      }
     }
   }
 
-  fn render_buffers(&self, mut render_pass: RenderPass<'_>, engine: &engine::Engine) {
-    // calculate lighting
+  fn render_buffers(&self, render_pass: &mut RenderPass<'_>, engine: &engine::Engine) {
+    let mut stats = RenderStats::default();
+    let opaque = self.queue.iter_opaque().filter(|draw| draw.visible);
+    self.render_draws(render_pass, engine, opaque, &mut stats);
+    self.render_stats.set(stats);
+  }
+
+  /// the blended draws, after the scene pass. with weighted blended transparency on the alpha
+  /// blended ones get accumulated and composited first, anything else goes on top back to front
+  pub(crate) fn render_transparent(&self, context: &mut PassContext<'_>) {
+    let engine = context.engine;
+    let is_order_independent = |draw: &&QueuedDraw| {
+      self
+        .scene
+        .get_shader(draw.get_shader())
+        .is_some_and(|shader| shader.is_order_independent())
+    };
+    let (blended, sorted): (Vec<&QueuedDraw>, Vec<&QueuedDraw>) = self
+      .queue
+      .iter_transparent()
+      .filter(|draw| draw.visible)
+      .partition(is_order_independent);
 
-    // loop through each shader, and render it's corresponding objects.
-    for shader in self.scene.iter_shaders() {
-      render_pass.set_pipeline(&shader.render_pipeline);
-      mesh::Mesh::render_meshes(&shader.meshes, &mut render_pass, engine);
+    let mut stats = self.render_stats.get();
+    if !blended.is_empty() {
+      if let Some(mut render_pass) = self.transparency.begin_accumulate(context) {
+        self.render_draws(&mut render_pass, engine, blended.into_iter(), &mut stats);
+      }
+      self.transparency.composite(context);
+    }
+
+    if !sorted.is_empty() {
+      let (Some((color, resolve)), Some(depth)) = (
+        context.get_target(render_graph::HDR, render_graph::HDR_MSAA),
+        context.get_view(render_graph::DEPTH),
+      ) else {
+        log::error!("the transparent pass ran before its attachments were made");
+        self.render_stats.set(stats);
+        return;
+      };
+      let mut render_pass = self.init_transparent_pass(color, resolve, depth, context.encoder);
+      self.render_draws(&mut render_pass, engine, sorted.into_iter(), &mut stats);
+    }
+    self.render_stats.set(stats);
+  }
+
+  fn render_draws<'d>(
+    &self,
+    render_pass: &mut RenderPass<'_>,
+    engine: &engine::Engine,
+    draws: impl Iterator<Item = &'d QueuedDraw>,
+    stats: &mut RenderStats,
+  ) {
+    let universal = engine.get_universal_bind_groups();
+    let (mut current_shader, mut current_material) = (None, None);
+
+    // the queue is sorted, so the pipeline and material only change when they have to
+    for draw in draws {
+      let Some(shader) = self.scene.get_shader(draw.get_shader()) else {
+        continue;
+      };
+      // left over from before msaa changed, and it didn't rebuild
+      if shader.get_sample_count() != engine.drivers.get_sample_count() {
+        continue;
+      }
+
+      if current_shader != Some(draw.get_shader()) {
+        render_pass.set_pipeline(&shader.render_pipeline);
+        stats.pipelines += 1;
+        stats.bind_groups +=
+          shader
+            .get_bindgroups()
+            .set_bind_groups(render_pass, &universal, false);
+        current_shader = Some(draw.get_shader());
+        // the new pipeline might put the material in another group
+        current_material = None;
+      }
+
+      if current_material != Some(draw.key.material) {
+        stats.bind_groups += draw
+          .mesh
+          .bind_material(shader.get_bindgroups(), render_pass);
+        current_material = Some(draw.key.material);
+      }
+
+      stats.bind_groups += draw.mesh.draw(
+        shader.get_bindgroups(),
+        render_pass,
+        self.locations.get_bind_group(),
+        self.locations.get_offset(draw.location_slot),
+      );
+      stats.draws += 1;
     }
   }
 
   fn finish_rendering(
     &self,
-    output: wgpu::SurfaceTexture,
+    frame: device_drivers::Frame,
     encoder: wgpu::CommandEncoder,
     drivers: &device_drivers::Drivers,
   ) {
     let command_buffer = encoder.finish();
     drivers.queue.submit(iter::once(command_buffer));
-    output.present();
+    drivers.present(frame);
   }
 
   fn init_encoder(&self, drivers: &device_drivers::Drivers) -> wgpu::CommandEncoder {
@@ -193,26 +727,23 @@ impl RenderTask {
 
   fn init_render_pass<'a>(
     &self,
-    output: &wgpu::SurfaceTexture,
+    view: &wgpu::TextureView,
+    resolve_target: Option<&wgpu::TextureView>,
+    depth: &wgpu::TextureView,
     encoder: &'a mut wgpu::CommandEncoder,
-    texture_bundle: &texture::TextureBundle,
   ) -> RenderPass<'a> {
-    let view = output
-      .texture
-      .create_view(&wgpu::TextureViewDescriptor::default());
-
     let render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
       label: Some("Render Pass"),
       color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-        view: &view,
-        resolve_target: None,
+        view,
+        resolve_target,
         ops: wgpu::Operations {
-          load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+          load: wgpu::LoadOp::Clear(self.background.get_clear_color()),
           store: wgpu::StoreOp::Store,
         },
       })],
       depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-        view: &texture_bundle.depth_buffer.view,
+        view: depth,
         depth_ops: Some(wgpu::Operations {
           load: wgpu::LoadOp::Clear(1.0),
           store: wgpu::StoreOp::Store,
@@ -224,4 +755,32 @@ impl RenderTask {
     });
     return render_pass;
   }
+
+  /// on top of what the scene pass drew, the depth is only tested
+  fn init_transparent_pass<'a>(
+    &self,
+    view: &wgpu::TextureView,
+    resolve_target: Option<&wgpu::TextureView>,
+    depth: &wgpu::TextureView,
+    encoder: &'a mut wgpu::CommandEncoder,
+  ) -> RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("Transparent Pass"),
+      color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+        view,
+        resolve_target,
+        ops: wgpu::Operations {
+          load: wgpu::LoadOp::Load,
+          store: wgpu::StoreOp::Store,
+        },
+      })],
+      depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+        view: depth,
+        depth_ops: None,
+        stencil_ops: None,
+      }),
+      occlusion_query_set: None,
+      timestamp_writes: None,
+    })
+  }
 }
@@ -0,0 +1,507 @@
+// a frame is a list of passes that say which attachments (render targets) they read and write.
+// the graph owns the attachments, remakes them when the window changes size, and runs the
+// passes in an order where everything a pass reads has already been written.
+// adding a pass (post processing, ui, ...) doesn't need render to change at all
+
+use std::fmt;
+
+use crate::{
+  engine::Engine,
+  gpu::{device_drivers::Drivers, texture::DynamicTexture},
+};
+
+/// the window's surface (or the offscreen texture when headless), always there
+pub const SURFACE: &str = "surface";
+/// the scene's depth buffer
+pub const DEPTH: &str = "depth";
+/// what the scene renders into, before post processing
+pub const HDR: &str = "hdr";
+/// the post stack bounces between this and hdr
+pub const POST_SCRATCH: &str = "post_scratch";
+/// what the scene really renders into when msaa is on, it gets resolved into hdr
+pub const HDR_MSAA: &str = "hdr_msaa";
+/// the shadow atlas, owned by the LightBuffer
+pub const SHADOW_ATLAS: &str = "shadow_atlas";
+/// weighted blended transparency's color and coverage, only there once it's been turned on
+/// (see gpu/transparency.rs). the msaa ones get resolved into them like hdr_msaa
+pub const OIT_ACCUM: &str = "oit_accum";
+pub const OIT_ACCUM_MSAA: &str = "oit_accum_msaa";
+pub const OIT_REVEAL: &str = "oit_reveal";
+pub const OIT_REVEAL_MSAA: &str = "oit_reveal_msaa";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttachmentSize {
+  /// follows the surface around
+  Surface,
+  /// a fraction (or multiple) of the surface, for half res effects
+  Scaled(f32),
+  Fixed(u32, u32),
+}
+
+impl AttachmentSize {
+  fn resolve(&self, surface: (u32, u32)) -> (u32, u32) {
+    match *self {
+      AttachmentSize::Surface => surface,
+      AttachmentSize::Scaled(scale) => (
+        ((surface.0 as f32 * scale) as u32).max(1),
+        ((surface.1 as f32 * scale) as u32).max(1),
+      ),
+      AttachmentSize::Fixed(width, height) => (width.max(1), height.max(1)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentSamples {
+  Single,
+  /// follows GraphicsSettings::msaa_samples
+  Msaa,
+  /// same as Msaa, but not made at all while msaa is off.
+  /// for color targets that get resolved into a single sampled one
+  MsaaOnly,
+}
+
+impl AttachmentSamples {
+  /// None if the attachment shouldn't exist right now
+  fn resolve(&self, msaa_samples: u32) -> Option<u32> {
+    match self {
+      AttachmentSamples::Single => Some(1),
+      AttachmentSamples::Msaa => Some(msaa_samples),
+      AttachmentSamples::MsaaOnly if msaa_samples > 1 => Some(msaa_samples),
+      AttachmentSamples::MsaaOnly => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttachmentDesc {
+  pub format: wgpu::TextureFormat,
+  pub size: AttachmentSize,
+  pub samples: AttachmentSamples,
+}
+
+impl AttachmentDesc {
+  pub fn new(format: wgpu::TextureFormat, size: AttachmentSize) -> Self {
+    Self {
+      format,
+      size,
+      samples: AttachmentSamples::Single,
+    }
+  }
+
+  pub fn with_samples(mut self, samples: AttachmentSamples) -> Self {
+    self.samples = samples;
+    self
+  }
+}
+
+enum AttachmentKind {
+  /// made and resized by the graph
+  Owned {
+    desc: AttachmentDesc,
+    texture: Option<DynamicTexture>,
+    // the size and sample count it was last made with, so it only gets remade when they change
+    allocated: ((u32, u32), u32),
+  },
+  /// lives somewhere else (the surface, the shadow atlas), only here for ordering
+  External,
+}
+
+struct Attachment {
+  name: String,
+  kind: AttachmentKind,
+}
+
+/// what a pass touches, the graph orders passes by this
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PassDesc {
+  pub name: String,
+  pub reads: Vec<String>,
+  pub writes: Vec<String>,
+}
+
+impl PassDesc {
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      ..Default::default()
+    }
+  }
+
+  pub fn reads(mut self, attachment: &str) -> Self {
+    self.reads.push(attachment.to_owned());
+    self
+  }
+
+  pub fn writes(mut self, attachment: &str) -> Self {
+    self.writes.push(attachment.to_owned());
+    self
+  }
+}
+
+/// everything a pass gets while it runs
+pub struct PassContext<'a> {
+  pub engine: &'a Engine,
+  pub encoder: &'a mut wgpu::CommandEncoder,
+  graph: &'a RenderGraph,
+  surface: &'a wgpu::TextureView,
+}
+
+impl<'a> PassContext<'a> {
+  /// the view of an attachment to render into or sample from,
+  /// None for external attachments other than the surface
+  pub fn get_view(&self, name: &str) -> Option<&'a wgpu::TextureView> {
+    if name == SURFACE {
+      return Some(self.surface);
+    }
+    self.graph.get_attachment(name).map(|texture| &texture.view)
+  }
+
+  pub fn get_attachment(&self, name: &str) -> Option<&'a DynamicTexture> {
+    self.graph.get_attachment(name)
+  }
+
+  /// what to render into and what to resolve it into: msaa_name resolved into name
+  /// while msaa is on, or just name with it off. None if name isn't there
+  pub fn get_target(
+    &self,
+    name: &str,
+    msaa_name: &str,
+  ) -> Option<(&'a wgpu::TextureView, Option<&'a wgpu::TextureView>)> {
+    let view = self.get_view(name)?;
+    match self.get_view(msaa_name) {
+      Some(msaa) => Some((msaa, Some(view))),
+      None => Some((view, None)),
+    }
+  }
+}
+
+pub trait GraphPass {
+  fn desc(&self) -> PassDesc;
+  fn run(&self, context: &mut PassContext<'_>);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphError {
+  UnknownAttachment {
+    pass: String,
+    attachment: String,
+  },
+  DuplicateName(String),
+  /// the passes that read each other's outputs in a loop
+  Cycle(Vec<String>),
+}
+
+impl fmt::Display for GraphError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      GraphError::UnknownAttachment { pass, attachment } => write!(
+        f,
+        "pass {} uses the attachment {}, which was never added",
+        pass, attachment
+      ),
+      GraphError::DuplicateName(name) => write!(f, "{} was added twice", name),
+      GraphError::Cycle(passes) => {
+        write!(
+          f,
+          "these passes depend on each other: {}",
+          passes.join(", ")
+        )
+      }
+    }
+  }
+}
+
+impl std::error::Error for GraphError {}
+
+/// the passes a read of the attachment waits for: the last one added before the reader
+/// that writes it, so passes can change an attachment in place one after the other.
+/// a pass added before anything writes what it reads waits for every pass that does
+fn writers_of(passes: &[PassDesc], reader: usize, attachment: &String) -> Vec<usize> {
+  let writes = |other: &usize| *other != reader && passes[*other].writes.contains(attachment);
+  match (0..reader).rev().find(writes) {
+    Some(last) => vec![last],
+    None => (reader + 1..passes.len()).filter(writes).collect(),
+  }
+}
+
+/// the order to run the passes in. a pass runs after the pass that writes what it reads
+/// (see writers_of), passes writing the same attachment run in the order they were added
+pub fn sort_passes(passes: &[PassDesc]) -> Result<Vec<usize>, GraphError> {
+  let count = passes.len();
+  let mut depends_on = vec![Vec::new(); count];
+
+  for (index, pass) in passes.iter().enumerate() {
+    for read in pass.reads.iter() {
+      depends_on[index].extend(writers_of(passes, index, read));
+    }
+    for (other, earlier) in passes.iter().enumerate().take(index) {
+      let writes_after_it = pass
+        .writes
+        .iter()
+        .any(|write| earlier.writes.contains(write));
+      if writes_after_it {
+        depends_on[index].push(other);
+      }
+    }
+  }
+
+  // kahn's algorithm, always picking the earliest added pass that's ready
+  let mut done = vec![false; count];
+  let mut order = Vec::with_capacity(count);
+  while order.len() < count {
+    let ready = (0..count).find(|index| {
+      !done[*index]
+        && depends_on[*index]
+          .iter()
+          .all(|dependency| done[*dependency])
+    });
+
+    match ready {
+      Some(index) => {
+        done[index] = true;
+        order.push(index);
+      }
+      None => {
+        let stuck = (0..count)
+          .filter(|index| !done[*index])
+          .map(|index| passes[index].name.clone())
+          .collect();
+        return Err(GraphError::Cycle(stuck));
+      }
+    }
+  }
+
+  Ok(order)
+}
+
+pub struct RenderGraph {
+  attachments: Vec<Attachment>,
+  passes: Vec<Box<dyn GraphPass>>,
+  order: Vec<usize>,
+}
+
+impl RenderGraph {
+  pub fn new() -> Self {
+    let mut graph = Self {
+      attachments: Vec::new(),
+      passes: Vec::new(),
+      order: Vec::new(),
+    };
+    graph.attachments.push(Attachment {
+      name: SURFACE.to_owned(),
+      kind: AttachmentKind::External,
+    });
+    graph
+  }
+
+  pub fn has_attachment(&self, name: &str) -> bool {
+    self
+      .attachments
+      .iter()
+      .any(|attachment| attachment.name == name)
+  }
+
+  fn push_attachment(&mut self, name: &str, kind: AttachmentKind) -> Result<(), GraphError> {
+    if self.has_attachment(name) {
+      return Err(GraphError::DuplicateName(name.to_owned()));
+    }
+    self.attachments.push(Attachment {
+      name: name.to_owned(),
+      kind,
+    });
+    Ok(())
+  }
+
+  /// a render target the graph makes, it shows up on the next prepare
+  pub fn add_attachment(&mut self, name: &str, desc: AttachmentDesc) -> Result<(), GraphError> {
+    let kind = AttachmentKind::Owned {
+      desc,
+      texture: None,
+      allocated: ((0, 0), 0),
+    };
+    self.push_attachment(name, kind)
+  }
+
+  /// something a pass renders into that's owned by someone else, just so passes get ordered
+  pub fn add_external(&mut self, name: &str) -> Result<(), GraphError> {
+    self.push_attachment(name, AttachmentKind::External)
+  }
+
+  /// the pass gets slotted in wherever its attachments say it has to go.
+  /// on error the graph is left as it was
+  pub fn add_pass(&mut self, pass: Box<dyn GraphPass>) -> Result<(), GraphError> {
+    let desc = pass.desc();
+    if self
+      .passes
+      .iter()
+      .any(|existing| existing.desc().name == desc.name)
+    {
+      return Err(GraphError::DuplicateName(desc.name));
+    }
+    if let Some(unknown) = desc
+      .reads
+      .iter()
+      .chain(&desc.writes)
+      .find(|attachment| !self.has_attachment(attachment))
+    {
+      return Err(GraphError::UnknownAttachment {
+        pass: desc.name,
+        attachment: unknown.clone(),
+      });
+    }
+
+    let mut descs: Vec<PassDesc> = self.passes.iter().map(|pass| pass.desc()).collect();
+    descs.push(desc);
+    self.order = sort_passes(&descs)?;
+    self.passes.push(pass);
+    Ok(())
+  }
+
+  /// pass names in the order they run
+  pub fn get_order(&self) -> Vec<String> {
+    self
+      .order
+      .iter()
+      .map(|index| self.passes[*index].desc().name)
+      .collect()
+  }
+
+  /// None for external attachments, MsaaOnly ones while msaa is off, or before the first prepare
+  pub fn get_attachment(&self, name: &str) -> Option<&DynamicTexture> {
+    let attachment = self.attachments.iter().find(|a| a.name == name)?;
+    match &attachment.kind {
+      AttachmentKind::Owned { texture, .. } => texture.as_ref(),
+      AttachmentKind::External => None,
+    }
+  }
+
+  /// makes (or remakes) every attachment that doesn't match the surface's size
+  /// or the msaa sample count anymore
+  pub fn prepare(&mut self, drivers: &Drivers) {
+    let surface_size = drivers.get_size();
+
+    for attachment in &mut self.attachments {
+      let AttachmentKind::Owned {
+        desc,
+        texture,
+        allocated,
+      } = &mut attachment.kind
+      else {
+        continue;
+      };
+
+      let Some(samples) = desc.samples.resolve(drivers.get_sample_count()) else {
+        *texture = None;
+        continue;
+      };
+      let size = desc.size.resolve(surface_size);
+      if texture.is_some() && *allocated == (size, samples) {
+        continue;
+      }
+
+      *texture = Some(DynamicTexture::create_attachment(
+        drivers,
+        size,
+        desc.format,
+        samples,
+        &attachment.name,
+      ));
+      *allocated = (size, samples);
+    }
+  }
+
+  /// records every pass into the encoder, in order
+  pub fn execute(
+    &self,
+    engine: &Engine,
+    encoder: &mut wgpu::CommandEncoder,
+    surface: &wgpu::TextureView,
+  ) {
+    let mut context = PassContext {
+      engine,
+      encoder,
+      graph: self,
+      surface,
+    };
+
+    for index in &self.order {
+      self.passes[*index].run(&mut context);
+    }
+  }
+}
+
+impl Default for RenderGraph {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn names(passes: &[PassDesc], order: &[usize]) -> Vec<String> {
+    order
+      .iter()
+      .map(|index| passes[*index].name.clone())
+      .collect()
+  }
+
+  #[test]
+  fn passes_run_after_what_they_read() {
+    let passes = [
+      PassDesc::new("post").reads("hdr").writes(SURFACE),
+      PassDesc::new("scene").reads("shadows").writes("hdr"),
+      PassDesc::new("shadows").writes("shadows"),
+      PassDesc::new("ui").writes(SURFACE),
+    ];
+    let order = sort_passes(&passes).unwrap();
+    assert_eq!(names(&passes, &order), ["shadows", "scene", "post", "ui"]);
+  }
+
+  #[test]
+  fn loops_are_errors() {
+    let passes = [
+      PassDesc::new("a").reads("y").writes("x"),
+      PassDesc::new("b").reads("x").writes("y"),
+      PassDesc::new("c").writes("z"),
+    ];
+    let error = sort_passes(&passes).unwrap_err();
+    assert_eq!(
+      error,
+      GraphError::Cycle(vec!["a".to_owned(), "b".to_owned()])
+    );
+  }
+
+  #[test]
+  fn passes_can_change_an_attachment_in_place() {
+    let passes = [
+      PassDesc::new("scene").writes("hdr"),
+      PassDesc::new("post")
+        .reads("hdr")
+        .writes("hdr")
+        .writes(SURFACE),
+      PassDesc::new("bloom").reads("hdr").writes("hdr"),
+      PassDesc::new("ui").reads("hdr").writes(SURFACE),
+    ];
+    let order = sort_passes(&passes).unwrap();
+    assert_eq!(names(&passes, &order), ["scene", "post", "bloom", "ui"]);
+  }
+
+  #[test]
+  fn attachments_follow_the_surface() {
+    assert_eq!(AttachmentSize::Surface.resolve((640, 480)), (640, 480));
+    assert_eq!(AttachmentSize::Scaled(0.5).resolve((640, 480)), (320, 240));
+    assert_eq!(AttachmentSize::Scaled(0.0).resolve((640, 480)), (1, 1));
+    assert_eq!(AttachmentSize::Fixed(64, 32).resolve((640, 480)), (64, 32));
+  }
+
+  #[test]
+  fn msaa_only_attachments_come_and_go() {
+    assert_eq!(AttachmentSamples::Single.resolve(4), Some(1));
+    assert_eq!(AttachmentSamples::Msaa.resolve(1), Some(1));
+    assert_eq!(AttachmentSamples::MsaaOnly.resolve(4), Some(4));
+    assert_eq!(AttachmentSamples::MsaaOnly.resolve(1), None);
+  }
+}
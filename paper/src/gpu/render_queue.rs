@@ -0,0 +1,146 @@
+// every draw of the frame, sorted so the gpu has to change as little as possible between them.
+// opaque draws are grouped by pipeline and then material, and drawn front to back inside that
+// so the depth test can skip what's hidden. transparent ones go back to front after them
+
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::gpu::{material::MaterialId, mesh::Mesh};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+  /// the shader's index in the scene
+  pub pipeline: usize,
+  pub material: MaterialId,
+  /// squared distance from the camera
+  pub depth: f32,
+}
+
+impl SortKey {
+  /// pipeline, then material, then front to back
+  pub fn cmp_opaque(&self, other: &SortKey) -> Ordering {
+    self
+      .pipeline
+      .cmp(&other.pipeline)
+      .then(self.material.cmp(&other.material))
+      .then(self.depth.total_cmp(&other.depth))
+  }
+
+  /// back to front, blending needs whatever's behind to be there already.
+  /// the pipeline and material only matter when two are just as far away
+  pub fn cmp_transparent(&self, other: &SortKey) -> Ordering {
+    other
+      .depth
+      .total_cmp(&self.depth)
+      .then(self.pipeline.cmp(&other.pipeline))
+      .then(self.material.cmp(&other.material))
+  }
+}
+
+pub struct QueuedDraw {
+  pub mesh: Arc<Mesh>,
+  pub key: SortKey,
+  /// which slot of the location buffer holds this mesh's transform
+  pub location_slot: u32,
+  /// false when it's outside the camera's frustum, it still casts shadows though
+  pub visible: bool,
+}
+
+impl QueuedDraw {
+  #[inline]
+  pub fn get_shader(&self) -> usize {
+    self.key.pipeline
+  }
+}
+
+#[derive(Default)]
+pub struct RenderQueue {
+  opaque: Vec<QueuedDraw>,
+  transparent: Vec<QueuedDraw>,
+}
+
+impl RenderQueue {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn clear(&mut self) {
+    self.opaque.clear();
+    self.transparent.clear();
+  }
+
+  pub fn push_opaque(&mut self, draw: QueuedDraw) {
+    self.opaque.push(draw);
+  }
+
+  pub fn push_transparent(&mut self, draw: QueuedDraw) {
+    self.transparent.push(draw);
+  }
+
+  pub fn sort(&mut self) {
+    self.opaque.sort_by(|a, b| a.key.cmp_opaque(&b.key));
+    self
+      .transparent
+      .sort_by(|a, b| a.key.cmp_transparent(&b.key));
+  }
+
+  /// opaque and alpha cutout draws, culled ones too (the shadows want those)
+  pub fn iter_opaque(&self) -> impl Iterator<Item = &QueuedDraw> {
+    self.opaque.iter()
+  }
+
+  /// blended draws back to front, culled ones too
+  pub fn iter_transparent(&self) -> impl Iterator<Item = &QueuedDraw> {
+    self.transparent.iter()
+  }
+}
+
+/// what the scene pass did last frame, the fewer pipelines and binds per draw the better
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+  /// how many times the pipeline was switched
+  pub pipelines: u32,
+  pub bind_groups: u32,
+  pub draws: u32,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn key(pipeline: usize, material: MaterialId, depth: f32) -> SortKey {
+    SortKey {
+      pipeline,
+      material,
+      depth,
+    }
+  }
+
+  #[test]
+  fn opaque_groups_state_and_transparent_goes_back_to_front() {
+    // handed out in order, so a < b < c
+    let [a, b, c] = [(); 3].map(|_| MaterialId::next());
+    let mut keys = vec![
+      key(1, b, 1.0),
+      key(0, c, 5.0),
+      key(0, a, 8.0),
+      key(0, c, 2.0),
+      key(1, b, 0.5),
+    ];
+
+    keys.sort_by(SortKey::cmp_opaque);
+    assert_eq!(
+      keys,
+      vec![
+        key(0, a, 8.0),
+        key(0, c, 2.0),
+        key(0, c, 5.0),
+        key(1, b, 0.5),
+        key(1, b, 1.0),
+      ]
+    );
+
+    keys.sort_by(SortKey::cmp_transparent);
+    let depths: Vec<f32> = keys.iter().map(|key| key.depth).collect();
+    assert_eq!(depths, vec![8.0, 5.0, 2.0, 1.0, 0.5]);
+  }
+}
@@ -0,0 +1,306 @@
+// how the engine renders, as opposed to what it renders.
+// what's asked for isn't always what the gpu can do, so Drivers keeps the settings it
+// actually ended up using (see Drivers::get_settings)
+
+use crate::files;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentMode {
+  /// waits for the screen, no tearing, never faster than the refresh rate
+  Vsync,
+  /// no tearing, but doesn't wait, the newest frame replaces the queued one
+  Mailbox,
+  /// shows frames as soon as they're done, can tear
+  Immediate,
+}
+
+impl PresentMode {
+  pub fn to_wgpu(self) -> wgpu::PresentMode {
+    match self {
+      PresentMode::Vsync => wgpu::PresentMode::Fifo,
+      PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+      PresentMode::Immediate => wgpu::PresentMode::Immediate,
+    }
+  }
+
+  /// what it's called in the settings file
+  pub fn get_name(self) -> &'static str {
+    match self {
+      PresentMode::Vsync => "vsync",
+      PresentMode::Mailbox => "mailbox",
+      PresentMode::Immediate => "immediate",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    [
+      PresentMode::Vsync,
+      PresentMode::Mailbox,
+      PresentMode::Immediate,
+    ]
+    .into_iter()
+    .find(|mode| mode.get_name() == name)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendPreference {
+  /// whatever wgpu thinks is best
+  Auto,
+  Vulkan,
+  Metal,
+  Dx12,
+  Gl,
+}
+
+impl BackendPreference {
+  /// auto is only the primary backends with a window, gl is often all a ci box has
+  pub fn to_backends(self, headless: bool) -> wgpu::Backends {
+    match self {
+      BackendPreference::Auto if headless => wgpu::Backends::all(),
+      BackendPreference::Auto => wgpu::Backends::PRIMARY,
+      BackendPreference::Vulkan => wgpu::Backends::VULKAN,
+      BackendPreference::Metal => wgpu::Backends::METAL,
+      BackendPreference::Dx12 => wgpu::Backends::DX12,
+      BackendPreference::Gl => wgpu::Backends::GL,
+    }
+  }
+
+  /// None for backends there's no preference for (webgpu, or wgpu's placeholder one)
+  pub fn from_backend(backend: wgpu::Backend) -> Option<Self> {
+    match backend {
+      wgpu::Backend::Vulkan => Some(BackendPreference::Vulkan),
+      wgpu::Backend::Metal => Some(BackendPreference::Metal),
+      wgpu::Backend::Dx12 => Some(BackendPreference::Dx12),
+      wgpu::Backend::Gl => Some(BackendPreference::Gl),
+      _ => None,
+    }
+  }
+
+  /// what it's called in the settings file
+  pub fn get_name(self) -> &'static str {
+    match self {
+      BackendPreference::Auto => "auto",
+      BackendPreference::Vulkan => "vulkan",
+      BackendPreference::Metal => "metal",
+      BackendPreference::Dx12 => "dx12",
+      BackendPreference::Gl => "gl",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    [
+      BackendPreference::Auto,
+      BackendPreference::Vulkan,
+      BackendPreference::Metal,
+      BackendPreference::Dx12,
+      BackendPreference::Gl,
+    ]
+    .into_iter()
+    .find(|backend| backend.get_name() == name)
+  }
+}
+
+/// one attempt at getting an adapter, see adapter_fallbacks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdapterRequest {
+  pub backends: wgpu::Backends,
+  /// a software adapter, slow but it's better than not starting at all
+  pub force_fallback_adapter: bool,
+}
+
+/// what to try, in order, when the preferred backend has no adapter or won't give a device:
+/// the preference, then gl, then a software adapter on anything
+pub fn adapter_fallbacks(backend: BackendPreference, headless: bool) -> Vec<AdapterRequest> {
+  let preferred = backend.to_backends(headless);
+  let mut requests = vec![AdapterRequest {
+    backends: preferred,
+    force_fallback_adapter: false,
+  }];
+
+  if !preferred.contains(wgpu::Backends::GL) {
+    requests.push(AdapterRequest {
+      backends: wgpu::Backends::GL,
+      force_fallback_adapter: false,
+    });
+  }
+
+  requests.push(AdapterRequest {
+    backends: wgpu::Backends::all(),
+    force_fallback_adapter: true,
+  });
+  requests
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GraphicsSettings {
+  /// 1 turns msaa off, rounded down to what the gpu supports
+  pub msaa_samples: u32,
+  pub present_mode: PresentMode,
+  /// how many frames can be queued up, 1 is the least input lag
+  pub frame_latency: u32,
+  /// only used when the device is made, changing it needs a restart
+  pub backend: BackendPreference,
+}
+
+impl Default for GraphicsSettings {
+  fn default() -> Self {
+    Self {
+      msaa_samples: 1,
+      present_mode: PresentMode::Vsync,
+      frame_latency: 1,
+      backend: BackendPreference::Auto,
+    }
+  }
+}
+
+impl GraphicsSettings {
+  /// where the settings get saved, in the settings folder
+  pub const FILE_NAME: &'static str = "graphics.cfg";
+
+  /// one `key = value` per line, what GraphicsSettings::from_file_string reads back
+  pub fn to_file_string(&self) -> String {
+    format!(
+      "# delete this file to benchmark the backends again\n\
+       backend = {}\n\
+       msaa_samples = {}\n\
+       present_mode = {}\n\
+       frame_latency = {}\n",
+      self.backend.get_name(),
+      self.msaa_samples,
+      self.present_mode.get_name(),
+      self.frame_latency
+    )
+  }
+
+  /// anything missing or unreadable keeps its default, people edit these by hand
+  pub fn from_file_string(contents: &str) -> Self {
+    let mut settings = Self::default();
+
+    for line in contents.lines().map(str::trim) {
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let Some((key, value)) = line.split_once('=') else {
+        log::warn!("ignoring setting \"{}\", it should be key = value", line);
+        continue;
+      };
+      let (key, value) = (key.trim(), value.trim());
+
+      let parsed = match key {
+        "backend" => BackendPreference::from_name(value).map(|backend| settings.backend = backend),
+        "msaa_samples" => value
+          .parse()
+          .ok()
+          .map(|samples| settings.msaa_samples = samples),
+        "present_mode" => PresentMode::from_name(value).map(|mode| settings.present_mode = mode),
+        "frame_latency" => value
+          .parse()
+          .ok()
+          .map(|latency| settings.frame_latency = latency),
+        _ => {
+          log::warn!("ignoring unknown setting {}", key);
+          Some(())
+        }
+      };
+      if parsed.is_none() {
+        log::warn!("ignoring {} = {}, it isn't a valid value", key, value);
+      }
+    }
+
+    settings
+  }
+
+  /// None if nothing has been saved yet
+  pub fn load_file() -> std::io::Result<Option<Self>> {
+    match files::load_settings_str(Self::FILE_NAME) {
+      Ok(contents) => Ok(Some(Self::from_file_string(&contents))),
+      Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(error) => Err(error),
+    }
+  }
+
+  pub fn save_file(&self) -> std::io::Result<()> {
+    files::save_settings_str(Self::FILE_NAME, &self.to_file_string())
+  }
+}
+
+/// the most samples that's both supported and no more than asked for, 1 always works
+pub fn pick_sample_count(requested: u32, is_supported: impl Fn(u32) -> bool) -> u32 {
+  [16, 8, 4, 2]
+    .into_iter()
+    .find(|count| *count <= requested && is_supported(*count))
+    .unwrap_or(1)
+}
+
+/// vsync is the only mode every surface has to support, so everything falls back to it
+pub fn pick_present_mode(wanted: PresentMode, available: &[wgpu::PresentMode]) -> PresentMode {
+  match available.contains(&wanted.to_wgpu()) {
+    true => wanted,
+    false => PresentMode::Vsync,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sample_counts_round_down_to_what_works() {
+    let up_to_four = |count: u32| count <= 4;
+    assert_eq!(pick_sample_count(8, up_to_four), 4);
+    assert_eq!(pick_sample_count(4, up_to_four), 4);
+    assert_eq!(pick_sample_count(3, up_to_four), 2);
+    assert_eq!(pick_sample_count(0, up_to_four), 1);
+    assert_eq!(pick_sample_count(16, |count| count == 1), 1);
+  }
+
+  #[test]
+  fn present_modes_fall_back_to_vsync() {
+    let available = [wgpu::PresentMode::Fifo, wgpu::PresentMode::Immediate];
+    assert_eq!(
+      pick_present_mode(PresentMode::Immediate, &available),
+      PresentMode::Immediate
+    );
+    assert_eq!(
+      pick_present_mode(PresentMode::Mailbox, &available),
+      PresentMode::Vsync
+    );
+  }
+
+  #[test]
+  fn settings_files_round_trip() {
+    let settings = GraphicsSettings {
+      msaa_samples: 4,
+      present_mode: PresentMode::Mailbox,
+      frame_latency: 2,
+      backend: BackendPreference::Gl,
+    };
+    let read = GraphicsSettings::from_file_string(&settings.to_file_string());
+    assert_eq!(read, settings);
+
+    // junk doesn't stop the rest from being read
+    let read = GraphicsSettings::from_file_string("backend = dx12\nmsaa_samples = lots\nnonsense");
+    assert_eq!(read.backend, BackendPreference::Dx12);
+    assert_eq!(read.msaa_samples, 1);
+  }
+
+  #[test]
+  fn adapters_fall_back_to_gl_then_software() {
+    let requests = adapter_fallbacks(BackendPreference::Vulkan, false);
+    let backends: Vec<_> = requests.iter().map(|request| request.backends).collect();
+    assert_eq!(
+      backends,
+      [
+        wgpu::Backends::VULKAN,
+        wgpu::Backends::GL,
+        wgpu::Backends::all()
+      ]
+    );
+    assert!(requests.last().unwrap().force_fallback_adapter);
+
+    // gl is already the first thing tried
+    assert_eq!(adapter_fallbacks(BackendPreference::Gl, false).len(), 2);
+    assert_eq!(adapter_fallbacks(BackendPreference::Auto, true).len(), 2);
+  }
+}
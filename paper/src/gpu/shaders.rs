@@ -1,6 +1,17 @@
-use std::{sync::Arc};
+use std::{collections::HashMap, sync::Arc};
 
-use crate::gpu::{device_drivers::Drivers, lights, mesh};
+pub mod pipeline_state;
+pub mod validation;
+
+use crate::files::{preprocessor::SourceMap, shader_watcher::ShaderChange};
+use pipeline_state::PipelineState;
+use validation::{ShaderError, ShaderErrorKind, ValidatedShader};
+use crate::gpu::{
+  device_drivers::Drivers,
+  material::BlendMode,
+  mesh,
+  transparency::{TransparencyMode, TransparencyPass},
+};
 #[allow(unused)]
 use crate::gpu::{
   device_drivers,
@@ -10,32 +21,131 @@ use crate::gpu::{
 
 pub struct RenderingBundle {
   shaders: Vec<ShaderPipeline>,
-  lights: Vec<lights::Light>,
+  // every pipeline's index by what it was built from, the file, defines and state
+  // are all in the builder
+  cache: HashMap<(ShaderBuilder, BlendMode), usize>,
+  // the debug view, every pipeline drawn as lines
+  wireframe: bool,
 }
 
 impl RenderingBundle {
   pub fn new() -> Self {
     Self {
       shaders: Vec::new(),
-      lights: Vec::new(),
+      cache: HashMap::new(),
+      wireframe: false,
     }
   }
 
   pub fn add_shader(&mut self, shader: ShaderPipeline) -> anyhow::Result<()> {
+    // a second one with the same key still gets drawn, but only the first is found again
+    let key = (shader.shader_builder.clone(), shader.blend_mode);
+    self.cache.entry(key).or_insert(self.shaders.len());
     self.shaders.push(shader);
     return Ok(());
   }
 
-  pub fn add_light(&mut self, light: lights::Light) {
-    self.lights.push(light);
-  }
-
   pub fn iter_shaders<'a>(&'a self) -> impl Iterator<Item = &'a ShaderPipeline> {
     self.shaders.iter()
   }
 
-  pub fn iter_mut_lights<'a>(&'a mut self) -> impl Iterator<Item = &'a mut lights::Light> {
-    self.lights.iter_mut()
+  pub fn get_shader(&self, index: usize) -> Option<&ShaderPipeline> {
+    self.shaders.get(index)
+  }
+
+  pub fn get_shader_mut(&mut self, index: usize) -> Option<&mut ShaderPipeline> {
+    self.shaders.get_mut(index)
+  }
+
+  #[inline]
+  pub fn get_shader_count(&self) -> usize {
+    self.shaders.len()
+  }
+
+  /// the pipeline that was already built from the same file, defines, state and blend mode,
+  /// if there is one
+  pub fn find_shader(
+    &self,
+    shader_builder: &ShaderBuilder,
+    blend_mode: BlendMode,
+  ) -> Option<usize> {
+    self
+      .cache
+      .get(&(shader_builder.clone(), blend_mode))
+      .copied()
+  }
+
+  #[inline]
+  pub fn is_wireframe(&self) -> bool {
+    self.wireframe
+  }
+
+  /// draws every pipeline's triangles as lines, or back the way they were built.
+  /// does nothing and gives back false if the device can't draw lines
+  pub fn set_wireframe(&mut self, drivers: &Drivers, wireframe: bool) -> bool {
+    if wireframe && !drivers.supports_wireframe() {
+      log::warn!("the gpu can't draw lines, so there's no wireframe view");
+      return false;
+    }
+
+    self.wireframe = wireframe;
+    for pipeline in self.shaders.iter_mut() {
+      if let Err(error) = pipeline.set_wireframe(drivers, wireframe) {
+        log::error!(
+          "failed to rebuild shader {} for the wireframe view: {}",
+          pipeline.get_name(),
+          error
+        );
+      }
+    }
+    true
+  }
+
+  /// rebuilds the pipelines that switch between the scene and the weighted blended targets,
+  /// the ones that don't compile keep drawing the old way
+  pub fn set_transparency_mode(&mut self, drivers: &Drivers, mode: TransparencyMode) {
+    for pipeline in self.shaders.iter_mut() {
+      if let Err(error) = pipeline.set_transparency_mode(drivers, mode) {
+        log::error!(
+          "failed to rebuild shader {} for {:?} transparency: {}",
+          pipeline.get_name(),
+          mode,
+          error
+        );
+      }
+    }
+  }
+
+  /// rebuilds every pipeline touched by the changed files,
+  /// if the new source is broken the old pipeline just keeps on rendering
+  pub fn reload_shaders(&mut self, drivers: &Drivers, changes: &[ShaderChange]) {
+    for pipeline in self.shaders.iter_mut() {
+      if !pipeline.is_affected_by(changes) {
+        continue;
+      }
+
+      match pipeline.rebuild(drivers) {
+        Ok(_) => log::info!("reloaded shader: {}", pipeline.get_name()),
+        Err(error) => log::error!(
+          "failed to reload shader {}, keeping the old one: {}",
+          pipeline.get_name(),
+          error
+        ),
+      }
+    }
+  }
+
+  /// rebuilds every pipeline, for when something they all depend on changed (like msaa)
+  pub fn rebuild_shaders(&mut self, drivers: &Drivers) {
+    for pipeline in self.shaders.iter_mut() {
+      if let Err(error) = pipeline.rebuild(drivers) {
+        log::error!(
+          "failed to rebuild shader {}, it won't be drawn: {}",
+          pipeline.get_name(),
+          error
+        );
+      }
+    }
   }
 
   pub fn get_meshes(&self) -> Vec<Arc<mesh::Mesh>> {
@@ -49,43 +159,109 @@ impl RenderingBundle {
   }
 }
 
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct ShaderBuilder {
   shader_file: Option<String>,
+  defines: Vec<String>,
+  state: PipelineState,
+}
+
+pub struct CompiledShader {
+  pub module: wgpu::ShaderModule,
+  pub source_map: SourceMap,
 }
 
 impl ShaderBuilder {
   pub fn from_file(filename: String) -> Self {
     Self {
       shader_file: Some(filename),
+      defines: Vec::new(),
+      state: PipelineState::default(),
     }
   }
 
-  pub fn build(self, drivers: &Drivers) -> Option<wgpu::ShaderModule> {
-    let filename = self.shader_file?;
-    let shader_string = crate::files::load_shader_str(&filename).ok()?;
+  /// turns on an #ifdef feature toggle in the shader
+  pub fn define(mut self, name: &str) -> Self {
+    self.defines.push(name.to_owned());
+    self
+  }
+
+  /// culling, polygon mode, topology and depth, see PipelineState
+  pub fn with_state(mut self, state: PipelineState) -> Self {
+    self.state = state;
+    self
+  }
+
+  #[inline]
+  pub fn get_state(&self) -> &PipelineState {
+    &self.state
+  }
 
-    let shader = drivers
+  pub fn get_file(&self) -> Option<&str> {
+    self.shader_file.as_deref()
+  }
+
+  /// preprocesses and validates the shader before handing it to wgpu,
+  /// so a typo gives back a proper error instead of taking down the engine
+  pub fn validate(&self) -> Result<ValidatedShader, ShaderError> {
+    let filename = self.shader_file.as_deref().ok_or(ShaderError::new(
+      ShaderErrorKind::Preprocess,
+      "",
+      String::from("no shader file was given"),
+    ))?;
+    validation::validate_shader(filename, &self.defines)
+  }
+
+  fn create_module(&self, drivers: &Drivers, shader: &ValidatedShader) -> wgpu::ShaderModule {
+    drivers
       .device
       .create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: Some("Shader"),
-        source: wgpu::ShaderSource::Wgsl(shader_string.into()),
-      });
+        label: self.get_file(),
+        source: wgpu::ShaderSource::Wgsl(shader.source.as_str().into()),
+      })
+  }
 
-    return Some(shader);
+  pub fn build(self, drivers: &Drivers) -> Result<CompiledShader, ShaderError> {
+    let shader = self.validate()?;
+    let module = self.create_module(drivers, &shader);
+
+    return Ok(CompiledShader {
+      module,
+      source_map: shader.source_map,
+    });
   }
 }
 
 pub struct ShaderPipeline {
   pub render_pipeline: wgpu::RenderPipeline,
   pub meshes: Vec<Arc<mesh::Mesh>>,
+
+  // kept around so the pipeline can be rebuilt when the file changes
+  shader_builder: ShaderBuilder,
+  registry: gpu_pointers::BindingRegistry,
+  // what the shader asked for last time it compiled, a reload can change these
+  bindgroups: gpu_pointers::MemoryLayouts,
+  source_map: SourceMap,
+  // the msaa sample count it was built for, it can't draw into anything else
+  sample_count: u32,
+  blend_mode: BlendMode,
+  // draws into the weighted blended targets instead of the scene
+  order_independent: bool,
+  // the debug view, overrides the builder's polygon mode with lines
+  wireframe: bool,
+}
+
+struct CompiledPipeline {
+  render_pipeline: wgpu::RenderPipeline,
+  bindgroups: gpu_pointers::MemoryLayouts,
+  source_map: SourceMap,
+  sample_count: u32,
 }
 
 impl ShaderPipeline {
   fn get_gpu_vertex_buffers<'a>() -> Vec<wgpu::VertexBufferLayout<'static>> {
-    let buffers: Vec<wgpu::VertexBufferLayout<'static>> = vec![
-      ModelVertex::desc(),
-      //instances::Instance::desc()
-    ];
+    let buffers: Vec<wgpu::VertexBufferLayout<'static>> =
+      vec![ModelVertex::desc(), instances::Instance::desc()];
 
     return buffers;
   }
@@ -122,36 +298,19 @@ impl ShaderPipeline {
 
   /* RENDERING PARAMETERS */
 
-  fn init_gpu_primitives_state() -> wgpu::PrimitiveState {
-    wgpu::PrimitiveState {
-      topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-      strip_index_format: None,
-      front_face: wgpu::FrontFace::Ccw, // 2.
-      // depth testing makes backface culling more expensive than it's worth
-      // cull_mode: Some(wgpu::Face::Back),
-      cull_mode: None,
-      // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-      polygon_mode: wgpu::PolygonMode::Fill,
-      // Requires Features::DEPTH_CLIP_CONTROL
-      unclipped_depth: false,
-      // Requires Features::CONSERVATIVE_RASTERIZATION
-      conservative: false,
+  // the state the builder asked for, with the wireframe view drawn over it
+  fn get_state(shader_builder: &ShaderBuilder, wireframe: bool) -> PipelineState {
+    match wireframe {
+      true => shader_builder
+        .state
+        .with_polygon_mode(wgpu::PolygonMode::Line),
+      false => shader_builder.state,
     }
   }
 
-  fn init_depth_buffer() -> wgpu::DepthStencilState {
-    wgpu::DepthStencilState {
-      format: texture::DynamicTexture::DEPTH_BUFFER_FORMAT,
-      depth_write_enabled: true,
-      depth_compare: wgpu::CompareFunction::Less,
-      stencil: wgpu::StencilState::default(),
-      bias: wgpu::DepthBiasState::default(),
-    }
-  }
-
-  fn init_msaa() -> wgpu::MultisampleState {
+  fn init_msaa(sample_count: u32) -> wgpu::MultisampleState {
     wgpu::MultisampleState {
-      count: 1,                         // 2.
+      count: sample_count,              // 2.
       mask: !0,                         // 3.
       alpha_to_coverage_enabled: false, // 4.
     }
@@ -161,8 +320,11 @@ impl ShaderPipeline {
   pub fn init_render_pipeline(
     device: &wgpu::Device,
     shader_module: &wgpu::ShaderModule,
-    surface_config: &wgpu::SurfaceConfiguration,
     bindgroup_data: &gpu_pointers::MemoryLayouts,
+    sample_count: u32,
+    blend_mode: BlendMode,
+    order_independent: bool,
+    state: &PipelineState,
   ) -> wgpu::RenderPipeline {
     let render_pipeline_layout = {
       let slice = &bindgroup_data.collect_slice();
@@ -175,25 +337,30 @@ impl ShaderPipeline {
     };
 
     let gpu_buffers = Self::get_gpu_vertex_buffers();
-    let color_target = [Some(wgpu::ColorTargetState {
-      // 4.
-      format: surface_config.format,
-      blend: Some(wgpu::BlendState::REPLACE),
+    // the scene goes into the hdr attachment, the post stack gets it onto the surface
+    let scene_target = [Some(wgpu::ColorTargetState {
+      format: texture::DynamicTexture::HDR_FORMAT,
+      blend: Some(blend_mode.get_blend_state()),
       write_mask: wgpu::ColorWrites::ALL,
     })];
+    let accumulate_targets = TransparencyPass::get_accumulate_targets();
+    let color_target: &[Option<wgpu::ColorTargetState>] = match order_independent {
+      true => &accumulate_targets,
+      false => &scene_target,
+    };
 
     let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
       label: Some("Render Pipeline"),
       layout: Some(&render_pipeline_layout),
 
       vertex: Self::init_vertex_state(shader_module, &gpu_buffers),
-      fragment: Some(Self::init_fragment_state(shader_module, &color_target)),
+      fragment: Some(Self::init_fragment_state(shader_module, color_target)),
 
-      primitive: Self::init_gpu_primitives_state(),
+      primitive: state.get_primitive_state(device.features()),
 
-      depth_stencil: Some(Self::init_depth_buffer()),
+      depth_stencil: Some(state.get_depth_stencil_state(blend_mode)),
 
-      multisample: Self::init_msaa(),
+      multisample: Self::init_msaa(sample_count),
 
       multiview: None, // 5.
       cache: None,     // 6.
@@ -201,32 +368,229 @@ impl ShaderPipeline {
     return render_pipeline;
   }
 
-  fn create_shader_module(
-    drivers: &device_drivers::Drivers,
-    shader_builder: ShaderBuilder,
-  ) -> anyhow::Result<wgpu::ShaderModule> {
-    shader_builder
-      .build(drivers)
-      .ok_or(anyhow::Error::msg(String::from("failed to build shader")))
+  /// the defines the blend mode needs on top of the builder's own (see sample.wgsl)
+  fn blend_defines(
+    shader_builder: &ShaderBuilder,
+    blend_mode: BlendMode,
+    order_independent: bool,
+  ) -> ShaderBuilder {
+    let mut builder = shader_builder.clone();
+    if blend_mode == BlendMode::AlphaCutout {
+      builder = builder.define("ALPHA_CUTOUT");
+    }
+    if blend_mode == BlendMode::Premultiplied {
+      builder = builder.define("PREMULTIPLIED");
+    }
+    if order_independent {
+      builder = builder.define("OIT");
+    }
+    builder
   }
 
-  pub async fn from_shader(
-    bindgroups: &gpu_pointers::MemoryLayouts,
+  /// compiles the shader and the pipeline around it,
+  /// anything wgpu complains about gets returned instead of crashing the engine
+  fn compile_pipeline(
     drivers: &device_drivers::Drivers,
-    shader_builder: ShaderBuilder,
-  ) -> anyhow::Result<Self> {
-    let shader = Self::create_shader_module(drivers, shader_builder)?;
+    shader_builder: &ShaderBuilder,
+    registry: &gpu_pointers::BindingRegistry,
+    blend_mode: BlendMode,
+    order_independent: bool,
+    wireframe: bool,
+  ) -> Result<CompiledPipeline, ShaderError> {
+    let shader_builder = &Self::blend_defines(shader_builder, blend_mode, order_independent);
+    let shader = shader_builder.validate()?;
+    let name = shader_builder.get_file().unwrap_or("");
+
+    let bindgroups = gpu_pointers::MemoryLayouts::reflect(&shader.module, registry)
+      .map_err(|error| ShaderError::from_binding_error(error, &shader, name))?;
+
+    drivers
+      .device
+      .push_error_scope(wgpu::ErrorFilter::Validation);
 
+    let module = shader_builder.create_module(drivers, &shader);
     let render_pipeline = Self::init_render_pipeline(
       &drivers.device,
-      &shader,
-      &drivers.surface_config,
-      bindgroups,
+      &module,
+      &bindgroups,
+      drivers.get_sample_count(),
+      blend_mode,
+      order_independent,
+      &Self::get_state(shader_builder, wireframe),
     );
 
-    Ok(Self {
+    let validation_error = pollster::block_on(drivers.device.pop_error_scope());
+    if let Some(error) = validation_error {
+      let message = error.to_string();
+      return Err(ShaderError::new(ShaderErrorKind::Pipeline, name, message));
+    }
+
+    Ok(CompiledPipeline {
       render_pipeline,
+      bindgroups,
+      source_map: shader.source_map,
+      sample_count: drivers.get_sample_count(),
+    })
+  }
+
+  pub fn new(
+    registry: &gpu_pointers::BindingRegistry,
+    drivers: &device_drivers::Drivers,
+    shader_builder: ShaderBuilder,
+  ) -> Result<Self, ShaderError> {
+    Self::new_blended(
+      registry,
+      drivers,
+      shader_builder,
+      BlendMode::Opaque,
+      TransparencyMode::default(),
+    )
+  }
+
+  /// a pipeline for meshes with this blend mode, the transparency mode decides whether
+  /// alpha blended ones draw into the scene or the weighted blended targets
+  pub fn new_blended(
+    registry: &gpu_pointers::BindingRegistry,
+    drivers: &device_drivers::Drivers,
+    shader_builder: ShaderBuilder,
+    blend_mode: BlendMode,
+    transparency: TransparencyMode,
+  ) -> Result<Self, ShaderError> {
+    let order_independent = transparency.is_order_independent(blend_mode);
+    let compiled = Self::compile_pipeline(
+      drivers,
+      &shader_builder,
+      registry,
+      blend_mode,
+      order_independent,
+      false,
+    )?;
+
+    Ok(Self {
+      render_pipeline: compiled.render_pipeline,
       meshes: vec![],
+      shader_builder,
+      registry: registry.clone(),
+      bindgroups: compiled.bindgroups,
+      source_map: compiled.source_map,
+      sample_count: compiled.sample_count,
+      blend_mode,
+      order_independent,
+      wireframe: false,
     })
   }
+
+  pub async fn from_shader(
+    registry: &gpu_pointers::BindingRegistry,
+    drivers: &device_drivers::Drivers,
+    shader_builder: ShaderBuilder,
+  ) -> Result<Self, ShaderError> {
+    Self::new(registry, drivers, shader_builder)
+  }
+
+  /// the bind group layouts that were reflected out of the shader
+  pub fn get_bindgroups(&self) -> &gpu_pointers::MemoryLayouts {
+    &self.bindgroups
+  }
+
+  #[inline]
+  pub fn get_sample_count(&self) -> u32 {
+    self.sample_count
+  }
+
+  #[inline]
+  pub fn get_blend_mode(&self) -> BlendMode {
+    self.blend_mode
+  }
+
+  /// true when it draws into the weighted blended targets instead of the scene
+  #[inline]
+  pub fn is_order_independent(&self) -> bool {
+    self.order_independent
+  }
+
+  pub fn get_name(&self) -> &str {
+    self.shader_builder.get_file().unwrap_or("unnamed shader")
+  }
+
+  pub fn is_affected_by(&self, changes: &[ShaderChange]) -> bool {
+    changes
+      .iter()
+      .any(|change| self.source_map.depends_on(change.get_path()))
+  }
+
+  /// recompiles the shader from disk, keeping the meshes,
+  /// the old pipeline is left untouched if anything fails
+  pub fn rebuild(&mut self, drivers: &device_drivers::Drivers) -> Result<(), ShaderError> {
+    let compiled = Self::compile_pipeline(
+      drivers,
+      &self.shader_builder,
+      &self.registry,
+      self.blend_mode,
+      self.order_independent,
+      self.wireframe,
+    )?;
+    self.use_compiled(compiled);
+    Ok(())
+  }
+
+  fn use_compiled(&mut self, compiled: CompiledPipeline) {
+    self.render_pipeline = compiled.render_pipeline;
+    self.bindgroups = compiled.bindgroups;
+    self.source_map = compiled.source_map;
+    self.sample_count = compiled.sample_count;
+  }
+
+  /// rebuilds it if the mode changes where it draws into, it's left as it was if that fails
+  pub fn set_transparency_mode(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    mode: TransparencyMode,
+  ) -> Result<(), ShaderError> {
+    let order_independent = mode.is_order_independent(self.blend_mode);
+    if order_independent == self.order_independent {
+      return Ok(());
+    }
+
+    let compiled = Self::compile_pipeline(
+      drivers,
+      &self.shader_builder,
+      &self.registry,
+      self.blend_mode,
+      order_independent,
+      self.wireframe,
+    )?;
+    self.use_compiled(compiled);
+    self.order_independent = order_independent;
+    Ok(())
+  }
+
+  #[inline]
+  pub fn is_wireframe(&self) -> bool {
+    self.wireframe
+  }
+
+  /// rebuilds it drawing only the edges of its triangles, or back the way it was built.
+  /// it's left as it was if that fails
+  pub fn set_wireframe(
+    &mut self,
+    drivers: &device_drivers::Drivers,
+    wireframe: bool,
+  ) -> Result<(), ShaderError> {
+    if wireframe == self.wireframe {
+      return Ok(());
+    }
+
+    let compiled = Self::compile_pipeline(
+      drivers,
+      &self.shader_builder,
+      &self.registry,
+      self.blend_mode,
+      self.order_independent,
+      wireframe,
+    )?;
+    self.use_compiled(compiled);
+    self.wireframe = wireframe;
+    Ok(())
+  }
 }
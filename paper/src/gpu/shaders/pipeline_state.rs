@@ -0,0 +1,204 @@
+// how a pipeline rasterizes and depth tests its triangles. it's part of the ShaderBuilder,
+// so it's part of what pipelines get cached by too: objects only share a pipeline when
+// the state matches. the defaults are what every pipeline was hardcoded to before
+
+use crate::gpu::{material::BlendMode, texture::DynamicTexture};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineState {
+  /// depth testing makes backface culling more expensive than it's worth, so it's off by default
+  pub cull_mode: Option<wgpu::Face>,
+  pub front_face: wgpu::FrontFace,
+  /// Line needs Features::POLYGON_MODE_LINE and Point POLYGON_MODE_POINT,
+  /// without them it quietly goes back to Fill
+  pub polygon_mode: wgpu::PolygonMode,
+  /// the mesh's indices get read as whatever this says, LineList turns triangles into edges
+  pub topology: wgpu::PrimitiveTopology,
+  /// off lets everything through, no matter what's in front of it
+  pub depth_test: bool,
+  /// transparent blend modes never write depth, whatever this says
+  pub depth_write: bool,
+  pub depth_compare: wgpu::CompareFunction,
+  /// pushes the depth back (or forward), for decals and outlines fighting with what they're on
+  pub depth_bias: wgpu::DepthBiasState,
+}
+
+impl Default for PipelineState {
+  fn default() -> Self {
+    Self {
+      cull_mode: None,
+      front_face: wgpu::FrontFace::Ccw,
+      polygon_mode: wgpu::PolygonMode::Fill,
+      topology: wgpu::PrimitiveTopology::TriangleList,
+      depth_test: true,
+      depth_write: true,
+      depth_compare: wgpu::CompareFunction::Less,
+      depth_bias: wgpu::DepthBiasState::default(),
+    }
+  }
+}
+
+impl PipelineState {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// just the edges of every triangle, both sides
+  pub fn wireframe() -> Self {
+    Self::default().with_polygon_mode(wgpu::PolygonMode::Line)
+  }
+
+  /// every pair of indices is a line, for debug drawing. they're hidden by the scene
+  /// but don't hide anything themselves
+  pub fn lines() -> Self {
+    Self::default()
+      .with_topology(wgpu::PrimitiveTopology::LineList)
+      .with_depth_write(false)
+  }
+
+  pub fn with_cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+    self.cull_mode = cull_mode;
+    self
+  }
+
+  pub fn with_front_face(mut self, front_face: wgpu::FrontFace) -> Self {
+    self.front_face = front_face;
+    self
+  }
+
+  pub fn with_polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+    self.polygon_mode = polygon_mode;
+    self
+  }
+
+  pub fn with_topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+    self.topology = topology;
+    self
+  }
+
+  pub fn with_depth_test(mut self, depth_test: bool) -> Self {
+    self.depth_test = depth_test;
+    self
+  }
+
+  pub fn with_depth_write(mut self, depth_write: bool) -> Self {
+    self.depth_write = depth_write;
+    self
+  }
+
+  pub fn with_depth_compare(mut self, depth_compare: wgpu::CompareFunction) -> Self {
+    self.depth_compare = depth_compare;
+    self
+  }
+
+  /// constant is in the depth buffer's smallest steps, slope_scale grows with the triangle's slope
+  pub fn with_depth_bias(mut self, constant: i32, slope_scale: f32) -> Self {
+    self.depth_bias = wgpu::DepthBiasState {
+      constant,
+      slope_scale,
+      clamp: 0.0,
+    };
+    self
+  }
+
+  /// whether the device can draw this polygon mode
+  pub fn supports_polygon_mode(polygon_mode: wgpu::PolygonMode, features: wgpu::Features) -> bool {
+    match polygon_mode {
+      wgpu::PolygonMode::Fill => true,
+      wgpu::PolygonMode::Line => features.contains(wgpu::Features::POLYGON_MODE_LINE),
+      wgpu::PolygonMode::Point => features.contains(wgpu::Features::POLYGON_MODE_POINT),
+    }
+  }
+
+  /// falls back to Fill without a word, it's up to whoever asks for lines or points to check
+  /// supports_polygon_mode first (like the wireframe view does), this runs for every rebuild
+  pub fn get_primitive_state(&self, features: wgpu::Features) -> wgpu::PrimitiveState {
+    let polygon_mode = match Self::supports_polygon_mode(self.polygon_mode, features) {
+      true => self.polygon_mode,
+      false => wgpu::PolygonMode::Fill,
+    };
+    // meshes always have u32 indices
+    let strip_index_format = match self.topology.is_strip() {
+      true => Some(wgpu::IndexFormat::Uint32),
+      false => None,
+    };
+
+    wgpu::PrimitiveState {
+      topology: self.topology,
+      strip_index_format,
+      front_face: self.front_face,
+      cull_mode: self.cull_mode,
+      polygon_mode,
+      // Requires Features::DEPTH_CLIP_CONTROL
+      unclipped_depth: false,
+      // Requires Features::CONSERVATIVE_RASTERIZATION
+      conservative: false,
+    }
+  }
+
+  pub fn get_depth_stencil_state(&self, blend_mode: BlendMode) -> wgpu::DepthStencilState {
+    let depth_compare = match self.depth_test {
+      true => self.depth_compare,
+      false => wgpu::CompareFunction::Always,
+    };
+
+    wgpu::DepthStencilState {
+      format: DynamicTexture::DEPTH_BUFFER_FORMAT,
+      depth_write_enabled: self.depth_write && blend_mode.writes_depth(),
+      depth_compare,
+      stencil: wgpu::StencilState::default(),
+      bias: self.depth_bias,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn defaults_match_the_old_hardcoded_state() {
+    let state = PipelineState::default();
+    let primitive = state.get_primitive_state(wgpu::Features::empty());
+    assert_eq!(primitive.cull_mode, None);
+    assert_eq!(primitive.polygon_mode, wgpu::PolygonMode::Fill);
+    assert_eq!(primitive.topology, wgpu::PrimitiveTopology::TriangleList);
+
+    let depth = state.get_depth_stencil_state(BlendMode::Opaque);
+    assert!(depth.depth_write_enabled);
+    assert_eq!(depth.depth_compare, wgpu::CompareFunction::Less);
+    // transparent materials never write depth
+    assert!(
+      !state
+        .get_depth_stencil_state(BlendMode::AlphaBlend)
+        .depth_write_enabled
+    );
+  }
+
+  #[test]
+  fn unsupported_polygon_modes_fall_back_to_fill() {
+    let wireframe = PipelineState::wireframe();
+    let without = wireframe.get_primitive_state(wgpu::Features::empty());
+    assert_eq!(without.polygon_mode, wgpu::PolygonMode::Fill);
+    let with = wireframe.get_primitive_state(wgpu::Features::POLYGON_MODE_LINE);
+    assert_eq!(with.polygon_mode, wgpu::PolygonMode::Line);
+
+    // strips need to know the index format, lists can't have one
+    let strip = PipelineState::new().with_topology(wgpu::PrimitiveTopology::LineStrip);
+    let strip = strip.get_primitive_state(wgpu::Features::empty());
+    assert_eq!(strip.strip_index_format, Some(wgpu::IndexFormat::Uint32));
+    let lines = PipelineState::lines().get_primitive_state(wgpu::Features::empty());
+    assert_eq!(lines.strip_index_format, None);
+  }
+
+  #[test]
+  fn depth_test_off_lets_everything_through() {
+    let state = PipelineState::new()
+      .with_depth_test(false)
+      .with_depth_bias(2, 1.5);
+    let depth = state.get_depth_stencil_state(BlendMode::Opaque);
+    assert_eq!(depth.depth_compare, wgpu::CompareFunction::Always);
+    assert_eq!(depth.bias.constant, 2);
+    assert_ne!(state, PipelineState::default());
+  }
+}
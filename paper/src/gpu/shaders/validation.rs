@@ -0,0 +1,299 @@
+// checks shaders with naga before wgpu ever sees them, none of this needs a gpu,
+// so it can be run from the command line (see src/bin/check_shaders.rs) or tests
+
+use std::fmt;
+
+use crate::{
+  files::{
+    self,
+    preprocessor::{PreprocessError, PreprocessedShader, SourceMap},
+  },
+  gpu::gpu_pointers::{self, BindingError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderErrorKind {
+  /// bad #include, missing file, unclosed #ifdef, etc.
+  Preprocess,
+  /// the wgsl didn't parse
+  Parse,
+  /// parsed fine, but naga didn't like the types/bindings/entry points
+  Validation,
+  /// the @group/@binding's don't line up with what the engine provides
+  Binding,
+  /// the shader was fine, but wgpu couldn't make a pipeline out of it
+  Pipeline,
+}
+
+/// a shader error pointing back at the original file, not the preprocessed mess
+#[derive(Debug, Clone)]
+pub struct ShaderError {
+  pub kind: ShaderErrorKind,
+  /// path relative to the shaders folder
+  pub file: String,
+  /// 1 based, 0 if the error isn't tied to a line
+  pub line: usize,
+  /// 1 based, 0 if the error isn't tied to a column
+  pub column: usize,
+  pub message: String,
+  /// the line the error happened on, empty if there is none
+  pub snippet: String,
+}
+
+impl ShaderError {
+  pub fn new(kind: ShaderErrorKind, file: &str, message: String) -> Self {
+    Self {
+      kind,
+      file: file.to_owned(),
+      line: 0,
+      column: 0,
+      message,
+      snippet: String::new(),
+    }
+  }
+
+  /// builds an error from a position in the preprocessed source,
+  /// using the source map to find out which file that line really came from
+  fn from_location(
+    kind: ShaderErrorKind,
+    source: &str,
+    source_map: &SourceMap,
+    root_file: &str,
+    location: Option<naga::SourceLocation>,
+    message: String,
+  ) -> Self {
+    let Some(location) = location else {
+      return Self::new(kind, root_file, message);
+    };
+
+    let output_line = location.line_number as usize;
+    let snippet = source
+      .lines()
+      .nth(output_line.saturating_sub(1))
+      .unwrap_or("")
+      .to_owned();
+
+    let (file, line) = match source_map.lookup(output_line) {
+      Some(original) => (original.file.clone(), original.line),
+      None => (root_file.to_owned(), output_line),
+    };
+
+    Self {
+      kind,
+      file,
+      line,
+      column: location.line_position as usize,
+      message,
+      snippet,
+    }
+  }
+
+  pub fn from_binding_error(
+    error: BindingError,
+    shader: &ValidatedShader,
+    root_file: &str,
+  ) -> Self {
+    let location = error
+      .span
+      .is_defined()
+      .then(|| error.span.location(&shader.source));
+
+    Self::from_location(
+      ShaderErrorKind::Binding,
+      &shader.source,
+      &shader.source_map,
+      root_file,
+      location,
+      error.message,
+    )
+  }
+}
+
+impl fmt::Display for ShaderError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}:{}:{}: {}",
+      self.file, self.line, self.column, self.message
+    )?;
+
+    if !self.snippet.is_empty() {
+      writeln!(f)?;
+      writeln!(f, "  | {}", self.snippet)?;
+      // point at the column, tabs are kept so the arrow still lines up
+      let padding: String = self
+        .snippet
+        .chars()
+        .take(self.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+      write!(f, "  | {}^", padding)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl std::error::Error for ShaderError {}
+
+impl From<PreprocessError> for ShaderError {
+  fn from(error: PreprocessError) -> Self {
+    Self {
+      kind: ShaderErrorKind::Preprocess,
+      file: error.file,
+      line: error.line,
+      column: 0,
+      message: error.message,
+      snippet: String::new(),
+    }
+  }
+}
+
+/// a preprocessed shader that naga is happy with
+pub struct ValidatedShader {
+  pub source: String,
+  pub source_map: SourceMap,
+  pub module: naga::Module,
+  pub info: naga::valid::ModuleInfo,
+}
+
+/// naga's error messages are nested a few levels deep, the useful part is usually at the bottom
+fn error_chain(error: &dyn std::error::Error) -> String {
+  let mut message = error.to_string();
+  let mut source = error.source();
+  while let Some(next) = source {
+    message.push_str(": ");
+    message.push_str(&next.to_string());
+    source = next.source();
+  }
+  message
+}
+
+pub fn validate_preprocessed(
+  root_file: &str,
+  shader: PreprocessedShader,
+) -> Result<ValidatedShader, ShaderError> {
+  let module = naga::front::wgsl::parse_str(&shader.source).map_err(|error| {
+    let location = error.location(&shader.source);
+    let message = error.message().to_owned();
+    ShaderError::from_location(
+      ShaderErrorKind::Parse,
+      &shader.source,
+      &shader.source_map,
+      root_file,
+      location,
+      message,
+    )
+  })?;
+
+  // capabilities the device doesn't actually have still get caught by wgpu,
+  // this is only here to catch mistakes early with good line numbers
+  let mut validator = naga::valid::Validator::new(
+    naga::valid::ValidationFlags::all(),
+    naga::valid::Capabilities::all(),
+  );
+
+  let info = validator.validate(&module).map_err(|error| {
+    let location = error.location(&shader.source);
+    let message = error_chain(error.as_inner());
+    ShaderError::from_location(
+      ShaderErrorKind::Validation,
+      &shader.source,
+      &shader.source_map,
+      root_file,
+      location,
+      message,
+    )
+  })?;
+
+  let shader = ValidatedShader {
+    source: shader.source,
+    source_map: shader.source_map,
+    module,
+    info,
+  };
+
+  // makes sure every @group/@binding is something the engine can actually bind
+  gpu_pointers::reflect_resources(&shader.module)
+    .map_err(|error| ShaderError::from_binding_error(error, &shader, root_file))?;
+
+  Ok(shader)
+}
+
+/// loads, preprocesses and validates a shader from the shaders folder
+pub fn validate_shader(filename: &str, defines: &[String]) -> Result<ValidatedShader, ShaderError> {
+  let shader = files::load_shader(filename, defines)?;
+  validate_preprocessed(filename, shader)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::files::preprocessor::{Preprocessor, ShaderFileLoader};
+
+  struct SingleFile(&'static str);
+
+  impl ShaderFileLoader for SingleFile {
+    fn read(&self, path: &str) -> std::io::Result<String> {
+      match path {
+        "shader_lib/lib.wgsl" => Ok("fn helper() -> f32 { return 1.0; }".to_owned()),
+        _ => Ok(self.0.to_owned()),
+      }
+    }
+
+    fn exists(&self, path: &str) -> bool {
+      path == "shader_lib/lib.wgsl"
+    }
+  }
+
+  fn validate(source: &'static str) -> Result<ValidatedShader, ShaderError> {
+    let loader = SingleFile(source);
+    let shader = Preprocessor::new(&loader, &[])
+      .process("test.wgsl")
+      .unwrap();
+    validate_preprocessed("test.wgsl", shader)
+  }
+
+  #[test]
+  fn accepts_valid_shaders() {
+    assert!(validate("#include \"lib.wgsl\"\nfn main() -> f32 { return helper(); }").is_ok());
+  }
+
+  #[test]
+  fn parse_errors_point_at_the_original_line() {
+    let error = validate("#include \"lib.wgsl\"\n\nfn main() -> f32 {\n  return helper() +;\n}")
+      .err()
+      .unwrap();
+
+    assert_eq!(error.kind, ShaderErrorKind::Parse);
+    assert_eq!(error.file, "test.wgsl");
+    assert_eq!(error.line, 4);
+    assert!(error.column > 0);
+    assert_eq!(error.snippet, "  return helper() +;");
+  }
+
+  #[test]
+  fn validation_errors_are_reported() {
+    let error = validate("fn main() -> f32 { return 1u; }").err().unwrap();
+    assert_eq!(error.kind, ShaderErrorKind::Validation);
+  }
+
+  #[test]
+  fn unknown_bindings_point_at_the_variable() {
+    let error = validate("@group(0) @binding(0)\nvar<uniform> camera: vec4<f32>;\n@group(1) @binding(0)\nvar<uniform> fog: vec4<f32>;")
+      .err()
+      .unwrap();
+
+    assert_eq!(error.kind, ShaderErrorKind::Binding);
+    assert_eq!(error.line, 4);
+    assert!(error.message.contains("fog"));
+  }
+
+  #[test]
+  fn bind_groups_cant_have_gaps() {
+    let error = validate("@group(1) @binding(0)\nvar<uniform> camera: vec4<f32>;")
+      .err()
+      .unwrap();
+    assert_eq!(error.kind, ShaderErrorKind::Binding);
+  }
+}
@@ -0,0 +1,537 @@
+// shadow mapping. every light with ShadowSettings gets a square in one big depth texture
+// (the atlas), the shadow pass renders the scene's depth from the light into it before
+// the main pass, and lighting.wgsl compares against it with a few pcf taps.
+// only directional and spot lights cast shadows, a point light would need six squares
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4};
+
+use crate::{
+  files::{preprocessor::SourceMap, shader_watcher::ShaderChange},
+  gpu::{
+    camera::OPENGL_TO_WGPU_MATRIX,
+    device_drivers::Drivers,
+    geometry::{GetBufferLayout, ModelVertex, VertexTrait},
+    gpu_pointers::{BindingRegistry, MemoryLayouts},
+    instances::Instance,
+    lights::{Light, LightKind},
+    object::{WORLD_RIGHT, WORLD_UP},
+    shaders::{
+      validation::{ShaderError, ShaderErrorKind},
+      ShaderBuilder,
+    },
+    texture::DynamicTexture,
+  },
+};
+
+/// how a light's shadow looks, see Light::with_shadows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+  /// width and height of the light's square in the atlas, in texels
+  pub resolution: u32,
+  /// pulls the compared depth towards the light, gets rid of acne on surfaces facing it
+  pub depth_bias: f32,
+  /// moves the looked up point along the surface normal (world units), for grazing angles
+  pub normal_bias: f32,
+  /// directional lights only, half the width of the shadowed box around the light's position
+  pub extent: f32,
+}
+
+impl Default for ShadowSettings {
+  fn default() -> Self {
+    Self {
+      resolution: 1024,
+      depth_bias: 0.002,
+      normal_bias: 0.02,
+      extent: 10.0,
+    }
+  }
+}
+
+/// a light's square in the atlas, in texels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasTile {
+  pub x: u32,
+  pub y: u32,
+  pub size: u32,
+}
+
+impl AtlasTile {
+  /// x, y, width, height as 0..1 uvs of the atlas
+  pub fn uv_rect(&self, atlas_size: u32) -> [f32; 4] {
+    let atlas_size = atlas_size as f32;
+    [
+      self.x as f32 / atlas_size,
+      self.y as f32 / atlas_size,
+      self.size as f32 / atlas_size,
+      self.size as f32 / atlas_size,
+    ]
+  }
+}
+
+/// packs squares of the given sizes into the atlas, biggest first. sizes get rounded up to a
+/// power of two, then every free square gets split in four until it's the right size,
+/// so nothing is wasted. a light that doesn't fit anymore gets None (and no shadow)
+pub fn pack_atlas(resolutions: &[u32], atlas_size: u32) -> Vec<Option<AtlasTile>> {
+  let sizes: Vec<u32> = resolutions
+    .iter()
+    .map(|resolution| (*resolution).max(1).next_power_of_two().min(atlas_size))
+    .collect();
+
+  let mut order: Vec<usize> = (0..sizes.len()).collect();
+  order.sort_by_key(|index| std::cmp::Reverse(sizes[*index]));
+
+  let mut tiles = vec![None; sizes.len()];
+  let mut free = vec![AtlasTile {
+    x: 0,
+    y: 0,
+    size: atlas_size,
+  }];
+
+  for index in order {
+    let size = sizes[index];
+    // the smallest free square it fits in, so the big ones stay free for big shadows
+    let Some(slot) = (0..free.len())
+      .filter(|slot| free[*slot].size >= size)
+      .min_by_key(|slot| free[*slot].size)
+    else {
+      continue;
+    };
+
+    let mut tile = free.swap_remove(slot);
+    while tile.size / 2 >= size {
+      let half = tile.size / 2;
+      let (x, y) = (tile.x, tile.y);
+      free.push(AtlasTile {
+        x: x + half,
+        y,
+        size: half,
+      });
+      free.push(AtlasTile {
+        x,
+        y: y + half,
+        size: half,
+      });
+      free.push(AtlasTile {
+        x: x + half,
+        y: y + half,
+        size: half,
+      });
+      tile.size = half;
+    }
+    tiles[index] = Some(tile);
+  }
+
+  tiles
+}
+
+/// world to the light's clip space, with depth going 0..1 like wgpu wants.
+/// None for lights that can't cast shadows
+pub fn shadow_view_proj(light: &Light, settings: &ShadowSettings) -> Option<Matrix4<f32>> {
+  let direction = match light.direction.magnitude2() > 0.0 {
+    true => light.direction.normalize(),
+    false => -WORLD_UP,
+  };
+  // look_at breaks when looking straight along the up vector
+  let up = match direction.dot(WORLD_UP).abs() > 0.99 {
+    true => WORLD_RIGHT,
+    false => WORLD_UP,
+  };
+
+  let (eye, projection) = match light.kind {
+    LightKind::Point => return None,
+    LightKind::Directional => {
+      // far enough back that anything inside the box casts onto everything else in it
+      let extent = settings.extent.max(0.01);
+      let eye = cgmath::Point3::from_vec(light.position - direction * extent * 2.0);
+      let projection = cgmath::ortho(-extent, extent, -extent, extent, 0.0, extent * 4.0);
+      (eye, projection)
+    }
+    LightKind::Spot { outer_angle, .. } => {
+      let far = match light.range > 0.0 {
+        true => light.range,
+        false => 100.0,
+      };
+      let fov = cgmath::Rad((outer_angle * 2.0).clamp(0.01, 3.1));
+      let eye = cgmath::Point3::from_vec(light.position);
+      (eye, cgmath::perspective(fov, 1.0, 0.05, far))
+    }
+  };
+
+  let view = Matrix4::look_at_rh(eye, eye + direction, up);
+  Some(OPENGL_TO_WGPU_MATRIX * projection * view)
+}
+
+/// one square of the atlas that gets rendered this frame
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowCaster {
+  pub tile: AtlasTile,
+  pub view_proj: Matrix4<f32>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowViewUniform {
+  view_proj: [[f32; 4]; 4],
+}
+
+/// the depth only pipeline made from shadow.wgsl
+pub struct ShadowPipeline {
+  pub render_pipeline: wgpu::RenderPipeline,
+  bindgroups: MemoryLayouts,
+  source_map: SourceMap,
+}
+
+impl ShadowPipeline {
+  const SHADER_FILE: &str = "shadow.wgsl";
+
+  fn compile(drivers: &Drivers, registry: &BindingRegistry) -> Result<Self, ShaderError> {
+    let shader_builder = ShaderBuilder::from_file(Self::SHADER_FILE.to_owned());
+    let shader = shader_builder.validate()?;
+
+    let bindgroups = MemoryLayouts::reflect(&shader.module, registry)
+      .map_err(|error| ShaderError::from_binding_error(error, &shader, Self::SHADER_FILE))?;
+
+    drivers
+      .device
+      .push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let module = drivers
+      .device
+      .create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(Self::SHADER_FILE),
+        source: wgpu::ShaderSource::Wgsl(shader.source.as_str().into()),
+      });
+    let render_pipeline = Self::init_render_pipeline(drivers, &module, &bindgroups);
+
+    if let Some(error) = pollster::block_on(drivers.device.pop_error_scope()) {
+      let message = error.to_string();
+      return Err(ShaderError::new(
+        ShaderErrorKind::Pipeline,
+        Self::SHADER_FILE,
+        message,
+      ));
+    }
+
+    Ok(Self {
+      render_pipeline,
+      bindgroups,
+      source_map: shader.source_map,
+    })
+  }
+
+  fn init_render_pipeline(
+    drivers: &Drivers,
+    module: &wgpu::ShaderModule,
+    bindgroups: &MemoryLayouts,
+  ) -> wgpu::RenderPipeline {
+    let layout = drivers
+      .device
+      .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Shadow Pipeline Layout"),
+        bind_group_layouts: &bindgroups.collect_slice(),
+        push_constant_ranges: &[],
+      });
+
+    drivers
+      .device
+      .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Shadow Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+          module,
+          entry_point: Some("vs_main"),
+          buffers: &[ModelVertex::desc(), Instance::desc()],
+          compilation_options: Default::default(),
+        },
+        // depth only
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+          cull_mode: None,
+          ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+          format: DynamicTexture::DEPTH_BUFFER_FORMAT,
+          depth_write_enabled: true,
+          depth_compare: wgpu::CompareFunction::Less,
+          stencil: wgpu::StencilState::default(),
+          // a bit of slope bias for everything, the per light bias goes on top in the shader
+          bias: wgpu::DepthBiasState {
+            constant: 0,
+            slope_scale: 1.0,
+            clamp: 0.0,
+          },
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+      })
+  }
+
+  pub fn get_bindgroups(&self) -> &MemoryLayouts {
+    &self.bindgroups
+  }
+}
+
+/// the view matrix of every caster in one uniform buffer, picked with a dynamic offset
+/// (same idea as the LocationBuffer), plus the pipeline that draws into the atlas
+pub struct ShadowPass {
+  pipeline: Option<ShadowPipeline>,
+
+  buffer: wgpu::Buffer,
+  bind_group: wgpu::BindGroup,
+  layout: wgpu::BindGroupLayout,
+  stride: u32,
+  capacity: u32,
+}
+
+impl GetBufferLayout for ShadowPass {
+  fn get_bind_layout(&self) -> wgpu::BindGroupLayout {
+    self.layout.clone()
+  }
+
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+    Self::bind_entries()
+  }
+}
+
+impl ShadowPass {
+  const VIEW_SIZE: u64 = std::mem::size_of::<ShadowViewUniform>() as u64;
+  const STARTING_CAPACITY: u32 = 8;
+
+  fn bind_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+    vec![wgpu::BindGroupLayoutEntry {
+      binding: 0,
+      visibility: wgpu::ShaderStages::VERTEX,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: true,
+        min_binding_size: wgpu::BufferSize::new(Self::VIEW_SIZE),
+      },
+      count: None,
+    }]
+  }
+
+  fn init_bind_group_layout(drivers: &Drivers) -> wgpu::BindGroupLayout {
+    drivers
+      .device
+      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &Self::bind_entries(),
+        label: Some("shadow_view_bind_group_layout"),
+      })
+  }
+
+  fn init_buffer(
+    drivers: &Drivers,
+    layout: &wgpu::BindGroupLayout,
+    stride: u32,
+    capacity: u32,
+  ) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let buffer = drivers.device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Shadow View Buffer"),
+      size: stride as u64 * capacity as u64,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+
+    let bind_group = drivers
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(Self::VIEW_SIZE),
+          }),
+        }],
+        label: Some("shadow_view_bind_group"),
+      });
+
+    (buffer, bind_group)
+  }
+
+  pub fn new(drivers: &Drivers) -> Self {
+    let alignment = drivers.device.limits().min_uniform_buffer_offset_alignment;
+    let stride = (Self::VIEW_SIZE as u32).div_ceil(alignment) * alignment;
+
+    let layout = Self::init_bind_group_layout(drivers);
+    let (buffer, bind_group) = Self::init_buffer(drivers, &layout, stride, Self::STARTING_CAPACITY);
+
+    Self {
+      pipeline: None,
+      buffer,
+      bind_group,
+      layout,
+      stride,
+      capacity: Self::STARTING_CAPACITY,
+    }
+  }
+
+  /// compiles shadow.wgsl, the registry needs the ShadowView and ObjectLocation layouts by now.
+  /// without a pipeline nothing casts shadows, but everything else still renders
+  pub fn init_pipeline(&mut self, drivers: &Drivers, registry: &BindingRegistry) {
+    match ShadowPipeline::compile(drivers, registry) {
+      Ok(pipeline) => self.pipeline = Some(pipeline),
+      Err(error) => log::error!(
+        "shadows are disabled, shadow.wgsl didn't compile: {}",
+        error
+      ),
+    }
+  }
+
+  pub fn reload_shaders(
+    &mut self,
+    drivers: &Drivers,
+    registry: &BindingRegistry,
+    changes: &[ShaderChange],
+  ) {
+    let affected = match &self.pipeline {
+      Some(pipeline) => changes
+        .iter()
+        .any(|change| pipeline.source_map.depends_on(change.get_path())),
+      None => false,
+    };
+    if !affected {
+      return;
+    }
+
+    match ShadowPipeline::compile(drivers, registry) {
+      Ok(pipeline) => {
+        self.pipeline = Some(pipeline);
+        log::info!("reloaded shader: {}", ShadowPipeline::SHADER_FILE);
+      }
+      Err(error) => log::error!(
+        "failed to reload shader {}, keeping the old one: {}",
+        ShadowPipeline::SHADER_FILE,
+        error
+      ),
+    }
+  }
+
+  /// uploads every caster's view matrix, caster i ends up at get_offset(i)
+  pub fn write(&mut self, drivers: &Drivers, casters: &[ShadowCaster]) {
+    if casters.is_empty() {
+      return;
+    }
+
+    let needed = casters.len() as u32;
+    if needed > self.capacity {
+      let capacity = needed.next_power_of_two();
+      (self.buffer, self.bind_group) =
+        Self::init_buffer(drivers, &self.layout, self.stride, capacity);
+      self.capacity = capacity;
+    }
+
+    let mut bytes = vec![0u8; self.stride as usize * casters.len()];
+    for (slot, caster) in casters.iter().enumerate() {
+      let view = ShadowViewUniform {
+        view_proj: caster.view_proj.into(),
+      };
+      let start = slot * self.stride as usize;
+      bytes[start..start + Self::VIEW_SIZE as usize].copy_from_slice(bytemuck::bytes_of(&view));
+    }
+
+    drivers.queue.write_buffer(&self.buffer, 0, &bytes);
+  }
+
+  /// starts a pass that clears the whole atlas, the casters' viewports get set by the caller
+  pub fn begin<'a>(
+    &self,
+    encoder: &'a mut wgpu::CommandEncoder,
+    atlas: &DynamicTexture,
+  ) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("Shadow Pass"),
+      color_attachments: &[],
+      depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+        view: &atlas.view,
+        depth_ops: Some(wgpu::Operations {
+          load: wgpu::LoadOp::Clear(1.0),
+          store: wgpu::StoreOp::Store,
+        }),
+        stencil_ops: None,
+      }),
+      occlusion_query_set: None,
+      timestamp_writes: None,
+    })
+  }
+
+  pub fn get_pipeline(&self) -> Option<&ShadowPipeline> {
+    self.pipeline.as_ref()
+  }
+
+  pub fn get_offset(&self, slot: u32) -> u32 {
+    slot * self.stride
+  }
+
+  pub fn get_bind_group(&self) -> &wgpu::BindGroup {
+    &self.bind_group
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::maths::Vec3;
+
+  fn overlaps(a: &AtlasTile, b: &AtlasTile) -> bool {
+    a.x < b.x + b.size && b.x < a.x + a.size && a.y < b.y + b.size && b.y < a.y + a.size
+  }
+
+  #[test]
+  fn tiles_fill_the_atlas_without_overlapping() {
+    let tiles = pack_atlas(&[512, 1024, 256, 1024, 1024, 512], 2048);
+    let placed: Vec<AtlasTile> = tiles.iter().flatten().copied().collect();
+    assert_eq!(placed.len(), 6);
+
+    for (i, a) in placed.iter().enumerate() {
+      assert!(a.x + a.size <= 2048 && a.y + a.size <= 2048);
+      for b in &placed[i + 1..] {
+        assert!(!overlaps(a, b), "{:?} overlaps {:?}", a, b);
+      }
+    }
+  }
+
+  #[test]
+  fn lights_that_dont_fit_get_no_tile() {
+    let tiles = pack_atlas(&[1024; 5], 2048);
+    assert_eq!(tiles.iter().flatten().count(), 4);
+    assert!(tiles[4].is_none());
+
+    // too big for the atlas gets shrunk down to it
+    let tiles = pack_atlas(&[8192, 300], 2048);
+    assert_eq!(tiles[0].unwrap().size, 2048);
+    assert!(tiles[1].is_none());
+    // and odd sizes get rounded up
+    assert_eq!(pack_atlas(&[300], 2048)[0].unwrap().size, 512);
+  }
+
+  #[test]
+  fn shadow_views_look_along_the_light() {
+    let settings = ShadowSettings::default();
+
+    let sun = Light::directional(Vec3::new(0.0, -1.0, 0.0), [1.0; 3]);
+    let view_proj = shadow_view_proj(&sun, &settings).unwrap();
+    let below = view_proj * cgmath::Vector4::new(0.0, -1.0, 0.0, 1.0);
+    let above = view_proj * cgmath::Vector4::new(0.0, 1.0, 0.0, 1.0);
+    // straight below the light is the middle of the square, and further away
+    assert!(below.x.abs() < 1e-5 && below.y.abs() < 1e-5);
+    assert!(above.z < below.z && (0.0..=1.0).contains(&below.z));
+
+    let spot = Light::spot(
+      Vec3::new(0.0, 0.0, 0.0),
+      Vec3::new(1.0, 0.0, 0.0),
+      0.3,
+      0.5,
+      [1.0; 3],
+    );
+    let view_proj = shadow_view_proj(&spot, &settings).unwrap();
+    let ahead = view_proj * cgmath::Vector4::new(5.0, 0.0, 0.0, 1.0);
+    let ndc = ahead.truncate() / ahead.w;
+    assert!(ndc.x.abs() < 1e-5 && ndc.y.abs() < 1e-5 && (0.0..=1.0).contains(&ndc.z));
+
+    let point = Light::point(Vec3::new(0.0, 0.0, 0.0), [1.0; 3]);
+    assert!(shadow_view_proj(&point, &settings).is_none());
+  }
+}
@@ -1,14 +1,21 @@
 use std::{collections::HashMap, sync::Arc};
 
 use anyhow::{Error, Ok};
-use image::GenericImageView;
-use wgpu::{BindGroup, BindGroupLayout};
+use wgpu::BindGroupLayout;
 
 use crate::{
   files,
-  gpu::{device_drivers::Drivers, geometry::GetBufferLayout},
+  gpu::{device_drivers::Drivers, geometry::GetBufferLayout, material::Material},
 };
 
+pub mod atlas;
+pub mod compressed;
+pub mod layered;
+
+use atlas::{AtlasBuilder, TextureAtlas};
+use compressed::CompressedImage;
+use layered::LayeredTexture;
+
 #[derive(Clone)]
 pub struct DynamicTexture {
   #[allow(unused)]
@@ -19,32 +26,61 @@ pub struct DynamicTexture {
 }
 
 impl DynamicTexture {
-  // depth buffer
   pub const DEPTH_BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
-  pub const DEPTH_BUFFER_LABEL: &str = "1engine_depth_buffer";
-  pub fn create_depth_buffer(drivers: &Drivers) -> Self {
-    let config = &drivers.surface_config;
+  // what the scene renders into, colors can go past 1 until the post stack tonemaps them
+  pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+  // shadow atlas, every shadow casting light renders its depth into a square of it
+  pub const SHADOW_ATLAS_LABEL: &str = "1engine_shadow_atlas";
+  pub fn create_shadow_atlas(drivers: &Drivers, size: u32) -> Self {
+    Self::create_attachment(
+      drivers,
+      (size, size),
+      Self::DEPTH_BUFFER_FORMAT,
+      1,
+      Self::SHADOW_ATLAS_LABEL,
+    )
+  }
 
+  /// a texture that can be rendered into and then sampled, like the render graph's attachments.
+  /// depth textures get a comparison sampler, everything else a linear one
+  pub fn create_attachment(
+    drivers: &Drivers,
+    (width, height): (u32, u32),
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    label: &str,
+  ) -> Self {
     let size = wgpu::Extent3d {
-      width: config.width,
-      height: config.height,
+      width,
+      height,
       depth_or_array_layers: 1,
     };
+    // multisampled textures can't be copied, they get resolved instead
+    let copy_usage = match sample_count {
+      1 => wgpu::TextureUsages::COPY_SRC,
+      _ => wgpu::TextureUsages::empty(),
+    };
 
     let desc = wgpu::TextureDescriptor {
-      label: Some(Self::DEPTH_BUFFER_LABEL),
+      label: Some(label),
       size,
       mip_level_count: 1,
-      sample_count: 1,
+      sample_count,
       dimension: wgpu::TextureDimension::D2,
-      format: Self::DEPTH_BUFFER_FORMAT,
-      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+      format,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+        | wgpu::TextureUsages::TEXTURE_BINDING
+        | copy_usage,
       view_formats: &[],
     };
     let texture = drivers.device.create_texture(&desc);
 
     let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-    let sampler = Self::get_sampler(drivers);
+    let sampler = match format.is_depth_stencil_format() {
+      true => Self::get_comparison_sampler(drivers),
+      false => Self::get_sampler(drivers),
+    };
 
     Self {
       texture,
@@ -54,6 +90,18 @@ impl DynamicTexture {
   }
 
   fn get_sampler(drivers: &Drivers) -> wgpu::Sampler {
+    drivers.device.create_sampler(&wgpu::SamplerDescriptor {
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      address_mode_w: wgpu::AddressMode::ClampToEdge,
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Linear,
+      mipmap_filter: wgpu::FilterMode::Nearest,
+      ..Default::default()
+    })
+  }
+
+  fn get_comparison_sampler(drivers: &Drivers) -> wgpu::Sampler {
     let sampler = drivers.device.create_sampler(&wgpu::SamplerDescriptor {
       address_mode_u: wgpu::AddressMode::ClampToEdge,
       address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -70,150 +118,531 @@ impl DynamicTexture {
   }
 }
 
+/// whether a texture holds colors, or data like normals that must not be gamma corrected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+  Srgb,
+  Linear,
+}
+
+impl ColorSpace {
+  fn rgba8_format(&self) -> wgpu::TextureFormat {
+    match self {
+      ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+      ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+    }
+  }
+}
+
+/// how a texture gets filtered between its pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureFilter {
+  /// smooth, blends between pixels and between mip levels
+  Linear,
+  /// blocky, for pixel art
+  Nearest,
+}
+
+/// what happens to uvs outside of 0 to 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WrapMode {
+  Clamp,
+  Repeat,
+  Mirror,
+}
+
+impl WrapMode {
+  fn to_wgpu(self) -> wgpu::AddressMode {
+    match self {
+      WrapMode::Clamp => wgpu::AddressMode::ClampToEdge,
+      WrapMode::Repeat => wgpu::AddressMode::Repeat,
+      WrapMode::Mirror => wgpu::AddressMode::MirrorRepeat,
+    }
+  }
+}
+
+/// everything about a sampler, the same settings always get the same sampler out of a SamplerCache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerSettings {
+  pub filter: TextureFilter,
+  pub wrap: WrapMode,
+  /// 1 is off, up to 16. only works with linear filtering
+  pub anisotropy: u16,
+}
+
+impl Default for SamplerSettings {
+  fn default() -> Self {
+    Self {
+      filter: TextureFilter::Linear,
+      wrap: WrapMode::Clamp,
+      anisotropy: 1,
+    }
+  }
+}
+
+impl SamplerSettings {
+  /// nearest everything, pixels stay square however close the camera gets
+  pub const PIXEL_ART: Self = Self {
+    filter: TextureFilter::Nearest,
+    wrap: WrapMode::Clamp,
+    anisotropy: 1,
+  };
+
+  pub fn with_filter(mut self, filter: TextureFilter) -> Self {
+    self.filter = filter;
+    self
+  }
+
+  pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+    self.wrap = wrap;
+    self
+  }
+
+  pub fn with_anisotropy(mut self, anisotropy: u16) -> Self {
+    self.anisotropy = anisotropy;
+    self
+  }
+
+  fn to_descriptor(self) -> wgpu::SamplerDescriptor<'static> {
+    let filter = match self.filter {
+      TextureFilter::Linear => wgpu::FilterMode::Linear,
+      TextureFilter::Nearest => wgpu::FilterMode::Nearest,
+    };
+    // wgpu won't make an anisotropic sampler that isn't linear all the way through
+    let anisotropy_clamp = match self.filter {
+      TextureFilter::Linear => self.anisotropy.clamp(1, 16),
+      TextureFilter::Nearest => 1,
+    };
+
+    wgpu::SamplerDescriptor {
+      label: Some("image_sampler"),
+      address_mode_u: self.wrap.to_wgpu(),
+      address_mode_v: self.wrap.to_wgpu(),
+      address_mode_w: self.wrap.to_wgpu(),
+      mag_filter: filter,
+      min_filter: filter,
+      mipmap_filter: filter,
+      anisotropy_clamp,
+      ..Default::default()
+    }
+  }
+
+  /// a new sampler every time, TextureBundle::get_sampler shares them instead
+  pub fn create(self, drivers: &Drivers) -> wgpu::Sampler {
+    drivers.device.create_sampler(&self.to_descriptor())
+  }
+}
+
+/// one sampler per distinct SamplerSettings
+#[derive(Default)]
+pub struct SamplerCache {
+  samplers: HashMap<SamplerSettings, wgpu::Sampler>,
+}
+
+impl SamplerCache {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn get(&mut self, drivers: &Drivers, settings: SamplerSettings) -> wgpu::Sampler {
+    self
+      .samplers
+      .entry(settings)
+      .or_insert_with(|| settings.create(drivers))
+      .clone()
+  }
+
+  pub fn len(&self) -> usize {
+    self.samplers.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.samplers.is_empty()
+  }
+}
+
+/// 16 bit and float images, they'd lose their precision (or everything past 1) as rgba8
+fn is_high_precision(img: &image::DynamicImage) -> bool {
+  use image::ColorType;
+  matches!(
+    img.color(),
+    ColorType::L16
+      | ColorType::La16
+      | ColorType::Rgb16
+      | ColorType::Rgba16
+      | ColorType::Rgb32F
+      | ColorType::Rgba32F
+  )
+}
+
+fn srgb_to_linear(channel: f32) -> f32 {
+  match channel <= 0.04045 {
+    true => channel / 12.92,
+    false => ((channel + 0.055) / 1.055).powf(2.4),
+  }
+}
+
+/// pixels on their way to the gpu, 8 bits a channel or 16 bit floats for hdr/16 bit images
+enum TexelData {
+  Rgba8(image::RgbaImage),
+  /// always linear
+  Float(image::Rgba32FImage),
+}
+
+impl TexelData {
+  fn from_image(img: &image::DynamicImage, color_space: ColorSpace) -> Self {
+    match is_high_precision(img) {
+      true => Self::float_from_image(img, color_space),
+      false => TexelData::Rgba8(img.to_rgba8()),
+    }
+  }
+
+  /// hdr/exr are linear already, anything else gets decoded if it's srgb
+  fn float_from_image(img: &image::DynamicImage, color_space: ColorSpace) -> Self {
+    let mut rgba = img.to_rgba32f();
+    let is_float = matches!(
+      img.color(),
+      image::ColorType::Rgb32F | image::ColorType::Rgba32F
+    );
+    if color_space == ColorSpace::Srgb && !is_float {
+      for pixel in rgba.pixels_mut() {
+        pixel.0[..3]
+          .iter_mut()
+          .for_each(|c| *c = srgb_to_linear(*c));
+      }
+    }
+    TexelData::Float(rgba)
+  }
+
+  fn dimensions(&self) -> (u32, u32) {
+    match self {
+      TexelData::Rgba8(rgba) => rgba.dimensions(),
+      TexelData::Float(rgba) => rgba.dimensions(),
+    }
+  }
+
+  fn get_format(&self, color_space: ColorSpace) -> wgpu::TextureFormat {
+    match self {
+      TexelData::Rgba8(_) => color_space.rgba8_format(),
+      TexelData::Float(_) => DynamicTexture::HDR_FORMAT,
+    }
+  }
+
+  /// the image and its whole mip chain, into one layer of the texture
+  fn write_with_mips(&self, drivers: &Drivers, texture: &wgpu::Texture, layer: u32) {
+    match self {
+      TexelData::Rgba8(rgba) => {
+        let mips = generate_mips(rgba);
+        for (level, image) in std::iter::once(rgba).chain(mips.iter()).enumerate() {
+          write_level(
+            drivers,
+            texture,
+            image.as_raw(),
+            image.dimensions(),
+            4,
+            level,
+            layer,
+          );
+        }
+      }
+      TexelData::Float(rgba) => {
+        let mips = generate_mips(rgba);
+        for (level, image) in std::iter::once(rgba).chain(mips.iter()).enumerate() {
+          let halves: Vec<u8> = image
+            .as_raw()
+            .iter()
+            .flat_map(|channel| half::f16::from_f32(*channel).to_le_bytes())
+            .collect();
+          write_level(
+            drivers,
+            texture,
+            &halves,
+            image.dimensions(),
+            8,
+            level,
+            layer,
+          );
+        }
+      }
+    }
+  }
+}
+
+fn write_level(
+  drivers: &Drivers,
+  texture: &wgpu::Texture,
+  bytes: &[u8],
+  (width, height): (u32, u32),
+  bytes_per_pixel: u32,
+  mip_level: usize,
+  layer: u32,
+) {
+  drivers.queue.write_texture(
+    wgpu::TexelCopyTextureInfo {
+      aspect: wgpu::TextureAspect::All,
+      texture,
+      mip_level: mip_level as u32,
+      origin: wgpu::Origin3d {
+        x: 0,
+        y: 0,
+        z: layer,
+      },
+    },
+    bytes,
+    wgpu::TexelCopyBufferLayout {
+      offset: 0,
+      bytes_per_row: Some(bytes_per_pixel * width),
+      rows_per_image: Some(height),
+    },
+    wgpu::Extent3d {
+      width,
+      height,
+      depth_or_array_layers: 1,
+    },
+  );
+}
+
+/// how many times a texture can be halved until it's 1x1, counting the full size one
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+  32 - width.max(height).max(1).leading_zeros()
+}
+
+/// every level of the mip chain after the full size one, each half the size of the last.
+/// done on the cpu with a triangle filter, it only happens once per texture
+fn generate_mips<P>(
+  rgba: &image::ImageBuffer<P, Vec<P::Subpixel>>,
+) -> Vec<image::ImageBuffer<P, Vec<P::Subpixel>>>
+where
+  P: image::Pixel + 'static,
+{
+  let (width, height) = rgba.dimensions();
+  (1..mip_level_count(width, height))
+    .map(|level| {
+      let level_width = (width >> level).max(1);
+      let level_height = (height >> level).max(1);
+      image::imageops::resize(
+        rgba,
+        level_width,
+        level_height,
+        image::imageops::FilterType::Triangle,
+      )
+    })
+    .collect()
+}
+
 pub struct ImageTexture {
   #[allow(unused)]
   pub texture: wgpu::Texture,
-  pub diffuse_bind_group: wgpu::BindGroup,
+  pub view: wgpu::TextureView,
+  pub sampler: wgpu::Sampler,
 }
 
 impl ImageTexture {
   pub fn from_bytes(
-    texture_bind_group_layout: &wgpu::BindGroupLayout,
     drivers: &Drivers,
     bytes: &[u8],
     label: &str,
+    color_space: ColorSpace,
+  ) -> anyhow::Result<Self> {
+    let sampler = SamplerSettings::default().create(drivers);
+    Self::from_bytes_with_sampler(drivers, bytes, label, color_space, sampler)
+  }
+
+  /// anything image can decode (png, jpg, hdr, exr...), or a .ktx2/.dds full of bcn blocks
+  pub fn from_bytes_with_sampler(
+    drivers: &Drivers,
+    bytes: &[u8],
+    label: &str,
+    color_space: ColorSpace,
+    sampler: wgpu::Sampler,
   ) -> anyhow::Result<Self> {
+    if compressed::is_compressed_container(bytes) {
+      let compressed = CompressedImage::from_bytes(bytes)?;
+      return Self::from_compressed(drivers, &compressed, Some(label), color_space, sampler);
+    }
+
     let img = image::load_from_memory(bytes)?;
-    Self::from_image(texture_bind_group_layout, &drivers, &img, Some(label))
+    Self::from_image_with_sampler(drivers, &img, Some(label), color_space, sampler)
+  }
+
+  /// a 1x1 texture, for materials that only have a color and no image
+  pub fn from_color(
+    drivers: &Drivers,
+    color: [u8; 4],
+    label: &str,
+    color_space: ColorSpace,
+  ) -> anyhow::Result<Self> {
+    let img =
+      image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+    Self::from_image(drivers, &img, Some(label), color_space)
   }
 
+  /// uploads the image with a full mip chain and a default sampler of its own
   pub fn from_image(
-    texture_bind_group_layout: &wgpu::BindGroupLayout,
     drivers: &Drivers,
     img: &image::DynamicImage,
     label: Option<&str>,
+    color_space: ColorSpace,
   ) -> anyhow::Result<Self> {
-    let rgba = img.to_rgba8();
-    let dimensions = img.dimensions();
-    let size = wgpu::Extent3d {
-      width: dimensions.0,
-      height: dimensions.1,
-      depth_or_array_layers: 1,
-    };
+    let sampler = SamplerSettings::default().create(drivers);
+    Self::from_image_with_sampler(drivers, img, label, color_space, sampler)
+  }
 
-    let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+  pub fn from_image_with_sampler(
+    drivers: &Drivers,
+    img: &image::DynamicImage,
+    label: Option<&str>,
+    color_space: ColorSpace,
+    sampler: wgpu::Sampler,
+  ) -> anyhow::Result<Self> {
+    let texels = TexelData::from_image(img, color_space);
+    let (width, height) = texels.dimensions();
     let texture = drivers.device.create_texture(&wgpu::TextureDescriptor {
       label,
-      size,
-      mip_level_count: 1,
+      size: wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: mip_level_count(width, height),
       sample_count: 1,
       dimension: wgpu::TextureDimension::D2,
-      format,
+      format: texels.get_format(color_space),
       usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
       view_formats: &[],
     });
+    texels.write_with_mips(drivers, &texture, 0);
 
-    Self::copy_to_texture(
-      texture_bind_group_layout,
-      drivers,
-      rgba,
-      dimensions,
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    Ok(Self {
       texture,
-    )
+      view,
+      sampler,
+    })
   }
 
-  fn get_diffuse_bind(
-    texture_bind_group_layout: &BindGroupLayout,
+  /// uploads the blocks as they are if the gpu can sample them, otherwise decompresses them
+  /// to rgba8 (with new mips) first. the file's own color space wins over the one passed in
+  pub fn from_compressed(
     drivers: &Drivers,
-    view: wgpu::TextureView,
+    compressed: &CompressedImage,
+    label: Option<&str>,
+    color_space: ColorSpace,
     sampler: wgpu::Sampler,
-  ) -> BindGroup {
-    let diffuse_bind_group = drivers
+  ) -> anyhow::Result<Self> {
+    let color_space = compressed.color_space.unwrap_or(color_space);
+    let format = compressed.format.to_wgpu(color_space);
+    let (width, height) = (compressed.width, compressed.height);
+
+    // bcn textures have to be made of whole blocks, smaller mips are padded by wgpu
+    let is_supported = drivers
       .device
-      .create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &texture_bind_group_layout,
-        entries: &[
-          wgpu::BindGroupEntry {
-            binding: 0,
-            resource: wgpu::BindingResource::TextureView(&view),
-          },
-          wgpu::BindGroupEntry {
-            binding: 1,
-            resource: wgpu::BindingResource::Sampler(&sampler),
-          },
-        ],
-        label: Some("diffuse_bind_group"),
-      });
-    diffuse_bind_group
-  }
-
-  fn copy_to_texture(
-    texture_bind_group_layout: &BindGroupLayout,
-    drivers: &Drivers,
-    rgba: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>,
-    dimensions: (u32, u32),
-    texture: wgpu::Texture,
-  ) -> Result<ImageTexture, Error> {
-    let size = wgpu::Extent3d {
-      width: dimensions.0,
-      height: dimensions.1,
-      depth_or_array_layers: 1,
-    };
+      .features()
+      .contains(format.required_features())
+      && width % 4 == 0
+      && height % 4 == 0;
+
+    if !is_supported {
+      let rgba = compressed.decompress().ok_or(Error::msg(format!(
+        "{:?} textures can only be used on gpus that support them",
+        compressed.format
+      )))?;
+      log::info!(
+        "decompressing {}, the gpu can't sample {:?}",
+        label.unwrap_or("a texture"),
+        format
+      );
+      let img = image::DynamicImage::ImageRgba8(rgba);
+      return Self::from_image_with_sampler(drivers, &img, label, color_space, sampler);
+    }
 
-    drivers.queue.write_texture(
-      wgpu::TexelCopyTextureInfo {
-        aspect: wgpu::TextureAspect::All,
-        texture: &texture,
-        mip_level: 0,
-        origin: wgpu::Origin3d::ZERO,
-      },
-      &rgba,
-      wgpu::TexelCopyBufferLayout {
-        offset: 0,
-        bytes_per_row: Some(4 * dimensions.0),
-        rows_per_image: Some(dimensions.1),
+    let desc = wgpu::TextureDescriptor {
+      label,
+      size: wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
       },
-      size,
-    );
+      mip_level_count: compressed.levels.len() as u32,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format,
+      usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+      view_formats: &[],
+    };
 
-    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // checked up front, wgpu panics on short uploads and levels the size doesn't have
+    let mut sizes = Vec::with_capacity(compressed.levels.len());
+    for (level, data) in compressed.levels.iter().enumerate() {
+      let size = desc.mip_level_size(level as u32).ok_or(Error::msg(format!(
+        "a {}x{} texture has no mip level {}",
+        width, height, level
+      )))?;
+      if data.len() < compressed.format.get_level_bytes(size.width, size.height) {
+        return Err(Error::msg(format!("mip level {} is too short", level)));
+      }
+      sizes.push(size.physical_size(format));
+    }
 
-    let sampler = Self::get_sampler(drivers);
+    let texture = drivers.device.create_texture(&desc);
+    let block_bytes = compressed.format.get_block_bytes() as u32;
+    for (level, (data, size)) in compressed.levels.iter().zip(sizes).enumerate() {
+      drivers.queue.write_texture(
+        wgpu::TexelCopyTextureInfo {
+          aspect: wgpu::TextureAspect::All,
+          texture: &texture,
+          mip_level: level as u32,
+          origin: wgpu::Origin3d::ZERO,
+        },
+        data,
+        wgpu::TexelCopyBufferLayout {
+          offset: 0,
+          bytes_per_row: Some(size.width / 4 * block_bytes),
+          rows_per_image: Some(size.height / 4),
+        },
+        size,
+      );
+    }
 
-    let diffuse_bind_group =
-      Self::get_diffuse_bind(texture_bind_group_layout, drivers, view, sampler);
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
     Ok(Self {
       texture,
-      diffuse_bind_group,
+      view,
+      sampler,
     })
   }
 
-  fn get_sampler(drivers: &Drivers) -> wgpu::Sampler {
-    let sampler = drivers.device.create_sampler(&wgpu::SamplerDescriptor {
-      address_mode_u: wgpu::AddressMode::ClampToEdge,
-      address_mode_v: wgpu::AddressMode::ClampToEdge,
-      address_mode_w: wgpu::AddressMode::ClampToEdge,
-      mag_filter: wgpu::FilterMode::Linear,
-      min_filter: wgpu::FilterMode::Nearest,
-      mipmap_filter: wgpu::FilterMode::Nearest,
-      ..Default::default()
-    });
-    sampler
+  /// the same texture on the gpu, sampled differently
+  pub fn with_sampler(&self, sampler: wgpu::Sampler) -> Self {
+    Self {
+      texture: self.texture.clone(),
+      view: self.view.clone(),
+      sampler,
+    }
   }
 }
 
 pub struct TextureBundle {
-  pub depth_buffer: DynamicTexture,
-
   fallback_texture: Arc<ImageTexture>,
+  // stand ins for materials that don't have a normal/specular map, or only a color
+  white_texture: Arc<ImageTexture>,
+  flat_normal_texture: Arc<ImageTexture>,
 
   image_textures: HashMap<String, Arc<ImageTexture>>,
-  texture_bind_group_layout: BindGroupLayout,
+  layered_textures: HashMap<String, Arc<LayeredTexture>>,
+  atlases: HashMap<String, Arc<TextureAtlas>>,
+  samplers: SamplerCache,
+  material_layout: BindGroupLayout,
 }
 
 impl GetBufferLayout for TextureBundle {
   fn get_bind_layout(&self) -> wgpu::BindGroupLayout {
-    return self.texture_bind_group_layout.clone();
+    return self.material_layout.clone();
+  }
+
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+    Material::bind_entries()
   }
 }
 
@@ -222,76 +651,95 @@ impl TextureBundle {
     return self.fallback_texture.clone();
   }
 
-  pub fn get_texture_bind(&self, label: &str) -> &BindGroup {
-    let label = String::from(label);
-    if self.image_textures.contains_key(&label) {
-      return &self.image_textures.get(&label).unwrap().diffuse_bind_group;
-    } else {
-      //println!("failed to find: {}", label);
-      //println!("out of {:?}", self.image_textures.keys());
-      return &self.fallback_texture.diffuse_bind_group;
-    }
+  pub fn get_white_texture(&self) -> Arc<ImageTexture> {
+    return self.white_texture.clone();
   }
 
-  fn init_texure_bindgroup_layout(drivers: &Drivers) -> BindGroupLayout {
-    drivers
-      .device
-      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        entries: &[
-          wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Texture {
-              multisampled: false,
-              view_dimension: wgpu::TextureViewDimension::D2,
-              sample_type: wgpu::TextureSampleType::Float { filterable: true },
-            },
-            count: None,
-          },
-          wgpu::BindGroupLayoutEntry {
-            binding: 1,
-            visibility: wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-            count: None,
-          },
-        ],
-        label: Some("texture_bind_group_layout"),
-      })
-  }
-
-  fn init_fallback_texture(
+  pub fn get_flat_normal_texture(&self) -> Arc<ImageTexture> {
+    return self.flat_normal_texture.clone();
+  }
+
+  pub fn get_material_layout(&self) -> &BindGroupLayout {
+    return &self.material_layout;
+  }
+
+  /// shared between everything asking for the same settings
+  pub fn get_sampler(&mut self, drivers: &Drivers, settings: SamplerSettings) -> wgpu::Sampler {
+    self.samplers.get(drivers, settings)
+  }
+
+  pub fn get_sampler_cache(&self) -> &SamplerCache {
+    &self.samplers
+  }
+
+  /// swaps the sampler of the texture stored under label, None if there isn't one.
+  /// materials made before this keep the old sampler, see Material::with_sampler for those
+  pub fn set_sampler(
+    &mut self,
     drivers: &Drivers,
-    texture_bind_group_layout: &BindGroupLayout,
-  ) -> Result<ImageTexture, Error> {
+    label: &str,
+    settings: SamplerSettings,
+  ) -> Option<Arc<ImageTexture>> {
+    let sampler = self.samplers.get(drivers, settings);
+    let texture = self.image_textures.get_mut(label)?;
+    *texture = Arc::new(texture.with_sampler(sampler));
+    Some(texture.clone())
+  }
+
+  /// the texture stored under label, or the fallback texture if there isn't one
+  pub fn get_texture(&self, label: &str) -> Arc<ImageTexture> {
+    match self.image_textures.get(label) {
+      Some(texture) => texture.clone(),
+      None => self.fallback_texture.clone(),
+    }
+  }
+
+  fn init_fallback_texture(drivers: &Drivers) -> Result<ImageTexture, Error> {
     // hard coded for stability
     let texture_bytes = include_bytes!("./missing_texture.png");
     let tex_data: image::DynamicImage = image::load_from_memory(texture_bytes)
       .expect("FATAL ERROR, PUT BACK THE HARDCODED FALLBACK TEXTURE, BOZO");
     let fallback_texture = ImageTexture::from_image(
-      texture_bind_group_layout,
       &drivers,
       &tex_data,
       Some("Fallback Texture"),
+      ColorSpace::Srgb,
     )?;
     Ok(fallback_texture)
   }
 
   pub fn new(drivers: &Drivers) -> anyhow::Result<Self> {
-    let texture_bind_group_layout = Self::init_texure_bindgroup_layout(drivers);
-    let fallback_texture = Self::init_fallback_texture(drivers, &texture_bind_group_layout)?;
-
-    // dynamic textures
-    let depth_buffer = DynamicTexture::create_depth_buffer(drivers);
+    let material_layout = Material::create_bind_group_layout(drivers);
+    let fallback_texture = Self::init_fallback_texture(drivers)?;
+    let white_texture =
+      ImageTexture::from_color(drivers, [255; 4], "White Texture", ColorSpace::Srgb)?;
+    // pointing straight out of the surface
+    let flat_normal_texture = ImageTexture::from_color(
+      drivers,
+      [128, 128, 255, 255],
+      "Flat Normal Texture",
+      ColorSpace::Linear,
+    )?;
 
     Ok(Self {
       image_textures: HashMap::new(),
+      layered_textures: HashMap::new(),
+      atlases: HashMap::new(),
+      samplers: SamplerCache::new(),
       fallback_texture: Arc::from(fallback_texture),
-      texture_bind_group_layout,
-      depth_buffer,
+      white_texture: Arc::new(white_texture),
+      flat_normal_texture: Arc::new(flat_normal_texture),
+      material_layout,
     })
   }
 
-  fn add_texture(&mut self, drivers: &Drivers, bytes: &[u8], label: &str) -> anyhow::Result<()> {
+  fn add_texture(
+    &mut self,
+    drivers: &Drivers,
+    bytes: &[u8],
+    label: &str,
+    color_space: ColorSpace,
+  ) -> anyhow::Result<Arc<ImageTexture>> {
     let label = String::from(label);
 
     if self.image_textures.contains_key(&label) {
@@ -300,10 +748,17 @@ impl TextureBundle {
       return Err(Error::msg(error_message));
     }
 
-    let tex = ImageTexture::from_bytes(&self.texture_bind_group_layout, drivers, bytes, &label)?;
-    self.image_textures.insert(label, Arc::new(tex));
+    let sampler = self.get_sampler(drivers, SamplerSettings::default());
+    let tex = Arc::new(ImageTexture::from_bytes_with_sampler(
+      drivers,
+      bytes,
+      &label,
+      color_space,
+      sampler,
+    )?);
+    self.image_textures.insert(label, tex.clone());
 
-    return Ok(());
+    return Ok(tex);
   }
 
   pub fn add_texture_from_file(
@@ -312,8 +767,229 @@ impl TextureBundle {
     file_name: &str,
     stored_name: &str,
   ) -> Result<(), anyhow::Error> {
-    let texture_data = files::load_image_bytes(file_name)?;
-    self.add_texture(drivers, &texture_data, stored_name)?;
+    self.add_texture_from_file_as(drivers, file_name, stored_name, ColorSpace::Srgb)?;
     Ok(())
   }
+
+  /// same as add_texture_from_file, linear is for anything that isn't a color (normals, masks...).
+  /// hdr/exr files are always linear, ktx2 and newer dds files say what they are themselves
+  pub fn add_texture_from_file_as(
+    &mut self,
+    drivers: &Drivers,
+    file_name: &str,
+    stored_name: &str,
+    color_space: ColorSpace,
+  ) -> anyhow::Result<Arc<ImageTexture>> {
+    let texture_data = files::load_image_bytes(file_name)?;
+    self.add_texture(drivers, &texture_data, stored_name, color_space)
+  }
+
+  /// stores an already decoded image, if something's already stored under label that's returned instead
+  pub fn add_image(
+    &mut self,
+    drivers: &Drivers,
+    img: &image::DynamicImage,
+    label: &str,
+    color_space: ColorSpace,
+    sampler: SamplerSettings,
+  ) -> anyhow::Result<Arc<ImageTexture>> {
+    if let Some(texture) = self.image_textures.get(label) {
+      return Ok(texture.clone());
+    }
+
+    let sampler = self.get_sampler(drivers, sampler);
+    let tex = Arc::new(ImageTexture::from_image_with_sampler(
+      drivers,
+      img,
+      Some(label),
+      color_space,
+      sampler,
+    )?);
+    self.image_textures.insert(String::from(label), tex.clone());
+
+    Ok(tex)
+  }
+
+  /// loads a texture from the images folder, stored under its file name
+  /// so every material using the same file shares it
+  pub fn load_texture(
+    &mut self,
+    drivers: &Drivers,
+    file_name: &str,
+    color_space: ColorSpace,
+  ) -> anyhow::Result<Arc<ImageTexture>> {
+    if let Some(texture) = self.image_textures.get(file_name) {
+      return Ok(texture.clone());
+    }
+
+    let texture_data = files::load_image_bytes(file_name)?;
+    self.add_texture(drivers, &texture_data, file_name, color_space)
+  }
+
+  /// arrays and cubemaps, None if nothing's stored under label
+  pub fn get_layered_texture(&self, label: &str) -> Option<Arc<LayeredTexture>> {
+    self.layered_textures.get(label).cloned()
+  }
+
+  fn insert_layered(
+    &mut self,
+    label: &str,
+    texture: LayeredTexture,
+  ) -> anyhow::Result<Arc<LayeredTexture>> {
+    if self.layered_textures.contains_key(label) {
+      return Err(Error::msg(format!(
+        "this texture already exists: {}",
+        label
+      )));
+    }
+    let texture = Arc::new(texture);
+    self
+      .layered_textures
+      .insert(String::from(label), texture.clone());
+    Ok(texture)
+  }
+
+  /// every file becomes a layer, in the order given. they all have to be the same size
+  pub fn add_texture_array_from_files(
+    &mut self,
+    drivers: &Drivers,
+    file_names: &[&str],
+    stored_name: &str,
+    color_space: ColorSpace,
+    sampler: SamplerSettings,
+  ) -> anyhow::Result<Arc<LayeredTexture>> {
+    let images = file_names
+      .iter()
+      .map(|file_name| load_image_file(file_name))
+      .collect::<anyhow::Result<Vec<_>>>()?;
+    let sampler = self.get_sampler(drivers, sampler);
+    let texture =
+      LayeredTexture::array_from_images(drivers, &images, Some(stored_name), color_space, sampler)?;
+    self.insert_layered(stored_name, texture)
+  }
+
+  /// six square images, in the +x, -x, +y, -y, +z, -z order
+  pub fn add_cubemap_from_files(
+    &mut self,
+    drivers: &Drivers,
+    file_names: [&str; 6],
+    stored_name: &str,
+    color_space: ColorSpace,
+  ) -> anyhow::Result<Arc<LayeredTexture>> {
+    let mut faces = Vec::with_capacity(6);
+    for file_name in file_names {
+      faces.push(load_image_file(file_name)?);
+    }
+    let faces: [image::DynamicImage; 6] = faces.try_into().unwrap();
+
+    let sampler = self.get_sampler(drivers, SamplerSettings::default());
+    let texture = LayeredTexture::cubemap_from_images(
+      drivers,
+      &faces,
+      Some(stored_name),
+      color_space,
+      sampler,
+    )?;
+    self.insert_layered(stored_name, texture)
+  }
+
+  /// a 2:1 panorama (usually an .hdr sky) projected onto a cubemap with face_size x face_size faces
+  pub fn add_cubemap_from_equirect_file(
+    &mut self,
+    drivers: &Drivers,
+    file_name: &str,
+    stored_name: &str,
+    face_size: u32,
+  ) -> anyhow::Result<Arc<LayeredTexture>> {
+    let panorama = load_image_file(file_name)?;
+    let sampler = self.get_sampler(drivers, SamplerSettings::default());
+    let texture = LayeredTexture::cubemap_from_equirect(
+      drivers,
+      &panorama,
+      face_size,
+      Some(stored_name),
+      ColorSpace::Srgb,
+      sampler,
+    )?;
+    self.insert_layered(stored_name, texture)
+  }
+
+  /// packs and uploads the atlas. its texture is stored under the same name as a normal texture,
+  /// so materials can use it through get_texture
+  pub fn add_atlas(
+    &mut self,
+    drivers: &Drivers,
+    builder: &AtlasBuilder,
+    stored_name: &str,
+  ) -> anyhow::Result<Arc<TextureAtlas>> {
+    if self.atlases.contains_key(stored_name) || self.image_textures.contains_key(stored_name) {
+      return Err(Error::msg(format!(
+        "this texture already exists: {}",
+        stored_name
+      )));
+    }
+
+    let sampler = self.get_sampler(drivers, SamplerSettings::default());
+    let atlas = Arc::new(builder.build(drivers, Some(stored_name), ColorSpace::Srgb, sampler)?);
+    self
+      .image_textures
+      .insert(String::from(stored_name), atlas.get_texture());
+    self
+      .atlases
+      .insert(String::from(stored_name), atlas.clone());
+    Ok(atlas)
+  }
+
+  /// an atlas of the files, each region is labeled with its file name
+  pub fn add_atlas_from_files(
+    &mut self,
+    drivers: &Drivers,
+    file_names: &[&str],
+    stored_name: &str,
+  ) -> anyhow::Result<Arc<TextureAtlas>> {
+    let mut builder = AtlasBuilder::new();
+    for file_name in file_names {
+      builder = builder.add_image(file_name, &load_image_file(file_name)?);
+    }
+    self.add_atlas(drivers, &builder, stored_name)
+  }
+
+  pub fn get_atlas(&self, label: &str) -> Option<Arc<TextureAtlas>> {
+    self.atlases.get(label).cloned()
+  }
+}
+
+fn load_image_file(file_name: &str) -> anyhow::Result<image::DynamicImage> {
+  let bytes = files::load_image_bytes(file_name)?;
+  Ok(image::load_from_memory(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mip_chains_go_down_to_one_pixel() {
+    assert_eq!(mip_level_count(1, 1), 1);
+    assert_eq!(mip_level_count(256, 256), 9);
+    // odd and non square sizes round down, the short side stays at 1
+    assert_eq!(mip_level_count(300, 20), 9);
+
+    let mips = generate_mips(&image::RgbaImage::new(8, 2));
+    let sizes: Vec<_> = mips.iter().map(|mip| mip.dimensions()).collect();
+    assert_eq!(sizes, [(4, 1), (2, 1), (1, 1)]);
+  }
+
+  #[test]
+  fn nearest_samplers_are_never_anisotropic() {
+    let settings = SamplerSettings::PIXEL_ART.with_anisotropy(8);
+    assert_eq!(settings.to_descriptor().anisotropy_clamp, 1);
+    assert_eq!(
+      settings.to_descriptor().mag_filter,
+      wgpu::FilterMode::Nearest
+    );
+
+    let settings = SamplerSettings::default().with_anisotropy(64);
+    assert_eq!(settings.to_descriptor().anisotropy_clamp, 16);
+  }
 }
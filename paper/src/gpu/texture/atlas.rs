@@ -0,0 +1,305 @@
+// packs lots of small images into one texture at runtime, so everything using them can share
+// a material (and a draw call). meshes get their uvs squeezed into their image's spot
+
+use std::{
+  collections::{HashMap, HashSet},
+  fmt,
+  sync::Arc,
+};
+
+use super::{ColorSpace, ImageTexture};
+use crate::gpu::{device_drivers::Drivers, geometry::ModelVertex};
+
+/// where an image ended up in the atlas, in pixels and in uv space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+  pub uv_offset: [f32; 2],
+  pub uv_scale: [f32; 2],
+}
+
+impl AtlasRegion {
+  fn new(x: u32, y: u32, width: u32, height: u32, atlas_size: u32) -> Self {
+    let size = atlas_size as f32;
+    Self {
+      x,
+      y,
+      width,
+      height,
+      uv_offset: [x as f32 / size, y as f32 / size],
+      uv_scale: [width as f32 / size, height as f32 / size],
+    }
+  }
+
+  /// a 0-1 uv of the original image to the same spot in the atlas.
+  /// repeating uvs (outside 0-1) can't work in an atlas, they'd run into the neighbours
+  #[inline]
+  pub fn remap_uv(&self, uv: [f32; 2]) -> [f32; 2] {
+    [
+      self.uv_offset[0] + uv[0] * self.uv_scale[0],
+      self.uv_offset[1] + uv[1] * self.uv_scale[1],
+    ]
+  }
+
+  pub fn remap_vertices(&self, vertices: &mut [ModelVertex]) {
+    for vertex in vertices {
+      vertex.tex_coords = self.remap_uv(vertex.tex_coords);
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtlasError {
+  Empty,
+  DuplicateLabel(String),
+  /// the images didn't fit in an atlas of the max size
+  TooBig(u32),
+}
+
+impl fmt::Display for AtlasError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AtlasError::Empty => write!(f, "an atlas needs at least one image"),
+      AtlasError::DuplicateLabel(label) => write!(f, "{} is in the atlas twice", label),
+      AtlasError::TooBig(max_size) => {
+        write!(f, "the images don't fit in a {0}x{0} atlas", max_size)
+      }
+    }
+  }
+}
+
+impl std::error::Error for AtlasError {}
+
+/// places the rectangles on shelves, tallest first, each with padding on every side.
+/// the positions are where the images themselves go (inside the padding), in the order given.
+/// None if they don't all fit in a square of atlas_size
+pub fn pack_shelves(
+  sizes: &[(u32, u32)],
+  atlas_size: u32,
+  padding: u32,
+) -> Option<Vec<(u32, u32)>> {
+  let mut order: Vec<usize> = (0..sizes.len()).collect();
+  order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+
+  let mut positions = vec![(0, 0); sizes.len()];
+  let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+  for i in order {
+    let (width, height) = (sizes[i].0 + padding * 2, sizes[i].1 + padding * 2);
+    if width > atlas_size {
+      return None;
+    }
+    if x + width > atlas_size {
+      x = 0;
+      y += shelf_height;
+      shelf_height = 0;
+    }
+    if y + height > atlas_size {
+      return None;
+    }
+
+    positions[i] = (x + padding, y + padding);
+    x += width;
+    shelf_height = shelf_height.max(height);
+  }
+  Some(positions)
+}
+
+pub struct AtlasBuilder {
+  images: Vec<(String, image::RgbaImage)>,
+  padding: u32,
+  max_size: u32,
+}
+
+impl Default for AtlasBuilder {
+  fn default() -> Self {
+    Self {
+      images: Vec::new(),
+      padding: 2,
+      max_size: 4096,
+    }
+  }
+}
+
+impl AtlasBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// pixels between the images, filled with their edges so filtering and
+  /// the first few mips don't bleed the neighbours in
+  pub fn with_padding(mut self, padding: u32) -> Self {
+    self.padding = padding;
+    self
+  }
+
+  pub fn with_max_size(mut self, max_size: u32) -> Self {
+    self.max_size = max_size;
+    self
+  }
+
+  /// atlases are always rgba8, hdr images lose their precision here
+  pub fn add_image(mut self, label: &str, img: &image::DynamicImage) -> Self {
+    self.images.push((String::from(label), img.to_rgba8()));
+    self
+  }
+
+  /// the smallest power of two square everything fits in, and where everything went
+  pub fn pack(&self) -> Result<(image::RgbaImage, HashMap<String, AtlasRegion>), AtlasError> {
+    if self.images.is_empty() {
+      return Err(AtlasError::Empty);
+    }
+
+    let mut labels = HashSet::new();
+    for (label, _) in &self.images {
+      if !labels.insert(label) {
+        return Err(AtlasError::DuplicateLabel(label.clone()));
+      }
+    }
+
+    let sizes: Vec<(u32, u32)> = self
+      .images
+      .iter()
+      .map(|(_, img)| img.dimensions())
+      .collect();
+    let area: u64 = sizes
+      .iter()
+      .map(|(w, h)| (w + self.padding * 2) as u64 * (h + self.padding * 2) as u64)
+      .sum();
+    let mut size = (area as f64).sqrt().ceil() as u32;
+    size = size.max(1).next_power_of_two();
+
+    let positions = loop {
+      if size > self.max_size {
+        return Err(AtlasError::TooBig(self.max_size));
+      }
+      if let Some(positions) = pack_shelves(&sizes, size, self.padding) {
+        break positions;
+      }
+      size *= 2;
+    };
+
+    let mut atlas = image::RgbaImage::new(size, size);
+    let mut regions = HashMap::new();
+    for ((label, img), (x, y)) in self.images.iter().zip(positions) {
+      let (width, height) = img.dimensions();
+      let padding = self.padding as i64;
+      // the image and its padding, every padding pixel copies the closest edge pixel
+      for py in -padding..height as i64 + padding {
+        for px in -padding..width as i64 + padding {
+          let source = img.get_pixel(
+            px.clamp(0, width as i64 - 1) as u32,
+            py.clamp(0, height as i64 - 1) as u32,
+          );
+          atlas.put_pixel((x as i64 + px) as u32, (y as i64 + py) as u32, *source);
+        }
+      }
+      regions.insert(label.clone(), AtlasRegion::new(x, y, width, height, size));
+    }
+
+    Ok((atlas, regions))
+  }
+
+  pub fn build(
+    &self,
+    drivers: &Drivers,
+    label: Option<&str>,
+    color_space: ColorSpace,
+    sampler: wgpu::Sampler,
+  ) -> anyhow::Result<TextureAtlas> {
+    let (atlas, regions) = self.pack()?;
+    let texture = ImageTexture::from_image_with_sampler(
+      drivers,
+      &image::DynamicImage::ImageRgba8(atlas),
+      label,
+      color_space,
+      sampler,
+    )?;
+    Ok(TextureAtlas {
+      texture: Arc::new(texture),
+      regions,
+    })
+  }
+}
+
+pub struct TextureAtlas {
+  texture: Arc<ImageTexture>,
+  regions: HashMap<String, AtlasRegion>,
+}
+
+impl TextureAtlas {
+  #[inline]
+  pub fn get_texture(&self) -> Arc<ImageTexture> {
+    self.texture.clone()
+  }
+
+  #[inline]
+  pub fn get_region(&self, label: &str) -> Option<&AtlasRegion> {
+    self.regions.get(label)
+  }
+
+  pub fn iter_regions(&self) -> impl Iterator<Item = (&String, &AtlasRegion)> {
+    self.regions.iter()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn shelves_never_overlap() {
+    let sizes = [(10, 4), (6, 8), (6, 8), (20, 2)];
+    let positions = pack_shelves(&sizes, 32, 1).unwrap();
+
+    for (i, (a, a_size)) in positions.iter().zip(sizes).enumerate() {
+      // at least the 1 pixel of padding before the edge
+      assert!(a.0 + a_size.0 < 32 && a.1 + a_size.1 < 32);
+      for (b, b_size) in positions.iter().zip(sizes).skip(i + 1) {
+        let apart_x = a.0 + a_size.0 < b.0 || b.0 + b_size.0 < a.0;
+        let apart_y = a.1 + a_size.1 < b.1 || b.1 + b_size.1 < a.1;
+        assert!(apart_x || apart_y, "{:?} and {:?} overlap", a, b);
+      }
+    }
+    assert_eq!(pack_shelves(&sizes, 16, 1), None);
+  }
+
+  #[test]
+  fn atlases_grow_and_extrude_their_edges() {
+    let red = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+      30,
+      30,
+      image::Rgba([255, 0, 0, 255]),
+    ));
+    let blue = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+      30,
+      10,
+      image::Rgba([0, 0, 255, 255]),
+    ));
+    let builder = AtlasBuilder::new()
+      .add_image("red", &red)
+      .add_image("blue", &blue);
+    let (atlas, regions) = builder.pack().unwrap();
+    assert_eq!(atlas.dimensions(), (64, 64));
+
+    let region = regions["blue"];
+    assert_eq!((region.width, region.height), (30, 10));
+    // the padding around blue is blue too
+    let edge = atlas.get_pixel(region.x - 1, region.y - 1);
+    assert_eq!(edge.0, [0, 0, 255, 255]);
+    let [u, v] = region.remap_uv([1.0, 1.0]);
+    assert_eq!(
+      [u * 64.0, v * 64.0],
+      [(region.x + 30) as f32, (region.y + 10) as f32]
+    );
+
+    let builder = builder.with_max_size(32);
+    assert_eq!(builder.pack().err(), Some(AtlasError::TooBig(32)));
+    let builder = AtlasBuilder::new()
+      .add_image("red", &red)
+      .add_image("red", &red);
+    assert!(matches!(builder.pack(), Err(AtlasError::DuplicateLabel(_))));
+  }
+}
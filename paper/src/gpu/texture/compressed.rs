@@ -0,0 +1,561 @@
+// pre-compressed textures, straight out of .ktx2 or .dds files.
+// they get uploaded as they are when the gpu can sample bcn, otherwise they're decompressed
+// to rgba8 here first (bc6h and bc7 can't be, those need a gpu that supports them)
+
+use super::ColorSpace;
+
+/// the block compressed formats the engine knows, every block is 4x4 pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+  /// rgb + 1 bit alpha, aka dxt1
+  Bc1,
+  /// rgb + 4 bit alpha, aka dxt3
+  Bc2,
+  /// rgb + smooth alpha, aka dxt5
+  Bc3,
+  /// one channel
+  Bc4,
+  /// two channels, normal maps
+  Bc5,
+  /// hdr rgb, gpu only
+  Bc6h,
+  /// high quality rgba, gpu only
+  Bc7,
+}
+
+impl BlockFormat {
+  pub fn get_block_bytes(self) -> usize {
+    match self {
+      BlockFormat::Bc1 | BlockFormat::Bc4 => 8,
+      _ => 16,
+    }
+  }
+
+  /// formats without an srgb version ignore the color space
+  pub fn to_wgpu(self, color_space: ColorSpace) -> wgpu::TextureFormat {
+    use wgpu::TextureFormat as F;
+    let srgb = color_space == ColorSpace::Srgb;
+    match self {
+      BlockFormat::Bc1 if srgb => F::Bc1RgbaUnormSrgb,
+      BlockFormat::Bc1 => F::Bc1RgbaUnorm,
+      BlockFormat::Bc2 if srgb => F::Bc2RgbaUnormSrgb,
+      BlockFormat::Bc2 => F::Bc2RgbaUnorm,
+      BlockFormat::Bc3 if srgb => F::Bc3RgbaUnormSrgb,
+      BlockFormat::Bc3 => F::Bc3RgbaUnorm,
+      BlockFormat::Bc4 => F::Bc4RUnorm,
+      BlockFormat::Bc5 => F::Bc5RgUnorm,
+      BlockFormat::Bc6h => F::Bc6hRgbUfloat,
+      BlockFormat::Bc7 if srgb => F::Bc7RgbaUnormSrgb,
+      BlockFormat::Bc7 => F::Bc7RgbaUnorm,
+    }
+  }
+
+  /// how many bytes a whole level takes, partial blocks at the edges still take a full block
+  pub fn get_level_bytes(self, width: u32, height: u32) -> usize {
+    let blocks_wide = width.div_ceil(4).max(1) as usize;
+    let blocks_high = height.div_ceil(4).max(1) as usize;
+    blocks_wide * blocks_high * self.get_block_bytes()
+  }
+}
+
+#[derive(Debug)]
+pub enum CompressedError {
+  /// neither a ktx2 nor a dds file
+  UnknownContainer,
+  /// shorter than its header says it is
+  Truncated,
+  UnsupportedFormat(String),
+  /// cubemaps, arrays, 3d textures, supercompression
+  Unsupported(&'static str),
+  /// 0 pixels wide or high
+  EmptyImage,
+  /// more mip levels than halving the size down to 1x1 gives
+  TooManyLevels(u32),
+}
+
+impl std::fmt::Display for CompressedError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CompressedError::UnknownContainer => write!(f, "not a ktx2 or dds file"),
+      CompressedError::Truncated => write!(f, "the file ends before its data does"),
+      CompressedError::UnsupportedFormat(format) => {
+        write!(f, "{} isn't a supported compressed format", format)
+      }
+      CompressedError::Unsupported(what) => write!(f, "{} aren't supported", what),
+      CompressedError::EmptyImage => write!(f, "the image has no pixels"),
+      CompressedError::TooManyLevels(count) => {
+        write!(f, "{} mip levels is more than the image's size has", count)
+      }
+    }
+  }
+}
+
+impl std::error::Error for CompressedError {}
+
+/// a decoded container, the pixel data is still compressed
+#[derive(Debug, Clone)]
+pub struct CompressedImage {
+  pub format: BlockFormat,
+  /// None when the file doesn't say (old dds files), the caller's color space is used then
+  pub color_space: Option<ColorSpace>,
+  pub width: u32,
+  pub height: u32,
+  /// biggest first, each one half the size of the last
+  pub levels: Vec<Vec<u8>>,
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+  0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+
+/// offsets and lengths come from the file, so a broken one can point past the end or overflow
+fn read_bytes(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], CompressedError> {
+  offset
+    .checked_add(length)
+    .and_then(|end| bytes.get(offset..end))
+    .ok_or(CompressedError::Truncated)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, CompressedError> {
+  let slice = read_bytes(bytes, offset, 4)?;
+  Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, CompressedError> {
+  let mut value = [0; 8];
+  value.copy_from_slice(read_bytes(bytes, offset, 8)?);
+  Ok(u64::from_le_bytes(value))
+}
+
+/// a u64 offset or length, anything this platform can't index can't be in the file either
+fn read_u64_index(bytes: &[u8], offset: usize) -> Result<usize, CompressedError> {
+  usize::try_from(read_u64(bytes, offset)?).map_err(|_| CompressedError::Truncated)
+}
+
+fn read_slice(bytes: &[u8], offset: usize, length: usize) -> Result<Vec<u8>, CompressedError> {
+  read_bytes(bytes, offset, length).map(<[u8]>::to_vec)
+}
+
+/// wgpu won't make a texture without pixels or with more levels than its size allows
+fn check_size(width: u32, height: u32, level_count: u32) -> Result<(), CompressedError> {
+  if width == 0 || height == 0 {
+    return Err(CompressedError::EmptyImage);
+  }
+  let size = wgpu::Extent3d {
+    width,
+    height,
+    depth_or_array_layers: 1,
+  };
+  if level_count > size.max_mips(wgpu::TextureDimension::D2) {
+    return Err(CompressedError::TooManyLevels(level_count));
+  }
+  Ok(())
+}
+
+/// whether the bytes look like something CompressedImage::from_bytes can read
+pub fn is_compressed_container(bytes: &[u8]) -> bool {
+  bytes.starts_with(&KTX2_IDENTIFIER) || bytes.starts_with(DDS_MAGIC)
+}
+
+impl CompressedImage {
+  /// reads a .ktx2 or .dds, whichever the bytes turn out to be
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self, CompressedError> {
+    if bytes.starts_with(&KTX2_IDENTIFIER) {
+      Self::from_ktx2(bytes)
+    } else if bytes.starts_with(DDS_MAGIC) {
+      Self::from_dds(bytes)
+    } else {
+      Err(CompressedError::UnknownContainer)
+    }
+  }
+
+  fn from_ktx2(bytes: &[u8]) -> Result<Self, CompressedError> {
+    let vk_format = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 20)?;
+    let height = read_u32(bytes, 24)?;
+    let depth = read_u32(bytes, 28)?;
+    let layers = read_u32(bytes, 32)?;
+    let faces = read_u32(bytes, 36)?;
+    let level_count = read_u32(bytes, 40)?.max(1);
+    let supercompression = read_u32(bytes, 44)?;
+
+    if supercompression != 0 {
+      return Err(CompressedError::Unsupported("supercompressed ktx2 files"));
+    }
+    if depth > 1 || layers > 1 || faces > 1 {
+      return Err(CompressedError::Unsupported(
+        "ktx2 arrays, cubemaps and 3d textures",
+      ));
+    }
+    check_size(width, height, level_count)?;
+
+    // the vulkan format numbers
+    let (format, color_space) = match vk_format {
+      131 | 133 => (BlockFormat::Bc1, ColorSpace::Linear),
+      132 | 134 => (BlockFormat::Bc1, ColorSpace::Srgb),
+      135 => (BlockFormat::Bc2, ColorSpace::Linear),
+      136 => (BlockFormat::Bc2, ColorSpace::Srgb),
+      137 => (BlockFormat::Bc3, ColorSpace::Linear),
+      138 => (BlockFormat::Bc3, ColorSpace::Srgb),
+      139 => (BlockFormat::Bc4, ColorSpace::Linear),
+      141 => (BlockFormat::Bc5, ColorSpace::Linear),
+      143 => (BlockFormat::Bc6h, ColorSpace::Linear),
+      145 => (BlockFormat::Bc7, ColorSpace::Linear),
+      146 => (BlockFormat::Bc7, ColorSpace::Srgb),
+      other => {
+        return Err(CompressedError::UnsupportedFormat(format!(
+          "vkFormat {}",
+          other
+        )))
+      }
+    };
+
+    // the level index comes right after the 80 byte header, level 0 is the biggest
+    let mut levels = Vec::new();
+    for level in 0..level_count {
+      let entry = 80 + level as usize * 24;
+      let offset = read_u64_index(bytes, entry)?;
+      let length = read_u64_index(bytes, entry + 8)?;
+      levels.push(read_slice(bytes, offset, length)?);
+    }
+
+    Ok(Self {
+      format,
+      color_space: Some(color_space),
+      width,
+      height,
+      levels,
+    })
+  }
+
+  fn from_dds(bytes: &[u8]) -> Result<Self, CompressedError> {
+    let height = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 16)?;
+    let level_count = read_u32(bytes, 28)?.max(1);
+    let four_cc = read_slice(bytes, 84, 4)?;
+    let caps2 = read_u32(bytes, 112)?;
+
+    const CUBEMAP: u32 = 0x200;
+    const VOLUME: u32 = 0x200000;
+    if caps2 & (CUBEMAP | VOLUME) != 0 {
+      return Err(CompressedError::Unsupported("dds cubemaps and volumes"));
+    }
+    check_size(width, height, level_count)?;
+
+    let (format, color_space, data_start) = match &four_cc[..] {
+      b"DXT1" => (BlockFormat::Bc1, None, 128),
+      b"DXT2" | b"DXT3" => (BlockFormat::Bc2, None, 128),
+      b"DXT4" | b"DXT5" => (BlockFormat::Bc3, None, 128),
+      b"ATI1" | b"BC4U" => (BlockFormat::Bc4, None, 128),
+      b"ATI2" | b"BC5U" => (BlockFormat::Bc5, None, 128),
+      // the newer header says exactly what it is, with a dxgi format
+      b"DX10" => {
+        if read_u32(bytes, 140)? > 1 {
+          return Err(CompressedError::Unsupported("dds texture arrays"));
+        }
+        let (format, color_space) = match read_u32(bytes, 128)? {
+          71 => (BlockFormat::Bc1, ColorSpace::Linear),
+          72 => (BlockFormat::Bc1, ColorSpace::Srgb),
+          74 => (BlockFormat::Bc2, ColorSpace::Linear),
+          75 => (BlockFormat::Bc2, ColorSpace::Srgb),
+          77 => (BlockFormat::Bc3, ColorSpace::Linear),
+          78 => (BlockFormat::Bc3, ColorSpace::Srgb),
+          80 => (BlockFormat::Bc4, ColorSpace::Linear),
+          83 => (BlockFormat::Bc5, ColorSpace::Linear),
+          95 => (BlockFormat::Bc6h, ColorSpace::Linear),
+          98 => (BlockFormat::Bc7, ColorSpace::Linear),
+          99 => (BlockFormat::Bc7, ColorSpace::Srgb),
+          other => {
+            return Err(CompressedError::UnsupportedFormat(format!(
+              "dxgi format {}",
+              other
+            )))
+          }
+        };
+        (format, Some(color_space), 148)
+      }
+      other => {
+        return Err(CompressedError::UnsupportedFormat(format!(
+          "dds four cc {:?}",
+          String::from_utf8_lossy(other)
+        )))
+      }
+    };
+
+    // levels are packed one after the other, biggest first
+    let mut levels = Vec::new();
+    let mut offset = data_start;
+    for level in 0..level_count {
+      let length = format.get_level_bytes((width >> level).max(1), (height >> level).max(1));
+      levels.push(read_slice(bytes, offset, length)?);
+      offset += length;
+    }
+
+    Ok(Self {
+      format,
+      color_space,
+      width,
+      height,
+      levels,
+    })
+  }
+
+  /// the biggest level as plain rgba8, None for the formats only a gpu can decode.
+  /// one and two channel formats come out as red and red/green, like the gpu samples them
+  pub fn decompress(&self) -> Option<image::RgbaImage> {
+    let decode_block: fn(&[u8]) -> [[u8; 4]; 16] = match self.format {
+      BlockFormat::Bc1 => |block| decode_bc1(block, true),
+      BlockFormat::Bc2 => decode_bc2,
+      BlockFormat::Bc3 => decode_bc3,
+      BlockFormat::Bc4 => decode_bc4_rgba,
+      BlockFormat::Bc5 => decode_bc5,
+      BlockFormat::Bc6h | BlockFormat::Bc7 => return None,
+    };
+
+    let blocks_wide = self.width.div_ceil(4).max(1);
+    let mut image = image::RgbaImage::new(self.width, self.height);
+    let block_bytes = self.format.get_block_bytes();
+
+    for (index, block) in self.levels.first()?.chunks_exact(block_bytes).enumerate() {
+      let (block_x, block_y) = (index as u32 % blocks_wide, index as u32 / blocks_wide);
+      for (pixel, color) in decode_block(block).into_iter().enumerate() {
+        let x = block_x * 4 + pixel as u32 % 4;
+        let y = block_y * 4 + pixel as u32 / 4;
+        // blocks hang off the edge of sizes that aren't a multiple of 4
+        if x < self.width && y < self.height {
+          image.put_pixel(x, y, image::Rgba(color));
+        }
+      }
+    }
+    Some(image)
+  }
+}
+
+fn unpack_565(color: u16) -> [u8; 3] {
+  let r = (color >> 11) & 0x1f;
+  let g = (color >> 5) & 0x3f;
+  let b = color & 0x1f;
+  [
+    ((r << 3) | (r >> 2)) as u8,
+    ((g << 2) | (g >> 4)) as u8,
+    ((b << 3) | (b >> 2)) as u8,
+  ]
+}
+
+fn mix(a: u8, b: u8, a_parts: u32, b_parts: u32) -> u8 {
+  ((a as u32 * a_parts + b as u32 * b_parts) / (a_parts + b_parts)) as u8
+}
+
+/// the color half of bc1/2/3. only bc1 on its own can use the 3 color + transparent mode
+fn decode_bc1(block: &[u8], allow_transparent: bool) -> [[u8; 4]; 16] {
+  let color0 = u16::from_le_bytes([block[0], block[1]]);
+  let color1 = u16::from_le_bytes([block[2], block[3]]);
+  let (c0, c1) = (unpack_565(color0), unpack_565(color1));
+
+  let channel = |parts0: u32, parts1: u32| -> [u8; 4] {
+    [
+      mix(c0[0], c1[0], parts0, parts1),
+      mix(c0[1], c1[1], parts0, parts1),
+      mix(c0[2], c1[2], parts0, parts1),
+      255,
+    ]
+  };
+  let palette = match color0 > color1 || !allow_transparent {
+    true => [channel(1, 0), channel(0, 1), channel(2, 1), channel(1, 2)],
+    false => [channel(1, 0), channel(0, 1), channel(1, 1), [0, 0, 0, 0]],
+  };
+
+  let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+  std::array::from_fn(|pixel| palette[((indices >> (pixel * 2)) & 0b11) as usize])
+}
+
+fn decode_bc2(block: &[u8]) -> [[u8; 4]; 16] {
+  let mut pixels = decode_bc1(&block[8..], false);
+  let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+  for (pixel, color) in pixels.iter_mut().enumerate() {
+    color[3] = ((alpha >> (pixel * 4)) & 0xf) as u8 * 17;
+  }
+  pixels
+}
+
+/// one channel, 2 endpoints and 3 bit indices. bc3's alpha and bc4/bc5's channels
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+  let (a0, a1) = (block[0], block[1]);
+  let palette: [u8; 8] = match a0 > a1 {
+    true => std::array::from_fn(|i| match i {
+      0 => a0,
+      1 => a1,
+      _ => mix(a0, a1, 8 - i as u32, i as u32 - 1),
+    }),
+    false => std::array::from_fn(|i| match i {
+      0 => a0,
+      1 => a1,
+      6 => 0,
+      7 => 255,
+      _ => mix(a0, a1, 6 - i as u32, i as u32 - 1),
+    }),
+  };
+
+  let mut bits = [0; 8];
+  bits[..6].copy_from_slice(&block[2..8]);
+  let indices = u64::from_le_bytes(bits);
+  std::array::from_fn(|pixel| palette[((indices >> (pixel * 3)) & 0b111) as usize])
+}
+
+fn decode_bc3(block: &[u8]) -> [[u8; 4]; 16] {
+  let mut pixels = decode_bc1(&block[8..], false);
+  let alpha = decode_bc4(&block[..8]);
+  for (color, alpha) in pixels.iter_mut().zip(alpha) {
+    color[3] = alpha;
+  }
+  pixels
+}
+
+fn decode_bc4_rgba(block: &[u8]) -> [[u8; 4]; 16] {
+  decode_bc4(block).map(|red| [red, 0, 0, 255])
+}
+
+fn decode_bc5(block: &[u8]) -> [[u8; 4]; 16] {
+  let (red, green) = (decode_bc4(&block[..8]), decode_bc4(&block[8..]));
+  std::array::from_fn(|pixel| [red[pixel], green[pixel], 0, 255])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a 4x4 dds with one bc1 block: pure red and pure blue, left half red, right half blue
+  fn red_blue_dds() -> Vec<u8> {
+    let mut bytes = vec![0; 128];
+    bytes[..4].copy_from_slice(DDS_MAGIC);
+    bytes[12..16].copy_from_slice(&4u32.to_le_bytes());
+    bytes[16..20].copy_from_slice(&4u32.to_le_bytes());
+    bytes[28..32].copy_from_slice(&1u32.to_le_bytes());
+    bytes[84..88].copy_from_slice(b"DXT1");
+
+    // 5 bits red, 6 green, 5 blue
+    let red = 0xf800u16;
+    let blue = 0x001fu16;
+    bytes.extend(red.to_le_bytes());
+    bytes.extend(blue.to_le_bytes());
+    // 2 bits per pixel, index 0 (red) for x 0 and 1, index 1 (blue) for x 2 and 3
+    bytes.extend([0b0101_0000u8; 4]);
+    bytes
+  }
+
+  #[test]
+  fn dds_bc1_is_read_and_decompressed() {
+    let image = CompressedImage::from_bytes(&red_blue_dds()).unwrap();
+    assert_eq!(image.format, BlockFormat::Bc1);
+    assert_eq!(image.color_space, None);
+    assert_eq!((image.width, image.height), (4, 4));
+    assert_eq!(image.levels[0].len(), 8);
+
+    let rgba = image.decompress().unwrap();
+    assert_eq!(rgba.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    assert_eq!(rgba.get_pixel(3, 3).0, [0, 0, 255, 255]);
+  }
+
+  #[test]
+  fn truncated_and_unknown_files_are_errors() {
+    let dds = red_blue_dds();
+    assert!(matches!(
+      CompressedImage::from_bytes(&dds[..130]),
+      Err(CompressedError::Truncated)
+    ));
+    assert!(matches!(
+      CompressedImage::from_bytes(b"not a texture"),
+      Err(CompressedError::UnknownContainer)
+    ));
+  }
+
+  #[test]
+  fn out_of_range_level_indices_are_errors() {
+    // a 4x4 bc4 ktx2 with one level, wherever the index says it is
+    let ktx2 = |offset: u64, length: u64| {
+      let mut bytes = KTX2_IDENTIFIER.to_vec();
+      for value in [139u32, 1, 4, 4, 0, 0, 1, 1, 0] {
+        bytes.extend(value.to_le_bytes());
+      }
+      bytes.resize(80, 0);
+      bytes.extend(offset.to_le_bytes());
+      bytes.extend(length.to_le_bytes());
+      bytes.extend(length.to_le_bytes());
+      bytes.extend([0; 8]);
+      bytes
+    };
+
+    assert!(CompressedImage::from_bytes(&ktx2(104, 8)).is_ok());
+    for (offset, length) in [
+      (u64::MAX, 8),
+      (104, u64::MAX),
+      (u64::MAX, u64::MAX),
+      (112, 8),
+    ] {
+      assert!(matches!(
+        CompressedImage::from_bytes(&ktx2(offset, length)),
+        Err(CompressedError::Truncated)
+      ));
+    }
+  }
+
+  #[test]
+  fn empty_images_and_impossible_mips_are_errors() {
+    let mut dds = red_blue_dds();
+    // a 4x4 image only has room for 3 levels
+    dds[28..32].copy_from_slice(&40u32.to_le_bytes());
+    assert!(matches!(
+      CompressedImage::from_bytes(&dds),
+      Err(CompressedError::TooManyLevels(40))
+    ));
+
+    let mut dds = red_blue_dds();
+    dds[16..20].copy_from_slice(&0u32.to_le_bytes());
+    assert!(matches!(
+      CompressedImage::from_bytes(&dds),
+      Err(CompressedError::EmptyImage)
+    ));
+  }
+
+  #[test]
+  fn ktx2_levels_are_found_through_the_index() {
+    let mut bytes = KTX2_IDENTIFIER.to_vec();
+    // vkFormat bc4, type size, 8x4, depth, layers, faces, 2 levels, no supercompression
+    for value in [139u32, 1, 8, 4, 0, 0, 1, 2, 0] {
+      bytes.extend(value.to_le_bytes());
+    }
+    bytes.resize(80, 0);
+    // level 0 is 2 blocks, level 1 (4x2) is 1, stored smallest first like ktx2 tools do
+    for (offset, length) in [(136u64, 16u64), (128, 8)] {
+      bytes.extend(offset.to_le_bytes());
+      bytes.extend(length.to_le_bytes());
+      bytes.extend(length.to_le_bytes());
+    }
+    bytes.extend([0, 255, 0, 0, 0, 0, 0, 0]);
+    bytes.extend([255, 0, 0, 0, 0, 0, 0, 0]);
+    bytes.extend([0, 255, 0, 0, 0, 0, 0, 0]);
+
+    let image = CompressedImage::from_bytes(&bytes).unwrap();
+    assert_eq!(image.format, BlockFormat::Bc4);
+    assert_eq!(image.color_space, Some(ColorSpace::Linear));
+    assert_eq!(image.levels.len(), 2);
+    assert_eq!(image.levels[1][0], 0);
+
+    // every index is 0, so each block is all its a0
+    let rgba = image.decompress().unwrap();
+    assert_eq!(rgba.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    assert_eq!(rgba.get_pixel(7, 3).0, [0, 0, 0, 255]);
+  }
+
+  #[test]
+  fn bc4_interpolates_between_its_endpoints() {
+    // a0 > a1, so 6 steps in between. pixel 0 uses index 2, 1/7 of the way
+    let block = [255, 0, 0b010, 0, 0, 0, 0, 0];
+    assert_eq!(decode_bc4(&block)[0], 218);
+    // a0 <= a1 has 0 and 255 at the end of the palette
+    let block = [10, 20, 0b110, 0, 0, 0, 0, 0];
+    let decoded = decode_bc4(&block);
+    assert_eq!((decoded[0], decoded[1]), (0, 10));
+  }
+}
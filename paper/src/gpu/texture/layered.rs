@@ -0,0 +1,301 @@
+// textures with more than one layer: 2d arrays (terrain splatting, lots of same sized sprites)
+// and cubemaps (skyboxes, reflections). every layer gets its own mip chain
+
+use std::f32::consts::PI;
+
+use anyhow::Error;
+use cgmath::InnerSpace;
+
+use super::{mip_level_count, ColorSpace, DynamicTexture, TexelData};
+use crate::{gpu::device_drivers::Drivers, maths::Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+  Array,
+  Cube,
+}
+
+impl LayerKind {
+  pub fn get_view_dimension(&self) -> wgpu::TextureViewDimension {
+    match self {
+      LayerKind::Array => wgpu::TextureViewDimension::D2Array,
+      LayerKind::Cube => wgpu::TextureViewDimension::Cube,
+    }
+  }
+}
+
+pub struct LayeredTexture {
+  pub texture: wgpu::Texture,
+  pub view: wgpu::TextureView,
+  pub sampler: wgpu::Sampler,
+  kind: LayerKind,
+}
+
+impl LayeredTexture {
+  /// every image becomes a layer, in order. they all have to be the same size
+  pub fn array_from_images(
+    drivers: &Drivers,
+    images: &[image::DynamicImage],
+    label: Option<&str>,
+    color_space: ColorSpace,
+    sampler: wgpu::Sampler,
+  ) -> anyhow::Result<Self> {
+    if images.is_empty() {
+      return Err(Error::msg("a texture array needs at least one image"));
+    }
+    Self::from_layers(
+      drivers,
+      images,
+      label,
+      color_space,
+      sampler,
+      LayerKind::Array,
+    )
+  }
+
+  /// six square images of the same size, in the +x, -x, +y, -y, +z, -z order
+  pub fn cubemap_from_images(
+    drivers: &Drivers,
+    faces: &[image::DynamicImage; 6],
+    label: Option<&str>,
+    color_space: ColorSpace,
+    sampler: wgpu::Sampler,
+  ) -> anyhow::Result<Self> {
+    if faces[0].width() != faces[0].height() {
+      return Err(Error::msg("cubemap faces have to be square"));
+    }
+    Self::from_layers(drivers, faces, label, color_space, sampler, LayerKind::Cube)
+  }
+
+  /// projects an equirectangular panorama (the usual 2:1 .hdr skies) onto the six faces.
+  /// done once on the cpu when loading, the faces stay as floats so hdr skies keep their brightness
+  pub fn cubemap_from_equirect(
+    drivers: &Drivers,
+    panorama: &image::DynamicImage,
+    face_size: u32,
+    label: Option<&str>,
+    color_space: ColorSpace,
+    sampler: wgpu::Sampler,
+  ) -> anyhow::Result<Self> {
+    let panorama = match TexelData::float_from_image(panorama, color_space) {
+      TexelData::Float(rgba) => rgba,
+      TexelData::Rgba8(_) => unreachable!(),
+    };
+    let faces = equirect_to_faces(&panorama, face_size.max(1));
+    // already linear, so it mustn't be decoded a second time
+    Self::from_layers(
+      drivers,
+      &faces,
+      label,
+      ColorSpace::Linear,
+      sampler,
+      LayerKind::Cube,
+    )
+  }
+
+  fn from_layers(
+    drivers: &Drivers,
+    images: &[image::DynamicImage],
+    label: Option<&str>,
+    color_space: ColorSpace,
+    sampler: wgpu::Sampler,
+    kind: LayerKind,
+  ) -> anyhow::Result<Self> {
+    let (width, height) = (images[0].width(), images[0].height());
+    if let Some(image) = images
+      .iter()
+      .find(|image| (image.width(), image.height()) != (width, height))
+    {
+      return Err(Error::msg(format!(
+        "every layer has to be {}x{}, found one that's {}x{}",
+        width,
+        height,
+        image.width(),
+        image.height()
+      )));
+    }
+
+    // one format for the whole texture, so one hdr layer makes all of them floats
+    let is_float = images.iter().any(super::is_high_precision);
+    let layers: Vec<TexelData> = images
+      .iter()
+      .map(|image| match is_float {
+        true => TexelData::float_from_image(image, color_space),
+        false => TexelData::from_image(image, color_space),
+      })
+      .collect();
+
+    let texture = drivers.device.create_texture(&wgpu::TextureDescriptor {
+      label,
+      size: wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: images.len() as u32,
+      },
+      mip_level_count: mip_level_count(width, height),
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: layers[0].get_format(color_space),
+      usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+      view_formats: &[],
+    });
+    for (layer, texels) in layers.iter().enumerate() {
+      texels.write_with_mips(drivers, &texture, layer as u32);
+    }
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+      label,
+      dimension: Some(kind.get_view_dimension()),
+      ..Default::default()
+    });
+
+    Ok(Self {
+      texture,
+      view,
+      sampler,
+      kind,
+    })
+  }
+
+  #[inline]
+  pub fn get_kind(&self) -> LayerKind {
+    self.kind
+  }
+
+  #[inline]
+  pub fn get_layer_count(&self) -> u32 {
+    self.texture.depth_or_array_layers()
+  }
+
+  #[inline]
+  pub fn is_float(&self) -> bool {
+    self.texture.format() == DynamicTexture::HDR_FORMAT
+  }
+
+  /// the texture at binding 0 and its sampler at 1, visible to the fragment shader
+  pub fn create_bind_group_layout(drivers: &Drivers, kind: LayerKind) -> wgpu::BindGroupLayout {
+    drivers
+      .device
+      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+          wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+              multisampled: false,
+              view_dimension: kind.get_view_dimension(),
+              sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+          },
+          wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+          },
+        ],
+        label: Some("layered_texture_bind_group_layout"),
+      })
+  }
+
+  pub fn create_bind_group(
+    &self,
+    drivers: &Drivers,
+    layout: &wgpu::BindGroupLayout,
+  ) -> wgpu::BindGroup {
+    drivers
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&self.view),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(&self.sampler),
+          },
+        ],
+        label: Some("layered_texture_bind_group"),
+      })
+  }
+}
+
+/// which way a texel of a cube face points, s and t go from -1 to 1 across the face
+fn cube_face_direction(face: usize, s: f32, t: f32) -> Vec3 {
+  let direction = match face {
+    0 => Vec3::new(1.0, -t, -s),
+    1 => Vec3::new(-1.0, -t, s),
+    2 => Vec3::new(s, 1.0, t),
+    3 => Vec3::new(s, -1.0, -t),
+    4 => Vec3::new(s, -t, 1.0),
+    _ => Vec3::new(-s, -t, -1.0),
+  };
+  direction.normalize()
+}
+
+/// where a direction lands on an equirectangular image, u wraps around and v goes top to bottom
+fn direction_to_equirect(direction: Vec3) -> (f32, f32) {
+  let u = direction.z.atan2(direction.x) / (2.0 * PI) + 0.5;
+  let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+  (u, v)
+}
+
+fn equirect_to_faces(panorama: &image::Rgba32FImage, face_size: u32) -> Vec<image::DynamicImage> {
+  (0..6)
+    .map(|face| {
+      let face = image::Rgba32FImage::from_fn(face_size, face_size, |x, y| {
+        // through the middle of the texel
+        let s = (x as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+        let t = (y as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+        let (u, v) = direction_to_equirect(cube_face_direction(face, s, t));
+        image::imageops::sample_bilinear(panorama, u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
+          .unwrap_or(image::Rgba([0.0, 0.0, 0.0, 1.0]))
+      });
+      image::DynamicImage::ImageRgba32F(face)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_close(a: Vec3, b: Vec3) {
+    assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+  }
+
+  #[test]
+  fn cube_faces_point_the_right_way() {
+    // the middle of each face points straight down its axis
+    let axes = [
+      Vec3::unit_x(),
+      -Vec3::unit_x(),
+      Vec3::unit_y(),
+      -Vec3::unit_y(),
+      Vec3::unit_z(),
+      -Vec3::unit_z(),
+    ];
+    for (face, axis) in axes.iter().enumerate() {
+      assert_close(cube_face_direction(face, 0.0, 0.0), *axis);
+    }
+    // the top of the +z face leans up, the top of +y leans towards -z
+    assert!(cube_face_direction(4, 0.0, -1.0).y > 0.0);
+    assert!(cube_face_direction(2, 0.0, -1.0).z < 0.0);
+  }
+
+  #[test]
+  fn equirect_covers_the_whole_sphere() {
+    let (_, v) = direction_to_equirect(Vec3::unit_y());
+    assert!(v.abs() < 1e-5, "straight up is the top row, got {}", v);
+    let (_, v) = direction_to_equirect(-Vec3::unit_y());
+    assert!((v - 1.0).abs() < 1e-5);
+
+    let (u, v) = direction_to_equirect(Vec3::unit_x());
+    assert!((u - 0.5).abs() < 1e-5 && (v - 0.5).abs() < 1e-5);
+    let (u, _) = direction_to_equirect(Vec3::unit_z());
+    assert!((u - 0.75).abs() < 1e-5);
+  }
+}
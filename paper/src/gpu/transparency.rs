@@ -0,0 +1,428 @@
+// transparent draws go after everything opaque (and the background), in their own pass that
+// reads the depth buffer but doesn't write it. by default they're sorted back to front, which
+// breaks down for meshes that cross each other. weighted blended oit (mcguire and bavoil)
+// doesn't care about the order: alpha blended draws add into an accumulation target and a
+// revealage target instead, and a fullscreen composite lays the average over the scene
+
+use crate::{
+  files::{preprocessor::SourceMap, shader_watcher::ShaderChange},
+  gpu::{
+    device_drivers::Drivers,
+    geometry::GetBufferLayout,
+    gpu_pointers::{BindingRegistry, EngineResource, MemoryLayouts},
+    material::BlendMode,
+    render_graph::{
+      self, AttachmentDesc, AttachmentSamples, AttachmentSize, PassContext, RenderGraph,
+    },
+    shaders::{
+      validation::{ShaderError, ShaderErrorKind},
+      ShaderBuilder,
+    },
+    texture::DynamicTexture,
+  },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransparencyMode {
+  /// drawn back to front by their distance to the camera, exact as long as nothing intersects
+  #[default]
+  Sorted,
+  /// order independent, an approximation but it can't pop when things cross each other.
+  /// only alpha blended and premultiplied materials use it, additive doesn't need it
+  WeightedBlended,
+}
+
+impl TransparencyMode {
+  /// whether draws with this blend mode go through the weighted blended targets
+  #[inline]
+  pub fn is_order_independent(&self, blend_mode: BlendMode) -> bool {
+    *self == TransparencyMode::WeightedBlended
+      && matches!(blend_mode, BlendMode::AlphaBlend | BlendMode::Premultiplied)
+  }
+}
+
+/// the fullscreen pipeline made from oit_composite.wgsl
+struct CompositePipeline {
+  render_pipeline: wgpu::RenderPipeline,
+  bindgroups: MemoryLayouts,
+  source_map: SourceMap,
+  sample_count: u32,
+}
+
+impl CompositePipeline {
+  const SHADER_FILE: &str = "oit_composite.wgsl";
+
+  fn compile(drivers: &Drivers, registry: &BindingRegistry) -> Result<Self, ShaderError> {
+    let shader = ShaderBuilder::from_file(Self::SHADER_FILE.to_owned()).validate()?;
+    let bindgroups = MemoryLayouts::reflect(&shader.module, registry)
+      .map_err(|error| ShaderError::from_binding_error(error, &shader, Self::SHADER_FILE))?;
+
+    drivers
+      .device
+      .push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let module = drivers
+      .device
+      .create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(Self::SHADER_FILE),
+        source: wgpu::ShaderSource::Wgsl(shader.source.as_str().into()),
+      });
+    let layout = drivers
+      .device
+      .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("OIT Composite Pipeline Layout"),
+        bind_group_layouts: &bindgroups.collect_slice(),
+        push_constant_ranges: &[],
+      });
+    // the average color over the scene, by how much of it is still showing through
+    let over = wgpu::BlendComponent {
+      src_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+      dst_factor: wgpu::BlendFactor::SrcAlpha,
+      operation: wgpu::BlendOperation::Add,
+    };
+    let sample_count = drivers.get_sample_count();
+    let render_pipeline = drivers
+      .device
+      .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("OIT Composite Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+          module: &module,
+          entry_point: Some("vs_main"),
+          buffers: &[],
+          compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+          module: &module,
+          entry_point: Some("fs_main"),
+          targets: &[Some(wgpu::ColorTargetState {
+            format: DynamicTexture::HDR_FORMAT,
+            blend: Some(wgpu::BlendState {
+              color: over,
+              alpha: over,
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+          })],
+          compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+          count: sample_count,
+          ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+      });
+
+    if let Some(error) = pollster::block_on(drivers.device.pop_error_scope()) {
+      let message = error.to_string();
+      return Err(ShaderError::new(
+        ShaderErrorKind::Pipeline,
+        Self::SHADER_FILE,
+        message,
+      ));
+    }
+
+    Ok(Self {
+      render_pipeline,
+      bindgroups,
+      source_map: shader.source_map,
+      sample_count,
+    })
+  }
+}
+
+/// which transparency mode is on, and the composite for the weighted blended one
+pub struct TransparencyPass {
+  mode: TransparencyMode,
+  // None until init_pipeline, or if oit_composite.wgsl didn't compile
+  pipeline: Option<CompositePipeline>,
+  layout: wgpu::BindGroupLayout,
+}
+
+impl GetBufferLayout for TransparencyPass {
+  fn get_bind_layout(&self) -> wgpu::BindGroupLayout {
+    self.layout.clone()
+  }
+
+  fn get_bind_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+    Self::bind_entries()
+  }
+}
+
+impl TransparencyPass {
+  pub const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+  pub const REVEAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+  /// the pipeline state for the weighted blended targets: color and alpha added up in accum,
+  /// and every alpha multiplied out of reveal (which starts at 1)
+  pub fn get_accumulate_targets() -> [Option<wgpu::ColorTargetState>; 2] {
+    let add = wgpu::BlendComponent {
+      src_factor: wgpu::BlendFactor::One,
+      dst_factor: wgpu::BlendFactor::One,
+      operation: wgpu::BlendOperation::Add,
+    };
+    let reveal = wgpu::BlendComponent {
+      src_factor: wgpu::BlendFactor::Zero,
+      dst_factor: wgpu::BlendFactor::OneMinusSrc,
+      operation: wgpu::BlendOperation::Add,
+    };
+
+    [
+      Some(wgpu::ColorTargetState {
+        format: Self::ACCUM_FORMAT,
+        blend: Some(wgpu::BlendState {
+          color: add,
+          alpha: add,
+        }),
+        write_mask: wgpu::ColorWrites::ALL,
+      }),
+      Some(wgpu::ColorTargetState {
+        format: Self::REVEAL_FORMAT,
+        blend: Some(wgpu::BlendState {
+          color: reveal,
+          alpha: reveal,
+        }),
+        write_mask: wgpu::ColorWrites::ALL,
+      }),
+    ]
+  }
+
+  fn bind_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+    let texture = |binding| wgpu::BindGroupLayoutEntry {
+      binding,
+      visibility: wgpu::ShaderStages::FRAGMENT,
+      ty: wgpu::BindingType::Texture {
+        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        view_dimension: wgpu::TextureViewDimension::D2,
+        multisampled: false,
+      },
+      count: None,
+    };
+    vec![texture(0), texture(1)]
+  }
+
+  fn init_bind_group_layout(drivers: &Drivers) -> wgpu::BindGroupLayout {
+    drivers
+      .device
+      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &Self::bind_entries(),
+        label: Some("transparency_bind_group_layout"),
+      })
+  }
+
+  pub fn new(drivers: &Drivers) -> Self {
+    Self {
+      mode: TransparencyMode::default(),
+      pipeline: None,
+      layout: Self::init_bind_group_layout(drivers),
+    }
+  }
+
+  /// compiles oit_composite.wgsl, the registry needs the Transparency layout by now.
+  /// without it the weighted blended draws never reach the scene
+  pub fn init_pipeline(&mut self, drivers: &Drivers, registry: &BindingRegistry) {
+    match CompositePipeline::compile(drivers, registry) {
+      Ok(pipeline) => self.pipeline = Some(pipeline),
+      Err(error) => log::error!(
+        "weighted blended transparency won't show up, {} didn't compile: {}",
+        CompositePipeline::SHADER_FILE,
+        error
+      ),
+    }
+  }
+
+  pub fn reload_shaders(
+    &mut self,
+    drivers: &Drivers,
+    registry: &BindingRegistry,
+    changes: &[ShaderChange],
+  ) {
+    let affected = match &self.pipeline {
+      Some(pipeline) => changes
+        .iter()
+        .any(|change| pipeline.source_map.depends_on(change.get_path())),
+      None => false,
+    };
+    if !affected {
+      return;
+    }
+
+    match CompositePipeline::compile(drivers, registry) {
+      Ok(pipeline) => {
+        self.pipeline = Some(pipeline);
+        log::info!("reloaded shader: {}", CompositePipeline::SHADER_FILE);
+      }
+      Err(error) => log::error!(
+        "failed to reload shader {}, keeping the old one: {}",
+        CompositePipeline::SHADER_FILE,
+        error
+      ),
+    }
+  }
+
+  /// for when the msaa sample count changed, the old pipeline can't draw into the new targets
+  pub fn rebuild(&mut self, drivers: &Drivers, registry: &BindingRegistry) {
+    self.pipeline = None;
+    self.init_pipeline(drivers, registry);
+  }
+
+  #[inline]
+  pub fn get_mode(&self) -> TransparencyMode {
+    self.mode
+  }
+
+  /// the scene's pipelines have to be rebuilt along with this, see RenderTask::set_transparency_mode.
+  /// the weighted blended targets are only added to the graph the first time they're needed
+  pub(crate) fn set_mode(&mut self, graph: &mut RenderGraph, mode: TransparencyMode) {
+    self.mode = mode;
+    if mode != TransparencyMode::WeightedBlended || graph.has_attachment(render_graph::OIT_ACCUM) {
+      return;
+    }
+
+    let accum = AttachmentDesc::new(Self::ACCUM_FORMAT, AttachmentSize::Surface);
+    let reveal = AttachmentDesc::new(Self::REVEAL_FORMAT, AttachmentSize::Surface);
+    graph
+      .add_attachment(render_graph::OIT_ACCUM, accum)
+      .and_then(|_| {
+        graph.add_attachment(
+          render_graph::OIT_ACCUM_MSAA,
+          accum.with_samples(AttachmentSamples::MsaaOnly),
+        )
+      })
+      .and_then(|_| graph.add_attachment(render_graph::OIT_REVEAL, reveal))
+      .and_then(|_| {
+        graph.add_attachment(
+          render_graph::OIT_REVEAL_MSAA,
+          reveal.with_samples(AttachmentSamples::MsaaOnly),
+        )
+      })
+      .expect("the weighted blended targets are only added once");
+  }
+
+  /// clears the weighted blended targets and starts drawing into them, against the scene's depth.
+  /// None if the targets haven't been made yet
+  pub(crate) fn begin_accumulate<'e>(
+    &self,
+    context: &'e mut PassContext<'_>,
+  ) -> Option<wgpu::RenderPass<'e>> {
+    let (accum, accum_resolve) =
+      context.get_target(render_graph::OIT_ACCUM, render_graph::OIT_ACCUM_MSAA)?;
+    let (reveal, reveal_resolve) =
+      context.get_target(render_graph::OIT_REVEAL, render_graph::OIT_REVEAL_MSAA)?;
+    let depth = context.get_view(render_graph::DEPTH)?;
+
+    let target = |view, resolve_target, clear| {
+      Some(wgpu::RenderPassColorAttachment {
+        view,
+        resolve_target,
+        ops: wgpu::Operations {
+          load: wgpu::LoadOp::Clear(clear),
+          store: wgpu::StoreOp::Store,
+        },
+      })
+    };
+    let render_pass = context
+      .encoder
+      .begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("OIT Accumulate Pass"),
+        color_attachments: &[
+          target(accum, accum_resolve, wgpu::Color::TRANSPARENT),
+          // nothing's covered yet, everything behind is fully revealed
+          target(reveal, reveal_resolve, wgpu::Color::WHITE),
+        ],
+        // read only, transparent draws don't write depth
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+          view: depth,
+          depth_ops: None,
+          stencil_ops: None,
+        }),
+        occlusion_query_set: None,
+        timestamp_writes: None,
+      });
+    Some(render_pass)
+  }
+
+  fn create_bind_group(
+    &self,
+    drivers: &Drivers,
+    accum: &wgpu::TextureView,
+    reveal: &wgpu::TextureView,
+  ) -> wgpu::BindGroup {
+    drivers
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &self.layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(accum),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::TextureView(reveal),
+          },
+        ],
+        label: Some("transparency_bind_group"),
+      })
+  }
+
+  /// lays what begin_accumulate drew over the scene
+  pub(crate) fn composite(&self, context: &mut PassContext<'_>) {
+    let Some(pipeline) = &self.pipeline else {
+      return;
+    };
+    let drivers = &context.engine.drivers;
+    // left over from before msaa changed, and it didn't rebuild
+    if pipeline.sample_count != drivers.get_sample_count() {
+      return;
+    }
+    let (Some(accum), Some(reveal), Some((color, resolve_target))) = (
+      context.get_view(render_graph::OIT_ACCUM),
+      context.get_view(render_graph::OIT_REVEAL),
+      context.get_target(render_graph::HDR, render_graph::HDR_MSAA),
+    ) else {
+      return;
+    };
+
+    let bind_group = self.create_bind_group(drivers, accum, reveal);
+    let mut bind_groups = context.engine.get_universal_bind_groups();
+    bind_groups.set(EngineResource::Transparency, &bind_group);
+
+    let mut render_pass = context
+      .encoder
+      .begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("OIT Composite Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+          view: color,
+          resolve_target,
+          ops: wgpu::Operations {
+            load: wgpu::LoadOp::Load,
+            store: wgpu::StoreOp::Store,
+          },
+        })],
+        ..Default::default()
+      });
+    render_pass.set_pipeline(&pipeline.render_pipeline);
+    pipeline
+      .bindgroups
+      .set_bind_groups(&mut render_pass, &bind_groups, false);
+    render_pass.draw(0..3, 0..1);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_blended_materials_go_order_independent() {
+    let oit = TransparencyMode::WeightedBlended;
+    assert!(oit.is_order_independent(BlendMode::AlphaBlend));
+    assert!(oit.is_order_independent(BlendMode::Premultiplied));
+    assert!(!oit.is_order_independent(BlendMode::Additive));
+    assert!(!oit.is_order_independent(BlendMode::Opaque));
+    assert!(!TransparencyMode::Sorted.is_order_independent(BlendMode::AlphaBlend));
+  }
+}
@@ -0,0 +1,142 @@
+// the sdl demo loop, only there with the window feature
+
+use std::sync::Arc;
+use cgmath::Rotation3;
+use sdl3::event::{Event, WindowEvent};
+
+use crate::{
+  engine,
+  gpu::{
+    background::Background,
+    benchmark,
+    lights::Light,
+    object::{Location, ObjectBuilder, SharedLocation},
+    post::PostEffect,
+  },
+  maths::Vec3,
+  window::{sdl_handle::SdlHandle, tickrate, user_input::MovementHandler},
+};
+
+fn handle_system_events(
+  event: &sdl3::event::Event,
+  window: &mut Arc<sdl3::video::Window>,
+  engine: &mut engine::Engine,
+) {
+  match event {
+    Event::Window {
+      window_id,
+      win_event: WindowEvent::PixelSizeChanged(width, height) | WindowEvent::Resized(width, height),
+      ..
+    } if *window_id == window.id() => {
+      engine.resize(*width as u32, *height as u32);
+    }
+    Event::Quit { .. } => {
+      engine.request_close();
+    }
+    _ => {}
+  }
+}
+
+pub struct EngineRuntime {
+  sdl_handle: SdlHandle,
+  pub engine: engine::Engine,
+}
+
+async fn init_objects(e: &mut engine::Engine, shared: &SharedLocation) -> anyhow::Result<()> {
+  e.texture_bundle
+    .add_texture_from_file(&e.drivers, "test_bake.png", "test")?;
+
+  let diffuse = e.texture_bundle.get_texture("test");
+
+  let object = ObjectBuilder::new()
+    .set_shared_location(shared.clone())
+    .load_meshes_from_objfile(&mut e.texture_bundle, &e.drivers, "test_bake_table.obj")?
+    .add_diffuse_texture(&e.drivers, diffuse)
+    .build();
+
+  e.render_task
+    .add_object(object, &e.drivers, &e.data_bindgroups)
+    .await?;
+
+  Ok(())
+}
+
+// the stylised look, order matters: glow before squashing the colors, lines and dithering last
+fn init_post_effects(e: &mut engine::Engine) -> anyhow::Result<()> {
+  let effects = [
+    PostEffect::bloom(),
+    PostEffect::tonemap(),
+    PostEffect::outline(),
+    PostEffect::Dither {
+      levels: 8.0,
+      pixel_size: 2.0,
+    },
+  ];
+
+  for effect in effects {
+    e.render_task
+      .add_post_effect(&e.drivers, &e.data_bindgroups, effect)?;
+  }
+
+  Ok(())
+}
+
+impl EngineRuntime {
+  pub async fn new_engine() -> anyhow::Result<Self> {
+    // picks the backend before anything's shown, only benchmarks if there's no settings file
+    let settings = benchmark::load_or_benchmark().await;
+    let sdl_handle = SdlHandle::new()?;
+    let engine = engine::Engine::new(&sdl_handle, sdl_handle.sdl_window.clone(), &settings).await?;
+
+    let new_engine = Self { sdl_handle, engine };
+
+    return Ok(new_engine);
+  }
+
+  pub async fn run_engine(mut self) -> anyhow::Result<()> {
+    let mut movement_buffer = vec![];
+    let mut sys_window = self.sdl_handle.sdl_window.clone();
+
+    let mut benchmark = tickrate::TimeMeasurer::new();
+
+    let mut shared = Location::from_pos(Vec3::new(0.0, 0.0, 0.0)).to_shared();
+
+    let sun = Light::directional(Vec3::new(-0.5, -1.0, -0.3), [1.0, 0.95, 0.9]);
+    self.engine.render_task.add_light(sun);
+    let red = Light::point(Vec3::new(-2.0, 1.0, 0.0), [1.0, 0.0, 0.0]).with_intensity(4.0);
+    self.engine.render_task.add_light(red);
+
+    init_objects(&mut self.engine, &shared).await?;
+    init_post_effects(&mut self.engine)?;
+    self
+      .engine
+      .render_task
+      .set_background(&self.engine.drivers, Background::sky());
+
+    while self.engine.is_running() {
+      benchmark.start_measure();
+
+      shared.modify_location(|loc| {
+        let rot_factor: cgmath::Quaternion<f32> =
+          cgmath::Quaternion::from_angle_z(cgmath::Deg(1.0));
+        loc.rot = loc.rot * rot_factor;
+      });
+
+      movement_buffer.clear();
+
+      for event in self.sdl_handle.event_pump.poll_iter() {
+        handle_system_events(&event, &mut sys_window, &mut self.engine);
+        MovementHandler::poll_movement(&mut self.engine, &mut movement_buffer, &event);
+      }
+      MovementHandler::apply_movement(&mut self.engine, &mut movement_buffer);
+
+      self.engine.tickrate.tick();
+      self.engine.redraw();
+      benchmark.stop_measure();
+      //println!("{}", benchmark.get_average());
+      self.engine.tickrate.sleep_until_next_frame();
+    }
+
+    Ok(())
+  }
+}
@@ -1,4 +1,6 @@
+#[cfg(feature = "window")]
 pub mod sdl_handle;
 pub mod tickrate;
+#[cfg(feature = "window")]
 pub mod translate_surface;
 pub mod user_input;
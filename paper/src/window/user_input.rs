@@ -1,20 +1,24 @@
+#[cfg(feature = "window")]
 use std::sync::Arc;
 
+#[cfg(feature = "window")]
 use sdl3::keyboard::Keycode;
 
-use crate::{
-  engine::Engine,
-  maths::{self, Angle},
-  SdlHandle,
-};
+use crate::maths;
+#[cfg(feature = "window")]
+use crate::{engine::Engine, maths::Angle, window::sdl_handle::SdlHandle};
 
+// the input types are always there (the camera takes them), reading them from sdl needs the window feature
+#[cfg(feature = "window")]
 type InputFunction = Box<dyn Fn(&mut Vec<InputType>) -> ()>;
+#[cfg(feature = "window")]
 struct InputWrapper {
   keycode: Keycode,
   is_pressed: bool,
   action: InputFunction,
 }
 
+#[cfg(feature = "window")]
 impl InputWrapper {
   pub fn new(key: Keycode, action: InputFunction) -> Self {
     Self {
@@ -46,6 +50,7 @@ pub enum InputType {
   RotateCamera(RotationDirection),
 }
 
+#[cfg(feature = "window")]
 pub struct MovementHandler {
   input_wrappers: Vec<InputWrapper>,
   mouse_util: sdl3::mouse::MouseUtil,
@@ -53,6 +58,7 @@ pub struct MovementHandler {
   mouse_sensitivity: f64,
 }
 
+#[cfg(feature = "window")]
 fn add_scalar(input: &mut Vec<InputType>, rot_degrees: f64, magnitude: f64) {
   input.push(InputType::MoveCamera(MovementDirection {
     direction: maths::Scalar {
@@ -62,6 +68,7 @@ fn add_scalar(input: &mut Vec<InputType>, rot_degrees: f64, magnitude: f64) {
   }))
 }
 
+#[cfg(feature = "window")]
 impl MovementHandler {
   pub fn new(sdl_context: &SdlHandle, window: Arc<sdl3::video::Window>) -> Self {
     Self {
@@ -99,25 +106,26 @@ impl MovementHandler {
     unread_movement: &mut Vec<InputType>,
     event: &sdl3::event::Event,
   ) {
+    let delta = engine.tickrate.get_delta();
+    // headless engines don't take input
+    let Some(user_input) = &mut engine.user_input else {
+      return;
+    };
+
     match event {
       sdl3::event::Event::KeyDown { keycode, .. } => {
         if let Some(key) = keycode {
-          engine.user_input.set_keys(key, true);
+          user_input.set_keys(key, true);
         }
       }
       sdl3::event::Event::KeyUp { keycode, .. } => {
         if let Some(key) = keycode {
-          engine.user_input.set_keys(key, false);
+          user_input.set_keys(key, false);
         }
       }
 
       sdl3::event::Event::MouseMotion { x, y, .. } => {
-        engine.user_input.calculate_mouse_delta(
-          unread_movement,
-          *x,
-          *y,
-          engine.tickrate.get_delta(),
-        );
+        user_input.calculate_mouse_delta(unread_movement, *x, *y, delta);
       }
 
       _ => {}
@@ -126,9 +134,11 @@ impl MovementHandler {
 
   pub fn apply_movement(engine: &mut Engine, unread_movement: &mut Vec<InputType>) {
     // loop through all the movement handlers and run them if they are active
-    for wrapper in engine.user_input.input_wrappers.iter() {
-      if wrapper.is_pressed {
-        wrapper.run_logic(unread_movement);
+    if let Some(user_input) = &engine.user_input {
+      for wrapper in user_input.input_wrappers.iter() {
+        if wrapper.is_pressed {
+          wrapper.run_logic(unread_movement);
+        }
       }
     }
     engine
@@ -0,0 +1,1346 @@
+// renders with a headless engine and compares against reference pngs in tests/golden.
+// they need a gpu (or a software adapter) and fail without one.
+// set UPDATE_GOLDEN=1 to write new golden images instead of comparing against them
+
+use std::{
+  path::PathBuf,
+  sync::{Arc, Mutex},
+};
+
+use paper::{
+  engine::Engine,
+  gpu::{
+    background::{Background, SkySettings},
+    benchmark,
+    instances::Instance,
+    lights::Light,
+    material::BlendMode,
+    object::{Location, Object, ObjectBuilder},
+    post::PostEffect,
+    settings::{BackendPreference, GraphicsSettings},
+    readback,
+    render_graph::{self, GraphError, GraphPass, PassContext, PassDesc},
+    shaders::{pipeline_state::PipelineState, ShaderBuilder},
+    shadows::ShadowSettings,
+    texture::{
+      atlas::AtlasBuilder,
+      layered::{LayerKind, LayeredTexture},
+      ColorSpace, ImageTexture, SamplerSettings,
+    },
+    transparency::TransparencyMode,
+  },
+  maths::Vec3,
+};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+
+// channels can be off by a little between drivers (software vs real gpus)
+const CHANNEL_TOLERANCE: u8 = 8;
+const MAX_WRONG_PIXELS: f32 = 0.01;
+
+fn headless_engine() -> Engine {
+  // assets are loaded relative to the workspace root, same as running the game
+  std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+
+  let mut engine = pollster::block_on(Engine::new_headless(WIDTH, HEIGHT))
+    .unwrap_or_else(|error| panic!("couldn't make a headless engine: {}", error));
+  // a sun over the shoulder of every camera the tests use, so nothing is pitch black
+  let sun = Light::directional(Vec3::new(1.0, -0.5, 0.0), [1.0, 1.0, 1.0]);
+  engine.render_task.add_light(sun);
+  engine
+}
+
+fn golden_path(name: &str) -> PathBuf {
+  PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/golden")
+    .join(format!("{}.png", name))
+}
+
+fn assert_matches_golden(name: &str, image: &image::RgbaImage) {
+  let path = golden_path(name);
+
+  if std::env::var_os("UPDATE_GOLDEN").is_some() {
+    image.save(&path).unwrap();
+    eprintln!("wrote golden image {}", path.display());
+    return;
+  }
+
+  let golden = image::open(&path)
+    .unwrap_or_else(|error| {
+      panic!(
+        "couldn't open the golden image {} ({}), run with UPDATE_GOLDEN=1 to write it",
+        path.display(),
+        error
+      )
+    })
+    .to_rgba8();
+  assert_eq!(
+    golden.dimensions(),
+    image.dimensions(),
+    "{} changed size",
+    name
+  );
+
+  let wrong_pixels = golden
+    .pixels()
+    .zip(image.pixels())
+    .filter(|(expected, actual)| {
+      expected
+        .0
+        .iter()
+        .zip(actual.0.iter())
+        .any(|(e, a)| e.abs_diff(*a) > CHANNEL_TOLERANCE)
+    })
+    .count();
+
+  let wrong_fraction = wrong_pixels as f32 / (image.width() * image.height()) as f32;
+  if wrong_fraction > MAX_WRONG_PIXELS {
+    let actual_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.png", name));
+    image.save(&actual_path).unwrap();
+    panic!(
+      "{} doesn't match its golden image, {} pixels are off (saved to {})",
+      name,
+      wrong_pixels,
+      actual_path.display()
+    );
+  }
+}
+
+#[test]
+fn empty_scene_clears_to_black() {
+  let mut engine = headless_engine();
+
+  engine.redraw();
+  let image = readback::read_offscreen(&engine.drivers).unwrap();
+
+  assert_eq!(image.dimensions(), (WIDTH, HEIGHT));
+  assert!(image.pixels().all(|pixel| pixel.0 == [0, 0, 0, 255]));
+}
+
+fn add_table(engine: &mut Engine, location: Location) {
+  let diffuse = engine.texture_bundle.get_texture("test");
+
+  let object = ObjectBuilder::new()
+    .set_shared_location(location.to_shared())
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "test_bake_table.obj",
+    )
+    .unwrap()
+    .add_diffuse_texture(&engine.drivers, diffuse)
+    .build();
+
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+}
+
+fn load_table_texture(engine: &mut Engine) {
+  engine
+    .texture_bundle
+    .add_texture_from_file(&engine.drivers, "test_bake.png", "test")
+    .unwrap();
+}
+
+#[test]
+fn renders_the_table() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  add_table(&mut engine, Location::from_pos(Vec3::new(0.0, 0.0, 0.0)));
+
+  // the camera starts inside the table looking away from it
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 2.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.3;
+
+  engine.redraw();
+  let image = readback::read_offscreen(&engine.drivers).unwrap();
+
+  assert_matches_golden("table", &image);
+}
+
+#[test]
+fn objects_keep_their_own_locations() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  add_table(&mut engine, Location::from_pos(Vec3::new(0.0, 0.0, -2.5)));
+  add_table(&mut engine, Location::from_pos(Vec3::new(0.0, 0.0, 2.5)));
+
+  engine.camera.camera.position = cgmath::Point3::new(-8.0, 3.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.3;
+
+  let image = engine.capture_frame().unwrap();
+
+  // one table on each side of the screen, not both drawn on top of each other
+  let has_table = |x_range: std::ops::Range<u32>| {
+    x_range
+      .flat_map(|x| (0..HEIGHT).map(move |y| (x, y)))
+      .any(|(x, y)| image.get_pixel(x, y).0[..3] != [0, 0, 0])
+  };
+  assert!(has_table(0..WIDTH / 2 - 10));
+  assert!(has_table(WIDTH / 2 + 10..WIDTH));
+
+  assert_matches_golden("two_tables", &image);
+}
+
+#[test]
+fn whatever_the_camera_cant_see_is_culled() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  add_table(&mut engine, Location::from_pos(Vec3::new(0.0, 0.0, 0.0)));
+  // well behind the camera
+  add_table(&mut engine, Location::from_pos(Vec3::new(-12.0, 0.0, 0.0)));
+
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 2.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.3;
+
+  let image = engine.capture_frame().unwrap();
+  let stats = engine.render_task.get_cull_stats();
+  assert!(stats.culled > 0);
+  assert_eq!(stats.drawn, stats.culled);
+  // the culled table wasn't in the picture anyway
+  assert_matches_golden("table", &image);
+
+  engine.render_task.set_culling(false);
+  let unculled = engine.capture_frame().unwrap();
+  let stats = engine.render_task.get_cull_stats();
+  assert_eq!(stats.culled, 0);
+  assert_eq!(image, unculled);
+
+  // the bounds follow the object around
+  let object = ObjectBuilder::new()
+    .set_location(Location::from_pos(Vec3::new(0.0, 0.0, 5.0)))
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "test_bake_table.obj",
+    )
+    .unwrap()
+    .build();
+  let bounds = object.get_world_bounds().unwrap();
+  let local = object.meshes[0].get_bounds().unwrap();
+  assert!(bounds.aabb.min.z >= 5.0 + local.aabb.min.z - 1e-4);
+  assert!(bounds.aabb.get_center().z > 4.0 && bounds.aabb.get_center().z < 6.0);
+}
+
+#[test]
+fn far_away_objects_switch_to_their_lods() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  let diffuse = engine.texture_bundle.get_texture("test");
+  // picks up test_bake_table_lod1.obj next to it
+  let object = ObjectBuilder::new()
+    .add_diffuse_texture(&engine.drivers, diffuse)
+    .load_meshes_from_objfile_with_lods(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "test_bake_table.obj",
+    )
+    .unwrap()
+    .build();
+  assert_eq!(object.lods.len(), 1);
+  let lod_meshes = object.lods[0].meshes.len() as u32;
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+
+  // the table's about 4 / distance of the screen high, so the lod takes over around 8 away
+  fn lod_at(engine: &mut Engine, distance: f32) -> usize {
+    engine.camera.camera.position = cgmath::Point3::new(-distance, 0.9, 0.0);
+    engine.redraw();
+    engine.render_task.get_current_lod(0)
+  }
+  assert_eq!(lod_at(&mut engine, 20.0), 1);
+  assert_eq!(engine.render_task.get_cull_stats().drawn, lod_meshes);
+  // only a bit bigger than where it switched isn't enough to switch back
+  assert_eq!(lod_at(&mut engine, 7.8), 1);
+  assert_eq!(lod_at(&mut engine, 4.0), 0);
+  assert_eq!(lod_at(&mut engine, 8.5), 0);
+  assert_eq!(lod_at(&mut engine, 20.0), 1);
+
+  // without any hysteresis it switches right at the edge
+  engine.render_task.set_lod_hysteresis(0.0);
+  assert_eq!(lod_at(&mut engine, 7.8), 0);
+}
+
+#[test]
+fn objects_share_pipelines_and_draws_are_batched() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  for z in [-2.5, 0.0, 2.5] {
+    add_table(&mut engine, Location::from_pos(Vec3::new(0.0, 0.0, z)));
+  }
+  assert_eq!(engine.render_task.get_pipeline_count(), 1);
+
+  engine.camera.camera.position = cgmath::Point3::new(-8.0, 3.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.3;
+  engine.redraw();
+
+  // one pipeline switch for all three, the universal groups only get bound with it
+  let stats = engine.render_task.get_render_stats();
+  assert_eq!(stats.pipelines, 1);
+  assert_eq!(stats.draws, 3);
+  let batched_binds = stats.bind_groups;
+
+  // the same shader with another define is another pipeline
+  let diffuse = engine.texture_bundle.get_texture("test");
+  let object = ObjectBuilder::new()
+    .set_location(Location::from_pos(Vec3::new(0.0, 0.0, 5.0)))
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "test_bake_table.obj",
+    )
+    .unwrap()
+    .add_diffuse_texture(&engine.drivers, diffuse)
+    .build();
+  let toon = ShaderBuilder::from_file("sample.wgsl".to_owned()).define("TOON");
+  pollster::block_on(engine.render_task.add_object_with_shader(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+    toon,
+  ))
+  .unwrap();
+  assert_eq!(engine.render_task.get_pipeline_count(), 2);
+
+  engine.redraw();
+  let stats = engine.render_task.get_render_stats();
+  assert_eq!(stats.pipelines, 2);
+  assert_eq!(stats.draws, 4);
+  // a new pipeline has to bind its universal groups again, a new mesh only its own
+  assert!(stats.bind_groups > batched_binds + 2);
+}
+
+#[test]
+fn instances_are_drawn_and_updated() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  let diffuse = engine.texture_bundle.get_texture("test");
+
+  let instances = vec![
+    Instance::new(Vec3::new(0.0, 0.0, -3.0)).with_tint([1.0, 0.2, 0.2, 1.0]),
+    Instance::new(Vec3::new(0.0, 0.0, 0.0)).with_scale(Vec3::new(0.5, 0.5, 0.5)),
+    Instance::new(Vec3::new(0.0, 0.0, 3.0)).with_tint([0.2, 0.2, 1.0, 1.0]),
+  ];
+
+  let object = ObjectBuilder::new()
+    .set_instances(instances)
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "test_bake_table.obj",
+    )
+    .unwrap()
+    .add_diffuse_texture(&engine.drivers, diffuse)
+    .build();
+
+  assert!(object
+    .meshes
+    .iter()
+    .all(|mesh| mesh.get_instance_count() == 3));
+  let meshes = object.meshes.clone();
+
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+
+  engine.camera.camera.position = cgmath::Point3::new(-9.0, 3.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.3;
+
+  let image = engine.capture_frame().unwrap();
+  assert_matches_golden("instanced_tables", &image);
+
+  // the right side of the screen is empty once only the left table is left
+  for mesh in &meshes {
+    mesh.update_instances(&engine.drivers, &[Instance::new(Vec3::new(0.0, 0.0, -3.0))]);
+  }
+  let image = engine.capture_frame().unwrap();
+  let right_side_empty = (WIDTH / 2 + 10..WIDTH)
+    .flat_map(|x| (0..HEIGHT).map(move |y| (x, y)))
+    .all(|(x, y)| image.get_pixel(x, y).0[..3] == [0, 0, 0]);
+  assert!(right_side_empty);
+}
+
+#[test]
+fn resizing_makes_a_new_target() {
+  let mut engine = headless_engine();
+
+  engine.resize(WIDTH / 2, HEIGHT / 2);
+  engine.redraw();
+  let image = readback::read_offscreen(&engine.drivers).unwrap();
+
+  assert_eq!(image.dimensions(), (WIDTH / 2, HEIGHT / 2));
+}
+
+#[test]
+fn screenshots_and_sequences_are_saved() {
+  let mut engine = headless_engine();
+
+  let folder = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("captures");
+  let _ = std::fs::remove_dir_all(&folder);
+
+  engine.screenshot(folder.join("shot.png"));
+  engine.redraw();
+  engine.record_frames(folder.join("sequence"), 2);
+  for _ in 0..3 {
+    engine.redraw();
+  }
+
+  let shot = image::open(folder.join("shot.png")).unwrap();
+  assert_eq!(shot.width(), WIDTH);
+  assert!(folder.join("sequence/frame_00000.png").exists());
+  assert!(folder.join("sequence/frame_00001.png").exists());
+  assert!(!folder.join("sequence/frame_00002.png").exists());
+
+  let frame = engine.capture_frame().unwrap();
+  assert_eq!(frame, readback::read_offscreen(&engine.drivers).unwrap());
+}
+
+// full ambient and no lights, every surface shows exactly its material's color
+fn show_unlit(engine: &mut Engine) {
+  let lights = engine.render_task.get_lights_mut();
+  lights.clear();
+  lights.set_ambient([1.0, 1.0, 1.0]);
+}
+
+#[test]
+fn mtl_materials_are_loaded_per_mesh() {
+  let mut engine = headless_engine();
+
+  let object = ObjectBuilder::new()
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "material_quads.obj",
+    )
+    .unwrap()
+    .build();
+
+  let names: Vec<&str> = object
+    .meshes
+    .iter()
+    .map(|mesh| mesh.get_material().name.as_str())
+    .collect();
+  assert_eq!(names, ["Red", "Textured"]);
+  assert_eq!(
+    object.meshes[0].get_material().get_params().diffuse_color,
+    [1.0, 0.0, 0.0]
+  );
+
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 0.0, 0.0);
+  show_unlit(&mut engine);
+
+  let image = engine.capture_frame().unwrap();
+
+  // left quad is just Kd, the right one is textured
+  let left = image.get_pixel(WIDTH / 4, HEIGHT / 2).0;
+  assert!(left[0] > 200 && left[1] < 10 && left[2] < 10);
+
+  assert_matches_golden("material_quads", &image);
+}
+
+fn load_gltf(engine: &mut Engine, file_name: &str) -> Object {
+  ObjectBuilder::new()
+    .load_meshes_from_gltf(&mut engine.texture_bundle, &engine.drivers, file_name)
+    .unwrap()
+    .build()
+}
+
+fn render_object(engine: &mut Engine, object: Object) -> image::RgbaImage {
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 0.0, 0.0);
+  show_unlit(engine);
+  engine.capture_frame().unwrap()
+}
+
+#[test]
+fn gltf_scenes_are_imported() {
+  let mut engine = headless_engine();
+
+  let object = load_gltf(&mut engine, "quads.gltf");
+
+  let names: Vec<&str> = object
+    .meshes
+    .iter()
+    .map(|mesh| mesh.get_material().name.as_str())
+    .collect();
+  assert_eq!(names.len(), 2);
+  assert!(names.contains(&"Red") && names.contains(&"Checker"));
+
+  let root = &object.nodes[0];
+  assert_eq!(root.name.as_deref(), Some("Root"));
+  assert_eq!(root.children, [1, 2, 3]);
+  assert_eq!(object.nodes[1].parent, Some(0));
+  assert_eq!(object.nodes[1].meshes.len(), 1);
+  assert_eq!(object.skins[0].joints, [3]);
+
+  // animations move the nodes, the meshes are left alone
+  let mut nodes = object.nodes.clone();
+  let animation = &object.animations[0];
+  assert_eq!(animation.duration(), 1.0);
+  animation.apply(0.5, &mut nodes);
+  assert_eq!(nodes[3].transform.translation, Vec3::new(0.0, 0.5, 0.0));
+
+  let image = render_object(&mut engine, object);
+
+  // red on the left, the embedded checker texture on the right
+  let left = image.get_pixel(WIDTH / 4, HEIGHT / 2).0;
+  assert!(left[0] > 200 && left[1] < 10 && left[2] < 10);
+  let right = image.get_pixel(WIDTH * 3 / 4, HEIGHT / 2).0;
+  assert!(right[0] < 10 && right[1] as u32 + right[2] as u32 > 200);
+
+  assert_matches_golden("gltf_quads", &image);
+}
+
+#[test]
+fn glb_files_load_the_same_as_gltf() {
+  let mut engine = headless_engine();
+
+  let object = load_gltf(&mut engine, "quads.glb");
+  assert_eq!(object.meshes.len(), 2);
+
+  let image = render_object(&mut engine, object);
+  assert_matches_golden("gltf_quads", &image);
+}
+
+fn add_material_quads(engine: &mut Engine) {
+  let object = ObjectBuilder::new()
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "material_quads.obj",
+    )
+    .unwrap()
+    .build();
+
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 0.0, 0.0);
+}
+
+#[test]
+fn point_and_spot_lights_only_reach_what_they_should() {
+  let mut engine = headless_engine();
+  add_material_quads(&mut engine);
+  let lights = engine.render_task.get_lights_mut();
+  lights.clear();
+
+  // nothing but ambient
+  let image = engine.capture_frame().unwrap();
+  let dark = image.get_pixel(WIDTH / 4, HEIGHT / 2).0;
+  assert!(dark[0] > 0 && dark[0] < 120);
+
+  // right in front of the red quad, too short to reach the other one
+  let point = Light::point(Vec3::new(-1.0, 0.0, -1.5), [1.0, 1.0, 1.0])
+    .with_intensity(2.0)
+    .with_range(2.0);
+  let point = engine.render_task.add_light(point);
+
+  let image = engine.capture_frame().unwrap();
+  let left = image.get_pixel(WIDTH / 4, HEIGHT / 2).0;
+  assert!(left[0] > 200);
+  let right = image.get_pixel(WIDTH * 3 / 4, HEIGHT / 2).0;
+  assert!(right[0] < 120 && right[1] < 120 && right[2] < 120);
+
+  // a narrow cone at the red quad, its corners and the other quad stay dark
+  let lights = engine.render_task.get_lights_mut();
+  lights.remove(point).unwrap();
+  let spot = Light::spot(
+    Vec3::new(-2.0, 0.0, -1.5),
+    Vec3::new(1.0, 0.0, 0.0),
+    0.25,
+    0.35,
+    [1.0, 1.0, 1.0],
+  )
+  .with_intensity(8.0);
+  lights.add(spot);
+
+  let image = engine.capture_frame().unwrap();
+  let lit = image.get_pixel(WIDTH / 4, HEIGHT / 2).0;
+  let corner = image.get_pixel(WIDTH / 4, HEIGHT / 2 - 25).0;
+  assert!(lit[0] > 200 && corner[0] < 120);
+  let right = image.get_pixel(WIDTH * 3 / 4, HEIGHT / 2).0;
+  assert!(right[0] < 120 && right[1] < 120 && right[2] < 120);
+}
+
+#[test]
+fn toon_shading_bands_the_light() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  let diffuse = engine.texture_bundle.get_texture("test");
+  let object = ObjectBuilder::new()
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "test_bake_table.obj",
+    )
+    .unwrap()
+    .add_diffuse_texture(&engine.drivers, diffuse)
+    .build();
+
+  let toon = ShaderBuilder::from_file("sample.wgsl".to_owned()).define("TOON");
+  pollster::block_on(engine.render_task.add_object_with_shader(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+    toon,
+  ))
+  .unwrap();
+
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 2.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.3;
+
+  let image = engine.capture_frame().unwrap();
+  assert_matches_golden("toon_table", &image);
+}
+
+/// where a point in the world ends up on screen
+fn project(engine: &Engine, point: Vec3) -> (u32, u32) {
+  let clip = engine.camera.camera.build_view_projection_matrix() * point.extend(1.0);
+  let ndc = clip.truncate() / clip.w;
+  let x = (ndc.x * 0.5 + 0.5) * WIDTH as f32;
+  let y = (0.5 - ndc.y * 0.5) * HEIGHT as f32;
+  (x as u32, y as u32)
+}
+
+#[test]
+fn shadow_casting_lights_darken_what_is_behind() {
+  let mut engine = headless_engine();
+
+  let white = engine.texture_bundle.get_white_texture();
+  let object = ObjectBuilder::new()
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "shadow_floor.obj",
+    )
+    .unwrap()
+    .add_diffuse_texture(&engine.drivers, white)
+    .build();
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+
+  engine.camera.camera.position = cgmath::Point3::new(-5.0, 3.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.5;
+
+  // slanted, so the shadow lands next to the blocker instead of right under it
+  let lights = engine.render_task.get_lights_mut();
+  lights.clear();
+  let sun =
+    Light::directional(Vec3::new(0.0, -1.0, 1.0), [1.0, 1.0, 1.0]).with_shadows(ShadowSettings {
+      resolution: 512,
+      extent: 4.0,
+      ..Default::default()
+    });
+  let sun = lights.add(sun);
+
+  let shadowed = project(&engine, Vec3::new(0.0, -1.0, 1.0));
+  let lit = project(&engine, Vec3::new(0.0, -1.0, -1.0));
+  let brightness = |image: &image::RgbaImage, (x, y): (u32, u32)| image.get_pixel(x, y).0[0];
+
+  let image = engine.capture_frame().unwrap();
+  assert!(brightness(&image, lit) > 150);
+  assert!(brightness(&image, shadowed) < 100);
+  assert_matches_golden("shadows", &image);
+
+  // same light without shadows lights both sides the same
+  let lights = engine.render_task.get_lights_mut();
+  lights.get_mut(sun).unwrap().shadows = None;
+  let image = engine.capture_frame().unwrap();
+  assert!(brightness(&image, lit).abs_diff(brightness(&image, shadowed)) < 10);
+}
+
+// clears whatever it writes to red, and notes down when it ran
+struct TintPass {
+  desc: PassDesc,
+  ran: Arc<Mutex<Vec<String>>>,
+}
+
+impl GraphPass for TintPass {
+  fn desc(&self) -> PassDesc {
+    self.desc.clone()
+  }
+
+  fn run(&self, context: &mut PassContext<'_>) {
+    self.ran.lock().unwrap().push(self.desc.name.clone());
+
+    let view = context.get_view(render_graph::SURFACE).unwrap();
+    context
+      .encoder
+      .begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("tint"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+          view,
+          resolve_target: None,
+          ops: wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color::RED),
+            store: wgpu::StoreOp::Store,
+          },
+        })],
+        ..Default::default()
+      });
+  }
+}
+
+#[test]
+fn graph_passes_run_after_what_they_read() {
+  let mut engine = headless_engine();
+
+  let ran = Arc::new(Mutex::new(Vec::new()));
+  let tint = TintPass {
+    desc: PassDesc::new("tint")
+      .reads(render_graph::DEPTH)
+      .writes(render_graph::SURFACE),
+    ran: ran.clone(),
+  };
+  engine.render_task.add_pass(tint).unwrap();
+  assert_eq!(
+    engine.render_task.get_graph().get_order(),
+    ["shadows", "scene", "transparent", "post", "tint"]
+  );
+
+  // added before the scene could've drawn over it, but still runs after
+  let image = engine.capture_frame().unwrap();
+  assert_eq!(*ran.lock().unwrap(), ["tint"]);
+  assert!(image.pixels().all(|pixel| pixel.0 == [255, 0, 0, 255]));
+
+  let broken = TintPass {
+    desc: PassDesc::new("broken").reads("nothing"),
+    ran: ran.clone(),
+  };
+  assert!(matches!(
+    engine.render_task.add_pass(broken),
+    Err(GraphError::UnknownAttachment { .. })
+  ));
+  assert_eq!(engine.render_task.get_graph().get_order().len(), 5);
+}
+
+#[test]
+fn graph_attachments_follow_resizes() {
+  let mut engine = headless_engine();
+
+  let depth_size = |engine: &Engine| {
+    let depth = engine
+      .render_task
+      .get_graph()
+      .get_attachment(render_graph::DEPTH);
+    let size = depth.unwrap().texture.size();
+    (size.width, size.height)
+  };
+  assert_eq!(depth_size(&engine), (WIDTH, HEIGHT));
+
+  engine.resize(WIDTH * 2, HEIGHT / 2);
+  assert_eq!(depth_size(&engine), (WIDTH * 2, HEIGHT / 2));
+  engine.redraw();
+}
+
+fn color_count(image: &image::RgbaImage) -> usize {
+  let mut colors: Vec<[u8; 4]> = image.pixels().map(|pixel| pixel.0).collect();
+  colors.sort();
+  colors.dedup();
+  colors.len()
+}
+
+#[test]
+fn post_effects_run_on_the_scene() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  add_table(&mut engine, Location::from_pos(Vec3::new(0.0, 0.0, 0.0)));
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 2.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.3;
+  let plain = engine.capture_frame().unwrap();
+
+  let posterize = PostEffect::Posterize { levels: 3.0 };
+  engine
+    .render_task
+    .add_post_effect(&engine.drivers, &engine.data_bindgroups, posterize)
+    .unwrap();
+  let posterized = engine.capture_frame().unwrap();
+  assert!(color_count(&posterized) <= 27 && color_count(&posterized) < color_count(&plain));
+
+  // red so it can't be mistaken for the background or the table
+  let outline = PostEffect::Outline {
+    color: [1.0, 0.0, 0.0],
+    thickness: 1.0,
+    depth_threshold: 0.1,
+    crease_threshold: 0.05,
+  };
+  engine
+    .render_task
+    .add_post_effect(&engine.drivers, &engine.data_bindgroups, outline)
+    .unwrap();
+  let outlined = engine.capture_frame().unwrap();
+  let red = |image: &image::RgbaImage| {
+    image
+      .pixels()
+      .filter(|pixel| pixel.0 == [255, 0, 0, 255])
+      .count()
+  };
+  assert_eq!(red(&posterized), 0);
+  assert!(red(&outlined) > 0);
+  assert_matches_golden("post_table", &outlined);
+
+  let missing = PostEffect::Custom {
+    shader: "not_a_shader.wgsl".to_owned(),
+    params: [[0.0; 4]; 2],
+  };
+  let added = engine
+    .render_task
+    .add_post_effect(&engine.drivers, &engine.data_bindgroups, missing);
+  assert!(added.is_err());
+  assert_eq!(engine.render_task.get_post_stack().len(), 2);
+}
+
+#[test]
+fn msaa_can_be_turned_on_and_off_while_running() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  add_table(&mut engine, Location::from_pos(Vec3::new(0.0, 0.0, 0.0)));
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 2.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.3;
+  engine
+    .render_task
+    .add_post_effect(
+      &engine.drivers,
+      &engine.data_bindgroups,
+      PostEffect::outline(),
+    )
+    .unwrap();
+  let aliased = engine.capture_frame().unwrap();
+
+  let applied = engine.set_graphics_settings(GraphicsSettings {
+    msaa_samples: 4,
+    ..*engine.get_graphics_settings()
+  });
+  // not every adapter can do 4x, but whatever it picked has to render
+  assert!(applied.msaa_samples <= 4);
+  assert_eq!(*engine.get_graphics_settings(), applied);
+  let smoothed = engine.capture_frame().unwrap();
+  assert_eq!(engine.render_task.get_post_stack().len(), 1);
+
+  let changed = aliased
+    .pixels()
+    .zip(smoothed.pixels())
+    .filter(|(a, b)| a != b)
+    .count();
+  if applied.msaa_samples > 1 {
+    // only the edges get smoothed
+    assert!(changed > 0);
+    assert!(changed < (WIDTH * HEIGHT / 10) as usize);
+  }
+
+  engine.set_graphics_settings(GraphicsSettings::default());
+  assert_eq!(engine.capture_frame().unwrap(), aliased);
+}
+
+#[test]
+fn backends_can_be_benchmarked() {
+  // only to find the assets and skip when there's no gpu at all
+  let engine = headless_engine();
+  let backend = BackendPreference::from_backend(engine.drivers.adapter.get_info().backend).unwrap();
+  drop(engine);
+
+  assert!(benchmark::available_backends().contains(&backend));
+
+  let result = pollster::block_on(benchmark::run_benchmark(backend, 3)).unwrap();
+  assert_eq!(result.backend, backend);
+  assert_eq!(result.frame_times.len(), 3);
+  assert_eq!(benchmark::pick_fastest(&[result]), Some(backend));
+}
+
+#[test]
+fn samplers_are_shared_and_can_be_swapped() {
+  let mut engine = headless_engine();
+  load_table_texture(&mut engine);
+
+  let cached = engine.texture_bundle.get_sampler_cache().len();
+  let first = engine
+    .texture_bundle
+    .get_sampler(&engine.drivers, SamplerSettings::PIXEL_ART);
+  let second = engine
+    .texture_bundle
+    .get_sampler(&engine.drivers, SamplerSettings::PIXEL_ART);
+  assert_eq!(first, second);
+  assert_eq!(engine.texture_bundle.get_sampler_cache().len(), cached + 1);
+
+  // close enough that the texture gets magnified
+  let look_closely = |engine: &mut Engine| {
+    engine.camera.camera.position = cgmath::Point3::new(-2.0, 1.5, 0.0);
+    engine.camera.camera.pitch_radians = -0.4;
+  };
+
+  let diffuse = engine.texture_bundle.get_texture("test");
+  let object = ObjectBuilder::new()
+    .set_sampler(
+      &engine.drivers,
+      &mut engine.texture_bundle,
+      SamplerSettings::PIXEL_ART,
+    )
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "test_bake_table.obj",
+    )
+    .unwrap()
+    .add_diffuse_texture(&engine.drivers, diffuse)
+    .build();
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+  // still no new sampler, the builder got the cached one
+  assert_eq!(engine.texture_bundle.get_sampler_cache().len(), cached + 1);
+
+  look_closely(&mut engine);
+  let blocky = engine.capture_frame().unwrap();
+
+  let mut engine = headless_engine();
+  load_table_texture(&mut engine);
+  add_table(&mut engine, Location::from_pos(Vec3::new(0.0, 0.0, 0.0)));
+  look_closely(&mut engine);
+  let smooth = engine.capture_frame().unwrap();
+
+  assert_ne!(smooth, blocky);
+  assert_matches_golden("table_pixel_art", &blocky);
+}
+
+// a 4x4 dds holding one bc1 block, all red
+fn red_dds() -> Vec<u8> {
+  let mut bytes = vec![0; 128];
+  bytes[..4].copy_from_slice(b"DDS ");
+  bytes[12..16].copy_from_slice(&4u32.to_le_bytes());
+  bytes[16..20].copy_from_slice(&4u32.to_le_bytes());
+  bytes[84..88].copy_from_slice(b"DXT1");
+  bytes.extend(0xf800u16.to_le_bytes());
+  bytes.extend([0; 6]);
+  bytes
+}
+
+#[test]
+fn textures_keep_their_formats() {
+  let mut engine = headless_engine();
+
+  // past 1 like out of a .hdr, rgba8 would clamp it
+  let hdr = image::DynamicImage::ImageRgba32F(image::Rgba32FImage::from_pixel(
+    4,
+    4,
+    image::Rgba([4.0, 2.0, 1.0, 1.0]),
+  ));
+  let texture = engine
+    .texture_bundle
+    .add_image(
+      &engine.drivers,
+      &hdr,
+      "sky",
+      ColorSpace::Linear,
+      SamplerSettings::default(),
+    )
+    .unwrap();
+  assert_eq!(texture.texture.format(), wgpu::TextureFormat::Rgba16Float);
+  assert_eq!(texture.texture.mip_level_count(), 3);
+
+  let normals = engine
+    .texture_bundle
+    .add_texture_from_file_as(
+      &engine.drivers,
+      "test_bake.png",
+      "normals",
+      ColorSpace::Linear,
+    )
+    .unwrap();
+  assert_eq!(normals.texture.format(), wgpu::TextureFormat::Rgba8Unorm);
+
+  // gpus without bc support get it decompressed instead
+  let dds = ImageTexture::from_bytes(&engine.drivers, &red_dds(), "dds", ColorSpace::Srgb).unwrap();
+  let has_bc = engine
+    .drivers
+    .device
+    .features()
+    .contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
+  let expected = match has_bc {
+    true => wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+    false => wgpu::TextureFormat::Rgba8UnormSrgb,
+  };
+  assert_eq!(dds.texture.format(), expected);
+
+  // and it still draws, red all over the table
+  let object = ObjectBuilder::new()
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "test_bake_table.obj",
+    )
+    .unwrap()
+    .add_diffuse_texture(&engine.drivers, std::sync::Arc::new(dds))
+    .build();
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 2.0, 0.0);
+  engine.camera.camera.pitch_radians = -0.3;
+
+  let image = engine.capture_frame().unwrap();
+  assert!(image.pixels().any(|pixel| pixel.0[0] > 20));
+  assert!(image
+    .pixels()
+    .all(|pixel| pixel.0[1] <= pixel.0[0] && pixel.0[2] <= pixel.0[0]));
+}
+
+#[test]
+fn arrays_cubemaps_and_atlases_can_be_bound() {
+  let mut engine = headless_engine();
+  let drivers = &engine.drivers;
+
+  let array = engine
+    .texture_bundle
+    .add_texture_array_from_files(
+      drivers,
+      &["test_bake.png", "test_bake.png", "test_bake.png"],
+      "terrain",
+      ColorSpace::Srgb,
+      SamplerSettings::default(),
+    )
+    .unwrap();
+  assert_eq!(array.get_layer_count(), 3);
+  assert_eq!(array.get_kind(), LayerKind::Array);
+  let layout = LayeredTexture::create_bind_group_layout(drivers, LayerKind::Array);
+  array.create_bind_group(drivers, &layout);
+
+  // a sky that's bright above the horizon and dark below it
+  let panorama =
+    image::DynamicImage::ImageRgb32F(image::Rgb32FImage::from_fn(64, 32, |_, y| match y < 16 {
+      true => image::Rgb([4.0, 4.0, 4.0]),
+      false => image::Rgb([0.1, 0.1, 0.1]),
+    }));
+  let sampler = engine
+    .texture_bundle
+    .get_sampler(drivers, SamplerSettings::default());
+  let sky = LayeredTexture::cubemap_from_equirect(
+    drivers,
+    &panorama,
+    16,
+    Some("sky"),
+    ColorSpace::Linear,
+    sampler.clone(),
+  )
+  .unwrap();
+  assert_eq!(sky.get_layer_count(), 6);
+  assert_eq!((sky.texture.width(), sky.texture.height()), (16, 16));
+  assert!(sky.is_float());
+  let layout = LayeredTexture::create_bind_group_layout(drivers, LayerKind::Cube);
+  sky.create_bind_group(drivers, &layout);
+
+  // faces have to be square
+  let face = image::DynamicImage::new_rgba8(8, 4);
+  let faces = std::array::from_fn(|_| face.clone());
+  assert!(
+    LayeredTexture::cubemap_from_images(drivers, &faces, None, ColorSpace::Srgb, sampler).is_err()
+  );
+
+  let atlas = engine
+    .texture_bundle
+    .add_atlas_from_files(drivers, &["test_bake.png", "detail.png"], "props")
+    .unwrap();
+  let region = *atlas.get_region("detail.png").unwrap();
+  let texture = atlas.get_texture();
+  assert!(Arc::ptr_eq(
+    &texture,
+    &engine.texture_bundle.get_texture("props")
+  ));
+  assert_eq!(
+    region.remap_uv([0.0, 0.0]),
+    [
+      region.x as f32 / texture.texture.width() as f32,
+      region.y as f32 / texture.texture.height() as f32
+    ]
+  );
+  assert!(engine
+    .texture_bundle
+    .add_atlas(drivers, &AtlasBuilder::new(), "props")
+    .is_err());
+}
+
+fn top_and_bottom(image: &image::RgbaImage) -> (image::Rgba<u8>, image::Rgba<u8>) {
+  (
+    *image.get_pixel(WIDTH / 2, 0),
+    *image.get_pixel(WIDTH / 2, HEIGHT - 1),
+  )
+}
+
+#[test]
+fn backgrounds_fill_what_the_scene_doesnt_cover() {
+  let mut engine = headless_engine();
+
+  let blue = Background::Solid([0.0, 0.0, 1.0]);
+  engine.render_task.set_background(&engine.drivers, blue);
+  let image = engine.capture_frame().unwrap();
+  assert!(image.pixels().all(|pixel| pixel.0 == [0, 0, 255, 255]));
+
+  // looking level, the top of the screen is further up than the bottom
+  let gradient = Background::Gradient {
+    top: [1.0, 0.0, 0.0],
+    bottom: [0.0, 0.0, 1.0],
+  };
+  engine.render_task.set_background(&engine.drivers, gradient);
+  let (top, bottom) = top_and_bottom(&engine.capture_frame().unwrap());
+  assert!(top.0[0] > top.0[2] && bottom.0[2] > bottom.0[0]);
+  // and it stays level when the camera tilts, straight down is all bottom color
+  engine.camera.camera.pitch_radians = -1.5;
+  let (top, _) = top_and_bottom(&engine.capture_frame().unwrap());
+  assert!(top.0[2] > top.0[0]);
+
+  // red sky, green ground
+  let panorama =
+    image::DynamicImage::ImageRgb32F(image::Rgb32FImage::from_fn(64, 32, |_, y| match y < 16 {
+      true => image::Rgb([1.0, 0.0, 0.0]),
+      false => image::Rgb([0.0, 1.0, 0.0]),
+    }));
+  let sampler = engine
+    .texture_bundle
+    .get_sampler(&engine.drivers, SamplerSettings::default());
+  let cubemap = LayeredTexture::cubemap_from_equirect(
+    &engine.drivers,
+    &panorama,
+    16,
+    Some("skybox"),
+    ColorSpace::Linear,
+    sampler,
+  )
+  .unwrap();
+  let skybox = Background::skybox(Arc::new(cubemap));
+  engine.render_task.set_background(&engine.drivers, skybox);
+  engine.camera.camera.pitch_radians = 0.0;
+  let (top, bottom) = top_and_bottom(&engine.capture_frame().unwrap());
+  assert!(top.0[0] > 200 && top.0[1] < 50);
+  assert!(bottom.0[1] > 200 && bottom.0[0] < 50);
+
+  // the background's pipeline is rebuilt along with everything else
+  let applied = engine.set_graphics_settings(GraphicsSettings {
+    msaa_samples: 4,
+    ..*engine.get_graphics_settings()
+  });
+  if applied.msaa_samples > 1 {
+    let (top, _) = top_and_bottom(&engine.capture_frame().unwrap());
+    assert!(top.0[0] > 200);
+  }
+}
+
+#[test]
+fn sky_is_drawn_behind_the_table() {
+  let mut engine = headless_engine();
+
+  load_table_texture(&mut engine);
+  add_table(&mut engine, Location::from_pos(Vec3::new(0.0, 0.0, 0.0)));
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 1.0, 0.0);
+  engine.camera.camera.pitch_radians = 0.1;
+
+  // still clouds, or the golden would depend on how long the test took to get here
+  let sky = Background::Sky(SkySettings {
+    cloud_speed: 0.0,
+    ..Default::default()
+  });
+  engine.render_task.set_background(&engine.drivers, sky);
+
+  let image = engine.capture_frame().unwrap();
+  assert_matches_golden("table_sky", &image);
+}
+
+fn add_glass_quads(engine: &mut Engine, blend_mode: Option<BlendMode>) {
+  let mut builder = ObjectBuilder::new();
+  if let Some(blend_mode) = blend_mode {
+    builder = builder.set_blend_mode(&engine.drivers, blend_mode);
+  }
+  let object = builder
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "glass_quads.obj",
+    )
+    .unwrap()
+    .build();
+
+  pollster::block_on(engine.render_task.add_object(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+  ))
+  .unwrap();
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 0.0, 0.0);
+}
+
+#[test]
+fn transparent_materials_blend_over_the_scene() {
+  let mut engine = headless_engine();
+  add_glass_quads(&mut engine, None);
+  // the wall and the two see through quads, each blend mode with its own pipeline
+  assert_eq!(engine.render_task.get_pipeline_count(), 2);
+
+  let glass = project(&engine, Vec3::new(0.0, 0.0, -1.5));
+  let haze = project(&engine, Vec3::new(0.0, 0.0, 1.5));
+  let wall = project(&engine, Vec3::new(1.0, 1.5, 0.0));
+
+  let image = engine.capture_frame().unwrap();
+  assert_eq!(engine.render_task.get_render_stats().draws, 3);
+  let wall_color = image.get_pixel(wall.0, wall.1).0;
+  assert!(wall_color[0] > 50 && wall_color[1] == 0 && wall_color[2] == 0);
+  // the red wall still shows through both of them
+  let glass_color = image.get_pixel(glass.0, glass.1).0;
+  assert!(glass_color[0] > 20 && glass_color[2] > 20 && glass_color[1] == 0);
+  let haze_color = image.get_pixel(haze.0, haze.1).0;
+  assert!(haze_color[0] > 20 && haze_color[1] > 20 && haze_color[2] == 0);
+  // the haze is fainter, so more of the wall is left
+  assert!(haze_color[0] > glass_color[0]);
+
+  // one layer of weighted blended transparency comes out the same as blending it straight
+  engine
+    .render_task
+    .set_transparency_mode(&engine.drivers, TransparencyMode::WeightedBlended);
+  let blended = engine.capture_frame().unwrap();
+  for (x, y) in [glass, haze, wall] {
+    let (sorted, blended) = (image.get_pixel(x, y).0, blended.get_pixel(x, y).0);
+    assert!(
+      sorted
+        .iter()
+        .zip(blended.iter())
+        .all(|(a, b)| a.abs_diff(*b) <= CHANNEL_TOLERANCE),
+      "{:?} and {:?} don't match",
+      sorted,
+      blended
+    );
+  }
+}
+
+#[test]
+fn alpha_cutout_throws_away_the_see_through_bits() {
+  let mut engine = headless_engine();
+  add_glass_quads(&mut engine, Some(BlendMode::AlphaCutout));
+  assert_eq!(engine.render_task.get_pipeline_count(), 1);
+
+  let glass = project(&engine, Vec3::new(0.0, 0.0, -1.5));
+  let haze = project(&engine, Vec3::new(0.0, 0.0, 1.5));
+  let image = engine.capture_frame().unwrap();
+
+  // the glass is right on the cutoff so it stays, solid, the haze is under it so it's gone
+  let glass_color = image.get_pixel(glass.0, glass.1).0;
+  assert!(glass_color[0] == 0 && glass_color[2] > 20);
+  let haze_color = image.get_pixel(haze.0, haze.1).0;
+  assert!(haze_color[0] > 20 && haze_color[1] == 0);
+}
+
+fn add_material_quads_with_state(engine: &mut Engine, state: PipelineState) {
+  let object = ObjectBuilder::new()
+    .load_meshes_from_objfile(
+      &mut engine.texture_bundle,
+      &engine.drivers,
+      "material_quads.obj",
+    )
+    .unwrap()
+    .build();
+  let builder = ShaderBuilder::from_file("sample.wgsl".to_owned()).with_state(state);
+
+  pollster::block_on(engine.render_task.add_object_with_shader(
+    object,
+    &engine.drivers,
+    &engine.data_bindgroups,
+    builder,
+  ))
+  .unwrap();
+  engine.camera.camera.position = cgmath::Point3::new(-4.0, 0.0, 0.0);
+}
+
+#[test]
+fn pipeline_state_is_part_of_what_pipelines_are_shared_by() {
+  let mut engine = headless_engine();
+
+  // the quads face the camera, culling their fronts leaves nothing but the clear color
+  let culled = PipelineState::new().with_cull_mode(Some(wgpu::Face::Front));
+  add_material_quads_with_state(&mut engine, culled);
+  assert_eq!(engine.render_task.get_pipeline_count(), 1);
+  let image = engine.capture_frame().unwrap();
+  assert_eq!(image.get_pixel(WIDTH / 4, HEIGHT / 2).0, [0, 0, 0, 255]);
+
+  // the same state again reuses the pipeline, another one gets its own
+  add_material_quads_with_state(&mut engine, culled);
+  assert_eq!(engine.render_task.get_pipeline_count(), 1);
+  let back = PipelineState::new().with_cull_mode(Some(wgpu::Face::Back));
+  add_material_quads_with_state(&mut engine, back);
+  assert_eq!(engine.render_task.get_pipeline_count(), 2);
+  let image = engine.capture_frame().unwrap();
+  assert!(image.get_pixel(WIDTH / 4, HEIGHT / 2).0[0] > 0);
+}
+
+#[test]
+fn wireframe_view_is_only_turned_on_where_lines_can_be_drawn() {
+  let mut engine = headless_engine();
+  add_material_quads(&mut engine);
+  let filled = engine.capture_frame().unwrap();
+
+  let supported = engine.drivers.supports_wireframe();
+  assert_eq!(
+    engine.render_task.set_wireframe(&engine.drivers, true),
+    supported
+  );
+  assert_eq!(engine.render_task.is_wireframe(), supported);
+  if supported {
+    // the middle of the red quad is empty now, only its edges are drawn
+    let image = engine.capture_frame().unwrap();
+    assert_eq!(
+      image.get_pixel(WIDTH / 4, HEIGHT / 2 - 10).0,
+      [0, 0, 0, 255]
+    );
+  }
+
+  // and back to filled triangles
+  assert!(engine.render_task.set_wireframe(&engine.drivers, false));
+  let image = engine.capture_frame().unwrap();
+  assert_eq!(
+    image.get_pixel(WIDTH / 4, HEIGHT / 2),
+    filled.get_pixel(WIDTH / 4, HEIGHT / 2)
+  );
+}